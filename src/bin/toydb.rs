@@ -10,7 +10,7 @@ use serde::Deserialize;
 use std::collections::HashMap;
 use toydb::errinput;
 use toydb::error::Result;
-use toydb::raft;
+use toydb::raft::{self, State as _};
 use toydb::sql;
 use toydb::storage;
 use toydb::Server;
@@ -26,6 +26,11 @@ fn main() -> Result<()> {
                 .help("Configuration file path")
                 .default_value("config/toydb.yaml"),
         )
+        .subcommand(
+            clap::Command::new("restore")
+                .about("Seeds the data directory from a backup, then exits")
+                .arg(clap::Arg::new("backup").help("Backup directory").required(true)),
+        )
         .get_matches();
     let cfg = Config::new(args.get_one::<String>("config").unwrap().as_ref())?;
 
@@ -36,33 +41,121 @@ fn main() -> Result<()> {
     }
     simplelog::SimpleLogger::init(loglevel, logconfig.build())?;
 
+    if let Some(args) = args.subcommand_matches("restore") {
+        let backup = args.get_one::<String>("backup").unwrap();
+        return restore(&cfg, std::path::Path::new(backup));
+    }
+
     let path = std::path::Path::new(&cfg.data_dir);
+    let sync = cfg.fsync.parse::<storage::SyncPolicy>()?;
+    let key = cfg.encryption_key()?;
+    let compression = match cfg.compression.as_str() {
+        "none" | "" => None,
+        codec => Some(codec.parse::<storage::Codec>()?),
+    };
+    let keydir = match cfg.keydir.as_str() {
+        "memory" | "" => storage::KeyDirMode::Memory,
+        "disk" => storage::KeyDirMode::Disk {
+            buffer_size: cfg.keydir_buffer_size,
+            block_size: cfg.keydir_block_size,
+        },
+        mode => return errinput!("invalid keydir mode {mode}"),
+    };
     let raft_log = match cfg.storage_raft.as_str() {
-        "bitcask" | "" => raft::Log::new(Box::new(storage::BitCask::new_compact(
-            path.join("log"),
-            cfg.compact_threshold,
-            COMPACT_MIN_BYTES,
-        )?))?,
+        "bitcask" | "" => {
+            let mut engine = storage::BitCask::new_compact(
+                path.join("log"),
+                key.clone(),
+                keydir,
+                cfg.compact_threshold,
+                COMPACT_MIN_BYTES,
+            )?;
+            engine.set_sync(sync)?;
+            engine.set_compression(compression, cfg.compression_threshold);
+            engine.set_block_cache(cfg.block_cache_size, cfg.block_cache_block_size)?;
+            engine.set_mmap(cfg.mmap);
+            raft::Log::new(Box::new(engine))?
+        }
         "memory" => raft::Log::new(Box::new(storage::Memory::new()))?,
         name => return errinput!("invalid Raft storage engine {name}"),
     };
     let raft_state: Box<dyn raft::State> = match cfg.storage_sql.as_str() {
         "bitcask" | "" => {
-            let engine = storage::BitCask::new_compact(
+            let mut engine = storage::BitCask::new_compact(
                 path.join("state"),
+                key,
+                keydir,
                 cfg.compact_threshold,
                 COMPACT_MIN_BYTES,
             )?;
-            Box::new(sql::engine::Raft::new_state(engine)?)
+            engine.set_sync(sync)?;
+            engine.set_compression(compression, cfg.compression_threshold);
+            engine.set_block_cache(cfg.block_cache_size, cfg.block_cache_block_size)?;
+            engine.set_mmap(cfg.mmap);
+            let mut state = sql::engine::Raft::new_state(engine)?;
+            state.set_change_feed(cfg.change_feed);
+            Box::new(state)
         }
         "memory" => {
-            let engine = storage::Memory::new();
-            Box::new(sql::engine::Raft::new_state(engine)?)
+            let mut state = sql::engine::Raft::new_state(storage::Memory::new())?;
+            state.set_change_feed(cfg.change_feed);
+            Box::new(state)
         }
         name => return errinput!("invalid SQL storage engine {name}"),
     };
 
-    Server::new(cfg.id, cfg.peers, raft_log, raft_state)?.serve(&cfg.listen_raft, &cfg.listen_sql)
+    let mut server = Server::new(cfg.id, cfg.peers, raft_log, raft_state)?;
+    server.set_backup_dir(cfg.backup_dir.into());
+    server.serve(&cfg.listen_raft, &cfg.listen_sql)
+}
+
+/// Seeds the data directory from a backup written via a backup request (e.g.
+/// toysql's !backup command). The data directory must not contain any data.
+///
+/// The backup contains the node's Raft log, term, vote, and commit index, along
+/// with the SQL state machine as of the applied index, so the restored node
+/// will resume where the backed up node left off. To restore an entire
+/// cluster, restore all nodes from the same backup. Transactions that were in
+/// flight when the backup was taken remain active, like after a crash.
+fn restore(cfg: &Config, backup: &std::path::Path) -> Result<()> {
+    if !matches!(cfg.storage_raft.as_str(), "bitcask" | "")
+        || !matches!(cfg.storage_sql.as_str(), "bitcask" | "")
+    {
+        return errinput!("restore requires bitcask storage");
+    }
+    let path = std::path::Path::new(&cfg.data_dir);
+    for name in ["log", "state"] {
+        if path.join(name).exists() {
+            return errinput!("data directory {} already contains {name}", path.display());
+        }
+    }
+
+    // Open the backup to verify it before copying it. The backup must be
+    // encrypted with the configured key, if any.
+    let open = |name| match cfg.encryption_key()? {
+        Some(key) => storage::BitCask::new_encrypted(backup.join(name), key),
+        None => storage::BitCask::new(backup.join(name)),
+    };
+    let raft_log = raft::Log::new(Box::new(open("log")?))?;
+    let raft_state = sql::engine::Raft::new_state(open("state")?)?;
+    let (last_index, _) = raft_log.get_last_index();
+    let applied_index = raft_state.get_applied_index();
+    if applied_index > last_index {
+        return errinput!("backup applied index {applied_index} beyond last index {last_index}");
+    }
+    drop(raft_log);
+    drop(raft_state);
+
+    std::fs::create_dir_all(path)?;
+    for name in ["log", "state"] {
+        std::fs::copy(backup.join(name), path.join(name))?;
+    }
+    log::info!(
+        "Restored {} from {} at applied index {applied_index}",
+        path.display(),
+        backup.display()
+    );
+    Ok(())
 }
 
 #[derive(Debug, Deserialize)]
@@ -73,7 +166,19 @@ struct Config {
     listen_raft: String,
     log_level: String,
     data_dir: String,
+    backup_dir: String,
     compact_threshold: f64,
+    fsync: String,
+    compression: String,
+    compression_threshold: usize,
+    encryption_key_file: String,
+    keydir: String,
+    keydir_buffer_size: usize,
+    keydir_block_size: usize,
+    block_cache_size: usize,
+    block_cache_block_size: usize,
+    mmap: bool,
+    change_feed: bool,
     storage_raft: String,
     storage_sql: String,
 }
@@ -86,7 +191,19 @@ impl Config {
             .set_default("listen_raft", "0.0.0.0:9705")?
             .set_default("log_level", "info")?
             .set_default("data_dir", "data")?
+            .set_default("backup_dir", "backups")?
             .set_default("compact_threshold", 0.2)?
+            .set_default("fsync", "always")?
+            .set_default("compression", "none")?
+            .set_default("compression_threshold", 64)?
+            .set_default("encryption_key_file", "")?
+            .set_default("keydir", "memory")?
+            .set_default("keydir_buffer_size", 100000)?
+            .set_default("keydir_block_size", 64)?
+            .set_default("block_cache_size", 0)?
+            .set_default("block_cache_block_size", 4096)?
+            .set_default("mmap", false)?
+            .set_default("change_feed", false)?
             .set_default("storage_raft", "bitcask")?
             .set_default("storage_sql", "bitcask")?
             .add_source(config::File::with_name(file))
@@ -94,4 +211,12 @@ impl Config {
             .build()?
             .try_deserialize()?)
     }
+
+    /// Reads the encryption key from the configured key file, if any.
+    fn encryption_key(&self) -> Result<Option<storage::EncryptionKey>> {
+        match self.encryption_key_file.as_str() {
+            "" => Ok(None),
+            file => Ok(Some(storage::EncryptionKey::from_file(file.as_ref())?)),
+        }
+    }
 }
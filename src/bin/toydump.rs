@@ -1,10 +1,17 @@
 //! toydump is a debug tool that prints a toyDB BitCask database in
 //! human-readable form. It only prints live BitCask data, not garbage entries.
+//!
+//! With --check, it instead checks the database for inconsistencies and prints
+//! any problems found, e.g. to vet a data directory before bringing a node back
+//! into the cluster. Opening the BitCask file also validates all log entries.
 #![warn(clippy::all)]
 
 use toydb::encoding::format::{self, Formatter as _};
+use toydb::errdata;
 use toydb::error::Result;
-use toydb::storage::{BitCask, Engine as _};
+use toydb::raft;
+use toydb::sql;
+use toydb::storage::{BitCask, EncryptionKey, Engine as _};
 
 fn main() -> Result<()> {
     let args = clap::command!()
@@ -15,14 +22,43 @@ fn main() -> Result<()> {
                 .num_args(0)
                 .help("file is a Raft log, not SQL database"),
             clap::Arg::new("raw").long("raw").num_args(0).help("also show raw key/value"),
+            clap::Arg::new("check")
+                .long("check")
+                .num_args(0)
+                .help("check for inconsistencies instead of printing contents"),
+            clap::Arg::new("key-file")
+                .long("key-file")
+                .help("encryption key file, if the file is encrypted"),
             clap::Arg::new("file").required(true),
         ])
         .get_matches();
     let raft: bool = *args.get_one("raft").unwrap();
     let raw: bool = *args.get_one("raw").unwrap();
+    let check: bool = *args.get_one("check").unwrap();
+    let key_file: Option<&String> = args.get_one("key-file");
     let file: &String = args.get_one("file").unwrap();
 
-    let mut engine = BitCask::new(file.into())?;
+    let mut engine = match key_file {
+        Some(key_file) => {
+            BitCask::new_encrypted(file.into(), EncryptionKey::from_file(key_file.as_ref())?)?
+        }
+        None => BitCask::new(file.into())?,
+    };
+    if check {
+        let problems = match raft {
+            true => raft::Log::new(Box::new(engine))?.check()?,
+            false => sql::engine::Local::new(engine)?.check()?,
+        };
+        for problem in &problems {
+            println!("{problem}");
+        }
+        if !problems.is_empty() {
+            return errdata!("found {} problems in {file}", problems.len());
+        }
+        println!("ok");
+        return Ok(());
+    }
+
     let mut scan = engine.scan(..);
     while let Some((key, value)) = scan.next().transpose()? {
         let mut string = match raft {
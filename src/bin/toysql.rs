@@ -92,6 +92,11 @@ impl ToySQL {
         };
 
         match command {
+            "!backup" => {
+                let args = getargs(1)?;
+                let applied_index = self.client.backup(args[0])?;
+                println!("Wrote backup {} at applied index {applied_index}", args[0]);
+            }
             "!headers" => match getargs(1)?[0] {
                 "on" => {
                     self.show_headers = true;
@@ -108,6 +113,7 @@ impl ToySQL {
 Enter a SQL statement terminated by a semicolon (;) to execute it and display the result.
 The following commands are also available:
 
+    !backup <name>     Write a backup of the server node to its backup directory
     !headers <on|off>  Enable or disable column headers
     !help              This help message
     !status            Display server status
@@ -130,7 +136,8 @@ Server:    {server} (leader {leader} in term {term} with {nodes} nodes)
 Raft log:  {committed} committed, {applied} applied, {raft_size} MB ({raft_storage} storage)
 Node logs: {logs}
 MVCC:      {active_txns} active txns, {versions} versions
-Storage:   {keys} keys, {logical_size} MB logical, {nodes}x {disk_size} MB disk, {garbage_percent}% garbage ({sql_storage} engine)
+Storage:   {keys} keys, {logical_size} MB logical, {compressed_size} MB compressed, {nodes}x {disk_size} MB disk, {garbage_percent}% garbage ({sql_storage} engine)
+Cache:     {cache_hits} hits, {cache_misses} misses
 "#,
                     server = status.server,
                     leader = status.raft.leader,
@@ -147,6 +154,10 @@ Storage:   {keys} keys, {logical_size} MB logical, {nodes}x {disk_size} MB disk,
                     keys = status.mvcc.storage.keys,
                     logical_size =
                         format_args!("{:.3}", status.mvcc.storage.size as f64 / 1000.0 / 1000.0),
+                    compressed_size = format_args!(
+                        "{:.3}",
+                        status.mvcc.storage.compressed_size as f64 / 1000.0 / 1000.0
+                    ),
                     garbage_percent = format_args!(
                         "{:.0}",
                         if status.mvcc.storage.total_disk_size > 0 {
@@ -162,6 +173,8 @@ Storage:   {keys} keys, {logical_size} MB logical, {nodes}x {disk_size} MB disk,
                         status.mvcc.storage.total_disk_size as f64 / 1000.0 / 1000.0
                     ),
                     sql_storage = status.mvcc.storage.name,
+                    cache_hits = status.mvcc.storage.cache_hits,
+                    cache_misses = status.mvcc.storage.cache_misses,
                 )
             }
             "!table" => {
@@ -196,6 +209,12 @@ Storage:   {keys} keys, {logical_size} MB logical, {nodes}x {disk_size} MB disk,
                 true => println!("Dropped table {}", name),
                 false => println!("Table {} did not exit", name),
             },
+            StatementResult::AlterTable { name } => println!("Altered table {}", name),
+            StatementResult::CreateIndex { name } => println!("Created index {}", name),
+            StatementResult::DropIndex { name, existed } => match existed {
+                true => println!("Dropped index {}", name),
+                false => println!("Index {} did not exist", name),
+            },
             StatementResult::Explain(plan) => println!("{}", plan),
             StatementResult::Select { columns, rows } => {
                 if self.show_headers {
@@ -3,11 +3,14 @@ use std::io::Write as _;
 use crate::encoding::Value as _;
 use crate::errdata;
 use crate::error::{Error, Result};
+use crate::raft;
 use crate::server::{Request, Response, Status};
-use crate::sql::engine::StatementResult;
+use crate::sql::engine::{Change, Changes, StatementResult};
 use crate::sql::types::Table;
+use crate::storage::mvcc;
 
 use rand::Rng;
+use std::collections::VecDeque;
 
 /// A toyDB client
 pub struct Client {
@@ -73,6 +76,34 @@ impl Client {
         }
     }
 
+    /// Fetches a batch of committed row changes from the change feed, starting
+    /// at the given version or cursor. Use Changes.next to fetch the next
+    /// batch. Errors unless the server has the change_feed option enabled.
+    pub fn changes(&mut self, from: impl Into<mvcc::ChangeCursor>) -> Result<Changes> {
+        match self.call(Request::Changes(from.into()))? {
+            Response::Changes(c) => Ok(c),
+            resp => errdata!("unexpected response: {resp:?}"),
+        }
+    }
+
+    /// Writes a consistent backup of the server node's Raft log and SQL
+    /// storage to a new directory with the given name in the server's backup
+    /// directory. The name must be a plain file name. Returns the Raft applied
+    /// index of the backup.
+    pub fn backup(&mut self, name: &str) -> Result<raft::Index> {
+        match self.call(Request::Backup(name.to_string()))? {
+            Response::Backup(applied_index) => Ok(applied_index),
+            resp => errdata!("unexpected response: {resp:?}"),
+        }
+    }
+
+    /// Subscribes to the change feed, starting at the given version. Returns
+    /// an iterator that polls the server for new changes as needed, and never
+    /// ends unless an error occurs.
+    pub fn subscribe(&mut self, from: mvcc::Version) -> Subscription<'_> {
+        Subscription { client: self, buffer: VecDeque::new(), next: from.into() }
+    }
+
     /// Returns the version and read-only state of the txn
     pub fn txn(&self) -> Option<(u64, bool)> {
         self.txn
@@ -119,3 +150,46 @@ impl Client {
         }
     }
 }
+
+/// A change feed subscription, which emits committed row changes by polling the
+/// server. See Client::subscribe().
+pub struct Subscription<'a> {
+    client: &'a mut Client,
+    buffer: VecDeque<Change>,
+    next: mvcc::ChangeCursor,
+}
+
+impl<'a> Subscription<'a> {
+    /// The interval between polls when there are no new changes.
+    const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+    /// Returns a cursor that can be used to resume the subscription via
+    /// Client::subscribe(). This is a version, so if the subscription is
+    /// resumed in the middle of a version, the changes of that version that
+    /// were already emitted will be emitted again.
+    pub fn cursor(&self) -> mvcc::Version {
+        self.buffer.front().map(|c| c.version).unwrap_or(self.next.version)
+    }
+}
+
+impl<'a> Iterator for Subscription<'a> {
+    type Item = Result<Change>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.buffer.is_empty() {
+            match self.client.changes(self.next.clone()) {
+                Ok(Changes { changes, next }) => {
+                    // Poll again right away if the cursor advanced past
+                    // changes that aren't row changes.
+                    if changes.is_empty() && next == self.next {
+                        std::thread::sleep(Self::POLL_INTERVAL);
+                    }
+                    self.buffer.extend(changes);
+                    self.next = next;
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        self.buffer.pop_front().map(Ok)
+    }
+}
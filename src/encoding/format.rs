@@ -4,10 +4,12 @@
 use super::{bincode, Key as _, Value as _};
 use crate::raft;
 use crate::sql;
+use crate::sql::types::format_tuple;
 use crate::storage::mvcc;
 
 use itertools::Itertools as _;
 use std::collections::BTreeSet;
+use std::ops::Bound;
 
 /// Formats raw key/value pairs.
 pub trait Formatter {
@@ -114,6 +116,15 @@ impl<I: Formatter> Formatter for MVCC<I> {
             mvcc::Key::Unversioned(innerkey) => {
                 format!("mvcc:Unversioned({})", I::key(&innerkey))
             }
+            mvcc::Key::TxnLock(version, innerkey) => {
+                format!("mvcc:TxnLock({version}, {})", I::key(&innerkey))
+            }
+            mvcc::Key::Lock(innerkey, version) => {
+                format!("mvcc:Lock({}, {version})", I::key(&innerkey))
+            }
+            mvcc::Key::RangeTombstone(start, version) => {
+                format!("mvcc:RangeTombstone({}, {version})", I::key(&start))
+            }
             mvcc::Key::NextVersion | mvcc::Key::TxnActive(_) | mvcc::Key::TxnActiveSnapshot(_) => {
                 format!("mvcc:{key:?}")
             }
@@ -135,13 +146,25 @@ impl<I: Formatter> Formatter for MVCC<I> {
                 };
                 format!("{{{}}}", active.iter().map(|v| v.to_string()).join(","))
             }
-            mvcc::Key::TxnActive(_) | mvcc::Key::TxnWrite(_, _) => Raw::bytes(value),
+            mvcc::Key::TxnActive(_) | mvcc::Key::TxnWrite(_, _) | mvcc::Key::TxnLock(_, _) => {
+                Raw::bytes(value)
+            }
+            mvcc::Key::Lock(_, _) => match mvcc::LockMode::decode(value) {
+                Ok(mode) => format!("{mode:?}"),
+                Err(_) => Raw::bytes(value),
+            },
             mvcc::Key::Version(userkey, _) => match bincode::deserialize(value) {
                 Ok(Some(value)) => I::value(&userkey, value),
                 Ok(None) => "None".to_string(),
                 Err(_) => Raw::bytes(value),
             },
             mvcc::Key::Unversioned(userkey) => I::value(&userkey, value),
+            mvcc::Key::RangeTombstone(_, _) => match bincode::deserialize(value) {
+                Ok(Bound::Included(end)) => format!("..={}", I::key(&end)),
+                Ok(Bound::Excluded(end)) => format!("..{}", I::key(&end)),
+                Ok(Bound::Unbounded::<Vec<u8>>) => "..".to_string(),
+                Err(_) => Raw::bytes(value),
+            },
         }
     }
 }
@@ -161,6 +184,15 @@ impl SQL {
         values.into_iter().map(|v| Self::literal(&v)).join(",")
     }
 
+    /// Formats a tuple of values, e.g. a primary key. Single values are
+    /// formatted as-is, multiple values are parenthesized.
+    fn tuple(values: &[sql::types::Value]) -> String {
+        match values {
+            [value] => Self::literal(value),
+            values => format!("({})", Self::values(values.to_vec())),
+        }
+    }
+
     fn schema(table: sql::types::Table) -> String {
         let re = regex::Regex::new(r#"\n\s*"#).expect("regex failed");
         re.replace_all(&table.to_string(), " ").into_owned()
@@ -169,30 +201,34 @@ impl SQL {
 
 impl Formatter for SQL {
     fn key(key: &[u8]) -> String {
-        // Special-case the applied_index key.
-        if key == sql::engine::Raft::APPLIED_INDEX_KEY {
+        // Special-case the applied_index and sql_format keys.
+        if key == sql::engine::Raft::APPLIED_INDEX_KEY || key == sql::engine::FORMAT_KEY {
             return String::from_utf8_lossy(key).into_owned();
         }
 
         let Ok(key) = sql::engine::Key::decode(key) else { return Raw::key(key) };
         match key {
             sql::engine::Key::Table(name) => format!("sql:Table({name})"),
-            sql::engine::Key::Index(table, column, value) => {
-                format!("sql:Index({table}.{column}, {})", Self::literal(&value))
-            }
-            sql::engine::Key::Row(table, id) => {
-                format!("sql:Row({table}, {})", Self::literal(&id))
+            sql::engine::Key::Index(table, columns, values) => {
+                format!("sql:Index({table}.{}, {})", columns.iter().join(","), Self::tuple(&values))
             }
+            sql::engine::Key::Row(table, id) => format!("sql:Row({table}, {})", Self::tuple(&id)),
+            sql::engine::Key::NextTableId => "sql:NextTableId".to_string(),
         }
     }
 
     fn value(key: &[u8], value: &[u8]) -> String {
-        // Special-case the applied_index key.
+        // Special-case the applied_index and sql_format keys.
         if key == sql::engine::Raft::APPLIED_INDEX_KEY {
             if let Ok(applied_index) = bincode::deserialize::<raft::Index>(value) {
                 return applied_index.to_string();
             }
         }
+        if key == sql::engine::FORMAT_KEY {
+            if let Ok(format) = bincode::deserialize::<u32>(value) {
+                return format.to_string();
+            }
+        }
 
         let Ok(key) = sql::engine::Key::decode(key) else { return Raw::key(value) };
         match key {
@@ -203,16 +239,23 @@ impl Formatter for SQL {
                 Self::schema(table)
             }
             sql::engine::Key::Row(_, _) => {
-                let Ok(row) = bincode::deserialize::<sql::types::Row>(value) else {
+                let Ok(row) = sql::engine::StoredRow::decode(value) else {
                     return Raw::bytes(value);
                 };
-                Self::values(row)
+                Self::values(row.values.into_owned())
             }
             sql::engine::Key::Index(_, _, _) => {
-                let Ok(index) = bincode::deserialize::<BTreeSet<sql::types::Value>>(value) else {
+                let Ok(index) = bincode::deserialize::<BTreeSet<Vec<sql::types::Value>>>(value)
+                else {
                     return Raw::bytes(value);
                 };
-                Self::values(index)
+                index.iter().map(|id| Self::tuple(id)).join(",")
+            }
+            sql::engine::Key::NextTableId => {
+                let Ok(id) = bincode::deserialize::<sql::types::TableId>(value) else {
+                    return Raw::bytes(value);
+                };
+                id.to_string()
             }
         }
     }
@@ -239,8 +282,18 @@ impl Formatter for SQLCommand {
             | sql::engine::Write::Delete { txn, .. }
             | sql::engine::Write::Insert { txn, .. }
             | sql::engine::Write::Update { txn, .. }
+            | sql::engine::Write::Lock { txn, .. }
             | sql::engine::Write::CreateTable { txn, .. }
-            | sql::engine::Write::DropTable { txn, .. } => Some(txn),
+            | sql::engine::Write::DropTable { txn, .. }
+            | sql::engine::Write::AddColumn { txn, .. }
+            | sql::engine::Write::DropColumn { txn, .. }
+            | sql::engine::Write::RenameColumn { txn, .. }
+            | sql::engine::Write::RenameTable { txn, .. }
+            | sql::engine::Write::SetColumnDefault { txn, .. }
+            | sql::engine::Write::SetColumnNullable { txn, .. }
+            | sql::engine::Write::CreateIndex { txn, .. }
+            | sql::engine::Write::BackfillIndex { txn, .. }
+            | sql::engine::Write::DropIndex { txn, .. } => Some(txn),
         };
         let ftxn =
             txn.filter(|t| !t.read_only).map(|t| format!("t{} ", t.version)).unwrap_or_default();
@@ -250,7 +303,7 @@ impl Formatter for SQLCommand {
             sql::engine::Write::Commit(_) => "COMMIT".to_string(),
             sql::engine::Write::Rollback(_) => "ROLLBACK".to_string(),
             sql::engine::Write::Delete { table, ids, .. } => {
-                format!("DELETE {table} {}", ids.iter().map(|id| id.to_string()).join(","))
+                format!("DELETE {table} {}", ids.iter().map(|id| format_tuple(id)).join(","))
             }
             sql::engine::Write::Insert { table, rows, .. } => {
                 format!(
@@ -260,10 +313,53 @@ impl Formatter for SQLCommand {
             }
             sql::engine::Write::Update { table, rows, .. } => format!(
                 "UPDATE {table} {}",
-                rows.into_iter().map(|(id, row)| format!("{id}→({})", SQL::values(row))).join(" ")
+                rows.into_iter()
+                    .map(|(id, row)| format!("{}→({})", format_tuple(&id), SQL::values(row)))
+                    .join(" ")
+            ),
+            sql::engine::Write::Lock { table, ids, mode, skip_locked, .. } => format!(
+                "LOCK {table} {} {mode:?}{}",
+                ids.iter().map(|id| format_tuple(id)).join(","),
+                if skip_locked { " SKIP LOCKED" } else { "" }
             ),
             sql::engine::Write::CreateTable { schema, .. } => SQL::schema(schema),
             sql::engine::Write::DropTable { table, .. } => format!("DROP TABLE {table}"),
+            sql::engine::Write::AddColumn { table, column, .. } => {
+                format!("ALTER TABLE {table} ADD COLUMN {} {}", column.name, column.datatype)
+            }
+            sql::engine::Write::DropColumn { table, column, .. } => {
+                format!("ALTER TABLE {table} DROP COLUMN {column}")
+            }
+            sql::engine::Write::RenameColumn { table, column, new_name, .. } => {
+                format!("ALTER TABLE {table} RENAME COLUMN {column} TO {new_name}")
+            }
+            sql::engine::Write::RenameTable { table, new_name, .. } => {
+                format!("ALTER TABLE {table} RENAME TO {new_name}")
+            }
+            sql::engine::Write::SetColumnDefault { table, column, default, .. } => match default {
+                Some(default) => {
+                    format!("ALTER TABLE {table} ALTER COLUMN {column} SET DEFAULT {default}")
+                }
+                None => format!("ALTER TABLE {table} ALTER COLUMN {column} DROP DEFAULT"),
+            },
+            sql::engine::Write::SetColumnNullable { table, column, nullable, .. } => {
+                let action = if nullable { "DROP" } else { "SET" };
+                format!("ALTER TABLE {table} ALTER COLUMN {column} {action} NOT NULL")
+            }
+            sql::engine::Write::CreateIndex { table, index, .. } => format!(
+                "CREATE {}INDEX {} ON {table} (column{} {})",
+                if index.unique { "UNIQUE " } else { "" },
+                index.name,
+                if index.columns.len() > 1 { "s" } else { "" },
+                index.columns.iter().join(", ")
+            ),
+            sql::engine::Write::BackfillIndex { index, after, limit, .. } => match after {
+                Some(after) => {
+                    format!("BACKFILL INDEX {index} AFTER {} LIMIT {limit}", format_tuple(&after))
+                }
+                None => format!("BACKFILL INDEX {index} LIMIT {limit}"),
+            },
+            sql::engine::Write::DropIndex { index, .. } => format!("DROP INDEX {index}"),
         };
         format!("{ftxn}{fcommand}")
     }
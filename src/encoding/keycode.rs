@@ -1,6 +1,7 @@
 //! KeyCode is a lexicographical order-preserving binary encoding for use with
 //! keys. It is designed for simplicity, not efficiency (i.e. it does not use
-//! varints or other compression methods).
+//! varints or other compression methods, unless explicitly requested via
+//! Varint).
 //!
 //! Ordering is important because it allows limited scans across specific parts
 //! of the keyspace, e.g. scanning an individual table or using an index range
@@ -12,19 +13,39 @@
 //!
 //! KeyCode supports a subset of primitive data types, encoded as follows:
 //!
-//! bool:    0x00 for false, 0x01 for true.
-//! u64:     Big-endian binary representation.
-//! i64:     Big-endian binary representation, with sign bit flipped.
-//! f64:     Big-endian binary representation, with sign bit flipped, and rest if negative.
-//! Vec<u8>: 0x00 is escaped as 0x00ff, terminated with 0x0000.
-//! String:  Like Vec<u8>.
+//! bool:      0x00 for false, 0x01 for true.
+//! u32/u64:   Big-endian binary representation.
+//! u128:      Like u64.
+//! i32/i64:   Big-endian binary representation, with sign bit flipped.
+//! i128:      Like i64.
+//! f64:       Big-endian binary representation, with sign bit flipped, and rest if negative.
+//! Vec<u8>:   0x00 is escaped as 0x00ff, terminated with 0x0000.
+//! String:    Like Vec<u8>.
+//! Varint<T>: Integers as a length prefix and minimal big-endian bytes, see below.
 //!
 //! Additionally, several container types are supported:
 //!
-//! Tuple:  Concatenation of elements, with no surrounding structure.
-//! Array:  Like tuple.
-//! Vec:    Like tuple.
-//! Enum:   The variant's enum index as a single u8 byte.
+//! Tuple:     Concatenation of elements, with no surrounding structure.
+//! Array:     Like tuple.
+//! Vec:       Each element prefixed by 0x01, terminated by 0x00.
+//! Option:    0x00 for None, 0x01 followed by the value for Some.
+//! Enum:      The variant's enum index as a single u8 byte.
+//! Newtype:   The inner value, with no surrounding structure.
+//!
+//! Vecs (and other sequences) have a variable number of elements, so unlike
+//! tuples they're terminated, allowing them to be nested in other types: e.g.
+//! (["a"], "b") sorts before (["a", "b"], "a"). A key prefix for all sequences
+//! starting with some elements can be built by omitting the terminator.
+//!
+//! Varint<T> wraps an integer to encode it in a variable-length form, using
+//! fewer bytes for small numbers while preserving order. Unsigned integers
+//! are encoded as the number of significant bytes N as a u8, followed by
+//! those N bytes in big-endian order: larger numbers have more significant
+//! bytes, and are thus ordered after smaller ones. Signed integers use a
+//! header of 0x80+N for non-negative numbers and 0x7f-N for negative numbers,
+//! where N is the number of significant bytes of the number's one's complement
+//! (i.e. -1 has 0 bytes and header 0x7f), followed by the N least significant
+//! bytes of the two's complement.
 //!
 //! SQL Value enums are encoded according to the above scheme, i.e. a single
 //! byte identifying the enum variant by index, then the primitive value.
@@ -48,6 +69,7 @@ use crate::errdata;
 use crate::error::{Error, Result};
 
 use serde::{de, de::IntoDeserializer as _, ser};
+use serde::{Deserialize, Serialize};
 
 /// Serializes a key to a binary KeyCode representation.
 ///
@@ -56,7 +78,7 @@ use serde::{de, de::IntoDeserializer as _, ser};
 /// reusable byte vector to encode into and return a reference to it, but we
 /// keep it simple.
 pub fn serialize<T: ser::Serialize>(key: &T) -> Vec<u8> {
-    let mut serializer = Serializer { output: Vec::new() };
+    let mut serializer = Serializer { output: Vec::new(), varint: false };
     // Panic on serialization failures, as this is typically an issue with the
     // provided data structure.
     key.serialize(&mut serializer).expect("keycode serialization failed");
@@ -76,9 +98,133 @@ pub fn deserialize<'a, T: de::Deserialize<'a>>(input: &'a [u8]) -> Result<T> {
     Ok(t)
 }
 
+/// Wraps an integer (i.e. u32, u64, u128, i32, i64, or i128) to encode it in
+/// an order-preserving variable-length form. See the module documentation for
+/// details. Other encodings (e.g. Bincode) encode the integer as is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Varint<T>(pub T);
+
+/// The newtype struct name used to signal a varint to the KeyCode serializer.
+const VARINT: &str = "$keycode::Varint";
+
+impl<T: Serialize> Serialize for Varint<T> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(VARINT, &self.0)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Varint<T> {
+    fn deserialize<D: de::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        struct Visitor<T>(std::marker::PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> de::Visitor<'de> for Visitor<T> {
+            type Value = Varint<T>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a varint")
+            }
+
+            fn visit_newtype_struct<D: de::Deserializer<'de>>(
+                self,
+                deserializer: D,
+            ) -> std::result::Result<Self::Value, D::Error> {
+                T::deserialize(deserializer).map(Varint)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(VARINT, Visitor(std::marker::PhantomData))
+    }
+}
+
+/// Encodes a sequence as a tuple, i.e. the concatenation of its elements with
+/// no element markers or terminator, for use with #[serde(with = "trailing")]
+/// on the last field of a key. Decoding reads elements until the end of the
+/// key. A single element thus has the same encoding as the element itself,
+/// and a key prefix for all keys whose trailing elements start with some
+/// elements is simply the key with those elements. An empty sequence can only
+/// be used as such a prefix, since it can't be decoded.
+pub mod trailing {
+    use super::*;
+    use std::borrow::Cow;
+
+    pub fn serialize<T, S>(elements: &[T], serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        T: Serialize,
+        S: ser::Serializer,
+    {
+        use ser::SerializeTuple as _;
+        let mut tuple = serializer.serialize_tuple(elements.len())?;
+        for element in elements.iter() {
+            tuple.serialize_element(element)?;
+        }
+        tuple.end()
+    }
+
+    pub fn deserialize<'de, T, D>(
+        deserializer: D,
+    ) -> std::result::Result<Cow<'static, [T]>, D::Error>
+    where
+        T: Deserialize<'de> + Clone,
+        D: de::Deserializer<'de>,
+    {
+        struct Visitor<T>(std::marker::PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> de::Visitor<'de> for Visitor<T> {
+            type Value = Vec<T>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("trailing elements")
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> std::result::Result<Self::Value, A::Error> {
+                let mut elements = Vec::new();
+                while let Some(element) = seq.next_element()? {
+                    elements.push(element);
+                }
+                Ok(elements)
+            }
+        }
+
+        let visitor = Visitor(std::marker::PhantomData);
+        deserializer.deserialize_tuple(usize::MAX, visitor).map(Cow::Owned)
+    }
+}
+
 /// Serializes keys as binary byte vectors.
 struct Serializer {
     output: Vec<u8>,
+    /// Whether integers should be encoded as varints, i.e. within a Varint.
+    varint: bool,
+}
+
+impl Serializer {
+    /// Encodes an unsigned varint: the number of significant bytes as a u8,
+    /// followed by those bytes in big-endian order.
+    fn serialize_varint_unsigned(&mut self, v: u128) {
+        let len = 16 - v.leading_zeros() as usize / 8;
+        self.output.push(len as u8);
+        self.output.extend(&v.to_be_bytes()[16 - len..]);
+    }
+
+    /// Encodes a signed varint: 0x80+N for non-negative numbers and 0x7f-N
+    /// for negative numbers, where N is the number of significant bytes in the
+    /// number's one's complement, followed by the N least significant bytes.
+    fn serialize_varint_signed(&mut self, v: i128) {
+        if v >= 0 {
+            let len = 16 - v.leading_zeros() as usize / 8;
+            self.output.push(0x80 + len as u8);
+            self.output.extend(&v.to_be_bytes()[16 - len..]);
+        } else {
+            let len = 16 - (!v).leading_zeros() as usize / 8;
+            self.output.push(0x7f - len as u8);
+            self.output.extend(&v.to_be_bytes()[16 - len..]);
+        }
+    }
 }
 
 impl<'a> ser::Serializer for &'a mut Serializer {
@@ -107,8 +253,16 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         unimplemented!()
     }
 
-    fn serialize_i32(self, _: i32) -> Result<()> {
-        unimplemented!()
+    /// i32 is encoded like i64.
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        if self.varint {
+            self.serialize_varint_signed(v.into());
+            return Ok(());
+        }
+        let mut bytes = v.to_be_bytes();
+        bytes[0] ^= 1 << 7; // flip sign bit
+        self.output.extend(bytes);
+        Ok(())
     }
 
     /// i64 uses the big-endian two's completement encoding, but flips the
@@ -119,6 +273,22 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     /// largest negative integer, is encoded as 01111111...11111111, ordered
     /// after all other negative integers but before positive integers.
     fn serialize_i64(self, v: i64) -> Result<()> {
+        if self.varint {
+            self.serialize_varint_signed(v.into());
+            return Ok(());
+        }
+        let mut bytes = v.to_be_bytes();
+        bytes[0] ^= 1 << 7; // flip sign bit
+        self.output.extend(bytes);
+        Ok(())
+    }
+
+    /// i128 is encoded like i64.
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        if self.varint {
+            self.serialize_varint_signed(v);
+            return Ok(());
+        }
         let mut bytes = v.to_be_bytes();
         bytes[0] ^= 1 << 7; // flip sign bit
         self.output.extend(bytes);
@@ -133,12 +303,32 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         unimplemented!()
     }
 
-    fn serialize_u32(self, _: u32) -> Result<()> {
-        unimplemented!()
+    /// u32 simply uses the big-endian encoding.
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        if self.varint {
+            self.serialize_varint_unsigned(v.into());
+            return Ok(());
+        }
+        self.output.extend(v.to_be_bytes());
+        Ok(())
     }
 
     /// u64 simply uses the big-endian encoding.
     fn serialize_u64(self, v: u64) -> Result<()> {
+        if self.varint {
+            self.serialize_varint_unsigned(v.into());
+            return Ok(());
+        }
+        self.output.extend(v.to_be_bytes());
+        Ok(())
+    }
+
+    /// u128 simply uses the big-endian encoding.
+    fn serialize_u128(self, v: u128) -> Result<()> {
+        if self.varint {
+            self.serialize_varint_unsigned(v);
+            return Ok(());
+        }
         self.output.extend(v.to_be_bytes());
         Ok(())
     }
@@ -185,12 +375,16 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         Ok(())
     }
 
+    /// None is encoded as 0x00, ordered before Some.
     fn serialize_none(self) -> Result<()> {
-        unimplemented!()
+        self.output.push(0x00);
+        Ok(())
     }
 
-    fn serialize_some<T: ser::Serialize + ?Sized>(self, _: &T) -> Result<()> {
-        unimplemented!()
+    /// Some is encoded as 0x01 followed by the value.
+    fn serialize_some<T: ser::Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        self.output.push(0x01);
+        value.serialize(self)
     }
 
     fn serialize_unit(self) -> Result<()> {
@@ -207,12 +401,17 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         Ok(())
     }
 
+    /// Newtype structs are serialized as the inner value. Varint uses this to
+    /// enable varint encoding of the inner integer.
     fn serialize_newtype_struct<T: ser::Serialize + ?Sized>(
         self,
-        _: &'static str,
-        _: &T,
+        name: &'static str,
+        value: &T,
     ) -> Result<()> {
-        unimplemented!()
+        let varint = std::mem::replace(&mut self.varint, name == VARINT);
+        let result = value.serialize(&mut *self);
+        self.varint = varint;
+        result
     }
 
     /// Newtype variants are serialized using the variant index and inner type.
@@ -227,7 +426,8 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         value.serialize(self)
     }
 
-    /// Sequences are serialized as the concatenation of the serialized elements.
+    /// Sequences are serialized as the serialized elements each prefixed by
+    /// 0x01, terminated by 0x00.
     fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq> {
         Ok(self)
     }
@@ -277,21 +477,26 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 }
 
-/// Sequences simply concatenate the serialized elements, with no external structure.
+/// Sequences prefix each element by 0x01, and are terminated by 0x00. Since
+/// 0x00 sorts before 0x01, shorter sequences sort before longer sequences with
+/// the same prefix.
 impl<'a> ser::SerializeSeq for &'a mut Serializer {
     type Ok = ();
     type Error = Error;
 
     fn serialize_element<T: ser::Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.output.push(0x01);
         value.serialize(&mut **self)
     }
 
     fn end(self) -> Result<()> {
+        self.output.push(0x00);
         Ok(())
     }
 }
 
-/// Tuples, like sequences, simply concatenate the serialized elements.
+/// Tuples have a fixed number of elements, so they simply concatenate the
+/// serialized elements, with no external structure.
 impl<'a> ser::SerializeTuple for &'a mut Serializer {
     type Ok = ();
     type Error = Error;
@@ -305,7 +510,7 @@ impl<'a> ser::SerializeTuple for &'a mut Serializer {
     }
 }
 
-/// Tuples, like sequences, simply concatenate the serialized elements.
+/// Tuple variants, like tuples, simply concatenate the serialized elements.
 impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
     type Ok = ();
     type Error = Error;
@@ -324,12 +529,45 @@ impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
 /// into.
 pub struct Deserializer<'de> {
     input: &'de [u8],
+    /// Whether integers are encoded as varints, i.e. within a Varint.
+    varint: bool,
 }
 
 impl<'de> Deserializer<'de> {
     /// Creates a deserializer for a byte slice.
     pub fn from_bytes(input: &'de [u8]) -> Self {
-        Deserializer { input }
+        Deserializer { input, varint: false }
+    }
+
+    /// Decodes and chops off the next unsigned varint.
+    fn decode_next_varint_unsigned(&mut self) -> Result<u128> {
+        let len = self.take_bytes(1)?[0] as usize;
+        if len > 16 {
+            return errdata!("invalid varint length {len}");
+        }
+        let bytes = self.take_bytes(len)?;
+        if bytes.first() == Some(&0x00) {
+            return errdata!("non-canonical varint {bytes:x?}");
+        }
+        let mut buf = [0; 16];
+        buf[16 - len..].copy_from_slice(bytes);
+        Ok(u128::from_be_bytes(buf))
+    }
+
+    /// Decodes and chops off the next signed varint.
+    fn decode_next_varint_signed(&mut self) -> Result<i128> {
+        let (len, fill) = match self.take_bytes(1)?[0] {
+            header @ 0x80..=0x90 => ((header - 0x80) as usize, 0x00),
+            header @ 0x6f..=0x7f => ((0x7f - header) as usize, 0xff),
+            header => return errdata!("invalid varint header {header:x}"),
+        };
+        let bytes = self.take_bytes(len)?;
+        if bytes.first() == Some(&fill) {
+            return errdata!("non-canonical varint {bytes:x?}");
+        }
+        let mut buf = [fill; 16];
+        buf[16 - len..].copy_from_slice(bytes);
+        Ok(i128::from_be_bytes(buf))
     }
 
     /// Chops off and returns the next len bytes of the byte slice, or errors if
@@ -390,16 +628,33 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         unimplemented!()
     }
 
-    fn deserialize_i32<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+    fn deserialize_i32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.varint {
+            return visitor.visit_i32(self.decode_next_varint_signed()?.try_into()?);
+        }
+        let mut bytes = self.take_bytes(4)?.to_vec();
+        bytes[0] ^= 1 << 7; // flip sign bit
+        visitor.visit_i32(i32::from_be_bytes(bytes.as_slice().try_into()?))
     }
 
     fn deserialize_i64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.varint {
+            return visitor.visit_i64(self.decode_next_varint_signed()?.try_into()?);
+        }
         let mut bytes = self.take_bytes(8)?.to_vec();
         bytes[0] ^= 1 << 7; // flip sign bit
         visitor.visit_i64(i64::from_be_bytes(bytes.as_slice().try_into()?))
     }
 
+    fn deserialize_i128<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.varint {
+            return visitor.visit_i128(self.decode_next_varint_signed()?);
+        }
+        let mut bytes = self.take_bytes(16)?.to_vec();
+        bytes[0] ^= 1 << 7; // flip sign bit
+        visitor.visit_i128(i128::from_be_bytes(bytes.as_slice().try_into()?))
+    }
+
     fn deserialize_u8<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
         unimplemented!()
     }
@@ -408,14 +663,27 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         unimplemented!()
     }
 
-    fn deserialize_u32<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+    fn deserialize_u32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.varint {
+            return visitor.visit_u32(self.decode_next_varint_unsigned()?.try_into()?);
+        }
+        visitor.visit_u32(u32::from_be_bytes(self.take_bytes(4)?.try_into()?))
     }
 
     fn deserialize_u64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.varint {
+            return visitor.visit_u64(self.decode_next_varint_unsigned()?.try_into()?);
+        }
         visitor.visit_u64(u64::from_be_bytes(self.take_bytes(8)?.try_into()?))
     }
 
+    fn deserialize_u128<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.varint {
+            return visitor.visit_u128(self.decode_next_varint_unsigned()?);
+        }
+        visitor.visit_u128(u128::from_be_bytes(self.take_bytes(16)?.try_into()?))
+    }
+
     fn deserialize_f32<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
         unimplemented!()
     }
@@ -454,8 +722,12 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         visitor.visit_byte_buf(bytes)
     }
 
-    fn deserialize_option<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.take_bytes(1)?[0] {
+            0x00 => visitor.visit_none(),
+            0x01 => visitor.visit_some(self),
+            b => errdata!("invalid option marker {b:x}"),
+        }
     }
 
     fn deserialize_unit<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
@@ -472,14 +744,17 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
 
     fn deserialize_newtype_struct<V: de::Visitor<'de>>(
         self,
-        _: &'static str,
-        _: V,
+        name: &'static str,
+        visitor: V,
     ) -> Result<V::Value> {
-        unimplemented!()
+        let varint = std::mem::replace(&mut self.varint, name == VARINT);
+        let result = visitor.visit_newtype_struct(&mut *self);
+        self.varint = varint;
+        result
     }
 
     fn deserialize_seq<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        visitor.visit_seq(self)
+        visitor.visit_seq(SeqDeserializer(self))
     }
 
     fn deserialize_tuple<V: de::Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value> {
@@ -526,7 +801,8 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     }
 }
 
-/// Sequences are simply deserialized until the byte slice is exhausted.
+/// Tuples are simply deserialized until the byte slice is exhausted, or the
+/// visitor has read all tuple elements.
 impl<'de> de::SeqAccess<'de> for Deserializer<'de> {
     type Error = Error;
 
@@ -541,6 +817,24 @@ impl<'de> de::SeqAccess<'de> for Deserializer<'de> {
     }
 }
 
+/// Deserializes sequence elements, prefixed by 0x01 and terminated by 0x00.
+struct SeqDeserializer<'a, 'de>(&'a mut Deserializer<'de>);
+
+impl<'de> de::SeqAccess<'de> for SeqDeserializer<'_, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>> {
+        match self.0.take_bytes(1)?[0] {
+            0x00 => Ok(None),
+            0x01 => seed.deserialize(&mut *self.0).map(Some),
+            b => errdata!("invalid sequence marker {b:x}"),
+        }
+    }
+}
+
 /// Enum variants are deserialized by their index.
 impl<'de> de::EnumAccess<'de> for &mut Deserializer<'de> {
     type Error = Error;
@@ -604,6 +898,7 @@ mod tests {
             bool,
             #[serde(borrow)] Cow<'a, str>,
         ),
+        Trailing(bool, #[serde(with = "trailing")] Cow<'a, [u64]>),
     }
 
     /// Assert that serializing a value yields the expected byte sequence (as a
@@ -682,11 +977,52 @@ mod tests {
         i64_65535: 65535i64 => "800000000000ffff",
         i64_max: i64::MAX => "ffffffffffffffff",
 
+        i32_min: i32::MIN => "00000000",
+        i32_neg_1: -1i32 => "7fffffff",
+        i32_0: 0i32 => "80000000",
+        i32_max: i32::MAX => "ffffffff",
+
+        i128_min: i128::MIN => "00000000000000000000000000000000",
+        i128_neg_1: -1i128 => "7fffffffffffffffffffffffffffffff",
+        i128_0: 0i128 => "80000000000000000000000000000000",
+        i128_max: i128::MAX => "ffffffffffffffffffffffffffffffff",
+
+        u32_min: u32::MIN => "00000000",
+        u32_65535: 65535_u32 => "0000ffff",
+        u32_max: u32::MAX => "ffffffff",
+
         u64_min: u64::MIN => "0000000000000000",
         u64_1: 1_u64 => "0000000000000001",
         u64_65535: 65535_u64 => "000000000000ffff",
         u64_max: u64::MAX => "ffffffffffffffff",
 
+        u128_min: u128::MIN => "00000000000000000000000000000000",
+        u128_max: u128::MAX => "ffffffffffffffffffffffffffffffff",
+
+        varint_u64_0: Varint(0_u64) => "00",
+        varint_u64_1: Varint(1_u64) => "0101",
+        varint_u64_255: Varint(255_u64) => "01ff",
+        varint_u64_256: Varint(256_u64) => "020100",
+        varint_u64_max: Varint(u64::MAX) => "08ffffffffffffffff",
+        varint_u32_65535: Varint(65535_u32) => "02ffff",
+        varint_u128_max: Varint(u128::MAX) => "10ffffffffffffffffffffffffffffffff",
+
+        varint_i64_min: Varint(i64::MIN) => "778000000000000000",
+        varint_i64_neg_257: Varint(-257_i64) => "7dfeff",
+        varint_i64_neg_256: Varint(-256_i64) => "7e00",
+        varint_i64_neg_1: Varint(-1_i64) => "7f",
+        varint_i64_0: Varint(0_i64) => "80",
+        varint_i64_1: Varint(1_i64) => "8101",
+        varint_i64_256: Varint(256_i64) => "820100",
+        varint_i64_max: Varint(i64::MAX) => "887fffffffffffffff",
+        varint_i32_neg_1: Varint(-1_i32) => "7f",
+        varint_i128_min: Varint(i128::MIN) => "6f80000000000000000000000000000000",
+
+        option_none: Option::<bool>::None => "00",
+        option_some: Some(true) => "0101",
+        option_some_none: Some(Option::<bool>::None) => "0100",
+        option_string: Some("foo".to_string()) => "01666f6f0000",
+
         bytes: ByteBuf::from(vec![0x01, 0xff]) => "01ff0000",
         bytes_empty: ByteBuf::new() => "0000",
         bytes_escape: ByteBuf::from(vec![0x00, 0x01, 0x02]) => "00ff01020000",
@@ -698,14 +1034,21 @@ mod tests {
 
         tuple: (true, u64::MAX, ByteBuf::from(vec![0x00, 0x01])) => "01ffffffffffffffff00ff010000",
         array_bool: [false, true, false] => "000100",
-        vec_bool: vec![false, true, false] => "000100",
-        vec_u64: vec![u64::MIN, u64::MAX, 65535_u64] => "0000000000000000ffffffffffffffff000000000000ffff",
+        vec_bool: vec![false, true, false] => "01000101010000",
+        vec_empty: Vec::<bool>::new() => "00",
+        vec_u64: vec![u64::MIN, u64::MAX, 65535_u64] => "01000000000000000001ffffffffffffffff01000000000000ffff00",
+        vec_string: vec!["a".to_string(), "".to_string()] => "0161000001000000",
+        vec_nested: vec![vec![true], vec![]] => "01010100010000",
+        tuple_vec: (vec!["a".to_string()], "b".to_string()) => "0161000000620000",
+        varint_tuple: (Varint(1_u64), Varint(-1_i64), true) => "01017f01",
 
         enum_unit: Key::Unit => "00",
         enum_newtype: Key::NewType("foo".to_string()) => "01666f6f0000",
         enum_tuple: Key::Tuple(false, vec![0x00, 0x01], u64::MAX) => "020000ff010000ffffffffffffffff",
         enum_cow: Key::Cow(vec![0x00, 0x01].into(), false, String::from("foo").into()) => "0300ff01000000666f6f0000",
         enum_cow_borrow: Key::Cow([0x00, 0x01].as_slice().into(), false, "foo".into()) => "0300ff01000000666f6f0000",
+        enum_trailing: Key::Trailing(true, vec![1, 2].into()) => "040100000000000000010000000000000002",
+        enum_trailing_single: Key::Trailing(true, vec![1].into()) => "04010000000000000001",
 
         value_null: Value::Null => "00",
         value_bool: Value::Boolean(true) => "0101",
@@ -714,19 +1057,57 @@ mod tests {
         value_string: Value::String("foo".to_string()) => "04666f6f0000",
     }
 
+    /// Asserts that encoded keys sort in the same order as the values.
+    fn assert_ordering<T: Serialize + Ord + std::fmt::Debug>(mut values: Vec<T>) {
+        values.sort();
+        values.dedup();
+        for pair in values.windows(2) {
+            assert!(serialize(&pair[0]) < serialize(&pair[1]), "{:?} >= {:?}", pair[0], pair[1]);
+        }
+    }
+
+    /// Tests that encodings preserve ordering, in particular for integers,
+    /// varints, options, and sequences nested in other types.
+    #[test]
+    fn ordering() {
+        let ints = [i64::MIN, -65536, -65535, -257, -256, -255, -2, -1, 0, 1, 255, 256, i64::MAX];
+        assert_ordering(ints.to_vec());
+        assert_ordering(ints.iter().map(|i| Varint(*i)).collect());
+        assert_ordering(ints.iter().map(|i| Varint(*i as i128)).collect());
+        assert_ordering(ints.iter().map(|i| *i as i32).collect());
+        assert_ordering(ints.iter().map(|i| i.unsigned_abs()).collect());
+        assert_ordering(ints.iter().map(|i| Varint(i.unsigned_abs())).collect());
+        assert_ordering(ints.iter().map(|i| Varint(i.unsigned_abs() as u128)).collect());
+        assert_ordering(vec![None, Some(-1), Some(0), Some(1)]);
+
+        let strings = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_ordering(vec![
+            (vec![], "b".to_string()),
+            (strings(&[""]), "a".to_string()),
+            (strings(&["", ""]), "a".to_string()),
+            (strings(&["a"]), "b".to_string()),
+            (strings(&["a"]), "c".to_string()),
+            (strings(&["a", "b"]), "a".to_string()),
+            (strings(&["a\x00"]), "a".to_string()),
+            (strings(&["ab"]), "a".to_string()),
+            (strings(&["b"]), "a".to_string()),
+        ]);
+        assert_ordering(vec![
+            vec![],
+            vec![vec![]],
+            vec![vec![1u64]],
+            vec![vec![1], vec![]],
+            vec![vec![2]],
+        ]);
+    }
+
     test_serialize_error! {
         char: 'a',
         f32: 0f32,
         i8: 0i8,
         i16: 0i16,
-        i32: 0i32,
-        i128: 0i128,
         u8: 0u8,
         u16: 0u16,
-        u32: 0u32,
-        u128: 0u128,
-        some: Some(true),
-        none: Option::<bool>::None,
         vec_u8: vec![0u8],
     }
 
@@ -737,13 +1118,20 @@ mod tests {
         f32: "00000000" as f32,
         i8: "00" as i8,
         i16: "0000" as i16,
-        i32: "00000000" as i32,
-        i128: "00000000000000000000000000000000" as i128,
+        i32_partial: "0000" as i32,
         u16: "0000" as u16,
-        u32: "00000000" as u32,
+        u32_partial: "0000" as u32,
         u64_partial: "0000" as u64,
-        u128: "00000000000000000000000000000000" as u128,
-        option: "00" as Option::<bool>,
+        u128_partial: "0000" as u128,
+        option_empty: "" as Option::<bool>,
+        option_invalid: "02" as Option::<bool>,
+        vec_unterminated: "0101" as Vec<bool>,
+        vec_invalid_marker: "0201" as Vec<bool>,
+        varint_partial: "0201" as Varint<u64>,
+        varint_overflow: "050100000000" as Varint<u32>,
+        varint_non_canonical: "0200ff" as Varint<u64>,
+        varint_signed_header: "60" as Varint<i64>,
+        varint_signed_non_canonical: "7eff" as Varint<i64>,
         string_utf8_invalid: "c0" as String,
         tuple_partial: "0001" as (bool, bool, bool),
         vec_u8: "0000" as Vec<u8>,
@@ -1,7 +1,8 @@
 use super::{NodeID, Term};
+use crate::encoding::format::{self, Formatter as _};
 use crate::encoding::{self, bincode, Key as _, Value as _};
 use crate::error::Result;
-use crate::storage;
+use crate::storage::{self, Backup, WriteOp};
 
 use serde::{Deserialize, Serialize};
 
@@ -153,8 +154,10 @@ impl Log {
         if term == self.term && vote == self.vote {
             return Ok(());
         }
+        // Always fsync the term and vote, regardless of the fsync policy. If
+        // they're lost in a crash, the node could vote twice in a term.
         self.engine.set(&Key::TermVote.encode(), bincode::serialize(&(term, vote)))?;
-        self.engine.flush()?;
+        self.engine.sync()?;
         self.term = term;
         self.vote = vote;
         Ok(())
@@ -164,15 +167,30 @@ impl Log {
     /// disk, returning its index. None implies a noop command, typically after
     /// Raft leader changes.
     pub fn append(&mut self, command: Option<Vec<u8>>) -> Result<Index> {
+        self.append_batch(vec![command])
+    }
+
+    /// Appends a batch of commands to the log at the current term, and flushes
+    /// them to disk together (i.e. group commit), returning the last index.
+    pub fn append_batch(&mut self, commands: Vec<Option<Vec<u8>>>) -> Result<Index> {
         assert!(self.term > 0, "can't append entry in term 0");
+        assert!(!commands.is_empty(), "can't append empty batch");
         // We could omit the index in the encoded value, since it's also stored
         // in the key, but we keep it simple.
-        let entry = Entry { index: self.last_index + 1, term: self.term, command };
-        self.engine.set(&Key::Entry(entry.index).encode(), entry.encode())?;
+        let count = commands.len() as Index;
+        let mut batch = Vec::with_capacity(commands.len());
+        for (index, command) in (self.last_index + 1..).zip(commands) {
+            let entry = Entry { index, term: self.term, command };
+            batch.push(WriteOp::Set {
+                key: Key::Entry(entry.index).encode(),
+                value: entry.encode(),
+            });
+        }
+        self.engine.write_batch(batch)?;
         self.engine.flush()?;
-        self.last_index = entry.index;
-        self.last_term = entry.term;
-        Ok(entry.index)
+        self.last_index += count;
+        self.last_term = self.term;
+        Ok(self.last_index)
     }
 
     /// Commits entries up to and including the given index. The index must
@@ -249,6 +267,8 @@ impl Log {
     /// terms will truncate the existing log at the first conflict and then
     /// splice the new entries.
     pub fn splice(&mut self, entries: Vec<Entry>) -> Result<Index> {
+        use std::ops::Bound::Included;
+
         let (Some(first), Some(last)) = (entries.first(), entries.last()) else {
             return Ok(self.last_index); // empty input is noop
         };
@@ -303,8 +323,10 @@ impl Log {
         for entry in entries {
             self.engine.set(&Key::Entry(entry.index).encode(), entry.encode())?;
         }
-        for index in last.index + 1..=self.last_index {
-            self.engine.delete(&Key::Entry(index).encode())?;
+        if last.index < self.last_index {
+            let from = Key::Entry(last.index + 1).encode();
+            let to = Key::Entry(self.last_index).encode();
+            self.engine.delete_range((Included(from), Included(to)))?;
         }
         self.engine.flush()?;
 
@@ -317,6 +339,73 @@ impl Log {
     pub fn status(&mut self) -> Result<storage::Status> {
         self.engine.status()
     }
+
+    /// Checks the log for inconsistencies, returning a description of each
+    /// problem found (if any). This scans the entire log, so it's mostly
+    /// useful for offline integrity checks, e.g. `toydump --check`.
+    pub fn check(&mut self) -> Result<Vec<String>> {
+        let mut problems = Vec::new();
+        let (mut last_index, mut last_term) = (0, 0);
+        let mut scan =
+            self.engine.scan_dyn((std::ops::Bound::Unbounded, std::ops::Bound::Unbounded));
+        while let Some((key, value)) = scan.next().transpose()? {
+            match Key::decode(&key) {
+                Ok(Key::Entry(index)) => {
+                    let entry = match Entry::decode(&value) {
+                        Ok(entry) => entry,
+                        Err(err) => {
+                            problems.push(format!("entry {index} is invalid: {err}"));
+                            continue;
+                        }
+                    };
+                    if entry.index != index {
+                        problems.push(format!("entry {index} has index {}", entry.index));
+                    }
+                    if index != last_index + 1 {
+                        problems.push(format!("entry {index} does not follow entry {last_index}"));
+                    }
+                    if entry.term < last_term {
+                        problems.push(format!(
+                            "entry {index} has term {} below {last_term}",
+                            entry.term
+                        ));
+                    }
+                    if entry.term > self.term {
+                        let term = self.term;
+                        problems.push(format!(
+                            "entry {index} has term {} beyond current term {term}",
+                            entry.term
+                        ));
+                    }
+                    (last_index, last_term) = (index, entry.term);
+                }
+                // These are decoded by Log::new().
+                Ok(Key::TermVote | Key::CommitIndex) => {}
+                Err(_) => problems.push(format!("invalid key {}", format::Raw::key(&key))),
+            }
+        }
+        drop(scan);
+
+        if self.commit_index > last_index {
+            problems
+                .push(format!("commit index {} beyond last index {last_index}", self.commit_index));
+        } else if self.commit_index > 0 {
+            match self.get(self.commit_index)? {
+                Some(entry) if entry.term != self.commit_term => problems.push(format!(
+                    "commit index {} has term {} but entry has term {}",
+                    self.commit_index, self.commit_term, entry.term
+                )),
+                Some(_) | None => {}
+            }
+        }
+        Ok(problems)
+    }
+
+    /// Takes a snapshot of the log, including the term, vote, and commit
+    /// index, which can be written to a new BitCask file.
+    pub fn backup(&mut self) -> Result<Backup> {
+        self.engine.backup()
+    }
 }
 
 /// A log entry iterator.
@@ -341,7 +430,6 @@ impl<'a> std::iter::Iterator for Iterator<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::encoding::format::{self, Formatter as _};
     use crossbeam::channel::Receiver;
     use regex::Regex;
     use std::fmt::Write as _;
@@ -360,6 +448,7 @@ mod tests {
     struct TestRunner {
         log: Log,
         op_rx: Receiver<testengine::Operation>,
+        faulty: testengine::Faulty<testengine::Mirror<storage::BitCask, storage::Memory>>,
         #[allow(dead_code)]
         tempdir: tempfile::TempDir, // deleted when dropped
     }
@@ -381,6 +470,36 @@ mod tests {
                     ));
                 }
 
+                // append_batch COMMAND...
+                "append_batch" => {
+                    let mut args = command.consume_args();
+                    let commands: Vec<_> =
+                        args.rest_pos().iter().map(|a| Some(a.value.as_bytes().to_vec())).collect();
+                    args.reject_rest()?;
+                    let args_len = commands.len() as Index;
+                    let last_index = self.log.append_batch(commands)?;
+                    for index in last_index + 1 - args_len..=last_index {
+                        let entry = self.log.get(index)?.expect("entry not found");
+                        output.push_str(&format!(
+                            "append → {}\n",
+                            format::Raft::<format::Raw>::entry(&entry)
+                        ));
+                    }
+                }
+
+                // check
+                // Checks the log for inconsistencies.
+                "check" => {
+                    command.consume_args().reject_rest()?;
+                    let problems = self.log.check()?;
+                    if problems.is_empty() {
+                        writeln!(output, "ok")?;
+                    }
+                    for problem in problems {
+                        writeln!(output, "{problem}")?;
+                    }
+                }
+
                 // commit INDEX
                 "commit" => {
                     let mut args = command.consume_args();
@@ -394,6 +513,18 @@ mod tests {
                     ));
                 }
 
+                // crash
+                // Simulates a crash, dropping unflushed writes, and reopens
+                // the log.
+                "crash" => {
+                    self.faulty.run(command)?;
+                    self.reload()?;
+                }
+
+                // corrupt KEY | fail [OP...]
+                // Injects storage faults, see testengine::Faulty.
+                "corrupt" | "fail" => self.faulty.run(command)?,
+
                 // dump
                 "dump" => {
                     command.consume_args().reject_rest()?;
@@ -409,6 +540,27 @@ mod tests {
                     }
                 }
 
+                // engine_delete KEY...
+                // Deletes raw keys directly in the storage engine.
+                "engine_delete" => {
+                    let mut args = command.consume_args();
+                    for arg in args.rest_pos() {
+                        self.log.engine.delete(&testengine::decode_binary(&arg.value))?;
+                    }
+                    args.reject_rest()?;
+                }
+
+                // engine_set KEY=VALUE...
+                // Sets raw key/value pairs directly in the storage engine.
+                "engine_set" => {
+                    let mut args = command.consume_args();
+                    for kv in args.rest_key() {
+                        let key = testengine::decode_binary(kv.key.as_ref().unwrap());
+                        self.log.engine.set(&key, testengine::decode_binary(&kv.value))?;
+                    }
+                    args.reject_rest()?;
+                }
+
                 // get INDEX...
                 "get" => {
                     let mut args = command.consume_args();
@@ -454,11 +606,7 @@ mod tests {
                 // reload
                 "reload" => {
                     command.consume_args().reject_rest()?;
-                    // To get owned access to the inner engine, temporarily
-                    // replace it with an empty memory engine.
-                    let engine =
-                        std::mem::replace(&mut self.log.engine, Box::new(storage::Memory::new()));
-                    self.log = Log::new(engine)?;
+                    self.reload()?;
                 }
 
                 // scan [RANGE]
@@ -566,6 +714,12 @@ mod tests {
                         format::Raft::<format::Raw>::key(&key),
                         format::Raw::key(&key)
                     )?,
+                    Operation::DeleteRange { range } => writeln!(
+                        output,
+                        "engine delete_range {} [{}]",
+                        testengine::format_range::<format::Raft<format::Raw>>(&range),
+                        testengine::format_range::<format::Raw>(&range)
+                    )?,
                     Operation::Flush => writeln!(output, "engine flush")?,
                     Operation::Set { key, value } => writeln!(
                         output,
@@ -582,15 +736,26 @@ mod tests {
     impl TestRunner {
         fn new() -> Self {
             // Use both a BitCask and a Memory engine, and mirror operations
-            // across them. Emit write events to op_tx.
+            // across them. Inject faults via a Faulty handle, and emit write
+            // events to op_tx.
             let (op_tx, op_rx) = crossbeam::channel::unbounded();
             let tempdir = tempfile::TempDir::with_prefix("toydb").expect("tempdir failed");
             let bitcask =
                 storage::BitCask::new(tempdir.path().join("bitcask")).expect("bitcask failed");
             let memory = storage::Memory::new();
-            let engine = testengine::Emit::new(testengine::Mirror::new(bitcask, memory), op_tx);
+            let faulty = testengine::Faulty::new(testengine::Mirror::new(bitcask, memory));
+            let engine = testengine::Emit::new(faulty.clone(), op_tx);
             let log = Log::new(Box::new(engine)).expect("log init failed");
-            Self { log, op_rx, tempdir }
+            Self { log, op_rx, faulty, tempdir }
+        }
+
+        /// Reopens the log from the existing engine.
+        fn reload(&mut self) -> crate::error::Result<()> {
+            // To get owned access to the inner engine, temporarily replace it
+            // with an empty memory engine.
+            let engine = std::mem::replace(&mut self.log.engine, Box::new(storage::Memory::new()));
+            self.log = Log::new(engine)?;
+            Ok(())
         }
 
         /// Parses an index@term pair.
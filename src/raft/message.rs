@@ -169,6 +169,10 @@ pub enum Request {
     Write(Vec<u8>),
     /// Requests Raft cluster status from the leader.
     Status,
+    /// Writes a backup of the local node's Raft log and state machine to the
+    /// given directory, which must not exist. This is executed locally on the
+    /// receiving node, regardless of its role.
+    Backup(std::path::PathBuf),
 }
 
 impl encoding::Value for Request {}
@@ -182,6 +186,8 @@ pub enum Response {
     Write(Vec<u8>),
     /// The current Raft leader status.
     Status(Status),
+    /// A completed backup, with the state machine's applied index.
+    Backup(Index),
 }
 
 impl encoding::Value for Response {}
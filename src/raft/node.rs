@@ -9,6 +9,7 @@ use itertools::Itertools as _;
 use log::{debug, info};
 use rand::Rng as _;
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
 
 /// A node ID. Unique within a cluster. Assigned manually when started.
 pub type NodeID = u8;
@@ -139,6 +140,34 @@ impl Node {
         })
     }
 
+    /// Processes a batch of inbound messages, as if they were stepped one at a
+    /// time. However, a leader appends consecutive client write requests to
+    /// its log together, flushing them to disk once (i.e. group commit).
+    pub fn step_batch(self, msgs: Vec<Envelope>) -> Result<Self> {
+        let mut node = self;
+        let mut msgs = msgs.into_iter().peekable();
+        while let Some(msg) = msgs.next() {
+            node = match node {
+                Node::Leader(n)
+                    if n.is_write(&msg) && msgs.peek().is_some_and(|m| n.is_write(m)) =>
+                {
+                    let mut writes = vec![msg];
+                    while let Some(msg) = msgs.next_if(|next| n.is_write(next)) {
+                        writes.push(msg);
+                    }
+                    for msg in &writes {
+                        assert_eq!(msg.to, n.id, "message to other node: {msg:?}");
+                        assert!(n.peers.contains(&msg.from) || msg.from == n.id, "unknown sender");
+                        debug!("Stepping {msg:?}");
+                    }
+                    n.step_writes(writes)?
+                }
+                node => node.step(msg)?,
+            };
+        }
+        Ok(node)
+    }
+
     /// Advances time by a tick.
     pub fn tick(self) -> Result<Self> {
         with_rawnode!(self, |n| n.tick())
@@ -230,6 +259,42 @@ impl<R: Role> RawNode<R> {
         rand::thread_rng().gen_range(self.opts.election_timeout_range.clone())
     }
 
+    /// Writes a backup of the Raft log and state machine to the given
+    /// directory, which must not exist, and responds to the client request
+    /// with the applied index. Since the node processes messages sequentially,
+    /// the snapshots are consistent: the state machine corresponds to a prefix
+    /// of the log. The snapshots are written out in a background thread, such
+    /// that the node keeps processing messages during the copy.
+    fn backup(&mut self, id: RequestID, dir: PathBuf) -> Result<()> {
+        let mut snapshot = || -> Result<_> {
+            if dir.exists() {
+                return errinput!("backup directory {} already exists", dir.display());
+            }
+            Ok((self.log.backup()?, self.state.backup()?, self.state.get_applied_index()))
+        };
+        let (log, state, applied_index) = match snapshot() {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                return self.send(self.id, Message::ClientResponse { id, response: Err(err) })
+            }
+        };
+
+        info!("Writing backup to {}", dir.display());
+        let (tx, from, term) = (self.tx.clone(), self.id, self.term());
+        std::thread::spawn(move || {
+            let response = std::fs::create_dir_all(&dir)
+                .map_err(Error::from)
+                .and_then(|_| log.write(&dir.join("log")))
+                .and_then(|_| state.write(&dir.join("state")))
+                .map(|_| Response::Backup(applied_index));
+            let message = Message::ClientResponse { id, response };
+            debug!("Sending {message:?}");
+            // The node may have shut down in the meanwhile.
+            tx.send(Envelope { from, to: from, term, message }).ok();
+        });
+        Ok(())
+    }
+
     /// Sends a message to the given recipient.
     fn send(&self, to: NodeID, message: Message) -> Result<()> {
         Self::send_with(&self.tx, Envelope { from: self.id, to, term: self.term(), message })
@@ -455,6 +520,12 @@ impl RawNode<Follower> {
                 self.send(msg.from, Message::CampaignResponse { vote: true })?;
             }
 
+            // Backups are taken locally, regardless of leader.
+            Message::ClientRequest { id, request: Request::Backup(dir) } => {
+                assert_eq!(msg.from, self.id, "client request from other node");
+                self.backup(id, dir)?;
+            }
+
             // Forward client requests to the leader, or abort them if there is
             // none. These will not be retried, the client should use timeouts.
             // Local client requests use our node ID as the sender.
@@ -581,7 +652,7 @@ impl RawNode<Candidate> {
         // previous entries in the log. See section 5.4.2 in the Raft paper.
         // We do this prior to the heartbeat, to avoid a wasted replication
         // roundtrip if the heartbeat response indicates the peer is behind.
-        node.propose(None)?;
+        node.propose(vec![None])?;
         node.maybe_commit_and_apply()?;
         node.heartbeat()?;
 
@@ -624,6 +695,11 @@ impl RawNode<Candidate> {
                 return self.into_follower(msg.term, Some(msg.from))?.step(msg);
             }
 
+            // Backups are taken locally, even while campaigning.
+            Message::ClientRequest { id, request: Request::Backup(dir) } => {
+                self.backup(id, dir)?;
+            }
+
             // Abort client requests while campaigning. The client must retry.
             Message::ClientRequest { id, request: _ } => {
                 self.send(msg.from, Message::ClientResponse { id, response: Err(Error::Abort) })?;
@@ -900,7 +976,7 @@ impl RawNode<Leader> {
             // it's replicated and applied to the state machine before returning
             // the response to the client.
             Message::ClientRequest { id, request: Request::Write(command) } => {
-                let index = self.propose(Some(command))?;
+                let index = self.propose(vec![Some(command)])?;
                 self.role.writes.insert(index, Write { from: msg.from, id });
                 if self.cluster_size() == 1 {
                     self.maybe_commit_and_apply()?;
@@ -926,6 +1002,11 @@ impl RawNode<Leader> {
                 self.send(msg.from, Message::ClientResponse { id, response })?;
             }
 
+            // A client submitted a backup command.
+            Message::ClientRequest { id, request: Request::Backup(dir) } => {
+                self.backup(id, dir)?;
+            }
+
             // Don't grant any votes (we've already voted for ourself).
             Message::Campaign { .. } => {
                 self.send(msg.from, Message::CampaignResponse { vote: false })?
@@ -966,22 +1047,55 @@ impl RawNode<Leader> {
         self.broadcast(Message::Heartbeat { last_index, commit_index, read_seq })
     }
 
-    /// Proposes a command for consensus by appending it to our log and
-    /// replicating it to peers. If successful, it will eventually be committed
-    /// and applied to the state machine.
-    fn propose(&mut self, command: Option<Vec<u8>>) -> Result<Index> {
-        let index = self.log.append(command)?;
+    /// Proposes commands for consensus by appending them to our log and
+    /// replicating them to peers, returning the last index. If successful,
+    /// they will eventually be committed and applied to the state machine.
+    fn propose(&mut self, commands: Vec<Option<Vec<u8>>>) -> Result<Index> {
+        let first_index = self.log.get_last_index().0 + 1;
+        let index = self.log.append_batch(commands)?;
         for peer in self.peers.iter().copied().sorted() {
-            // Eagerly send the entry to the peer if it's in steady state and
+            // Eagerly send the entries to the peer if it's in steady state and
             // we've sent all previous entries. Otherwise, the peer is lagging
             // and we're probing past entries for a match.
-            if index == self.progress(peer).next_index {
+            if first_index == self.progress(peer).next_index {
                 self.maybe_send_append(peer, false)?;
             }
         }
         Ok(index)
     }
 
+    /// Returns true if the message is a client write request in the current
+    /// term, which can be group committed via step_writes().
+    fn is_write(&self, msg: &Envelope) -> bool {
+        msg.term == self.term()
+            && matches!(msg.message, Message::ClientRequest { request: Request::Write(_), .. })
+    }
+
+    /// Processes a batch of client write requests in the current term. They
+    /// are proposed together, such that they're appended to the log and
+    /// flushed to disk once (i.e. group commit), but are otherwise handled
+    /// like individual write requests in step().
+    fn step_writes(mut self, msgs: Vec<Envelope>) -> Result<Node> {
+        let mut writes = Vec::with_capacity(msgs.len());
+        let mut commands = Vec::with_capacity(msgs.len());
+        for msg in msgs {
+            assert!(self.is_write(&msg), "not a write request: {msg:?}");
+            let Message::ClientRequest { id, request: Request::Write(command) } = msg.message
+            else {
+                unreachable!()
+            };
+            writes.push(Write { from: msg.from, id });
+            commands.push(Some(command));
+        }
+        let last_index = self.propose(commands)?;
+        let first_index = last_index + 1 - writes.len() as Index;
+        self.role.writes.extend((first_index..=last_index).zip(writes));
+        if self.cluster_size() == 1 {
+            self.maybe_commit_and_apply()?;
+        }
+        Ok(self.into())
+    }
+
     /// Commits new entries that have been replicated to a quorum and applies
     /// them to the state machine, returning results to clients.
     fn maybe_commit_and_apply(&mut self) -> Result<Index> {
@@ -1273,7 +1387,6 @@ mod tests {
         /// The request ID to use for the next client request.
         next_request_id: u64,
         /// Temporary directory (deleted when dropped).
-        #[allow(dead_code)]
         tempdir: tempfile::TempDir,
     }
 
@@ -1282,6 +1395,34 @@ mod tests {
         fn run(&mut self, command: &goldenscript::Command) -> Result<String, Box<dyn Error>> {
             let mut output = String::new();
             match command.name.as_str() {
+                // backup ID NAME
+                // Sends a client request to the given node to write a backup
+                // of its log and state machine to the given backup name, and
+                // waits for the background write to respond.
+                "backup" => {
+                    let mut args = command.consume_args();
+                    let id = args.next_pos().ok_or("must specify node ID")?.parse()?;
+                    let name = &args.next_pos().ok_or("must specify backup name")?.value;
+                    args.reject_rest()?;
+                    let dir = self.tempdir.path().join("backup").join(name);
+                    let request_id = uuid::Uuid::from_u64_pair(0, self.next_request_id);
+                    self.request(id, Request::Backup(dir), &mut output)?;
+                    while self.requests.contains_key(&request_id) {
+                        std::thread::sleep(std::time::Duration::from_millis(1));
+                        self.receive(id, &mut output)?;
+                    }
+                }
+
+                // backup_dump NAME
+                // Outputs the contents of the given backup.
+                "backup_dump" => {
+                    let mut args = command.consume_args();
+                    let name = &args.next_pos().ok_or("must specify backup name")?.value;
+                    args.reject_rest()?;
+                    let dir = self.tempdir.path().join("backup").join(name);
+                    self.backup_dump(&dir, &mut output)?;
+                }
+
                 // campaign [ID...]
                 // Transition the given nodes to candidates and campaign.
                 "campaign" => {
@@ -1362,17 +1503,27 @@ mod tests {
                     self.partition(&ids, &mut output)?;
                 }
 
-                // put ID KEY=VALUE
+                // put ID KEY=VALUE...
                 // Sends a client request to the given node to write a key/value
-                // pair to the state machine (key/value store).
+                // pair to the state machine (key/value store). Multiple pairs
+                // are sent as separate requests, stepped as a single batch.
                 "put" => {
                     let mut args = command.consume_args();
                     let id = args.next_pos().ok_or("must specify node ID")?.parse()?;
-                    let kv = args.next_key().ok_or("must specify key/value pair")?.clone();
-                    let (key, value) = (kv.key.unwrap(), kv.value);
+                    let requests: Vec<_> = args
+                        .rest_key()
+                        .into_iter()
+                        .map(|kv| {
+                            let (key, value) = (kv.key.clone().unwrap(), kv.value.clone());
+                            Request::Write(KVCommand::Put { key, value }.encode())
+                        })
+                        .collect();
                     args.reject_rest()?;
-                    let request = Request::Write(KVCommand::Put { key, value }.encode());
-                    self.request(id, request, &mut output)?;
+                    match requests.len() {
+                        0 => return Err("must specify key/value pair".into()),
+                        1 => self.request(id, requests[0].clone(), &mut output)?,
+                        _ => self.request_batch(id, requests, &mut output)?,
+                    }
                 }
 
                 // restart [commit_index=INDEX] [applied_index=INDEX] [ID...]
@@ -1641,6 +1792,34 @@ mod tests {
             Ok(())
         }
 
+        /// Outputs the Raft log and KV state machine contents of a backup.
+        fn backup_dump(
+            &self,
+            dir: &std::path::Path,
+            output: &mut String,
+        ) -> Result<(), Box<dyn Error>> {
+            let engine = storage::BitCask::new(dir.join("log"))?;
+            let mut log = Log::new(Box::new(engine))?;
+            let (last_index, last_term) = log.get_last_index();
+            let (commit_index, commit_term) = log.get_commit_index();
+            let (term, vote) = log.get_term();
+            writeln!(
+                output,
+                "log term={term} last={last_index}@{last_term} commit={commit_index}@{commit_term} vote={vote:?}",
+            )?;
+            for entry in log.scan(..) {
+                writeln!(output, "log entry {}", Self::format_entry(&entry?))?;
+            }
+
+            let (applied_index, data): (Index, std::collections::BTreeMap<String, String>) =
+                bincode::deserialize(&std::fs::read(dir.join("state"))?)?;
+            writeln!(output, "state applied={applied_index}")?;
+            for (key, value) in data {
+                writeln!(output, "state {key}={value}")?;
+            }
+            Ok(())
+        }
+
         /// Partitions the given nodes from all other nodes in the cluster
         /// (bidirectionally). The given nodes can communicate with each other
         /// unless they were previously partitioned.
@@ -1721,6 +1900,32 @@ mod tests {
             self.transition(id, |n| n.step(msg), output)
         }
 
+        /// Submits a batch of client requests to the given node, stepping them
+        /// into the node together.
+        fn request_batch(
+            &mut self,
+            id: NodeID,
+            requests: Vec<Request>,
+            output: &mut String,
+        ) -> Result<(), Box<dyn Error>> {
+            let term = self.nodes.get(&id).ok_or(format!("unknown node {id}"))?.term();
+            let mut msgs = Vec::with_capacity(requests.len());
+            for request in requests {
+                let request_id = uuid::Uuid::from_u64_pair(0, self.next_request_id);
+                self.next_request_id += 1;
+                self.requests.insert(request_id, request.clone());
+                let msg = Envelope {
+                    from: id,
+                    to: id,
+                    term,
+                    message: Message::ClientRequest { id: request_id, request },
+                };
+                writeln!(output, "c{id}@{term} → n{id} {}", Self::format_message(&msg.message))?;
+                msgs.push(msg);
+            }
+            self.transition(id, |n| n.step_batch(msgs), output)
+        }
+
         /// Restarts the given nodes. If commit_index or applied_index are
         /// given, the log commit index or state machine will regress.
         fn restart(
@@ -2073,6 +2278,7 @@ mod tests {
                             Request::Read(v) => format!("read 0x{}", hex::encode(v)),
                             Request::Write(v) => format!("write 0x{}", hex::encode(v)),
                             Request::Status => "status".to_string(),
+                            Request::Backup(_) => "backup".to_string(),
                         }
                     )
                 }
@@ -2084,6 +2290,7 @@ mod tests {
                             Ok(Response::Read(v)) => format!("read 0x{}", hex::encode(v)),
                             Ok(Response::Write(v)) => format!("write 0x{}", hex::encode(v)),
                             Ok(Response::Status(v)) => format!("status {v:?}"),
+                            Ok(Response::Backup(index)) => format!("backup {index}"),
                             Err(e) => format!("Error::{e:#?}"),
                         }
                     )
@@ -2114,6 +2321,7 @@ mod tests {
             match request {
                 Request::Read(c) | Request::Write(c) => KVCommand::decode(c).unwrap().to_string(),
                 Request::Status => "status".to_string(),
+                Request::Backup(_) => "backup".to_string(),
             }
         }
 
@@ -2124,6 +2332,7 @@ mod tests {
                     KVResponse::decode(r).unwrap().to_string()
                 }
                 Ok(Response::Status(status)) => format!("{status:#?}"),
+                Ok(Response::Backup(index)) => format!("applied_index={index}"),
                 Err(e) => format!("Error::{e:?} ({e})"),
             }
         }
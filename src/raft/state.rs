@@ -1,5 +1,7 @@
 use super::{Entry, Index};
+use crate::errinput;
 use crate::error::Result;
+use crate::storage::Backup;
 
 /// A Raft-managed state machine. Raft itself does not care what the state
 /// machine is, nor what the commands and results do -- it will simply apply
@@ -48,17 +50,27 @@ pub trait State: Send {
     /// This is only executed on a single replica/node, so it must not result in
     /// any state changes (i.e. it must not write).
     fn read(&self, command: Vec<u8>) -> Result<Vec<u8>>;
+
+    /// Takes a consistent snapshot of the state machine as of the current
+    /// applied index, which can be written to a new file that can later be
+    /// used to seed a data directory. The snapshot is written after this
+    /// returns, without access to the state machine. Errors by default, for
+    /// state machines that don't support backups.
+    fn backup(&self) -> Result<Backup> {
+        errinput!("state machine does not support backups")
+    }
 }
 
 /// Test helper state machines.
 #[cfg(test)]
 pub mod test {
     use super::*;
-    use crate::encoding::{self, Value as _};
+    use crate::encoding::{self, bincode, Value as _};
     use crossbeam::channel::Sender;
     use itertools::Itertools as _;
     use serde::{Deserialize, Serialize};
     use std::collections::BTreeMap;
+    use std::io::Write as _;
 
     /// Wraps a state machine and emits applied entries to the provided channel.
     pub struct Emit {
@@ -86,6 +98,10 @@ pub mod test {
         fn read(&self, command: Vec<u8>) -> Result<Vec<u8>> {
             self.inner.read(command)
         }
+
+        fn backup(&self) -> Result<Backup> {
+            self.inner.backup()
+        }
     }
 
     /// A simple string key/value store. Takes KVCommands.
@@ -130,6 +146,11 @@ pub mod test {
                 c @ KVCommand::Put { .. } => panic!("{c} submitted as read command"),
             }
         }
+
+        fn backup(&self) -> Result<Backup> {
+            let data = bincode::serialize(&(self.applied_index, &self.data));
+            Ok(Backup::new(move |path| Ok(std::fs::File::create_new(path)?.write_all(&data)?)))
+        }
     }
 
     /// A KV command. Returns the corresponding KVResponse.
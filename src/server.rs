@@ -1,8 +1,9 @@
 use crate::encoding::{self, Value as _};
+use crate::errinput;
 use crate::error::{Error, Result};
 use crate::raft;
 use crate::sql;
-use crate::sql::engine::{Catalog as _, Engine as _, StatementResult};
+use crate::sql::engine::{Catalog as _, Changes, Engine as _, StatementResult};
 use crate::sql::types::{Row, Table};
 use crate::storage;
 
@@ -12,6 +13,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::io::Write as _;
 use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Component, Path, PathBuf};
 
 /// The outbound Raft peer channel capacity. This buffers messages when a Raft
 /// peer is slow or unavailable. Beyond this, messages will be dropped.
@@ -20,6 +22,10 @@ const RAFT_PEER_CHANNEL_CAPACITY: usize = 1000;
 /// The retry interval when connecting to a Raft peer.
 const RAFT_PEER_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
 
+/// The maximum number of changes to return for a change feed request. Schema
+/// and index changes count towards it, so fewer row changes may be returned.
+const CHANGES_BATCH_SIZE: usize = 1000;
+
 /// A toyDB server. Routes messages to/from an inner Raft node.
 ///
 /// - Listens for inbound Raft connections via TCP and passes messages to the
@@ -37,6 +43,9 @@ pub struct Server {
     node_rx: Receiver<raft::Envelope>,
     /// Raft peer IDs and addresses.
     peers: HashMap<raft::NodeID, String>,
+    /// The directory to write backups to. Clients can only choose a backup
+    /// name within it.
+    backup_dir: PathBuf,
 }
 
 impl Server {
@@ -59,9 +68,15 @@ impl Server {
             )?,
             peers,
             node_rx,
+            backup_dir: PathBuf::from("backups"),
         })
     }
 
+    /// Sets the directory to write backups to.
+    pub fn set_backup_dir(&mut self, dir: PathBuf) {
+        self.backup_dir = dir;
+    }
+
     /// Serves Raft and SQL requests indefinitely. Consumes the server.
     pub fn serve(self, raft_addr: impl ToSocketAddrs, sql_addr: impl ToSocketAddrs) -> Result<()> {
         let raft_listener = TcpListener::bind(raft_addr)?;
@@ -103,7 +118,8 @@ impl Server {
 
             // Serve inbound SQL connections.
             let sql_engine = sql::engine::Raft::new(raft_request_tx);
-            s.spawn(move || Self::sql_accept(id, sql_listener, sql_engine));
+            let backup_dir = self.backup_dir;
+            s.spawn(move || Self::sql_accept(id, sql_listener, sql_engine, &backup_dir));
         });
 
         Ok(())
@@ -232,24 +248,35 @@ impl Server {
                 }
 
                 // Track inbound client requests and step them into the node.
+                // Any other queued requests are stepped along with it as a
+                // batch, such that writes are group committed.
                 recv(request_rx) -> result => {
-                    let (request, response_tx) = result.expect("request_rx disconnected");
-                    let id = uuid::Uuid::new_v4();
-                    let msg = raft::Envelope{
-                        from: node.id(),
-                        to: node.id(),
-                        term: node.term(),
-                        message: raft::Message::ClientRequest{id, request},
-                    };
-                    node = node.step(msg).expect("step failed");
-                    response_txs.insert(id, response_tx);
+                    let first = result.expect("request_rx disconnected");
+                    let requests = std::iter::once(first).chain(request_rx.try_iter());
+                    let mut msgs = Vec::new();
+                    for (request, response_tx) in requests {
+                        let id = uuid::Uuid::new_v4();
+                        msgs.push(raft::Envelope{
+                            from: node.id(),
+                            to: node.id(),
+                            term: node.term(),
+                            message: raft::Message::ClientRequest{id, request},
+                        });
+                        response_txs.insert(id, response_tx);
+                    }
+                    node = node.step_batch(msgs).expect("step failed");
                 }
             }
         }
     }
 
     /// Accepts new SQL client connections and spawns session threads for them.
-    fn sql_accept(id: raft::NodeID, listener: TcpListener, sql_engine: sql::engine::Raft) {
+    fn sql_accept(
+        id: raft::NodeID,
+        listener: TcpListener,
+        sql_engine: sql::engine::Raft,
+        backup_dir: &Path,
+    ) {
         std::thread::scope(|s| loop {
             let (socket, peer) = match listener.accept() {
                 Ok(sp) => sp,
@@ -261,7 +288,7 @@ impl Server {
             let session = sql_engine.session();
             s.spawn(move || {
                 debug!("Client {peer} connected");
-                match Self::sql_session(id, socket, session) {
+                match Self::sql_session(id, socket, session, backup_dir) {
                     Ok(()) => debug!("Client {peer} disconnected"),
                     Err(err) => error!("Client {peer} error: {err}"),
                 }
@@ -275,6 +302,7 @@ impl Server {
         id: raft::NodeID,
         socket: TcpStream,
         mut session: sql::engine::Session<sql::engine::Raft>,
+        backup_dir: &Path,
     ) -> Result<()> {
         let mut reader = std::io::BufReader::new(socket.try_clone()?);
         let mut writer = std::io::BufWriter::new(socket);
@@ -296,6 +324,12 @@ impl Server {
                     .status()
                     .map(|s| Status { server: id, raft: s.raft, mvcc: s.mvcc })
                     .map(Response::Status),
+                Request::Changes(from) => {
+                    session.changes(from, CHANGES_BATCH_SIZE).map(Response::Changes)
+                }
+                Request::Backup(name) => Self::backup_path(backup_dir, &name)
+                    .and_then(|dir| session.backup(dir))
+                    .map(Response::Backup),
             };
 
             // Process response.
@@ -305,6 +339,21 @@ impl Server {
         }
         Ok(())
     }
+
+    /// Resolves a client-provided backup name to a directory in the backup
+    /// directory. The name must be a plain file name, without path separators
+    /// or "..", such that clients can't write outside of the backup directory.
+    fn backup_path(backup_dir: &Path, name: &str) -> Result<PathBuf> {
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(c)), None)
+                if c == name && !name.contains(['/', '\\']) && !name.contains("..") =>
+            {
+                Ok(backup_dir.join(name))
+            }
+            _ => errinput!("invalid backup name {name:?}, must be a plain file name"),
+        }
+    }
 }
 
 /// A SQL client request.
@@ -318,6 +367,12 @@ pub enum Request {
     ListTables,
     /// Returns server status.
     Status,
+    /// Returns the change feed of committed row changes, starting at the given
+    /// cursor.
+    Changes(storage::mvcc::ChangeCursor),
+    /// Writes a backup of the server node's Raft log and SQL storage to a
+    /// new directory with the given name in the server's backup directory.
+    Backup(String),
 }
 
 impl encoding::Value for Request {}
@@ -330,6 +385,8 @@ pub enum Response {
     GetTable(Table),
     ListTables(Vec<String>),
     Status(Status),
+    Changes(Changes),
+    Backup(raft::Index),
 }
 
 impl encoding::Value for Response {}
@@ -3,9 +3,10 @@
 use super::Session;
 use crate::errinput;
 use crate::error::Result;
-use crate::sql::types::{Expression, Row, Rows, Table, Value};
+use crate::sql::types::{Column, Expression, Index, Row, Rows, Table, TupleRange, Value};
 use crate::storage::mvcc;
 
+use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet};
 
 /// A SQL engine. This provides low-level CRUD (create, read, update, delete)
@@ -25,6 +26,11 @@ pub trait Engine<'a>: Sized {
     /// Begins a read-only transaction as of a historical version.
     fn begin_as_of(&'a self, version: mvcc::Version) -> Result<Self::Transaction>;
 
+    /// Returns the change feed of committed row changes, starting at the given
+    /// cursor. Returns at most limit changes, and schema and index changes
+    /// count towards the limit too. See `mvcc::MVCC::changes()` for details.
+    fn changes(&'a self, from: mvcc::ChangeCursor, limit: usize) -> Result<Changes>;
+
     /// Creates a session for executing SQL statements. Can't outlive engine.
     fn session(&'a self) -> Session<'a, Self> {
         Session::new(self)
@@ -42,32 +48,69 @@ pub trait Transaction {
     fn version(&self) -> mvcc::Version;
     /// Whether the transaction is read-only.
     fn read_only(&self) -> bool;
+    /// The transaction's MVCC state, including concurrent active transactions.
+    fn state(&self) -> &mvcc::TransactionState;
 
     /// Commits the transaction.
     fn commit(self) -> Result<()>;
     /// Rolls back the transaction.
     fn rollback(self) -> Result<()>;
 
-    /// Deletes table rows by primary key, if they exist.
-    fn delete(&self, table: &str, ids: &[Value]) -> Result<()>;
-    /// Fetches table rows by primary key, if they exist.
-    fn get(&self, table: &str, ids: &[Value]) -> Result<Vec<Row>>;
+    /// Deletes table rows by primary key, if they exist. A primary key is given
+    /// as the values of the primary key columns.
+    fn delete(&self, table: &str, ids: &[Vec<Value>]) -> Result<()>;
+    /// Fetches table rows by primary key, if they exist. A key may only give
+    /// values for the leading primary key columns, in which case all rows with
+    /// that key prefix are fetched.
+    fn get(&self, table: &str, ids: &[Vec<Value>]) -> Result<Vec<Row>>;
     /// Inserts new table rows.
     fn insert(&self, table: &str, rows: Vec<Row>) -> Result<()>;
-    /// Looks up a set of primary keys by index values. Uses a BTreeSet for test
-    /// determinism.
-    fn lookup_index(&self, table: &str, column: &str, values: &[Value]) -> Result<BTreeSet<Value>>;
+    /// Locks table rows by primary key in the given mode, until the transaction
+    /// commits or rolls back. Errors if any row can't be locked, unless
+    /// skip_locked is true, in which case these rows are skipped. Returns the
+    /// primary keys of the locked rows.
+    fn lock(
+        &self,
+        table: &str,
+        ids: &[Vec<Value>],
+        mode: mvcc::LockMode,
+        skip_locked: bool,
+    ) -> Result<Vec<Vec<Value>>>;
+    /// Looks up a set of primary keys by index values, for the secondary index
+    /// on the given columns. The values may only be given for the leading index
+    /// columns, in which case all entries with that prefix are returned. Uses a
+    /// BTreeSet for test determinism.
+    fn lookup_index(
+        &self,
+        table: &str,
+        columns: &[String],
+        values: &[Vec<Value>],
+    ) -> Result<BTreeSet<Vec<Value>>>;
+    /// Looks up the primary keys of all index entries in the given range of
+    /// index values, for the secondary index on the given columns. The range
+    /// bounds may only give values for the leading index columns. Returns the
+    /// keys in index order, or in reverse index order if reverse is true.
+    fn lookup_index_range(
+        &self,
+        table: &str,
+        columns: &[String],
+        range: TupleRange,
+        reverse: bool,
+    ) -> Result<Vec<Vec<Value>>>;
     /// Scans a table's rows, optionally applying the given filter.
     fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Rows>;
+    /// Scans a table's rows whose primary keys are in the given range, in
+    /// primary key order or reverse primary key order. The range bounds may
+    /// only give values for the leading primary key columns.
+    fn scan_range(&self, table: &str, range: TupleRange, reverse: bool) -> Result<Rows>;
     /// Updates table rows by primary key. Uses a BTreeMap for test determinism.
-    fn update(&self, table: &str, rows: BTreeMap<Value, Row>) -> Result<()>;
+    fn update(&self, table: &str, rows: BTreeMap<Vec<Value>, Row>) -> Result<()>;
 }
 
 /// The catalog stores table schema information. It is required for
-/// Engine::Transaction, and thus fully transactional. For simplicity, it only
-/// supports very simple operations: creating and dropping tables. There are no
-/// ALTER TABLE schema changes, nor CREATE INDEX -- everything has to be
-/// specified when the table is initially created.
+/// Engine::Transaction, and thus fully transactional. It supports creating and
+/// dropping tables, and simple ALTER TABLE schema changes of columns and table
+/// names, and creating or dropping named secondary indexes.
 ///
 /// This type is separate from Transaction, even though Engine::Transaction
 /// requires transactions to implement it. This allows better control of when
@@ -78,6 +121,43 @@ pub trait Catalog {
     /// Drops a table. Errors if it does not exist, unless if_exists is true.
     /// Returns true if the table existed and was deleted.
     fn drop_table(&self, table: &str, if_exists: bool) -> Result<bool>;
+    /// Adds a column to a table. Existing rows get the column default, and are
+    /// validated against the column's constraints. The column ID must not have
+    /// been used in the table before, see Table::next_column_id().
+    fn add_column(&self, table: &str, column: Column) -> Result<()>;
+    /// Drops a column from a table. The primary key can't be dropped.
+    fn drop_column(&self, table: &str, column: &str) -> Result<()>;
+    /// Renames a table column.
+    fn rename_column(&self, table: &str, column: &str, new_name: &str) -> Result<()>;
+    /// Renames a table, including foreign key references to it.
+    fn rename_table(&self, table: &str, new_name: &str) -> Result<()>;
+    /// Sets a column's default value. If None, the default is dropped, which
+    /// for nullable columns means a NULL default.
+    fn set_column_default(&self, table: &str, column: &str, default: Option<Value>) -> Result<()>;
+    /// Sets whether a column is nullable. Errors if a non-nullable column has
+    /// existing NULL values.
+    fn set_column_nullable(&self, table: &str, column: &str, nullable: bool) -> Result<()>;
+    /// Creates a named secondary index on table columns. The index is
+    /// write-only: writes maintain it, but queries don't use it until it has
+    /// been built from existing rows with backfill_index(). Errors if the index
+    /// name is already used by any table, or if the columns are already
+    /// indexed.
+    fn create_index(&self, table: &str, index: Index) -> Result<()>;
+    /// Backfills a write-only index from up to limit existing rows after the
+    /// given primary key (or from the start), in primary key order. Returns
+    /// the primary key to continue after, or None once all rows are indexed,
+    /// at which point the index is readable. Errors if a unique index has
+    /// duplicate values. Transactions that began before the index was created
+    /// don't maintain it, and must have completed before the backfill starts.
+    fn backfill_index(
+        &self,
+        index: &str,
+        after: Option<Vec<Value>>,
+        limit: usize,
+    ) -> Result<Option<Vec<Value>>>;
+    /// Drops a named secondary index. Errors if it does not exist, unless
+    /// if_exists is true. Returns true if the index existed and was deleted.
+    fn drop_index(&self, index: &str, if_exists: bool) -> Result<bool>;
     /// Fetches a table schema, or None if it doesn't exist.
     fn get_table(&self, table: &str) -> Result<Option<Table>>;
     /// Returns a list of all table schemas.
@@ -88,3 +168,29 @@ pub trait Catalog {
         self.get_table(table)?.ok_or(errinput!("table {table} does not exist"))
     }
 }
+
+/// A committed table row change, emitted by the change feed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    /// The version of the transaction that made the change.
+    pub version: mvcc::Version,
+    /// The table name.
+    pub table: String,
+    /// The row's primary key.
+    pub id: Vec<Value>,
+    /// The old row, or None if it was inserted.
+    pub old: Option<Row>,
+    /// The new row, or None if it was deleted.
+    pub new: Option<Row>,
+}
+
+/// A batch of change feed changes, ordered by version.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Changes {
+    /// The row changes.
+    pub changes: Vec<Change>,
+    /// The cursor to resume the change feed from.
+    pub next: mvcc::ChangeCursor,
+}
+
+impl crate::encoding::Value for Changes {}
@@ -1,13 +1,29 @@
-use super::Catalog;
-use crate::encoding::{self, Key as _, Value as _};
-use crate::errinput;
-use crate::error::Result;
-use crate::sql::types::{Expression, Row, Rows, Table, Value};
-use crate::storage::{self, mvcc};
+use super::{upgrade, Catalog, Change, Changes, Transaction as _};
+use crate::encoding::format::{self, Formatter as _};
+use crate::encoding::{self, bincode, keycode, Key as _, Value as _};
+use crate::error::{Error, Result};
+use crate::sql::types::{
+    format_tuple, Column, ColumnId, Expression, Index, Row, Rows, Table, TableId, TupleRange, Value,
+};
+use crate::storage::{self, mvcc, WriteOp};
+use crate::{errdata, errinput};
 
+use itertools::Either;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::{Bound, RangeBounds};
+
+/// The unversioned key used to store the storage format version. Just use a
+/// string for simplicity.
+pub const FORMAT_KEY: &[u8] = b"sql_format";
+
+/// The current storage format version, i.e. the encoding of keys, table
+/// schemas, rows, and index entries. It must be incremented whenever the
+/// encoding changes, with an upgrade from the previous version. Databases
+/// without a format version use the original format, i.e. version 0.
+pub const FORMAT_VERSION: u32 = 1;
 
 /// A SQL engine using local storage. This provides the main SQL storage logic,
 /// including with the Raft SQL engine which dispatches to this engine for
@@ -18,9 +34,38 @@ pub struct Local<E: storage::Engine + 'static> {
 }
 
 impl<E: storage::Engine> Local<E> {
-    /// Creates a new local SQL engine using the given storage engine.
-    pub fn new(engine: E) -> Self {
-        Self { mvcc: mvcc::MVCC::new(engine) }
+    /// Creates a new local SQL engine using the given storage engine. Upgrades
+    /// the stored data from older format versions, and errors if it was
+    /// written by a newer, unknown format version.
+    pub fn new(engine: E) -> Result<Self> {
+        let local = Self { mvcc: mvcc::MVCC::new(engine) };
+        let format = match local.get_unversioned(FORMAT_KEY)? {
+            Some(bytes) => bincode::deserialize(&bytes)?,
+            None => 0,
+        };
+        match format {
+            FORMAT_VERSION => {}
+            0 => {
+                let upgrade = upgrade::FromV0::new(&local.mvcc)?;
+                let format = (FORMAT_KEY, bincode::serialize(&FORMAT_VERSION));
+                local
+                    .mvcc
+                    .rewrite(|key, version, value| upgrade.rewrite(key, version, value), format)?
+            }
+            format => {
+                return errdata!(
+                    "unsupported SQL storage format version {format}, expected {}",
+                    FORMAT_VERSION
+                )
+            }
+        }
+        Ok(local)
+    }
+
+    /// Enables or disables the change feed of committed row writes (see
+    /// mvcc::MVCC::set_change_feed). Disabled by default.
+    pub fn set_change_feed(&mut self, enabled: bool) {
+        self.mvcc.set_change_feed(enabled)
     }
 
     /// Resumes a transaction from the given state. This is usually encapsulated
@@ -40,6 +85,141 @@ impl<E: storage::Engine> Local<E> {
     pub fn set_unversioned(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
         self.mvcc.set_unversioned(key, value)
     }
+
+    /// Checks the SQL data for inconsistencies, returning a description of
+    /// each problem found (if any). In addition to the MVCC checks, this checks
+    /// that the latest committed table schemas and rows are valid, and that
+    /// secondary indexes match the rows. This scans the entire dataset, so it's
+    /// mostly useful for offline integrity checks, e.g. `toydump --check`.
+    pub fn check(&self) -> Result<Vec<String>> {
+        let mut problems = self.mvcc.check()?;
+        let txn = Transaction::new(self.mvcc.begin_read_only()?);
+
+        // Keys are ordered as table schemas, index entries, rows, and then the
+        // next table ID. Collect the index entries, and remove each row from
+        // them as it's seen. Any remaining index entries refer to missing rows.
+        let mut tables: BTreeMap<TableId, Table> = BTreeMap::new();
+        let mut indexes = BTreeMap::new();
+        let mut next_table_id = None;
+        let mut scan = txn.txn.scan(..);
+        while let Some((key, bytes)) = scan.next().transpose()? {
+            match Key::decode(&key) {
+                Ok(Key::Table(name)) => match Table::decode(&bytes) {
+                    Ok(table) if table.name != name => {
+                        problems.push(format!("table {name} has schema for table {}", table.name))
+                    }
+                    Ok(table) => {
+                        if let Err(err) = table.validate(&txn) {
+                            problems.push(format!("table {name} has invalid schema: {err}"));
+                        }
+                        if let Some(other) = tables.get(&table.id) {
+                            problems.push(format!(
+                                "tables {} and {name} have the same ID {}",
+                                other.name, table.id
+                            ));
+                        }
+                        tables.insert(table.id, table);
+                    }
+                    Err(err) => problems.push(format!("table {name} has invalid schema: {err}")),
+                },
+
+                Ok(Key::Index(table_id, columns, values)) => {
+                    let (columns, values) = (columns.into_owned(), values.into_owned());
+                    let fvalues = format_tuple(&values);
+                    let Some(table) = tables.get(&table_id) else {
+                        problems
+                            .push(format!("index entry {fvalues} in unknown table ID {table_id}"));
+                        continue;
+                    };
+                    if !has_index(table, &columns) {
+                        problems.push(format!(
+                            "index entry {fvalues} for unknown index on {} column IDs {}",
+                            table.name,
+                            format_tuple(&columns)
+                        ));
+                        continue;
+                    }
+                    match BTreeSet::<Vec<Value>>::decode(&bytes) {
+                        Ok(ids) => _ = indexes.insert((table_id, columns, values), ids),
+                        Err(err) => problems.push(format!(
+                            "index {}.{} entry {fvalues} is invalid: {err}",
+                            table.name,
+                            format_tuple(&table.column_names(&table.index_columns(&columns)))
+                        )),
+                    }
+                }
+
+                Ok(Key::Row(table_id, id)) => {
+                    let fid = format_tuple(&id);
+                    let Some(table) = tables.get(&table_id) else {
+                        problems.push(format!("row {fid} in unknown table ID {table_id}"));
+                        continue;
+                    };
+                    let name = &table.name;
+                    let row = match StoredRow::decode_upgrade(table, &bytes) {
+                        Ok(row) => row,
+                        Err(err) => {
+                            problems.push(format!("row {name}.{fid} is invalid: {err}"));
+                            continue;
+                        }
+                    };
+                    if let Err(err) = table.validate_row(&row, true, &txn) {
+                        problems.push(format!("row {name}.{fid} is invalid: {err}"));
+                        continue;
+                    }
+                    if table.row_id(&row) != *id {
+                        let pk = format_tuple(&table.row_id(&row));
+                        problems.push(format!("row {name}.{fid} has primary key {pk}"));
+                    }
+                    // Write-only indexes may not have been backfilled yet, so
+                    // rows may be missing from them, but their entries must
+                    // still refer to existing rows.
+                    let readable = table.readable_indexes();
+                    for columns in table.secondary_indexes() {
+                        let values = project(&row, &columns);
+                        let key = (table.id, table.column_ids(&columns), values);
+                        let found =
+                            indexes.get_mut(&key).is_some_and(|ids| ids.remove(id.as_ref()));
+                        if !found && readable.contains(&columns) {
+                            let cnames = format_tuple(&table.column_names(&columns));
+                            problems.push(format!(
+                                "row {name}.{fid} missing from index {name}.{cnames}"
+                            ));
+                        }
+                    }
+                }
+
+                Ok(Key::NextTableId) => match bincode::deserialize::<TableId>(&bytes) {
+                    Ok(id) => next_table_id = Some(id),
+                    Err(err) => problems.push(format!("next table ID is invalid: {err}")),
+                },
+
+                Err(_) => problems.push(format!("invalid key {}", format::Raw::key(&key))),
+            }
+        }
+
+        if let Some(next_table_id) = next_table_id {
+            for table in tables.values().filter(|t| t.id >= next_table_id) {
+                problems.push(format!(
+                    "table {} has ID {} beyond next table ID {next_table_id}",
+                    table.name, table.id
+                ));
+            }
+        }
+        for ((table_id, columns, values), ids) in indexes {
+            let table = &tables[&table_id];
+            let cnames = format_tuple(&table.column_names(&table.index_columns(&columns)));
+            let fvalues = format_tuple(&values);
+            for id in ids {
+                problems.push(format!(
+                    "index {}.{cnames} entry {fvalues} refers to missing row {}",
+                    table.name,
+                    format_tuple(&id)
+                ));
+            }
+        }
+        Ok(problems)
+    }
 }
 
 impl<'a, E: storage::Engine> super::Engine<'a> for Local<E> {
@@ -56,6 +236,48 @@ impl<'a, E: storage::Engine> super::Engine<'a> for Local<E> {
     fn begin_as_of(&self, version: mvcc::Version) -> Result<Self::Transaction> {
         Ok(Self::Transaction::new(self.mvcc.begin_as_of(version)?))
     }
+
+    fn changes(&self, from: mvcc::ChangeCursor, limit: usize) -> Result<Changes> {
+        let (mvcc_changes, next) = self.mvcc.changes(from, limit)?;
+        let mut changes = Vec::with_capacity(mvcc_changes.len());
+        let mut schemas = HashMap::new();
+        for change in mvcc_changes {
+            // Only emit row changes, not schema and index changes.
+            let Key::Row(table, id) = Key::decode(&change.key)? else { continue };
+            // Decode rows with the table schema as of the change. If the table
+            // was dropped by the change, use the schema from before it. Only
+            // committed versions below the oldest active transaction are
+            // emitted, so a snapshot without active transactions sees them.
+            let mut schema = None;
+            for version in [change.version + 1, change.version] {
+                if let Entry::Vacant(entry) = schemas.entry((version, table)) {
+                    let state = mvcc::TransactionState {
+                        version,
+                        read_only: true,
+                        active: BTreeSet::new(),
+                    };
+                    let txn = Transaction::new(self.mvcc.resume(state)?);
+                    entry.insert(txn.get_table_by_id(table)?);
+                }
+                schema = schemas[&(version, table)].as_ref();
+                if schema.is_some() {
+                    break;
+                }
+            }
+            let Some(schema) = schema else {
+                return errdata!("no schema for table {table} at version {}", change.version);
+            };
+            let decode = |bytes: &[u8]| StoredRow::decode_upgrade(schema, bytes);
+            changes.push(Change {
+                version: change.version,
+                table: schema.name.clone(),
+                id: id.into_owned(),
+                old: change.old.as_deref().map(decode).transpose()?,
+                new: change.new.as_deref().map(decode).transpose()?,
+            });
+        }
+        Ok(Changes { changes, next })
+    }
 }
 
 /// A SQL transaction, wrapping an MVCC transaction.
@@ -69,53 +291,78 @@ impl<E: storage::Engine> Transaction<E> {
         Self { txn }
     }
 
-    /// Returns the transaction's internal state.
-    pub(super) fn state(&self) -> &mvcc::TransactionState {
-        self.txn.state()
-    }
-
-    /// Fetches the matching primary keys for the given secondary index value,
-    /// or an empty set if there is none. The value must already be normalized.
-    fn get_index(&self, table: &str, column: &str, value: &Value) -> Result<BTreeSet<Value>> {
-        debug_assert!(self.has_index(table, column)?, "no index on {table}.{column}");
-        debug_assert!(value.is_normalized(), "value not normalized");
+    /// Fetches the matching primary keys for the given secondary index values,
+    /// or an empty set if there is none. The values must already be normalized.
+    fn get_index(
+        &self,
+        table: &Table,
+        columns: &[ColumnId],
+        values: &[Value],
+    ) -> Result<BTreeSet<Vec<Value>>> {
+        debug_assert!(has_index(table, columns), "no index on {}.{columns:?}", table.name);
+        debug_assert!(values.iter().all(|v| v.is_normalized()), "value not normalized");
         Ok(self
             .txn
-            .get(&Key::Index(table.into(), column.into(), value.into()).encode())?
+            .get(&Key::Index(table.id, columns.into(), values.into()).encode())?
             .map(|v| BTreeSet::decode(&v))
             .transpose()?
             .unwrap_or_default())
     }
 
+    /// Fetches the matching primary keys for all secondary index entries whose
+    /// values start with the given values, i.e. for the leading index columns.
+    /// The values must already be normalized.
+    fn scan_index(
+        &self,
+        table: &Table,
+        columns: &[ColumnId],
+        values: &[Value],
+    ) -> Result<BTreeSet<Vec<Value>>> {
+        debug_assert!(has_index(table, columns), "no index on {}.{columns:?}", table.name);
+        debug_assert!(values.iter().all(|v| v.is_normalized()), "value not normalized");
+        let prefix = Key::Index(table.id, columns.into(), values.into()).encode();
+        let mut ids = BTreeSet::new();
+        let mut scan = self.txn.scan_prefix(&prefix);
+        while let Some((_, value)) = scan.next().transpose()? {
+            ids.extend(BTreeSet::decode(&value)?);
+        }
+        Ok(ids)
+    }
+
     /// Fetches a single row by primary key, or None if it doesn't exist. The key
     /// must already be normalized.
-    fn get_row(&self, table: &str, id: &Value) -> Result<Option<Row>> {
-        debug_assert!(id.is_normalized(), "value not normalized");
+    fn get_row(&self, table: &Table, id: &[Value]) -> Result<Option<Row>> {
+        debug_assert!(id.iter().all(|v| v.is_normalized()), "value not normalized");
         self.txn
-            .get(&Key::Row(table.into(), id.into()).encode())?
-            .map(|v| Row::decode(&v))
+            .get(&Key::Row(table.id, id.into()).encode())?
+            .map(|v| StoredRow::decode_upgrade(table, &v))
             .transpose()
     }
 
-    /// Returns true if the given secondary index exists.
-    fn has_index(&self, table: &str, column: &str) -> Result<bool> {
-        let table = self.must_get_table(table)?;
-        Ok(table.columns.iter().find(|c| c.name == column).map(|c| c.index).unwrap_or(false))
+    /// Returns the column IDs of the secondary index on the given column names,
+    /// or an error if there is no such index.
+    fn index_column_ids(&self, table: &Table, columns: &[String]) -> Result<Vec<ColumnId>> {
+        let Some(index) =
+            table.secondary_indexes().into_iter().find(|c| table.column_names(c) == columns)
+        else {
+            return errinput!("no index on {}.{}", table.name, format_tuple(columns));
+        };
+        Ok(table.column_ids(&index))
     }
 
-    /// Stores a secondary index entry for the given column value, replacing the
-    /// existing entry. The value and ids must already be normalized.
+    /// Stores a secondary index entry for the given column values, replacing
+    /// the existing entry. The values and ids must already be normalized.
     fn set_index(
         &self,
-        table: &str,
-        column: &str,
-        value: &Value,
-        ids: BTreeSet<Value>,
+        table: &Table,
+        columns: &[ColumnId],
+        values: &[Value],
+        ids: BTreeSet<Vec<Value>>,
     ) -> Result<()> {
-        debug_assert!(self.has_index(table, column)?, "no index on {table}.{column}");
-        debug_assert!(value.is_normalized(), "value not normalized");
-        debug_assert!(ids.iter().all(|v| v.is_normalized()), "value not normalized");
-        let key = Key::Index(table.into(), column.into(), value.into()).encode();
+        debug_assert!(has_index(table, columns), "no index on {}.{columns:?}", table.name);
+        debug_assert!(values.iter().all(|v| v.is_normalized()), "value not normalized");
+        debug_assert!(ids.iter().flatten().all(|v| v.is_normalized()), "value not normalized");
+        let key = Key::Index(table.id, columns.into(), values.into()).encode();
         if ids.is_empty() {
             self.txn.delete(&key)
         } else {
@@ -123,6 +370,99 @@ impl<E: storage::Engine> Transaction<E> {
         }
     }
 
+    /// Adds secondary index entries on the given columns for up to limit table
+    /// rows after the given primary key (or from the start), merging them into
+    /// any existing entries written concurrently. If unique is true, errors on
+    /// duplicate values. Returns the primary key of the last row, or None if
+    /// all rows have been indexed.
+    fn build_index(
+        &self,
+        table: &Table,
+        columns: &[usize],
+        unique: bool,
+        after: Option<&[Value]>,
+        limit: usize,
+    ) -> Result<Option<Vec<Value>>> {
+        let start = after.map_or(Bound::Unbounded, |id| Bound::Excluded(id.to_vec()));
+        let mut index: BTreeMap<Vec<Value>, BTreeSet<Vec<Value>>> = BTreeMap::new();
+        let (mut count, mut last) = (0, None);
+        for row in self.scan_range(&table.name, (start, Bound::Unbounded), false)?.take(limit) {
+            let row = row?;
+            let id = table.row_id(&row);
+            index.entry(project(&row, columns)).or_default().insert(id.clone());
+            (count, last) = (count + 1, Some(id));
+        }
+
+        let names = table.column_names(columns);
+        let ids = table.column_ids(columns);
+        for (values, mut pks) in index {
+            pks.extend(self.get_index(table, &ids, &values)?);
+            if unique && pks.len() > 1 && !values.iter().any(|v| v.is_undefined()) {
+                return match &values[..] {
+                    [value] => errinput!("value {value} already in unique column {}", names[0]),
+                    values => errinput!(
+                        "values {} already in unique columns {}",
+                        format_tuple(values),
+                        format_tuple(&names)
+                    ),
+                };
+            }
+            self.set_index(table, &ids, &values, pks)?;
+        }
+        Ok(last.filter(|_| count == limit))
+    }
+
+    /// Deletes all entries of the secondary index on the given columns.
+    fn delete_index(&self, table: &Table, columns: &[usize]) -> Result<()> {
+        let prefix = KeyPrefix::Index(table.id, table.column_ids(columns).into());
+        self.txn.delete_prefix(&prefix.encode())
+    }
+
+    /// Allocates a new table ID. The next ID is stored under a versioned key,
+    /// such that concurrent table creations conflict instead of assigning the
+    /// same ID. Databases upgraded from the original format don't have this
+    /// key, so it starts after the largest ID in use.
+    fn next_table_id(&self) -> Result<TableId> {
+        let key = Key::NextTableId.encode();
+        let id = match self.txn.get(&key)? {
+            Some(value) => bincode::deserialize(&value)?,
+            None => self.list_tables()?.iter().map(|t| t.id + 1).max().unwrap_or(1),
+        };
+        self.txn.set(&key, bincode::serialize(&(id + 1)))?;
+        Ok(id)
+    }
+
+    /// Fetches a table schema by table ID, if it exists.
+    fn get_table_by_id(&self, id: TableId) -> Result<Option<Table>> {
+        Ok(self.list_tables()?.into_iter().find(|t| t.id == id))
+    }
+
+    /// Stores a table schema, after validating it. The table must exist.
+    fn set_table(&self, table: &Table) -> Result<()> {
+        table.validate(self)?;
+        self.txn.set(&Key::Table((&table.name).into()).encode(), table.encode())
+    }
+
+    /// Rewrites any rows stored with an older schema version using the current
+    /// schema version, such that their values no longer depend on the row
+    /// layouts or default values of previous versions.
+    fn upgrade_rows(&self, table: &Table) -> Result<()> {
+        let mut scan = self.txn.scan_prefix(&KeyPrefix::Row(table.id).encode());
+        let mut upgrade = Vec::new();
+        while let Some((key, value)) = scan.next().transpose()? {
+            let row = StoredRow::decode(&value)?;
+            if row.version != table.version {
+                let values = table.upgrade_row(row.version, row.values.into_owned())?;
+                upgrade.push((key, StoredRow::new(table, &values).encode()));
+            }
+        }
+        drop(scan);
+        for (key, value) in upgrade {
+            self.txn.set(&key, value)?;
+        }
+        Ok(())
+    }
+
     /// Returns all tables referencing a table, as (table, column index) pairs.
     /// This includes references from the table itself.
     fn table_references(&self, table: &str) -> Result<Vec<(Table, Vec<usize>)>> {
@@ -153,6 +493,10 @@ impl<E: storage::Engine> super::Transaction for Transaction<E> {
         self.txn.read_only()
     }
 
+    fn state(&self) -> &mvcc::TransactionState {
+        self.txn.state()
+    }
+
     fn commit(self) -> Result<()> {
         self.txn.commit()
     }
@@ -161,25 +505,23 @@ impl<E: storage::Engine> super::Transaction for Transaction<E> {
         self.txn.rollback()
     }
 
-    fn delete(&self, table: &str, ids: &[Value]) -> Result<()> {
+    fn delete(&self, table: &str, ids: &[Vec<Value>]) -> Result<()> {
         let table = self.must_get_table(table)?;
-        let indexes: Vec<_> = table.columns.iter().enumerate().filter(|(_, c)| c.index).collect();
+        let indexes = table.secondary_indexes();
 
-        // Check for foreign key references to the deleted rows.
+        // Check for foreign key references to the deleted rows. References can
+        // only target single-column primary keys, so the IDs are single values.
         for (source, refs) in self.table_references(&table.name)? {
             let self_reference = source.name == table.name;
             for i in refs {
                 let column = &source.columns[i];
-                let mut source_ids = if i == source.primary_key {
+                let mut source_ids = if source.primary_key == [i] {
                     // If the reference is from a primary key column, do a lookup.
-                    self.get(&source.name, ids)?
-                        .into_iter()
-                        .map(|row| row.into_iter().nth(i).expect("short row"))
-                        .collect()
+                    self.get(&source.name, ids)?.iter().map(|row| source.row_id(row)).collect()
                 } else {
                     // Otherwise (commonly), do a secondary index lookup.
                     // All foreign keys have a secondary index.
-                    self.lookup_index(&source.name, &column.name, ids)?
+                    self.lookup_index(&source.name, std::slice::from_ref(&column.name), ids)?
                 };
                 // We can ignore any references between the deleted rows,
                 // including a row referring to itself.
@@ -190,11 +532,12 @@ impl<E: storage::Engine> super::Transaction for Transaction<E> {
                 }
                 if let Some(source_id) = source_ids.into_iter().next() {
                     return errinput!(
-                        "row referenced by {}.{} for {}.{}={source_id}",
+                        "row referenced by {}.{} for {}.{}={}",
                         source.name,
                         column.name,
                         source.name,
-                        source.columns[source.primary_key].name
+                        format_tuple(&source.column_names(&source.primary_key)),
+                        format_tuple(&source_id),
                     );
                 }
             }
@@ -205,65 +548,154 @@ impl<E: storage::Engine> super::Transaction for Transaction<E> {
             //
             // NB: We don't need to normalize in the above loop, because we're
             // calling methods that themselves perform normalization.
-            let id = id.normalize_ref();
+            let id = normalize(id);
 
             // Remove the primary key from any index entries. There must be an
             // index entry for each row.
             if !indexes.is_empty() {
-                if let Some(row) = self.get_row(&table.name, &id)? {
-                    for (i, column) in indexes.iter().copied() {
-                        let mut index = self.get_index(&table.name, &column.name, &row[i])?;
-                        index.remove(&id);
-                        self.set_index(&table.name, &column.name, &row[i], index)?;
+                if let Some(row) = self.get_row(&table, &id)? {
+                    for columns in &indexes {
+                        let (ids, values) = (table.column_ids(columns), project(&row, columns));
+                        let mut index = self.get_index(&table, &ids, &values)?;
+                        index.remove(id.as_ref());
+                        self.set_index(&table, &ids, &values, index)?;
                     }
                 }
             }
 
             // Delete the row.
-            self.txn.delete(&Key::Row((&table.name).into(), id).encode())?;
+            self.txn.delete(&Key::Row(table.id, id).encode())?;
         }
         Ok(())
     }
 
-    fn get(&self, table: &str, ids: &[Value]) -> Result<Vec<Row>> {
-        ids.iter().filter_map(|id| self.get_row(table, &id.normalize_ref()).transpose()).collect()
+    fn get(&self, table: &str, ids: &[Vec<Value>]) -> Result<Vec<Row>> {
+        let table = self.must_get_table(table)?;
+        let mut rows = Vec::with_capacity(ids.len());
+        for id in ids {
+            let id = normalize(id);
+            if id.len() > table.primary_key.len() {
+                return errinput!("invalid primary key {}", format_tuple(&id));
+            }
+            if id.len() == table.primary_key.len() {
+                rows.extend(self.get_row(&table, &id)?);
+                continue;
+            }
+            // The ID is a prefix of the primary key, so scan the matching rows.
+            let prefix = Key::Row(table.id, id).encode();
+            let mut scan = self.txn.scan_prefix(&prefix);
+            while let Some((_, value)) = scan.next().transpose()? {
+                rows.push(StoredRow::decode_upgrade(&table, &value)?);
+            }
+        }
+        Ok(rows)
     }
 
     fn insert(&self, table: &str, rows: Vec<Row>) -> Result<()> {
         let table = self.must_get_table(table)?;
+        let indexes = table.secondary_indexes();
         for mut row in rows {
             // Normalize the row.
             row = row.into_iter().map(|v| v.normalize()).collect();
 
-            // Insert the row.
+            // Insert the row and update any secondary indexes, atomically.
             table.validate_row(&row, false, self)?;
-            let id = &row[table.primary_key];
-            self.txn.set(&Key::Row((&table.name).into(), id.into()).encode(), row.encode())?;
-
-            // Update any secondary indexes.
-            for (i, column) in table.columns.iter().enumerate().filter(|(_, c)| c.index) {
-                let mut index = self.get_index(&table.name, &column.name, &row[i])?;
+            let id = table.row_id(&row);
+            let mut batch = vec![WriteOp::Set {
+                key: Key::Row(table.id, (&id).into()).encode(),
+                value: StoredRow::new(&table, &row).encode(),
+            }];
+            for columns in &indexes {
+                let (ids, values) = (table.column_ids(columns), project(&row, columns));
+                let mut index = self.get_index(&table, &ids, &values)?;
                 index.insert(id.clone());
-                self.set_index(&table.name, &column.name, &row[i], index)?;
+                batch.push(WriteOp::Set {
+                    key: Key::Index(table.id, ids.into(), values.into()).encode(),
+                    value: index.encode(),
+                });
             }
+            self.txn.write_batch(batch)?;
         }
         Ok(())
     }
 
-    fn lookup_index(&self, table: &str, column: &str, values: &[Value]) -> Result<BTreeSet<Value>> {
-        debug_assert!(self.has_index(table, column)?, "index lookup without index");
+    fn lock(
+        &self,
+        table: &str,
+        ids: &[Vec<Value>],
+        mode: mvcc::LockMode,
+        skip_locked: bool,
+    ) -> Result<Vec<Vec<Value>>> {
+        let table = self.must_get_table(table)?;
+        let mut locked = Vec::with_capacity(ids.len());
+        for id in ids {
+            let id = normalize(id);
+            match self.txn.lock(&Key::Row(table.id, id.clone()).encode(), mode) {
+                Ok(()) => locked.push(id.into_owned()),
+                Err(Error::Serialization) if skip_locked => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(locked)
+    }
+
+    fn lookup_index(
+        &self,
+        table: &str,
+        columns: &[String],
+        values: &[Vec<Value>],
+    ) -> Result<BTreeSet<Vec<Value>>> {
+        let table = self.must_get_table(table)?;
+        let columns = self.index_column_ids(&table, columns)?;
         let mut pks = BTreeSet::new();
-        for v in values {
-            pks.extend(self.get_index(table, column, &v.normalize_ref())?)
+        for values in values {
+            let values = normalize(values);
+            if values.len() > columns.len() {
+                return errinput!("invalid index values {}", format_tuple(&values));
+            }
+            if values.len() == columns.len() {
+                pks.extend(self.get_index(&table, &columns, &values)?);
+            } else {
+                pks.extend(self.scan_index(&table, &columns, &values)?);
+            }
         }
         Ok(pks)
     }
 
+    fn lookup_index_range(
+        &self,
+        table: &str,
+        columns: &[String],
+        range: TupleRange,
+        reverse: bool,
+    ) -> Result<Vec<Vec<Value>>> {
+        let table = self.must_get_table(table)?;
+        let columns = self.index_column_ids(&table, columns)?;
+        let mut ids = Vec::new();
+        let prefix = KeyPrefix::Index(table.id, (&columns).into()).encode();
+        let Some(range) = encode_range(range, &prefix, |values| {
+            Key::Index(table.id, (&columns).into(), values.into()).encode()
+        }) else {
+            return Ok(ids);
+        };
+        let mut scan = self.txn.scan(range);
+        while let Some((_, value)) = scan.next().transpose()? {
+            ids.extend(BTreeSet::<Vec<Value>>::decode(&value)?);
+        }
+        // Index entries are ordered by primary key for each index value, so
+        // reversing the entire result yields the reverse index order.
+        if reverse {
+            ids.reverse();
+        }
+        Ok(ids)
+    }
+
     fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Rows> {
+        let table = self.must_get_table(table)?;
         Ok(Box::new(
             self.txn
-                .scan_prefix(&KeyPrefix::Row(table.into()).encode())
-                .map(|r| r.and_then(|(_, v)| Row::decode(&v)))
+                .scan_prefix(&KeyPrefix::Row(table.id).encode())
+                .map(move |r| r.and_then(|(_, v)| StoredRow::decode_upgrade(&table, &v)))
                 .filter_map(move |r| match r {
                     Ok(row) => match &filter {
                         Some(filter) => match filter.evaluate(Some(&row)) {
@@ -279,17 +711,32 @@ impl<E: storage::Engine> super::Transaction for Transaction<E> {
         ))
     }
 
-    fn update(&self, table: &str, rows: BTreeMap<Value, Row>) -> Result<()> {
+    fn scan_range(&self, table: &str, range: TupleRange, reverse: bool) -> Result<Rows> {
+        let table = self.must_get_table(table)?;
+        let prefix = KeyPrefix::Row(table.id).encode();
+        let Some(range) = encode_range(range, &prefix, |id| Key::Row(table.id, id.into()).encode())
+        else {
+            return Ok(Box::new(std::iter::empty()));
+        };
+        let scan = match reverse {
+            true => Either::Left(self.txn.scan(range).rev()),
+            false => Either::Right(self.txn.scan(range)),
+        };
+        Ok(Box::new(scan.map(move |r| r.and_then(|(_, v)| StoredRow::decode_upgrade(&table, &v)))))
+    }
+
+    fn update(&self, table: &str, rows: BTreeMap<Vec<Value>, Row>) -> Result<()> {
         let table = self.must_get_table(table)?;
+        let indexes = table.secondary_indexes();
 
         for (mut id, mut row) in rows {
             // Normalize the ID and row.
-            id = id.normalize();
+            id = id.into_iter().map(|v| v.normalize()).collect();
             row = row.into_iter().map(|v| v.normalize()).collect();
 
             // If the primary key changes, we simply do a delete and insert.
             // This simplifies constraint validation.
-            if id != row[table.primary_key] {
+            if id != table.row_id(&row) {
                 self.delete(&table.name, &[id])?;
                 self.insert(&table.name, vec![row])?;
                 continue;
@@ -300,41 +747,43 @@ impl<E: storage::Engine> super::Transaction for Transaction<E> {
             table.validate_row(&row, true, self)?;
 
             // Update indexes, knowing that the primary key has not changed.
-            let indexes: Vec<_> =
-                table.columns.iter().enumerate().filter(|(_, c)| c.index).collect();
             if !indexes.is_empty() {
                 let old = self.get(&table.name, &[id.clone()])?.remove(0);
-                for (i, column) in indexes {
-                    // If the value didn't change, we don't have to do anything.
-                    if old[i] == row[i] {
+                for columns in &indexes {
+                    let (old_values, new_values) = (project(&old, columns), project(&row, columns));
+                    // If the values didn't change, we don't have to do anything.
+                    if old_values == new_values {
                         continue;
                     }
+                    let ids = table.column_ids(columns);
 
-                    // Remove the old value from the index entry.
-                    let mut index = self.get_index(&table.name, &column.name, &old[i])?;
+                    // Remove the old values from the index entry.
+                    let mut index = self.get_index(&table, &ids, &old_values)?;
                     index.remove(&id);
-                    self.set_index(&table.name, &column.name, &old[i], index)?;
+                    self.set_index(&table, &ids, &old_values, index)?;
 
-                    // Insert the new value into the index entry.
-                    let mut index = self.get_index(&table.name, &column.name, &row[i])?;
+                    // Insert the new values into the index entry.
+                    let mut index = self.get_index(&table, &ids, &new_values)?;
                     index.insert(id.clone());
-                    self.set_index(&table.name, &column.name, &row[i], index)?;
+                    self.set_index(&table, &ids, &new_values, index)?;
                 }
             }
 
             // Update the row.
-            self.txn.set(&Key::Row((&table.name).into(), (&id).into()).encode(), row.encode())?;
+            let key = Key::Row(table.id, (&id).into()).encode();
+            self.txn.set(&key, StoredRow::new(&table, &row).encode())?;
         }
         Ok(())
     }
 }
 
 impl<E: storage::Engine> Catalog for Transaction<E> {
-    fn create_table(&self, table: Table) -> Result<()> {
+    fn create_table(&self, mut table: Table) -> Result<()> {
         if self.get_table(&table.name)?.is_some() {
             return errinput!("table {} already exists", table.name);
         }
         table.validate(self)?;
+        table.id = self.next_table_id()?;
         self.txn.set(&Key::Table((&table.name).into()).encode(), table.encode())
     }
 
@@ -360,33 +809,254 @@ impl<E: storage::Engine> Catalog for Transaction<E> {
         // Delete the table schema entry.
         self.txn.delete(&Key::Table((&table.name).into()).encode())?;
 
-        // Delete the table rows. storage::Engine doesn't support writing while
-        // scanning, so we buffer all keys in a vector. We could also do this in
-        // batches, although we'd want to do the batching above Raft to avoid
-        // blocking Raft processing for the duration of the drop.
-        let keys: Vec<Vec<u8>> = self
-            .txn
-            .scan_prefix(&KeyPrefix::Row((&table.name).into()).encode())
-            .map(|r| r.map(|(key, _)| key))
-            .collect::<Result<_>>()?;
-        for key in keys {
-            self.txn.delete(&key)?;
-        }
-
-        // Delete any secondary indexes.
-        for column in table.columns.iter().filter(|c| c.index) {
-            let keys: Vec<_> = self
-                .txn
-                .scan_prefix(
-                    &KeyPrefix::Index((&table.name).into(), (&column.name).into()).encode(),
-                )
-                .map(|r| r.map(|(key, _)| key))
-                .collect::<Result<_>>()?;
-            for key in keys {
-                self.txn.delete(&key)?;
+        // Delete the table rows and any secondary indexes. These are range
+        // deletes, which write a single range tombstone each regardless of the
+        // table size.
+        self.txn.delete_prefix(&KeyPrefix::Row(table.id).encode())?;
+        for columns in table.secondary_indexes() {
+            self.delete_index(&table, &columns)?;
+        }
+
+        Ok(true)
+    }
+
+    fn add_column(&self, table: &str, mut column: Column) -> Result<()> {
+        let mut table = self.must_get_table(table)?;
+        if table.columns.iter().any(|c| c.name == column.name) {
+            return errinput!("column {} already exists in table {}", column.name, table.name);
+        }
+        if column.id < table.next_column_id() {
+            return errinput!("column ID {} already used in table {}", column.id, table.name);
+        }
+        if column.default.is_none() && self.scan(&table.name, None)?.next().is_some() {
+            return errinput!(
+                "column {} must have a default value, since table has rows",
+                column.name
+            );
+        }
+        column.default = column.default.map(|v| v.normalize());
+
+        // Add the column in a new schema version. Existing rows are upgraded
+        // with the default value when read.
+        table.layouts.push(table.columns.iter().map(|c| c.id).collect());
+        table.version += 1;
+        table.columns.push(column);
+        self.set_table(&table)?;
+
+        // Build the secondary index, and validate the existing rows against
+        // the column's constraints (e.g. uniqueness and references).
+        let i = table.columns.len() - 1;
+        if table.columns[i].index {
+            self.build_index(&table, &[i], table.columns[i].unique, None, usize::MAX)?;
+        }
+        for row in self.scan(&table.name, None)? {
+            table.validate_row(&row?, true, self)?;
+        }
+        Ok(())
+    }
+
+    fn drop_column(&self, table: &str, column: &str) -> Result<()> {
+        let mut table = self.must_get_table(table)?;
+        let Some(i) = table.columns.iter().position(|c| c.name == column) else {
+            return errinput!("unknown column {column} in table {}", table.name);
+        };
+        if table.primary_key.contains(&i) {
+            return errinput!("can't drop primary key column {column}");
+        }
+
+        // Delete any secondary indexes containing the column.
+        for columns in table.secondary_indexes().iter().filter(|c| c.contains(&i)) {
+            self.delete_index(&table, columns)?;
+        }
+
+        // Drop the column in a new schema version. Existing rows skip the
+        // column value when read.
+        table.layouts.push(table.columns.iter().map(|c| c.id).collect());
+        table.version += 1;
+        let column = table.columns.remove(i);
+        for key in table.primary_key.iter_mut().filter(|key| **key > i) {
+            *key -= 1;
+        }
+        table.indexes.retain(|index| !index.columns.contains(&column.id));
+        self.set_table(&table)
+    }
+
+    fn rename_column(&self, table: &str, column: &str, new_name: &str) -> Result<()> {
+        let mut table = self.must_get_table(table)?;
+        let Some(i) = table.columns.iter().position(|c| c.name == column) else {
+            return errinput!("unknown column {column} in table {}", table.name);
+        };
+        if table.columns.iter().any(|c| c.name == new_name) {
+            return errinput!("column {new_name} already exists in table {}", table.name);
+        }
+        table.columns[i].name = new_name.to_string();
+        self.set_table(&table)
+    }
+
+    fn rename_table(&self, table: &str, new_name: &str) -> Result<()> {
+        let mut table = self.must_get_table(table)?;
+        if self.get_table(new_name)?.is_some() {
+            return errinput!("table {new_name} already exists");
+        }
+        let old_name = std::mem::replace(&mut table.name, new_name.to_string());
+
+        // Store the renamed schema, and update references to it, including
+        // any self-references.
+        let references = self.table_references(&old_name)?;
+        for column in &mut table.columns {
+            if column.references.as_ref() == Some(&old_name) {
+                column.references = Some(new_name.to_string());
             }
         }
+        self.txn.delete(&Key::Table((&old_name).into()).encode())?;
+        self.set_table(&table)?;
+        for (mut source, refs) in references.into_iter().filter(|(t, _)| t.name != old_name) {
+            for i in refs {
+                source.columns[i].references = Some(new_name.to_string());
+            }
+            self.set_table(&source)?;
+        }
+        Ok(())
+    }
+
+    fn set_column_default(&self, table: &str, column: &str, default: Option<Value>) -> Result<()> {
+        let mut table = self.must_get_table(table)?;
+        let Some(i) = table.columns.iter().position(|c| c.name == column) else {
+            return errinput!("unknown column {column} in table {}", table.name);
+        };
 
+        // Rows written before the column was added read the default value, so
+        // upgrade them first to retain the current default.
+        let id = table.columns[i].id;
+        if table.layouts.iter().any(|layout| !layout.contains(&id)) {
+            self.upgrade_rows(&table)?;
+        }
+
+        let column = &mut table.columns[i];
+        column.default = match default {
+            Some(value) => Some(value.normalize()),
+            None if column.nullable => Some(Value::Null),
+            None => None,
+        };
+        self.set_table(&table)
+    }
+
+    fn set_column_nullable(&self, table: &str, column: &str, nullable: bool) -> Result<()> {
+        let mut table = self.must_get_table(table)?;
+        let Some(i) = table.columns.iter().position(|c| c.name == column) else {
+            return errinput!("unknown column {column} in table {}", table.name);
+        };
+
+        if !nullable {
+            let mut rows = self.scan(&table.name, None)?;
+            while let Some(row) = rows.next().transpose()? {
+                if row[i] == Value::Null {
+                    let id = format_tuple(&table.row_id(&row));
+                    return errinput!("column {column} has NULL value in row {id}");
+                }
+            }
+        }
+
+        let column = &mut table.columns[i];
+        column.nullable = nullable;
+        match (&column.default, nullable) {
+            (None, true) => column.default = Some(Value::Null),
+            (Some(Value::Null), false) => column.default = None,
+            _ => {}
+        }
+        self.set_table(&table)
+    }
+
+    fn create_index(&self, table: &str, mut index: Index) -> Result<()> {
+        let mut table = self.must_get_table(table)?;
+        // If an identical write-only index exists, e.g. because a backfill was
+        // interrupted, keep it such that the caller resumes the backfill.
+        if table.indexes.iter().any(|i| {
+            i.write_only
+                && i.name == index.name
+                && i.columns == index.columns
+                && i.unique == index.unique
+        }) {
+            return Ok(());
+        }
+        if self.list_tables()?.iter().flat_map(|t| &t.indexes).any(|i| i.name == index.name) {
+            return errinput!("index {} already exists", index.name);
+        }
+        let mut columns = Vec::with_capacity(index.columns.len());
+        for id in &index.columns {
+            let Some(i) = table.columns.iter().position(|c| c.id == *id) else {
+                return errinput!("unknown column ID {id} in table {}", table.name);
+            };
+            columns.push(i);
+        }
+
+        // Single-column indexes use the column's secondary index, while
+        // multi-column indexes are separate.
+        if let [i] = columns[..] {
+            if table.primary_key == [i] {
+                return errinput!("can't index primary key column {}", table.columns[i].name);
+            }
+            let column = &mut table.columns[i];
+            if column.index {
+                return errinput!("column {} is already indexed", column.name);
+            }
+            column.index = true;
+            column.unique = index.unique;
+        } else if table.indexes.iter().any(|x| x.columns == index.columns) {
+            let names = format_tuple(&table.column_names(&columns));
+            return errinput!("columns {names} are already indexed");
+        }
+        index.write_only = true;
+        table.indexes.push(index);
+        self.set_table(&table)
+    }
+
+    fn backfill_index(
+        &self,
+        index: &str,
+        after: Option<Vec<Value>>,
+        limit: usize,
+    ) -> Result<Option<Vec<Value>>> {
+        let Some(mut table) =
+            self.list_tables()?.into_iter().find(|t| t.indexes.iter().any(|i| i.name == index))
+        else {
+            return errinput!("index {index} does not exist");
+        };
+        let position = table.indexes.iter().position(|i| i.name == index).expect("no index");
+        let Index { ref columns, unique, write_only, .. } = table.indexes[position];
+        if !write_only {
+            return errinput!("index {index} has already been backfilled");
+        }
+        if limit == 0 {
+            return errinput!("backfill limit must be positive");
+        }
+        let columns = table.index_columns(columns);
+        let next = self.build_index(&table, &columns, unique, after.as_deref(), limit)?;
+        if next.is_none() {
+            table.indexes[position].write_only = false;
+            self.set_table(&table)?;
+        }
+        Ok(next)
+    }
+
+    fn drop_index(&self, index: &str, if_exists: bool) -> Result<bool> {
+        let Some(mut table) =
+            self.list_tables()?.into_iter().find(|t| t.indexes.iter().any(|i| i.name == index))
+        else {
+            if if_exists {
+                return Ok(false);
+            }
+            return errinput!("index {index} does not exist");
+        };
+        let position = table.indexes.iter().position(|i| i.name == index).expect("no index");
+        let index = table.indexes.remove(position);
+        let columns = table.index_columns(&index.columns);
+        if let [i] = columns[..] {
+            table.columns[i].index = false;
+            table.columns[i].unique = false;
+        }
+        self.set_table(&table)?;
+        self.delete_index(&table, &columns)?;
         Ok(true)
     }
 
@@ -402,20 +1072,27 @@ impl<E: storage::Engine> Catalog for Transaction<E> {
     }
 }
 
-/// SQL engine keys, using the KeyCode order-preserving encoding. For
-/// simplicity, table and column names are used directly as identifiers in
-/// keys, instead of e.g. numberic IDs. It is not possible to change
-/// table/column names, so this is fine.
+/// SQL engine keys, using the KeyCode order-preserving encoding. Table schemas
+/// are keyed by table name, while rows and index entries are keyed by the
+/// table's ID and the index column IDs. These never change, so renaming a
+/// table or column only has to update its schema.
+///
+/// Index and row values are the last key component, encoded as the plain
+/// concatenation of the values (see keycode::trailing). A single value thus
+/// has the same encoding as in the original format, and an index or row key
+/// is also a prefix of all keys whose values start with the key's values.
 ///
 /// Uses Cow to allow encoding borrowed values but decoding owned values.
 #[derive(Debug, Deserialize, Serialize)]
 pub enum Key<'a> {
     /// A table schema by table name.
     Table(Cow<'a, str>),
-    /// An index entry, by table name, index name, and index value.
-    Index(Cow<'a, str>, Cow<'a, str>, Cow<'a, Value>),
-    /// A table row, by table name and primary key value.
-    Row(Cow<'a, str>, Cow<'a, Value>),
+    /// An index entry, by table ID, index column IDs, and index values.
+    Index(TableId, Cow<'a, [ColumnId]>, #[serde(with = "keycode::trailing")] Cow<'a, [Value]>),
+    /// A table row, by table ID and primary key values.
+    Row(TableId, #[serde(with = "keycode::trailing")] Cow<'a, [Value]>),
+    /// The next table ID to assign when creating a table.
+    NextTableId,
 }
 
 impl<'a> encoding::Key<'a> for Key<'a> {}
@@ -426,10 +1103,97 @@ impl<'a> encoding::Key<'a> for Key<'a> {}
 enum KeyPrefix<'a> {
     /// All table schemas.
     Table,
-    /// An entire table index, by table and index name.
-    Index(Cow<'a, str>, Cow<'a, str>),
-    /// An entire table's rows, by table name.
-    Row(Cow<'a, str>),
+    /// An entire table index, by table ID and index column IDs.
+    Index(TableId, Cow<'a, [ColumnId]>),
+    /// An entire table's rows, by table ID.
+    Row(TableId),
 }
 
 impl<'a> encoding::Key<'a> for KeyPrefix<'a> {}
+
+/// A row as stored, along with the table schema version it was written with.
+/// Reads upgrade it to the current schema version, see Table::upgrade_row().
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StoredRow<'a> {
+    /// The table schema version the row was written with.
+    pub version: u32,
+    /// The row values, in column order as of the schema version.
+    pub values: Cow<'a, [Value]>,
+}
+
+impl<'a> StoredRow<'a> {
+    /// Creates a stored row with the table's current schema version.
+    pub fn new(table: &Table, values: &'a [Value]) -> Self {
+        Self { version: table.version, values: values.into() }
+    }
+
+    /// Encodes the row using Bincode.
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self)
+    }
+
+    /// Decodes a row using Bincode, without upgrading it.
+    pub fn decode(bytes: &[u8]) -> Result<StoredRow<'static>> {
+        bincode::deserialize(bytes)
+    }
+
+    /// Decodes a row and upgrades it to the table's current schema version.
+    pub fn decode_upgrade(table: &Table, bytes: &[u8]) -> Result<Row> {
+        let row = StoredRow::decode(bytes)?;
+        table.upgrade_row(row.version, row.values.into_owned())
+    }
+}
+
+/// Encodes a range of row or index values as a key range, or None if the range
+/// is empty. Uses the given function to encode value prefixes as key prefixes
+/// (see Key), and the key prefix of the entire row or index keyspace for
+/// unbounded ranges.
+fn encode_range(
+    (start, end): TupleRange,
+    prefix: &[u8],
+    encode: impl Fn(&[Value]) -> Vec<u8>,
+) -> Option<impl RangeBounds<Vec<u8>>> {
+    // Returns the first key after all keys with the given prefix. This is used
+    // both to exclude a start prefix and to include an end prefix.
+    let prefix_end = |prefix: &[u8]| match encoding::prefix_range(prefix).1 {
+        Bound::Excluded(end) => end,
+        _ => panic!("unbounded key prefix"), // key prefixes never end with 0xff
+    };
+    let (keyspace_start, keyspace_end) = encoding::prefix_range(prefix);
+    let start = match start {
+        Bound::Included(values) => Bound::Included(encode(&normalize(&values))),
+        Bound::Excluded(values) => Bound::Included(prefix_end(&encode(&normalize(&values)))),
+        Bound::Unbounded => keyspace_start,
+    };
+    let end = match end {
+        Bound::Included(values) => Bound::Excluded(prefix_end(&encode(&normalize(&values)))),
+        Bound::Excluded(values) => Bound::Excluded(encode(&normalize(&values))),
+        Bound::Unbounded => keyspace_end,
+    };
+    // Storage scans panic on inverted ranges, so check for empty ranges.
+    let empty = match (&start, &end) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start), Bound::Excluded(end)) => start >= end,
+        _ => false,
+    };
+    (!empty).then_some((start, end))
+}
+
+/// Normalizes a tuple of values, e.g. a primary key, only cloning it if any of
+/// the values need normalization.
+fn normalize(values: &[Value]) -> Cow<'_, [Value]> {
+    if values.iter().all(|v| v.is_normalized()) {
+        return Cow::Borrowed(values);
+    }
+    Cow::Owned(values.iter().map(|v| v.clone().normalize()).collect())
+}
+
+/// Returns true if the table has a secondary index on the given column IDs.
+fn has_index(table: &Table, columns: &[ColumnId]) -> bool {
+    table.secondary_indexes().iter().any(|c| table.column_ids(c) == columns)
+}
+
+/// Returns the row values of the given column indexes, e.g. index values.
+fn project(row: &[Value], columns: &[usize]) -> Vec<Value> {
+    columns.iter().map(|i| row[*i].clone()).collect()
+}
@@ -2,8 +2,9 @@ mod engine;
 mod local;
 mod raft;
 mod session;
+mod upgrade;
 
-pub use engine::{Catalog, Engine, Transaction};
-pub use local::{Key, Local};
+pub use engine::{Catalog, Change, Changes, Engine, Transaction};
+pub use local::{Key, Local, StoredRow, FORMAT_KEY, FORMAT_VERSION};
 pub use raft::{Raft, Status, Write};
 pub use session::{Session, StatementResult};
@@ -1,9 +1,9 @@
-use super::{Catalog, Engine as _, Transaction as _};
+use super::{Catalog, Changes, Engine as _, Transaction as _};
 use crate::encoding::{self, bincode, Value as _};
 use crate::errdata;
 use crate::error::Result;
 use crate::raft;
-use crate::sql::types::{Expression, Row, Rows, Table, Value};
+use crate::sql::types::{Column, Expression, Index, Row, Rows, Table, TupleRange, Value};
 use crate::storage::{self, mvcc};
 
 use crossbeam::channel::Sender;
@@ -81,6 +81,16 @@ impl Raft {
         let mvcc = self.read(Read::Status)?;
         Ok(Status { raft, mvcc })
     }
+
+    /// Writes a consistent backup of the local node's Raft log and SQL storage
+    /// to the given directory, which must not exist. Returns the Raft applied
+    /// index of the backup. See `toydb restore` for restoring it.
+    pub fn backup(&self, dir: std::path::PathBuf) -> Result<raft::Index> {
+        match self.execute(raft::Request::Backup(dir))? {
+            raft::Response::Backup(applied_index) => Ok(applied_index),
+            resp => errdata!("unexpected Raft backup response {resp:?}"),
+        }
+    }
 }
 
 impl<'a> super::Engine<'a> for Raft {
@@ -97,6 +107,10 @@ impl<'a> super::Engine<'a> for Raft {
     fn begin_as_of(&'a self, version: mvcc::Version) -> Result<Self::Transaction> {
         Transaction::begin(self, true, Some(version))
     }
+
+    fn changes(&'a self, from: mvcc::ChangeCursor, limit: usize) -> Result<Changes> {
+        self.read(Read::Changes { from, limit })
+    }
 }
 
 /// A Raft SQL engine transaction.
@@ -139,6 +153,10 @@ impl<'a> super::Transaction for Transaction<'a> {
         self.state.read_only
     }
 
+    fn state(&self) -> &mvcc::TransactionState {
+        &self.state
+    }
+
     fn commit(self) -> Result<()> {
         if self.state.read_only {
             return Ok(()); // noop
@@ -153,7 +171,7 @@ impl<'a> super::Transaction for Transaction<'a> {
         self.engine.write(Write::Rollback(self.state.into()))
     }
 
-    fn delete(&self, table: &str, ids: &[Value]) -> Result<()> {
+    fn delete(&self, table: &str, ids: &[Vec<Value>]) -> Result<()> {
         self.engine.write(Write::Delete {
             txn: (&self.state).into(),
             table: table.into(),
@@ -161,7 +179,7 @@ impl<'a> super::Transaction for Transaction<'a> {
         })
     }
 
-    fn get(&self, table: &str, ids: &[Value]) -> Result<Vec<Row>> {
+    fn get(&self, table: &str, ids: &[Vec<Value>]) -> Result<Vec<Row>> {
         self.engine.read(Read::Get {
             txn: (&self.state).into(),
             table: table.into(),
@@ -173,15 +191,52 @@ impl<'a> super::Transaction for Transaction<'a> {
         self.engine.write(Write::Insert { txn: (&self.state).into(), table: table.into(), rows })
     }
 
-    fn lookup_index(&self, table: &str, column: &str, values: &[Value]) -> Result<BTreeSet<Value>> {
+    fn lock(
+        &self,
+        table: &str,
+        ids: &[Vec<Value>],
+        mode: mvcc::LockMode,
+        skip_locked: bool,
+    ) -> Result<Vec<Vec<Value>>> {
+        self.engine.write(Write::Lock {
+            txn: (&self.state).into(),
+            table: table.into(),
+            ids: ids.into(),
+            mode,
+            skip_locked,
+        })
+    }
+
+    fn lookup_index(
+        &self,
+        table: &str,
+        columns: &[String],
+        values: &[Vec<Value>],
+    ) -> Result<BTreeSet<Vec<Value>>> {
         self.engine.read(Read::LookupIndex {
             txn: (&self.state).into(),
             table: table.into(),
-            column: column.into(),
+            columns: columns.into(),
             values: values.into(),
         })
     }
 
+    fn lookup_index_range(
+        &self,
+        table: &str,
+        columns: &[String],
+        range: TupleRange,
+        reverse: bool,
+    ) -> Result<Vec<Vec<Value>>> {
+        self.engine.read(Read::LookupIndexRange {
+            txn: (&self.state).into(),
+            table: table.into(),
+            columns: columns.into(),
+            range,
+            reverse,
+        })
+    }
+
     fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Rows> {
         let scan: Vec<_> = self.engine.read(Read::Scan {
             txn: (&self.state).into(),
@@ -191,7 +246,17 @@ impl<'a> super::Transaction for Transaction<'a> {
         Ok(Box::new(scan.into_iter().map(Ok)))
     }
 
-    fn update(&self, table: &str, rows: BTreeMap<Value, Row>) -> Result<()> {
+    fn scan_range(&self, table: &str, range: TupleRange, reverse: bool) -> Result<Rows> {
+        let scan: Vec<_> = self.engine.read(Read::ScanRange {
+            txn: (&self.state).into(),
+            table: table.into(),
+            range,
+            reverse,
+        })?;
+        Ok(Box::new(scan.into_iter().map(Ok)))
+    }
+
+    fn update(&self, table: &str, rows: BTreeMap<Vec<Value>, Row>) -> Result<()> {
         self.engine.write(Write::Update { txn: (&self.state).into(), table: table.into(), rows })
     }
 }
@@ -209,6 +274,87 @@ impl<'a> Catalog for Transaction<'a> {
         })
     }
 
+    fn add_column(&self, table: &str, column: Column) -> Result<()> {
+        self.engine.write(Write::AddColumn {
+            txn: (&self.state).into(),
+            table: table.into(),
+            column,
+        })
+    }
+
+    fn drop_column(&self, table: &str, column: &str) -> Result<()> {
+        self.engine.write(Write::DropColumn {
+            txn: (&self.state).into(),
+            table: table.into(),
+            column: column.into(),
+        })
+    }
+
+    fn rename_column(&self, table: &str, column: &str, new_name: &str) -> Result<()> {
+        self.engine.write(Write::RenameColumn {
+            txn: (&self.state).into(),
+            table: table.into(),
+            column: column.into(),
+            new_name: new_name.into(),
+        })
+    }
+
+    fn rename_table(&self, table: &str, new_name: &str) -> Result<()> {
+        self.engine.write(Write::RenameTable {
+            txn: (&self.state).into(),
+            table: table.into(),
+            new_name: new_name.into(),
+        })
+    }
+
+    fn set_column_default(&self, table: &str, column: &str, default: Option<Value>) -> Result<()> {
+        self.engine.write(Write::SetColumnDefault {
+            txn: (&self.state).into(),
+            table: table.into(),
+            column: column.into(),
+            default,
+        })
+    }
+
+    fn set_column_nullable(&self, table: &str, column: &str, nullable: bool) -> Result<()> {
+        self.engine.write(Write::SetColumnNullable {
+            txn: (&self.state).into(),
+            table: table.into(),
+            column: column.into(),
+            nullable,
+        })
+    }
+
+    fn create_index(&self, table: &str, index: Index) -> Result<()> {
+        self.engine.write(Write::CreateIndex {
+            txn: (&self.state).into(),
+            table: table.into(),
+            index,
+        })
+    }
+
+    fn backfill_index(
+        &self,
+        index: &str,
+        after: Option<Vec<Value>>,
+        limit: usize,
+    ) -> Result<Option<Vec<Value>>> {
+        self.engine.write(Write::BackfillIndex {
+            txn: (&self.state).into(),
+            index: index.into(),
+            after,
+            limit,
+        })
+    }
+
+    fn drop_index(&self, index: &str, if_exists: bool) -> Result<bool> {
+        self.engine.write(Write::DropIndex {
+            txn: (&self.state).into(),
+            index: index.into(),
+            if_exists,
+        })
+    }
+
     fn get_table(&self, table: &str) -> Result<Option<Table>> {
         self.engine.read(Read::GetTable { txn: (&self.state).into(), table: table.into() })
     }
@@ -238,7 +384,7 @@ impl<E: storage::Engine> State<E> {
     /// Creates a new Raft state maching using the given storage engine for
     /// local storage.
     pub fn new(engine: E) -> Result<Self> {
-        let local = super::Local::new(engine);
+        let local = super::Local::new(engine)?;
         let applied_index = local
             .get_unversioned(Raft::APPLIED_INDEX_KEY)?
             .map(|b| bincode::deserialize(&b))
@@ -247,6 +393,13 @@ impl<E: storage::Engine> State<E> {
         Ok(State { local, applied_index })
     }
 
+    /// Enables or disables the change feed of committed row writes. It
+    /// should be set the same on all nodes, since any node can become leader
+    /// and serve the feed. Disabled by default.
+    pub fn set_change_feed(&mut self, enabled: bool) {
+        self.local.set_change_feed(enabled)
+    }
+
     /// Executes a write command.
     fn write(&self, command: Write) -> Result<Vec<u8>> {
         Ok(match command {
@@ -267,6 +420,9 @@ impl<E: storage::Engine> State<E> {
             Write::Update { txn, table, rows } => {
                 bincode::serialize(&self.local.resume(txn.into_owned())?.update(&table, rows)?)
             }
+            Write::Lock { txn, table, ids, mode, skip_locked } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.lock(&table, &ids, mode, skip_locked)?,
+            ),
 
             Write::CreateTable { txn, schema } => {
                 bincode::serialize(&self.local.resume(txn.into_owned())?.create_table(schema)?)
@@ -274,6 +430,39 @@ impl<E: storage::Engine> State<E> {
             Write::DropTable { txn, table, if_exists } => bincode::serialize(
                 &self.local.resume(txn.into_owned())?.drop_table(&table, if_exists)?,
             ),
+            Write::AddColumn { txn, table, column } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.add_column(&table, column)?,
+            ),
+            Write::DropColumn { txn, table, column } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.drop_column(&table, &column)?,
+            ),
+            Write::RenameColumn { txn, table, column, new_name } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.rename_column(&table, &column, &new_name)?,
+            ),
+            Write::RenameTable { txn, table, new_name } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.rename_table(&table, &new_name)?,
+            ),
+            Write::SetColumnDefault { txn, table, column, default } => bincode::serialize(
+                &self
+                    .local
+                    .resume(txn.into_owned())?
+                    .set_column_default(&table, &column, default)?,
+            ),
+            Write::SetColumnNullable { txn, table, column, nullable } => bincode::serialize(
+                &self
+                    .local
+                    .resume(txn.into_owned())?
+                    .set_column_nullable(&table, &column, nullable)?,
+            ),
+            Write::CreateIndex { txn, table, index } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.create_index(&table, index)?,
+            ),
+            Write::BackfillIndex { txn, index, after, limit } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.backfill_index(&index, after, limit)?,
+            ),
+            Write::DropIndex { txn, index, if_exists } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.drop_index(&index, if_exists)?,
+            ),
         })
     }
 }
@@ -301,8 +490,14 @@ impl<E: storage::Engine> raft::State for State<E> {
         // Persist the applied index. We don't have to flush, because it's ok to
         // lose a tail of the state machine writes (e.g. if the machine
         // crashes). Raft will replay the log from the last known applied index.
+        // Failing to persist it is non-deterministic, so panic: returning an
+        // error would tell the client that an applied write failed.
+        if let Err(e) =
+            self.local.set_unversioned(Raft::APPLIED_INDEX_KEY, bincode::serialize(&entry.index))
+        {
+            panic!("non-deterministic apply failure: {e}")
+        }
         self.applied_index = entry.index;
-        self.local.set_unversioned(Raft::APPLIED_INDEX_KEY, bincode::serialize(&entry.index))?;
         result
     }
 
@@ -316,14 +511,20 @@ impl<E: storage::Engine> raft::State for State<E> {
                 txn.state().encode()
             }
             Read::Status => self.local.mvcc.status()?.encode(),
+            Read::Changes { from, limit } => self.local.changes(from, limit)?.encode(),
 
             Read::Get { txn, table, ids } => {
                 self.local.resume(txn.into_owned())?.get(&table, &ids)?.encode()
             }
-            Read::LookupIndex { txn, table, column, values } => self
+            Read::LookupIndex { txn, table, columns, values } => self
+                .local
+                .resume(txn.into_owned())?
+                .lookup_index(&table, &columns, &values)?
+                .encode(),
+            Read::LookupIndexRange { txn, table, columns, range, reverse } => self
                 .local
                 .resume(txn.into_owned())?
-                .lookup_index(&table, &column, &values)?
+                .lookup_index_range(&table, &columns, range, reverse)?
                 .encode(),
             Read::Scan { txn, table, filter } => {
                 // For simplicity, buffer the entire scan. See `State` comment.
@@ -333,6 +534,14 @@ impl<E: storage::Engine> raft::State for State<E> {
                     .collect::<Result<Vec<_>>>()?
                     .encode()
             }
+            Read::ScanRange { txn, table, range, reverse } => {
+                // For simplicity, buffer the entire scan. See `State` comment.
+                self.local
+                    .resume(txn.into_owned())?
+                    .scan_range(&table, range, reverse)?
+                    .collect::<Result<Vec<_>>>()?
+                    .encode()
+            }
             Read::GetTable { txn, table } => {
                 self.local.resume(txn.into_owned())?.get_table(&table)?.encode()
             }
@@ -341,6 +550,12 @@ impl<E: storage::Engine> raft::State for State<E> {
             }
         })
     }
+
+    fn backup(&self) -> Result<storage::Backup> {
+        // The engine snapshot includes the applied index, which is stored as
+        // an unversioned key, and any in-flight transactions.
+        self.local.mvcc.engine.lock()?.backup()
+    }
 }
 
 /// A Raft engine read. Values correspond to engine method parameters. Uses
@@ -351,23 +566,40 @@ pub enum Read<'a> {
         as_of: Option<mvcc::Version>,
     },
     Status,
+    Changes {
+        from: mvcc::ChangeCursor,
+        limit: usize,
+    },
 
     Get {
         txn: Cow<'a, mvcc::TransactionState>,
         table: Cow<'a, str>,
-        ids: Cow<'a, [Value]>,
+        ids: Cow<'a, [Vec<Value>]>,
     },
     LookupIndex {
         txn: Cow<'a, mvcc::TransactionState>,
         table: Cow<'a, str>,
-        column: Cow<'a, str>,
-        values: Cow<'a, [Value]>,
+        columns: Cow<'a, [String]>,
+        values: Cow<'a, [Vec<Value>]>,
+    },
+    LookupIndexRange {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        columns: Cow<'a, [String]>,
+        range: TupleRange,
+        reverse: bool,
     },
     Scan {
         txn: Cow<'a, mvcc::TransactionState>,
         table: Cow<'a, str>,
         filter: Option<Expression>,
     },
+    ScanRange {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        range: TupleRange,
+        reverse: bool,
+    },
 
     GetTable {
         txn: Cow<'a, mvcc::TransactionState>,
@@ -388,12 +620,87 @@ pub enum Write<'a> {
     Commit(Cow<'a, mvcc::TransactionState>),
     Rollback(Cow<'a, mvcc::TransactionState>),
 
-    Delete { txn: Cow<'a, mvcc::TransactionState>, table: Cow<'a, str>, ids: Cow<'a, [Value]> },
-    Insert { txn: Cow<'a, mvcc::TransactionState>, table: Cow<'a, str>, rows: Vec<Row> },
-    Update { txn: Cow<'a, mvcc::TransactionState>, table: Cow<'a, str>, rows: BTreeMap<Value, Row> },
+    Delete {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        ids: Cow<'a, [Vec<Value>]>,
+    },
+    Insert {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        rows: Vec<Row>,
+    },
+    Update {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        rows: BTreeMap<Vec<Value>, Row>,
+    },
+    Lock {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        ids: Cow<'a, [Vec<Value>]>,
+        mode: mvcc::LockMode,
+        skip_locked: bool,
+    },
 
-    CreateTable { txn: Cow<'a, mvcc::TransactionState>, schema: Table },
-    DropTable { txn: Cow<'a, mvcc::TransactionState>, table: Cow<'a, str>, if_exists: bool },
+    CreateTable {
+        txn: Cow<'a, mvcc::TransactionState>,
+        schema: Table,
+    },
+    DropTable {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        if_exists: bool,
+    },
+    AddColumn {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        column: Column,
+    },
+    DropColumn {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        column: Cow<'a, str>,
+    },
+    RenameColumn {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        column: Cow<'a, str>,
+        new_name: Cow<'a, str>,
+    },
+    RenameTable {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        new_name: Cow<'a, str>,
+    },
+    SetColumnDefault {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        column: Cow<'a, str>,
+        default: Option<Value>,
+    },
+    SetColumnNullable {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        column: Cow<'a, str>,
+        nullable: bool,
+    },
+    CreateIndex {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        index: Index,
+    },
+    BackfillIndex {
+        txn: Cow<'a, mvcc::TransactionState>,
+        index: Cow<'a, str>,
+        after: Option<Vec<Value>>,
+        limit: usize,
+    },
+    DropIndex {
+        txn: Cow<'a, mvcc::TransactionState>,
+        index: Cow<'a, str>,
+        if_exists: bool,
+    },
 }
 
 impl<'a> encoding::Value for Write<'a> {}
@@ -404,3 +711,219 @@ pub struct Status {
     pub raft: raft::Status,
     pub mvcc: mvcc::Status,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raft::State as _;
+    use crate::sql::types::{Column, DataType};
+    use crate::storage::engine::test::Faulty;
+    use crate::storage::Engine as _;
+
+    use itertools::Itertools as _;
+    use std::error::Error;
+    use std::fmt::Write as _;
+    use std::result::Result;
+    use test_each_file::test_each_path;
+
+    // Run goldenscript tests in src/sql/engine/testscripts/raft.
+    test_each_path! { in "src/sql/engine/testscripts/raft" as scripts => test_goldenscript }
+
+    fn test_goldenscript(path: &std::path::Path) {
+        goldenscript::run(&mut TestRunner::new(), path).expect("goldenscript failed")
+    }
+
+    /// Runs Raft SQL state machine tests. Applies write commands as Raft log
+    /// entries, and replays them from the applied index after a crash.
+    struct TestRunner {
+        state: State<Faulty<storage::Memory>>,
+        faulty: Faulty<storage::Memory>,
+        /// The Raft log, i.e. all entries submitted for application.
+        log: Vec<raft::Entry>,
+        /// Transactions begun via apply, by version.
+        txns: BTreeMap<mvcc::Version, mvcc::TransactionState>,
+    }
+
+    impl goldenscript::Runner for TestRunner {
+        fn run(&mut self, command: &goldenscript::Command) -> Result<String, Box<dyn Error>> {
+            let mut output = String::new();
+            match command.name.as_str() {
+                // apply WRITE [ARGS...]
+                //
+                // Appends a write command to the log and applies it:
+                //
+                // - begin
+                // - commit VERSION
+                // - rollback VERSION
+                // - create_table VERSION TABLE
+                // - insert VERSION TABLE ID=VALUE...
+                // - rename_table VERSION TABLE NEW_NAME
+                // - noop
+                "apply" => {
+                    let mut args = command.consume_args();
+                    let write = args.next_pos().ok_or("write not given")?.value.clone();
+                    let command = match write.as_str() {
+                        "noop" => None,
+                        "begin" => Some(Write::Begin.encode()),
+                        "commit" | "rollback" => {
+                            let txn = self.txn(args.next_pos().ok_or("version not given")?)?;
+                            Some(match write.as_str() {
+                                "commit" => Write::Commit(Cow::Owned(txn)).encode(),
+                                _ => Write::Rollback(Cow::Owned(txn)).encode(),
+                            })
+                        }
+                        "create_table" => {
+                            let txn = self.txn(args.next_pos().ok_or("version not given")?)?;
+                            let name = args.next_pos().ok_or("table not given")?.value.clone();
+                            let schema = Self::schema(name);
+                            Some(Write::CreateTable { txn: Cow::Owned(txn), schema }.encode())
+                        }
+                        "insert" => {
+                            let txn = self.txn(args.next_pos().ok_or("version not given")?)?;
+                            let table = args.next_pos().ok_or("table not given")?.value.clone();
+                            let mut rows = Vec::new();
+                            for arg in args.rest_key() {
+                                let id = arg.key.as_deref().unwrap().parse()?;
+                                let value = arg.value.clone();
+                                rows.push(vec![Value::Integer(id), Value::String(value)]);
+                            }
+                            Some(
+                                Write::Insert { txn: Cow::Owned(txn), table: table.into(), rows }
+                                    .encode(),
+                            )
+                        }
+                        "rename_table" => {
+                            let txn = self.txn(args.next_pos().ok_or("version not given")?)?;
+                            let table = args.next_pos().ok_or("table not given")?.value.clone();
+                            let new_name = args.next_pos().ok_or("name not given")?.value.clone();
+                            let write = Write::RenameTable {
+                                txn: Cow::Owned(txn),
+                                table: table.into(),
+                                new_name: new_name.into(),
+                            };
+                            Some(write.encode())
+                        }
+                        write => return Err(format!("unknown write {write}").into()),
+                    };
+                    args.reject_rest()?;
+
+                    let index = self.log.len() as raft::Index + 1;
+                    let entry = raft::Entry { index, term: 1, command };
+                    self.log.push(entry.clone());
+                    let result = self.state.apply(entry)?;
+                    if write == "begin" {
+                        let txn = mvcc::TransactionState::decode(&result)?;
+                        writeln!(output, "{index} → begin v{}", txn.version)?;
+                        self.txns.insert(txn.version, txn);
+                    } else {
+                        writeln!(output, "{index} → {write}")?;
+                    }
+                }
+
+                // crash
+                // Simulates a crash, dropping unflushed writes, and reopens the
+                // state machine.
+                "crash" => {
+                    self.faulty.run(command)?;
+                    self.state = State::new(self.faulty.clone())?;
+                }
+
+                // corrupt KEY | fail [OP...]
+                // Injects storage faults, see storage::engine::test::Faulty.
+                "corrupt" | "fail" => self.faulty.run(command)?,
+
+                // flush
+                "flush" => {
+                    command.consume_args().reject_rest()?;
+                    self.faulty.flush()?;
+                }
+
+                // replay
+                // Replays log entries after the applied index, like Raft does
+                // when a node restarts.
+                "replay" => {
+                    command.consume_args().reject_rest()?;
+                    let applied_index = self.state.get_applied_index();
+                    for entry in self.log[applied_index as usize..].iter().cloned() {
+                        let index = entry.index;
+                        match self.state.apply(entry) {
+                            Ok(_) => writeln!(output, "replay {index} → ok")?,
+                            Err(e) => writeln!(output, "replay {index} → Error: {e}")?,
+                        }
+                    }
+                }
+
+                // scan TABLE
+                // Scans a table with a new read-only transaction.
+                "scan" => {
+                    let mut args = command.consume_args();
+                    let table = args.next_pos().ok_or("table not given")?.value.clone();
+                    args.reject_rest()?;
+                    let txn = mvcc::TransactionState::decode(
+                        &self.state.read(Read::BeginReadOnly { as_of: None }.encode())?,
+                    )?;
+                    let scan =
+                        Read::Scan { txn: Cow::Owned(txn), table: table.into(), filter: None };
+                    let rows = Vec::<Row>::decode(&self.state.read(scan.encode())?)?;
+                    for row in rows {
+                        writeln!(output, "{}", row.iter().join(", "))?;
+                    }
+                }
+
+                // status
+                // Shows the applied index.
+                "status" => {
+                    command.consume_args().reject_rest()?;
+                    writeln!(output, "applied_index={}", self.state.get_applied_index())?;
+                }
+
+                name => return Err(format!("unknown command {name}").into()),
+            }
+            Ok(output)
+        }
+    }
+
+    impl TestRunner {
+        fn new() -> Self {
+            let faulty = Faulty::new(storage::Memory::new());
+            let state = State::new(faulty.clone()).expect("state failed");
+            Self { state, faulty, log: Vec::new(), txns: BTreeMap::new() }
+        }
+
+        /// Looks up a transaction begun via apply.
+        fn txn(
+            &self,
+            arg: &goldenscript::Argument,
+        ) -> Result<mvcc::TransactionState, Box<dyn Error>> {
+            let version = arg.parse()?;
+            Ok(self.txns.get(&version).ok_or(format!("unknown transaction {version}"))?.clone())
+        }
+
+        /// Returns a test table schema with an integer id primary key and a
+        /// string value column.
+        fn schema(name: String) -> Table {
+            let column = |id, name: &str, datatype| Column {
+                id,
+                name: name.to_string(),
+                datatype,
+                nullable: false,
+                default: None,
+                unique: false,
+                index: false,
+                references: None,
+            };
+            Table {
+                id: 0,
+                name,
+                primary_key: vec![0],
+                columns: vec![
+                    Column { unique: true, ..column(0, "id", DataType::Integer) },
+                    column(1, "value", DataType::String),
+                ],
+                version: 0,
+                layouts: Vec::new(),
+                indexes: Vec::new(),
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use super::raft::{Raft, Status};
-use super::{Engine, Transaction as _};
+use super::{Catalog as _, Changes, Engine, Transaction as _};
 use crate::error::{Error, Result};
+use crate::raft;
 use crate::sql::execution::ExecutionResult;
 use crate::sql::parser::{ast, Parser};
 use crate::sql::planner::Plan;
@@ -10,6 +11,19 @@ use crate::{errdata, errinput};
 
 use log::error;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The default number of rows to index per CREATE INDEX backfill batch.
+const BACKFILL_BATCH_SIZE: usize = 1000;
+
+/// How long to wait before checking for older transactions or retrying a
+/// conflicting batch during a CREATE INDEX backfill. Retries of a conflicting
+/// batch double the delay each time.
+const BACKFILL_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// The maximum number of times to retry a conflicting CREATE INDEX backfill
+/// batch before giving up.
+const BACKFILL_MAX_RETRIES: u32 = 8;
 
 /// A SQL client session. Executes raw SQL statements against a SQL engine and
 /// handles transaction control.
@@ -18,12 +32,20 @@ pub struct Session<'a, E: Engine<'a>> {
     engine: &'a E,
     /// The current transaction, if any.
     txn: Option<E::Transaction>,
+    /// The number of rows to index per CREATE INDEX backfill batch.
+    backfill_batch_size: usize,
 }
 
 impl<'a, E: Engine<'a>> Session<'a, E> {
     /// Creates a new session using the given SQL engine.
     pub fn new(engine: &'a E) -> Self {
-        Self { engine, txn: None }
+        Self { engine, txn: None, backfill_batch_size: BACKFILL_BATCH_SIZE }
+    }
+
+    /// Sets the number of rows to index per CREATE INDEX backfill batch.
+    pub fn set_backfill_batch_size(&mut self, size: usize) {
+        assert!(size > 0, "backfill batch size must be positive");
+        self.backfill_batch_size = size;
     }
 
     /// Executes a client statement.
@@ -71,14 +93,79 @@ impl<'a, E: Engine<'a>> Session<'a, E> {
                 Ok(StatementResult::Explain(Plan::build(*statement, txn)?.optimize()?))
             }),
             statement => {
-                let read_only = matches!(statement, ast::Statement::Select { .. });
-                self.with_txn(read_only, |txn| {
+                // SELECT ... FOR UPDATE/SHARE takes row locks, which requires
+                // a read-write transaction.
+                let read_only = matches!(
+                    statement,
+                    ast::Statement::Select { lock: None, .. } | ast::Statement::SetOperation { .. }
+                );
+                let result = self.with_txn(read_only, |txn| {
                     Plan::build(statement, txn)?.optimize()?.execute(txn)?.try_into()
-                })
+                })?;
+                // CREATE INDEX creates a write-only index, which must be
+                // backfilled before queries can use it.
+                if let StatementResult::CreateIndex { name } = &result {
+                    self.backfill_index(name)?;
+                }
+                Ok(result)
             }
         }
     }
 
+    /// Backfills a newly created write-only index from existing rows, in
+    /// batches of backfill_batch_size rows. In an explicit transaction, all
+    /// batches run in it. Otherwise, each batch runs in a separate implicit
+    /// transaction, such that we don't build the entire index in memory or in a
+    /// single Raft command. Write conflicts with concurrent writers are retried
+    /// with exponential backoff, up to BACKFILL_MAX_RETRIES times per batch,
+    /// and we first wait for any transactions that began before the index was
+    /// created, since they don't maintain it. If the backfill fails (e.g. on
+    /// duplicate values in a unique index), the index is dropped.
+    fn backfill_index(&mut self, index: &str) -> Result<()> {
+        if self.txn.is_none() {
+            self.wait_for_older_txns()?;
+        }
+        let mut after = None;
+        let mut retries = 0;
+        let result = loop {
+            let limit = self.backfill_batch_size;
+            match self.with_txn(false, |txn| txn.backfill_index(index, after.clone(), limit)) {
+                Ok(Some(next)) => (after, retries) = (Some(next), 0),
+                Ok(None) => break Ok(()),
+                Err(Error::Serialization)
+                    if self.txn.is_none() && retries < BACKFILL_MAX_RETRIES =>
+                {
+                    std::thread::sleep(BACKFILL_RETRY_DELAY * 2u32.pow(retries));
+                    retries += 1;
+                }
+                Err(err) => break Err(err),
+            }
+        };
+        if result.is_err() {
+            if let Err(err) = self.with_txn(false, |txn| txn.drop_index(index, true)) {
+                error!("failed to drop index {index} after backfill error: {err}");
+            }
+        }
+        result
+    }
+
+    /// Waits until all read-write transactions that are currently active have
+    /// completed, i.e. until there are no active transactions below the
+    /// current version.
+    fn wait_for_older_txns(&self) -> Result<()> {
+        let mut version = None;
+        loop {
+            let txn = self.engine.begin_read_only()?;
+            let version = *version.get_or_insert(txn.version());
+            let done = txn.state().active.range(..version).next().is_none();
+            txn.commit()?;
+            if done {
+                return Ok(());
+            }
+            std::thread::sleep(BACKFILL_RETRY_DELAY);
+        }
+    }
+
     /// Runs a closure in the session's explicit transaction, if there is one,
     /// otherwise a temporary implicit transaction. If read_only is true, uses a
     /// read-only implicit transaction. Does not retry errors.
@@ -105,6 +192,12 @@ impl<'a, E: Engine<'a>> Session<'a, E> {
         }
         result
     }
+
+    /// Returns the change feed of committed row changes, starting at the given
+    /// cursor. Not affected by the session's transaction, if any.
+    pub fn changes(&self, from: mvcc::ChangeCursor, limit: usize) -> Result<Changes> {
+        self.engine.changes(from, limit)
+    }
 }
 
 impl<'a> Session<'a, Raft> {
@@ -112,6 +205,12 @@ impl<'a> Session<'a, Raft> {
     pub fn status(&self) -> Result<Status> {
         self.engine.status()
     }
+
+    /// Writes a backup of the local node to the given directory, returning
+    /// the Raft applied index of the backup.
+    pub fn backup(&self, dir: std::path::PathBuf) -> Result<raft::Index> {
+        self.engine.backup(dir)
+    }
 }
 
 /// If the session has an open transaction when dropped, roll it back.
@@ -127,6 +226,7 @@ impl<'a, E: Engine<'a>> Drop for Session<'a, E> {
 
 /// A session statement result. Sent across the wire to SQL clients.
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
 pub enum StatementResult {
     Begin { version: mvcc::Version, read_only: bool },
     Commit { version: mvcc::Version },
@@ -134,6 +234,9 @@ pub enum StatementResult {
     Explain(Plan),
     CreateTable { name: String },
     DropTable { name: String, existed: bool },
+    AlterTable { name: String },
+    CreateIndex { name: String },
+    DropIndex { name: String, existed: bool },
     Delete { count: u64 },
     Insert { count: u64 },
     Update { count: u64 },
@@ -151,6 +254,9 @@ impl TryFrom<ExecutionResult> for StatementResult {
         Ok(match result {
             ExecutionResult::CreateTable { name } => Self::CreateTable { name },
             ExecutionResult::DropTable { name, existed } => Self::DropTable { name, existed },
+            ExecutionResult::AlterTable { name } => Self::AlterTable { name },
+            ExecutionResult::CreateIndex { name } => Self::CreateIndex { name },
+            ExecutionResult::DropIndex { name, existed } => Self::DropIndex { name, existed },
             ExecutionResult::Delete { count } => Self::Delete { count },
             ExecutionResult::Insert { count } => Self::Insert { count },
             ExecutionResult::Update { count } => Self::Update { count },
@@ -0,0 +1,258 @@
+//! Upgrades of the SQL storage format from older format versions, see
+//! Local::FORMAT_VERSION. Each upgrade rewrites the keys and values of an
+//! older version to the current version, across all MVCC versions, via
+//! mvcc::MVCC::rewrite(). The older formats are retained here for decoding.
+
+use super::{Key, StoredRow};
+use crate::encoding::{self, bincode, Key as _, Value as _};
+use crate::errdata;
+use crate::error::Result;
+use crate::sql::types::{Column, ColumnId, DataType, Table, TableId, Value};
+use crate::storage::{self, mvcc};
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Upgrades from the original format (version 0), where rows and index entries
+/// were keyed by table and column names, rows were stored as plain values, and
+/// table schemas had no IDs, versions, or named indexes.
+///
+/// Tables are assigned IDs in name order, and columns are assigned IDs in
+/// column order. Since a table may have been dropped and recreated with other
+/// columns, index column IDs are looked up in the schema as of each version.
+pub struct FromV0 {
+    /// The table IDs and the column names of each schema version, by name.
+    tables: BTreeMap<String, (TableId, BTreeMap<mvcc::Version, Vec<String>>)>,
+}
+
+impl FromV0 {
+    /// Prepares an upgrade by collecting all versions of all table schemas.
+    pub fn new<E: storage::Engine>(mvcc: &mvcc::MVCC<E>) -> Result<Self> {
+        let mut schemas: BTreeMap<String, BTreeMap<mvcc::Version, Vec<String>>> = BTreeMap::new();
+        let mut engine = mvcc.engine.lock()?;
+        let mut scan = engine.scan(..);
+        while let Some((key, value)) = scan.next().transpose()? {
+            let mvcc::Key::Version(key, version) = mvcc::Key::decode(&key)? else { continue };
+            let KeyV0::Table(name) = KeyV0::decode(&key)? else { continue };
+            let versions = schemas.entry(name.into_owned()).or_default();
+            if let Some(value) = bincode::deserialize::<Option<Vec<u8>>>(&value)? {
+                let table = TableV0::decode(&value)?;
+                versions.insert(version, table.columns.into_iter().map(|c| c.name).collect());
+            }
+        }
+        let tables = schemas.into_iter().zip(1..).map(|((name, v), id)| (name, (id, v))).collect();
+        Ok(Self { tables })
+    }
+
+    /// Upgrades a key and value written at the given version.
+    pub fn rewrite(
+        &self,
+        key: &[u8],
+        version: mvcc::Version,
+        value: Option<&[u8]>,
+    ) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+        Ok(match KeyV0::decode(key)? {
+            KeyV0::Table(name) => {
+                let id = self.table_id(&name)?;
+                let value = value.map(|v| TableV0::decode(v).map(|t| t.upgrade(id).encode()));
+                (Key::Table(name).encode(), value.transpose()?)
+            }
+            KeyV0::Index(table, column, value_) => {
+                let value = value.map(|v| -> Result<_> {
+                    let ids: BTreeSet<Vec<Value>> =
+                        BTreeSet::<Value>::decode(v)?.into_iter().map(|id| vec![id]).collect();
+                    Ok(ids.encode())
+                });
+                let (id, columns) =
+                    (self.table_id(&table)?, vec![self.column_id(&table, &column, version)?]);
+                (
+                    Key::Index(id, columns.into(), vec![value_.into_owned()].into()).encode(),
+                    value.transpose()?,
+                )
+            }
+            KeyV0::Row(table, id) => {
+                let value = value.map(|v| -> Result<_> {
+                    let row = Vec::<Value>::decode(v)?;
+                    Ok(StoredRow { version: 0, values: row.into() }.encode())
+                });
+                let key = Key::Row(self.table_id(&table)?, vec![id.into_owned()].into());
+                (key.encode(), value.transpose()?)
+            }
+        })
+    }
+
+    /// Returns the ID assigned to a table.
+    fn table_id(&self, table: &str) -> Result<TableId> {
+        match self.tables.get(table) {
+            Some((id, _)) => Ok(*id),
+            None => errdata!("no schema for table {table}"),
+        }
+    }
+
+    /// Returns the ID of a column in the table schema as of the given version.
+    fn column_id(&self, table: &str, column: &str, version: mvcc::Version) -> Result<ColumnId> {
+        let schema = self.tables.get(table).and_then(|(_, v)| v.range(..=version).next_back());
+        let Some((_, columns)) = schema else {
+            return errdata!("no schema for table {table} at version {version}");
+        };
+        match columns.iter().position(|c| c == column) {
+            Some(i) => Ok(i as ColumnId),
+            None => errdata!("unknown column {table}.{column} at version {version}"),
+        }
+    }
+}
+
+/// Version 0 keys.
+#[derive(Deserialize, Serialize)]
+enum KeyV0<'a> {
+    /// A table schema by table name.
+    Table(Cow<'a, str>),
+    /// An index entry, by table name, index name, and index value.
+    Index(Cow<'a, str>, Cow<'a, str>, Cow<'a, Value>),
+    /// A table row, by table name and primary key value.
+    Row(Cow<'a, str>, Cow<'a, Value>),
+}
+
+impl<'a> encoding::Key<'a> for KeyV0<'a> {}
+
+/// A version 0 table schema.
+#[derive(Deserialize, Serialize)]
+struct TableV0 {
+    name: String,
+    primary_key: usize,
+    columns: Vec<ColumnV0>,
+}
+
+impl encoding::Value for TableV0 {}
+
+impl TableV0 {
+    /// Upgrades the schema with the given table ID, assigning column IDs in
+    /// column order.
+    fn upgrade(self, id: TableId) -> Table {
+        let columns = self
+            .columns
+            .into_iter()
+            .enumerate()
+            .map(|(i, c)| Column {
+                id: i as ColumnId,
+                name: c.name,
+                datatype: c.datatype,
+                nullable: c.nullable,
+                default: c.default,
+                unique: c.unique,
+                index: c.index,
+                references: c.references,
+            })
+            .collect();
+        Table {
+            id,
+            name: self.name,
+            primary_key: vec![self.primary_key],
+            columns,
+            version: 0,
+            layouts: Vec::new(),
+            indexes: Vec::new(),
+        }
+    }
+}
+
+/// A version 0 table column.
+#[derive(Deserialize, Serialize)]
+struct ColumnV0 {
+    name: String,
+    datatype: DataType,
+    nullable: bool,
+    default: Option<Value>,
+    unique: bool,
+    index: bool,
+    references: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        Catalog as _, Engine as _, Local, Transaction as _, FORMAT_KEY, FORMAT_VERSION,
+    };
+    use super::*;
+    use crate::encoding::bincode;
+    use crate::storage::{mvcc, Engine as _, Memory};
+
+    /// Tests that a database written in the original format is upgraded when
+    /// opened, and can be read and written afterwards.
+    #[test]
+    fn upgrade_from_v0() -> Result<()> {
+        let column = |name: &str, datatype, index| ColumnV0 {
+            name: name.to_string(),
+            datatype,
+            nullable: index,
+            default: index.then_some(Value::Null),
+            unique: !index,
+            index,
+            references: None,
+        };
+        let table = TableV0 {
+            name: "t".to_string(),
+            primary_key: 0,
+            columns: vec![
+                column("id", DataType::Integer, false),
+                column("value", DataType::String, true),
+            ],
+        };
+        let (a, b) = (Value::String("a".into()), Value::String("b".into()));
+
+        // Write a table with two rows and an index at version 1, and delete
+        // the second row at version 2.
+        let mut engine = Memory::new();
+        let mut set = |key: KeyV0, version, value: Option<Vec<u8>>| {
+            let key = mvcc::Key::Version(key.encode().into(), version).encode();
+            engine.set(&key, bincode::serialize(&value))
+        };
+        set(KeyV0::Table("t".into()), 1, Some(table.encode()))?;
+        for (id, value) in [(1, &a), (2, &b)] {
+            let row = vec![Value::Integer(id), value.clone()];
+            set(KeyV0::Row("t".into(), Cow::Owned(Value::Integer(id))), 1, Some(row.encode()))?;
+            let ids = BTreeSet::from([Value::Integer(id)]);
+            let key = KeyV0::Index("t".into(), "value".into(), Cow::Borrowed(value));
+            set(key, 1, Some(ids.encode()))?;
+        }
+        set(KeyV0::Row("t".into(), Cow::Owned(Value::Integer(2))), 2, None)?;
+        set(KeyV0::Index("t".into(), "value".into(), Cow::Borrowed(&b)), 2, None)?;
+        engine.set(&mvcc::Key::NextVersion.encode(), 3_u64.encode())?;
+
+        // Open the engine, which upgrades it.
+        let local = Local::new(engine)?;
+        assert_eq!(local.get_unversioned(FORMAT_KEY)?, Some(bincode::serialize(&FORMAT_VERSION)));
+        assert_eq!(local.check()?, Vec::<String>::new());
+
+        let txn = local.begin_as_of(2)?;
+        let rows: Vec<_> = txn.scan("t", None)?.collect::<Result<_>>()?;
+        assert_eq!(rows, vec![vec![Value::Integer(1), a.clone()], vec![Value::Integer(2), b]]);
+        txn.rollback()?;
+
+        // The table was assigned ID 1, and new tables are assigned later IDs.
+        let txn = local.begin()?;
+        assert_eq!(txn.must_get_table("t")?.id, 1);
+        txn.create_table(TableV0 { name: "u".to_string(), ..table }.upgrade(0))?;
+        assert_eq!(txn.must_get_table("u")?.id, 2);
+
+        assert_eq!(txn.get("t", &[vec![Value::Integer(1)]])?, vec![vec![Value::Integer(1), a]]);
+        assert_eq!(txn.get("t", &[vec![Value::Integer(2)]])?, Vec::<Vec<Value>>::new());
+        txn.insert("t", vec![vec![Value::Integer(3), Value::String("c".into())]])?;
+        let ids = txn.lookup_index("t", &["value".to_string()], &[vec!["c".into()]])?;
+        assert_eq!(ids, BTreeSet::from([vec![Value::Integer(3)]]));
+        txn.commit()?;
+        assert_eq!(local.check()?, Vec::<String>::new());
+        Ok(())
+    }
+
+    /// Tests that an unknown, newer format version errors.
+    #[test]
+    fn unknown_version() -> Result<()> {
+        let mut engine = Memory::new();
+        let key = mvcc::Key::Unversioned(FORMAT_KEY.into()).encode();
+        engine.set(&key, bincode::serialize(&(FORMAT_VERSION + 1)))?;
+        assert!(Local::new(engine).is_err());
+        Ok(())
+    }
+}
@@ -3,7 +3,7 @@ use crate::sql::planner::Aggregate;
 use crate::sql::types::{Expression, Row, Rows, Value};
 
 use itertools::Itertools as _;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 
 /// Aggregates row values from the source according to the aggregates, using the
 /// group_by expressions as buckets.
@@ -34,14 +34,8 @@ struct Aggregator {
 impl Aggregator {
     /// Creates a new aggregator for the given aggregates and GROUP BY buckets.
     fn new(aggregates: Vec<Aggregate>, group_by: Vec<Expression>) -> Self {
-        use Aggregate::*;
         let accumulators = aggregates.iter().map(Accumulator::new).collect();
-        let exprs = aggregates
-            .into_iter()
-            .map(|aggregate| match aggregate {
-                Average(expr) | Count(expr) | Max(expr) | Min(expr) | Sum(expr) => expr,
-            })
-            .collect();
+        let exprs = aggregates.iter().map(|aggregate| aggregate.expr().clone()).collect();
         Self { buckets: BTreeMap::new(), empty: accumulators, group_by, exprs }
     }
 
@@ -86,9 +80,10 @@ impl Aggregator {
 /// Accumulates aggregate values. Uses an enum rather than a trait since we need
 /// to keep these in a vector (could use boxed trait objects too).
 #[derive(Clone)]
-enum Accumulator {
+pub(super) enum Accumulator {
     Average { count: i64, sum: Value },
     Count(i64),
+    Distinct { seen: HashSet<Value>, accumulator: Box<Accumulator> },
     Max(Option<Value>),
     Min(Option<Value>),
     Sum(Option<Value>),
@@ -96,10 +91,13 @@ enum Accumulator {
 
 impl Accumulator {
     /// Creates a new accumulator from an aggregate kind.
-    fn new(aggregate: &Aggregate) -> Self {
+    pub(super) fn new(aggregate: &Aggregate) -> Self {
         match aggregate {
             Aggregate::Average(_) => Self::Average { count: 0, sum: Value::Integer(0) },
             Aggregate::Count(_) => Self::Count(0),
+            Aggregate::Distinct(aggregate) => {
+                Self::Distinct { seen: HashSet::new(), accumulator: Box::new(Self::new(aggregate)) }
+            }
             Aggregate::Max(_) => Self::Max(None),
             Aggregate::Min(_) => Self::Min(None),
             Aggregate::Sum(_) => Self::Sum(None),
@@ -107,7 +105,7 @@ impl Accumulator {
     }
 
     /// Adds a value to the accumulator.
-    fn add(&mut self, value: Value) -> Result<()> {
+    pub(super) fn add(&mut self, value: Value) -> Result<()> {
         use std::cmp::Ordering;
 
         // NULL values are ignored in aggregates.
@@ -123,6 +121,13 @@ impl Accumulator {
 
             Self::Count(c) => *c += 1,
 
+            // Only pass on values that haven't been seen before.
+            Self::Distinct { seen, accumulator } => {
+                if seen.insert(value.clone()) {
+                    accumulator.add(value)?;
+                }
+            }
+
             Self::Max(max @ None) => *max = Some(value),
             Self::Max(Some(max)) => {
                 if value.cmp(max) == Ordering::Greater {
@@ -144,13 +149,14 @@ impl Accumulator {
     }
 
     /// Returns the aggregate value.
-    fn value(self) -> Result<Value> {
+    pub(super) fn value(&self) -> Result<Value> {
         Ok(match self {
             Self::Average { count: 0, sum: _ } => Value::Null,
-            Self::Average { count, sum } => sum.checked_div(&Value::Integer(count))?,
-            Self::Count(c) => c.into(),
+            Self::Average { count, sum } => sum.checked_div(&Value::Integer(*count))?,
+            Self::Count(c) => (*c).into(),
+            Self::Distinct { accumulator, .. } => accumulator.value()?,
             Self::Max(None) | Self::Min(None) | Self::Sum(None) => Value::Null,
-            Self::Max(Some(v)) | Self::Min(Some(v)) | Self::Sum(Some(v)) => v,
+            Self::Max(Some(v)) | Self::Min(Some(v)) | Self::Sum(Some(v)) => v.clone(),
         })
     }
 }
@@ -1,11 +1,14 @@
 use super::aggregate;
 use super::join;
+use super::set;
 use super::source;
+use super::subquery;
 use super::transform;
+use super::window;
 use super::write;
 use crate::error::Result;
 use crate::sql::engine::{Catalog, Transaction};
-use crate::sql::planner::{Node, Plan};
+use crate::sql::planner::{Alteration, Node, Plan};
 use crate::sql::types::{Label, Rows};
 
 /// Executes a plan, returning an execution result.
@@ -29,6 +32,35 @@ pub fn execute_plan(
             ExecutionResult::DropTable { name: table, existed }
         }
 
+        Plan::AlterTable { table, alteration } => {
+            match alteration {
+                Alteration::AddColumn(column) => catalog.add_column(&table, column)?,
+                Alteration::DropColumn(column) => catalog.drop_column(&table, &column)?,
+                Alteration::RenameColumn { column, new_name } => {
+                    catalog.rename_column(&table, &column, &new_name)?
+                }
+                Alteration::RenameTable(new_name) => catalog.rename_table(&table, &new_name)?,
+                Alteration::SetDefault { column, default } => {
+                    catalog.set_column_default(&table, &column, default)?
+                }
+                Alteration::SetNullable { column, nullable } => {
+                    catalog.set_column_nullable(&table, &column, nullable)?
+                }
+            }
+            ExecutionResult::AlterTable { name: table }
+        }
+
+        Plan::CreateIndex { table, index } => {
+            let name = index.name.clone();
+            catalog.create_index(&table, index)?;
+            ExecutionResult::CreateIndex { name }
+        }
+
+        Plan::DropIndex { name, if_exists } => {
+            let existed = catalog.drop_index(&name, if_exists)?;
+            ExecutionResult::DropIndex { name, existed }
+        }
+
         Plan::Delete { table, primary_key, source } => {
             let source = execute(source, txn)?;
             let count = write::delete(txn, table, primary_key, source)?;
@@ -63,36 +95,84 @@ pub fn execute(node: Node, txn: &impl Transaction) -> Result<Rows> {
             aggregate::aggregate(source, group_by, aggregates)?
         }
 
+        // The CTE labels are only used for planning and display.
+        Node::CommonTable { source, name: _, alias: _, columns: _ } => execute(*source, txn)?,
+
+        Node::Distinct { source, on } => {
+            let source = execute(*source, txn)?;
+            transform::distinct(source, on)
+        }
+
+        Node::Except { left, right, distinct } => {
+            let size = left.size();
+            let left = execute(*left, txn)?;
+            let right = execute(*right, txn)?;
+            set::except(left, right, size, distinct)?
+        }
+
         Node::Filter { source, predicate } => {
             let source = execute(*source, txn)?;
             transform::filter(source, predicate)
         }
 
-        Node::HashJoin { left, left_column, right, right_column, outer } => {
+        Node::HashJoin { left, left_column, right, right_column, r#type } => {
             let right_size = right.size();
             let left = execute(*left, txn)?;
             let right = execute(*right, txn)?;
-            join::hash(left, left_column, right, right_column, right_size, outer)?
+            join::hash(left, left_column, right, right_column, right_size, r#type)?
+        }
+
+        Node::IndexLookup { table, columns, values, alias: _ } => {
+            let columns = table.column_names(&columns);
+            source::lookup_index(txn, table.name, columns, values)?
         }
 
-        Node::IndexLookup { table, column, values, alias: _ } => {
-            let column = table.columns.into_iter().nth(column).expect("invalid column").name;
-            let table = table.name;
-            source::lookup_index(txn, table, column, values)?
+        Node::IndexRangeScan { table, columns, range, reverse, alias: _ } => {
+            let columns = table.column_names(&columns);
+            source::scan_index_range(txn, table.name, columns, range, reverse)?
+        }
+
+        Node::Intersect { left, right, distinct } => {
+            let size = left.size();
+            let left = execute(*left, txn)?;
+            let right = execute(*right, txn)?;
+            set::intersect(left, right, size, distinct)?
         }
 
         Node::KeyLookup { table, keys, alias: _ } => source::lookup_key(txn, table.name, keys)?,
 
-        Node::Limit { source, limit } => {
+        Node::KeyRangeScan { table, range, reverse, alias: _ } => {
+            source::scan_key_range(txn, table.name, range, reverse)?
+        }
+
+        // A limit on a sort only needs to buffer and sort the top rows.
+        Node::Limit { source, limit } => match *source {
+            Node::Order { source, orders } => {
+                let source = execute(*source, txn)?;
+                transform::top(source, orders, limit)?
+            }
+            Node::Offset { source, offset } if matches!(*source, Node::Order { .. }) => {
+                let Node::Order { source, orders } = *source else { unreachable!() };
+                let source = execute(*source, txn)?;
+                let source = transform::top(source, orders, limit.saturating_add(offset))?;
+                transform::offset(source, offset)
+            }
+            source => {
+                let source = execute(source, txn)?;
+                transform::limit(source, limit)
+            }
+        },
+
+        Node::Lock { source, table, primary_key, mode, skip_locked, limit } => {
             let source = execute(*source, txn)?;
-            transform::limit(source, limit)
+            write::lock(txn, table, primary_key, source, mode, skip_locked, limit)?
         }
 
-        Node::NestedLoopJoin { left, right, predicate, outer } => {
+        Node::NestedLoopJoin { left, right, predicate, r#type } => {
             let right_size = right.size();
             let left = execute(*left, txn)?;
             let right = execute(*right, txn)?;
-            join::nested_loop(left, right, right_size, predicate, outer)?
+            join::nested_loop(left, right, right_size, predicate, r#type)?
         }
 
         Node::Nothing { .. } => source::nothing(),
@@ -112,6 +192,10 @@ pub fn execute(node: Node, txn: &impl Transaction) -> Result<Rows> {
             transform::project(source, expressions)
         }
 
+        Node::RecursiveUnion { anchor, recursive, name, distinct, limit } => {
+            set::recursive_union(txn, *anchor, *recursive, name, distinct, limit)?
+        }
+
         Node::Remap { source, targets } => {
             let source = execute(*source, txn)?;
             transform::remap(source, targets)
@@ -119,7 +203,27 @@ pub fn execute(node: Node, txn: &impl Transaction) -> Result<Rows> {
 
         Node::Scan { table, filter, alias: _ } => source::scan(txn, table, filter)?,
 
+        Node::Subquery { source, subquery, r#type, correlated } => {
+            let source = execute(*source, txn)?;
+            subquery::subquery(txn, source, *subquery, r#type, correlated)?
+        }
+
+        Node::Union { left, right, distinct } => {
+            let size = left.size();
+            let left = execute(*left, txn)?;
+            let right = execute(*right, txn)?;
+            set::union(left, right, size, distinct)
+        }
+
         Node::Values { rows } => source::values(rows),
+
+        Node::Window { source, functions } => {
+            let source = execute(*source, txn)?;
+            window::window(source, functions)?
+        }
+
+        // Work tables are bound to rows while executing a RecursiveUnion.
+        Node::WorkTable { name, size: _ } => panic!("unbound work table {name}"),
     })
 }
 
@@ -127,6 +231,9 @@ pub fn execute(node: Node, txn: &impl Transaction) -> Result<Rows> {
 pub enum ExecutionResult {
     CreateTable { name: String },
     DropTable { name: String, existed: bool },
+    AlterTable { name: String },
+    CreateIndex { name: String },
+    DropIndex { name: String, existed: bool },
     Delete { count: u64 },
     Insert { count: u64 },
     Update { count: u64 },
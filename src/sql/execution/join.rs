@@ -1,5 +1,6 @@
 use crate::errdata;
 use crate::error::Result;
+use crate::sql::planner::JoinType;
 use crate::sql::types::{Expression, Row, Rows, Value};
 
 use itertools::Itertools as _;
@@ -7,18 +8,19 @@ use std::collections::HashMap;
 use std::iter::Peekable;
 
 /// A nested loop join. Iterates over the right source for every row in the left
-/// source, optionally filtering on the join predicate. If outer is true, and
+/// source, optionally filtering on the join predicate. For outer joins, if
 /// there are no matches in the right source for a row in the left source, a
 /// joined row with NULL values for the right source is returned (typically used
-/// for a LEFT JOIN).
+/// for a LEFT JOIN). Semi and anti joins emit the left row if there is a match
+/// or no match respectively, without the right columns.
 pub(super) fn nested_loop(
     left: Rows,
     right: Rows,
     right_size: usize,
     predicate: Option<Expression>,
-    outer: bool,
+    r#type: JoinType,
 ) -> Result<Rows> {
-    Ok(Box::new(NestedLoopIterator::new(left, right, right_size, predicate, outer)?))
+    Ok(Box::new(NestedLoopIterator::new(left, right, right_size, predicate, r#type)?))
 }
 
 /// NestedLoopIterator implements nested loop joins.
@@ -40,8 +42,8 @@ struct NestedLoopIterator {
     right_match: bool,
     /// The join predicate.
     predicate: Option<Expression>,
-    /// If true, emit a row when there is no match in the right source.
-    outer: bool,
+    /// The join type.
+    r#type: JoinType,
 }
 
 impl NestedLoopIterator {
@@ -50,11 +52,11 @@ impl NestedLoopIterator {
         right: Rows,
         right_size: usize,
         predicate: Option<Expression>,
-        outer: bool,
+        r#type: JoinType,
     ) -> Result<Self> {
         let left = left.peekable();
         let right_init = right.clone();
-        Ok(Self { left, right, right_init, right_size, right_match: false, predicate, outer })
+        Ok(Self { left, right, right_init, right_size, right_match: false, predicate, r#type })
     }
 
     // Returns the next joined row, if any, with error handling.
@@ -74,30 +76,42 @@ impl NestedLoopIterator {
                     },
                     None => true,
                 };
-                if is_match {
-                    self.right_match = true;
-                    return Ok(Some(row));
+                if !is_match {
+                    continue;
+                }
+                self.right_match = true;
+                match self.r#type {
+                    JoinType::Inner | JoinType::Outer => return Ok(Some(row)),
+                    // Semi and anti joins only need a single match.
+                    JoinType::Semi | JoinType::Anti => break,
                 }
             }
 
-            // We reached the end of the right source, reset it.
+            // We reached the end of the right source (or a semi/anti join
+            // match), reset it.
             self.right = self.right_init.clone();
+            let right_match = std::mem::take(&mut self.right_match);
 
-            // If there was no match for this row, and this is an outer join,
-            // emit a row with right NULLs.
-            if !self.right_match && self.outer {
-                let row = left_row
-                    .iter()
-                    .cloned()
-                    .chain(std::iter::repeat(Value::Null).take(self.right_size))
-                    .collect();
-                self.left.next();
-                return Ok(Some(row));
-            }
+            // Emit the left row if appropriate for the join type. For outer
+            // joins without a match, emit a row with right NULLs.
+            let row = match self.r#type {
+                JoinType::Outer if !right_match => Some(
+                    left_row
+                        .iter()
+                        .cloned()
+                        .chain(std::iter::repeat_n(Value::Null, self.right_size))
+                        .collect(),
+                ),
+                JoinType::Semi if right_match => Some(left_row.clone()),
+                JoinType::Anti if !right_match => Some(left_row.clone()),
+                JoinType::Inner | JoinType::Outer | JoinType::Semi | JoinType::Anti => None,
+            };
 
             // Move onto the next left row.
             self.left.next();
-            self.right_match = false;
+            if row.is_some() {
+                return Ok(row);
+            }
         }
 
         // Otherwise, there's either a None or Err in left. Return it.
@@ -115,16 +129,17 @@ impl Iterator for NestedLoopIterator {
 
 /// Executes a hash join. This builds a hash table of rows from the right source
 /// keyed on the join value, then iterates over the left source and looks up
-/// matching rows in the hash table. If outer is true, and there is no match
-/// in the right source for a row in the left source, a row with NULL values
-/// for the right source is emitted instead.
+/// matching rows in the hash table. For outer joins, if there is no match in
+/// the right source for a row in the left source, a row with NULL values for
+/// the right source is emitted instead. Semi and anti joins emit the left row
+/// if there is a match or no match respectively, without the right columns.
 pub(super) fn hash(
     left: Rows,
     left_column: usize,
     right: Rows,
     right_column: usize,
     right_size: usize,
-    outer: bool,
+    r#type: JoinType,
 ) -> Result<Rows> {
     // Build the hash table from the right source.
     let mut rows = right;
@@ -147,17 +162,22 @@ pub(super) fn hash(
             return Box::new(std::iter::once(result));
         };
         // Join the left row with any matching right rows.
-        match right.get(&row[left_column]) {
-            Some(matches) => Box::new(
+        match (right.get(&row[left_column]), &r#type) {
+            (Some(_), JoinType::Semi) | (None, JoinType::Anti) => {
+                Box::new(std::iter::once(Ok(row)))
+            }
+            (Some(matches), JoinType::Inner | JoinType::Outer) => Box::new(
                 std::iter::once(row)
                     .cartesian_product(matches.clone())
                     .map(|(l, r)| l.into_iter().chain(r).collect())
                     .map(Ok),
             ),
-            None if outer => {
+            (None, JoinType::Outer) => {
                 Box::new(std::iter::once(Ok(row.into_iter().chain(empty.clone()).collect())))
             }
-            None => Box::new(std::iter::empty()),
+            (Some(_), JoinType::Anti) | (None, JoinType::Inner | JoinType::Semi) => {
+                Box::new(std::iter::empty())
+            }
         }
     })))
 }
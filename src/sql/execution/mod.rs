@@ -1,8 +1,11 @@
 mod aggregate;
 mod execute;
 mod join;
+mod set;
 mod source;
+mod subquery;
 mod transform;
+mod window;
 mod write;
 
 pub use execute::{execute_plan, ExecutionResult};
@@ -0,0 +1,165 @@
+use super::execute::execute;
+use crate::errinput;
+use crate::error::Result;
+use crate::sql::engine::Transaction;
+use crate::sql::planner::Node;
+use crate::sql::types::{DataType, Expression, Row, Rows};
+
+use itertools::Itertools as _;
+use std::collections::{HashMap, HashSet};
+
+/// Emits the left rows followed by the right rows. If distinct is true, rows
+/// that have already been emitted are discarded.
+pub(super) fn union(left: Rows, right: Rows, size: usize, distinct: bool) -> Rows {
+    let rows = check_types(left.chain(right), vec![None; size]);
+    if !distinct {
+        return Box::new(rows);
+    }
+    let mut seen = HashSet::new();
+    Box::new(rows.filter_ok(move |row| seen.insert(row.clone())))
+}
+
+/// Emits the left rows that are also in the right rows, which are buffered in
+/// a hashmap of row counts. If distinct is true, each row is emitted at most
+/// once. Otherwise, each right row can match a single left row.
+pub(super) fn intersect(left: Rows, right: Rows, size: usize, distinct: bool) -> Result<Rows> {
+    let mut types = vec![None; size];
+    let mut counts = count_rows(right, &mut types)?;
+    Ok(Box::new(check_types(left, types).filter_ok(move |row| match counts.get_mut(row) {
+        Some(count) if *count > 0 => {
+            *count = if distinct { 0 } else { *count - 1 };
+            true
+        }
+        Some(_) | None => false,
+    })))
+}
+
+/// Emits the left rows that aren't in the right rows, which are buffered in a
+/// hashmap of row counts. If distinct is true, each row is emitted at most
+/// once. Otherwise, each right row cancels out a single left row.
+pub(super) fn except(left: Rows, right: Rows, size: usize, distinct: bool) -> Result<Rows> {
+    let mut types = vec![None; size];
+    let mut counts = count_rows(right, &mut types)?;
+    Ok(Box::new(check_types(left, types).filter_ok(move |row| match counts.get_mut(row) {
+        Some(count) if *count > 0 => {
+            *count -= 1;
+            false
+        }
+        Some(_) => !distinct,
+        None => {
+            // Record emitted rows to discard later duplicates.
+            if distinct {
+                counts.insert(row.clone(), 0);
+            }
+            true
+        }
+    })))
+}
+
+/// Buffers the given rows in a hashmap of row counts, recording their column
+/// types (see check_types).
+fn count_rows(rows: Rows, types: &mut [Option<DataType>]) -> Result<HashMap<Row, usize>> {
+    let mut counts = HashMap::new();
+    for result in rows {
+        let row = result?;
+        check_row_types(types, &row)?;
+        *counts.entry(row).or_default() += 1;
+    }
+    Ok(counts)
+}
+
+/// Checks that the non-NULL row values have the same data types as previous
+/// rows, starting with the given column types. Set operations combine rows
+/// from separate queries, which must agree on the column types. The planner
+/// checks the types it can infer, and this catches the rest (e.g. outer
+/// references).
+fn check_types(
+    rows: impl Iterator<Item = Result<Row>> + Clone,
+    mut types: Vec<Option<DataType>>,
+) -> impl Iterator<Item = Result<Row>> + Clone {
+    rows.map(move |result| {
+        let row = result?;
+        check_row_types(&mut types, &row)?;
+        Ok(row)
+    })
+}
+
+/// Checks the types of a single row, recording any new column types.
+fn check_row_types(types: &mut [Option<DataType>], row: &Row) -> Result<()> {
+    for (i, (datatype, value)) in types.iter_mut().zip(row).enumerate() {
+        match (&datatype, value.datatype()) {
+            (_, None) => {}
+            (None, Some(vtype)) => *datatype = Some(vtype),
+            (Some(ctype), Some(vtype)) if *ctype == vtype => {}
+            (Some(ctype), Some(vtype)) => {
+                return errinput!("mismatched types {ctype} and {vtype} in column {}", i + 1);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Evaluates a recursive union (i.e. WITH RECURSIVE) by iterating to a
+/// fixpoint. The anchor rows are emitted first, then the recursive term is
+/// executed with the work table bound to the previous iteration's rows until it
+/// no longer emits any rows. If distinct is true, rows that have already been
+/// emitted are discarded. Since the row iterator can't hold onto the
+/// transaction, all iterations are evaluated eagerly. If limit is given, stops
+/// iterating once that many rows have been emitted, which allows an outer
+/// LIMIT to terminate an otherwise infinite recursion. Like other set
+/// operations, the anchor and recursive rows must have the same column types.
+pub(super) fn recursive_union(
+    txn: &impl Transaction,
+    anchor: Node,
+    recursive: Node,
+    name: String,
+    distinct: bool,
+    limit: Option<usize>,
+) -> Result<Rows> {
+    let mut types = vec![None; anchor.size()];
+    let mut seen = HashSet::new();
+    let mut collect = |rows: Rows| -> Result<Vec<Row>> {
+        let mut rows: Vec<Row> = rows.collect::<Result<_>>()?;
+        rows.iter().try_for_each(|row| check_row_types(&mut types, row))?;
+        if distinct {
+            rows.retain(|row| seen.insert(row.clone()))
+        }
+        Ok(rows)
+    };
+
+    let limit = limit.unwrap_or(usize::MAX);
+    let mut rows = Vec::new();
+    let mut work = collect(execute(anchor, txn)?)?;
+    while !work.is_empty() {
+        if rows.len() + work.len() >= limit {
+            work.truncate(limit - rows.len());
+            rows.append(&mut work);
+            break;
+        }
+        let node = bind(recursive.clone(), &name, &work)?;
+        let next = collect(execute(node, txn)?)?;
+        rows.append(&mut work);
+        work = next;
+    }
+    Ok(Box::new(rows.into_iter().map(Ok)))
+}
+
+/// Binds the named work table to the given rows, by replacing WorkTable nodes
+/// with Values nodes. This includes work table references in subqueries.
+fn bind(node: Node, name: &str, rows: &[Row]) -> Result<Node> {
+    node.transform(&Ok, &|node| match node {
+        Node::WorkTable { name: work, .. } if work == name => Ok(Node::Values {
+            rows: rows
+                .iter()
+                .map(|row| row.iter().cloned().map(Expression::Constant).collect())
+                .collect(),
+        }),
+        Node::Subquery { source, subquery, r#type, correlated } => Ok(Node::Subquery {
+            source,
+            subquery: Box::new(bind(*subquery, name, rows)?),
+            r#type,
+            correlated,
+        }),
+        node => Ok(node),
+    })
+}
@@ -1,6 +1,6 @@
 use crate::error::Result;
 use crate::sql::engine::Transaction;
-use crate::sql::types::{Expression, Rows, Table, Value};
+use crate::sql::types::{Expression, Rows, Table, TupleRange, Value};
 
 /// A table scan source.
 pub(super) fn scan(
@@ -12,7 +12,11 @@ pub(super) fn scan(
 }
 
 /// A primary key lookup source.
-pub(super) fn lookup_key(txn: &impl Transaction, table: String, keys: Vec<Value>) -> Result<Rows> {
+pub(super) fn lookup_key(
+    txn: &impl Transaction,
+    table: String,
+    keys: Vec<Vec<Value>>,
+) -> Result<Rows> {
     Ok(Box::new(txn.get(&table, &keys)?.into_iter().map(Ok)))
 }
 
@@ -20,10 +24,32 @@ pub(super) fn lookup_key(txn: &impl Transaction, table: String, keys: Vec<Value>
 pub(super) fn lookup_index(
     txn: &impl Transaction,
     table: String,
-    column: String,
-    values: Vec<Value>,
+    columns: Vec<String>,
+    values: Vec<Vec<Value>>,
+) -> Result<Rows> {
+    let ids: Vec<_> = txn.lookup_index(&table, &columns, &values)?.into_iter().collect();
+    Ok(Box::new(txn.get(&table, &ids)?.into_iter().map(Ok)))
+}
+
+/// A primary key range scan source.
+pub(super) fn scan_key_range(
+    txn: &impl Transaction,
+    table: String,
+    range: TupleRange,
+    reverse: bool,
+) -> Result<Rows> {
+    txn.scan_range(&table, range, reverse)
+}
+
+/// A secondary index range scan source.
+pub(super) fn scan_index_range(
+    txn: &impl Transaction,
+    table: String,
+    columns: Vec<String>,
+    range: TupleRange,
+    reverse: bool,
 ) -> Result<Rows> {
-    let ids: Vec<_> = txn.lookup_index(&table, &column, &values)?.into_iter().collect();
+    let ids = txn.lookup_index_range(&table, &columns, range, reverse)?;
     Ok(Box::new(txn.get(&table, &ids)?.into_iter().map(Ok)))
 }
 
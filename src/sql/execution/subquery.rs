@@ -0,0 +1,93 @@
+use super::execute::execute;
+use crate::errinput;
+use crate::error::Result;
+use crate::sql::engine::Transaction;
+use crate::sql::planner::{Node, SubqueryType};
+use crate::sql::types::{Expression, Row, Rows, Value};
+
+/// Evaluates a subquery for each source row, appending the result as an
+/// additional column. An uncorrelated subquery is executed once, and its result
+/// is used for every source row. A correlated subquery is executed for every
+/// source row, with its outer column references bound to the row's values.
+/// Since the row iterator can't hold onto the transaction, correlated
+/// subqueries are evaluated eagerly for all source rows.
+pub(super) fn subquery(
+    txn: &impl Transaction,
+    source: Rows,
+    subquery: Node,
+    r#type: SubqueryType,
+    correlated: bool,
+) -> Result<Rows> {
+    if !correlated {
+        let rows = fetch(txn, subquery, &r#type)?;
+        return Ok(Box::new(source.map(move |result| {
+            result.and_then(|mut row| {
+                row.push(evaluate(&r#type, &row, &rows)?);
+                Ok(row)
+            })
+        })));
+    }
+
+    let rows = source
+        .map(|result| {
+            let mut row = result?;
+            let rows = fetch(txn, bind(subquery.clone(), &row)?, &r#type)?;
+            row.push(evaluate(&r#type, &row, &rows)?);
+            Ok(row)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Box::new(rows.into_iter().map(Ok)))
+}
+
+/// Binds the outer column references of a correlated subquery to the values
+/// of the given outer row. Nested subqueries have their own outer references,
+/// and aren't visited by Node.transform().
+fn bind(subquery: Node, row: &Row) -> Result<Node> {
+    let bind = |expr| match expr {
+        Expression::Outer(index) => Ok(Expression::Constant(row[index].clone())),
+        expr => Ok(expr),
+    };
+    subquery.transform(&|node| node.transform_expressions(&bind, &Ok), &Ok)
+}
+
+/// Executes a subquery, and fetches the rows needed to compute its value.
+fn fetch(txn: &impl Transaction, subquery: Node, r#type: &SubqueryType) -> Result<Vec<Row>> {
+    let rows = execute(subquery, txn)?;
+    match r#type {
+        // EXISTS only needs to know whether there is a row, and a scalar
+        // subquery only needs two rows to know that there are too many.
+        SubqueryType::Exists => rows.take(1).collect(),
+        SubqueryType::Scalar => rows.take(2).collect(),
+        SubqueryType::In(_) => rows.collect(),
+    }
+}
+
+/// Computes the subquery value for a source row, given the subquery rows.
+fn evaluate(r#type: &SubqueryType, row: &Row, rows: &[Row]) -> Result<Value> {
+    Ok(match r#type {
+        SubqueryType::Exists => Value::Boolean(!rows.is_empty()),
+
+        SubqueryType::Scalar => match rows {
+            [] => Value::Null,
+            [subrow] => subrow[0].clone(),
+            _ => return errinput!("subquery returned more than one row"),
+        },
+
+        // Evaluate = comparisons against each value, to get the usual NULL
+        // semantics: if there is no match, but a comparison yields NULL (i.e.
+        // either side is NULL), the result is NULL.
+        SubqueryType::In(expr) => {
+            let value = Expression::Constant(expr.evaluate(Some(row))?);
+            let mut result = Value::Boolean(false);
+            for subrow in rows {
+                let subvalue = Expression::Constant(subrow[0].clone());
+                match Expression::Equal(value.clone().into(), subvalue.into()).evaluate(None)? {
+                    Value::Boolean(true) => return Ok(Value::Boolean(true)),
+                    Value::Null => result = Value::Null,
+                    _ => {}
+                }
+            }
+            result
+        }
+    })
+}
@@ -1,10 +1,26 @@
 use itertools::izip;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
 
 use crate::errinput;
 use crate::error::Result;
 use crate::sql::planner::Direction;
 use crate::sql::types::{Expression, Rows, Value};
 
+/// Emits the first row for each distinct set of values of the given
+/// expressions, discarding the rest (i.e. SELECT DISTINCT [ON]).
+pub(super) fn distinct(source: Rows, on: Vec<Expression>) -> Rows {
+    let mut seen = HashSet::new();
+    Box::new(source.filter_map(move |r| {
+        r.and_then(|row| {
+            let values: Vec<Value> =
+                on.iter().map(|e| e.evaluate(Some(&row))).collect::<Result<_>>()?;
+            Ok(seen.insert(values).then_some(row))
+        })
+        .transpose()
+    }))
+}
+
 /// Filters the input rows (i.e. WHERE).
 pub(super) fn filter(source: Rows, predicate: Expression) -> Rows {
     Box::new(source.filter_map(move |r| {
@@ -59,6 +75,39 @@ pub(super) fn order(source: Rows, order: Vec<(Expression, Direction)>) -> Result
     Ok(Box::new(irows.into_iter().map(|(_, row)| Ok(row))))
 }
 
+/// Sorts the rows and emits the first limit rows (i.e. ORDER BY ... LIMIT).
+/// Unlike order(), this only buffers the top limit rows in a binary heap.
+pub(super) fn top(source: Rows, order: Vec<(Expression, Direction)>, limit: usize) -> Result<Rows> {
+    /// A sort value, ordered by the sort direction.
+    #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+    enum SortValue {
+        Ascending(Value),
+        Descending(Reverse<Value>),
+    }
+
+    // The heap is a max-heap, so the last of the top rows can be popped when
+    // a row sorts before it. The row index is used as a tiebreaker, such that
+    // equal rows retain their input order like the stable sort in order().
+    let mut heap = BinaryHeap::new();
+    for (index, row) in source.enumerate() {
+        let row = row?;
+        let mut values = Vec::with_capacity(order.len());
+        for (expr, direction) in &order {
+            let value = expr.evaluate(Some(&row))?;
+            values.push(match direction {
+                Direction::Ascending => SortValue::Ascending(value),
+                Direction::Descending => SortValue::Descending(Reverse(value)),
+            });
+        }
+        heap.push((values, index, row));
+        if heap.len() > limit {
+            heap.pop();
+        }
+    }
+
+    Ok(Box::new(heap.into_sorted_vec().into_iter().map(|(_, _, row)| Ok(row))))
+}
+
 /// Projects the rows using the given expressions (i.e. SELECT).
 pub(super) fn project(source: Rows, expressions: Vec<Expression>) -> Rows {
     Box::new(source.map(move |r| {
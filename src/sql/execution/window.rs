@@ -0,0 +1,283 @@
+use super::aggregate::Accumulator;
+use crate::errinput;
+use crate::error::Result;
+use crate::sql::planner::{Direction, FrameBound, FrameUnits, Window, WindowFrame, WindowFunction};
+use crate::sql::types::{Row, Rows, Value};
+
+use itertools::izip;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Evaluates window functions over the source rows, and appends their values
+/// to each row (i.e. OVER). Buffers the entire row set in memory, and emits
+/// the rows in the source order.
+pub(super) fn window(source: Rows, functions: Vec<(WindowFunction, Window)>) -> Result<Rows> {
+    let mut rows: Vec<Row> = source.collect::<Result<_>>()?;
+    let columns = functions
+        .iter()
+        .map(|(function, window)| evaluate(&rows, function, window))
+        .collect::<Result<Vec<_>>>()?;
+    for column in columns {
+        for (row, value) in rows.iter_mut().zip(column) {
+            row.push(value);
+        }
+    }
+    Ok(Box::new(rows.into_iter().map(Ok)))
+}
+
+/// Evaluates a window function for all rows, returning the values in row order.
+fn evaluate(rows: &[Row], function: &WindowFunction, window: &Window) -> Result<Vec<Value>> {
+    // Group the row indexes by partition, retaining the row order.
+    let mut partitions: HashMap<Vec<Value>, Vec<usize>> = HashMap::new();
+    for (index, row) in rows.iter().enumerate() {
+        let key: Vec<Value> =
+            window.partition_by.iter().map(|e| e.evaluate(Some(row))).collect::<Result<_>>()?;
+        partitions.entry(key).or_default().push(index);
+    }
+
+    let mut values = vec![Value::Null; rows.len()];
+    for indexes in partitions.into_values() {
+        let partition = Partition::new(rows, indexes, window)?;
+        let mut cache = None;
+        for position in 0..partition.len() {
+            let value = partition.evaluate(function, position, &mut cache)?;
+            values[partition.indexes[position]] = value;
+        }
+    }
+    Ok(values)
+}
+
+/// A window partition, with its rows sorted by the window's ORDER BY.
+struct Partition<'a> {
+    /// All source rows.
+    rows: &'a [Row],
+    /// The partition's row indexes, in window order.
+    indexes: Vec<usize>,
+    /// The ORDER BY values of each partition row, in window order.
+    keys: Vec<Vec<Value>>,
+    /// The peer group boundaries of each partition row. Peers are rows with
+    /// equal ORDER BY values.
+    peers: Vec<Range<usize>>,
+    /// The peer group number of each partition row, starting at 0.
+    groups: Vec<usize>,
+    /// The window.
+    window: &'a Window,
+}
+
+impl<'a> Partition<'a> {
+    /// Sorts the given partition rows, and computes their peer groups.
+    fn new(rows: &'a [Row], indexes: Vec<usize>, window: &'a Window) -> Result<Self> {
+        let mut sorted = Vec::with_capacity(indexes.len());
+        for index in indexes {
+            let row = &rows[index];
+            let key: Vec<Value> = window
+                .order_by
+                .iter()
+                .map(|(e, _)| e.evaluate(Some(row)))
+                .collect::<Result<_>>()?;
+            sorted.push((index, key));
+        }
+        // The sort is stable, so peers retain the source row order.
+        sorted.sort_by(|(_, a), (_, b)| compare(window, a, b));
+        let (indexes, keys): (Vec<_>, Vec<_>) = sorted.into_iter().unzip();
+
+        let (mut peers, mut groups) = (Vec::with_capacity(keys.len()), Vec::new());
+        let mut start = 0;
+        for end in 1..=keys.len() {
+            if end < keys.len() && compare(window, &keys[start], &keys[end]).is_eq() {
+                continue;
+            }
+            peers.extend(std::iter::repeat_n(start..end, end - start));
+            groups.extend(std::iter::repeat_n(groups.last().map_or(0, |g| g + 1), end - start));
+            start = end;
+        }
+        Ok(Self { rows, indexes, keys, peers, groups, window })
+    }
+
+    /// Returns the number of rows in the partition.
+    fn len(&self) -> usize {
+        self.indexes.len()
+    }
+
+    /// Returns the source row at the given partition position.
+    fn row(&self, position: usize) -> &Row {
+        &self.rows[self.indexes[position]]
+    }
+
+    /// Evaluates the window function for the row at the given position. The
+    /// cache holds the last aggregate frame and accumulator. When consecutive
+    /// rows have frames with the same start, and the end only grows (e.g.
+    /// running totals, peers, or entire partitions), the accumulator is
+    /// updated with the new rows rather than rebuilt from the entire frame.
+    fn evaluate(
+        &self,
+        function: &WindowFunction,
+        position: usize,
+        cache: &mut Option<(Range<usize>, Accumulator)>,
+    ) -> Result<Value> {
+        let row = self.row(position);
+        Ok(match function {
+            WindowFunction::Aggregate(aggregate) => {
+                let frame = self.frame(position)?;
+                let (mut accumulator, added) = match cache.take() {
+                    Some((cached, accumulator))
+                        if cached.start == frame.start
+                            && cached.start <= cached.end
+                            && cached.end <= frame.end =>
+                    {
+                        (accumulator, cached.end..frame.end)
+                    }
+                    Some(_) | None => (Accumulator::new(aggregate), frame.clone()),
+                };
+                for position in added {
+                    accumulator.add(aggregate.expr().evaluate(Some(self.row(position)))?)?;
+                }
+                let value = accumulator.value()?;
+                *cache = Some((frame, accumulator));
+                value
+            }
+
+            WindowFunction::DenseRank => Value::Integer(self.groups[position] as i64 + 1),
+
+            WindowFunction::FirstValue(expr) => match self.frame(position)? {
+                frame if frame.is_empty() => Value::Null,
+                frame => expr.evaluate(Some(self.row(frame.start)))?,
+            },
+
+            WindowFunction::Lag(expr, offset, default)
+            | WindowFunction::Lead(expr, offset, default) => {
+                let offset = match offset.evaluate(Some(row))? {
+                    Value::Integer(offset) => offset,
+                    Value::Null => return Ok(Value::Null),
+                    value => return errinput!("invalid lag/lead offset {value}"),
+                };
+                let target = match function {
+                    WindowFunction::Lag(..) => (position as i64).checked_sub(offset),
+                    _ => (position as i64).checked_add(offset),
+                };
+                match target.and_then(|t| usize::try_from(t).ok()) {
+                    Some(target) if target < self.len() => expr.evaluate(Some(self.row(target)))?,
+                    Some(_) | None => default.evaluate(Some(row))?,
+                }
+            }
+
+            WindowFunction::LastValue(expr) => match self.frame(position)? {
+                frame if frame.is_empty() => Value::Null,
+                frame => expr.evaluate(Some(self.row(frame.end - 1)))?,
+            },
+
+            WindowFunction::Ntile(expr) => {
+                let buckets = match expr.evaluate(Some(row))? {
+                    Value::Integer(n) if n > 0 => n as usize,
+                    Value::Null => return Ok(Value::Null),
+                    value => return errinput!("invalid ntile bucket count {value}"),
+                };
+                // The first len % buckets buckets get an extra row.
+                let (size, extra) = (self.len() / buckets, self.len() % buckets);
+                let bucket = match position < extra * (size + 1) {
+                    true => position / (size + 1),
+                    false => extra + (position - extra * (size + 1)) / size,
+                };
+                Value::Integer(bucket as i64 + 1)
+            }
+
+            WindowFunction::Rank => Value::Integer(self.peers[position].start as i64 + 1),
+
+            WindowFunction::RowNumber => Value::Integer(position as i64 + 1),
+        })
+    }
+
+    /// Returns the window frame of the row at the given position, as a range
+    /// of partition positions. The range may be empty.
+    fn frame(&self, position: usize) -> Result<Range<usize>> {
+        let WindowFrame { units, start, end } = &self.window.frame;
+        let len = self.len();
+        let range = match units {
+            FrameUnits::Rows => {
+                let offset = |value: &Value| match value {
+                    Value::Integer(offset @ 0..) => Ok(*offset as usize),
+                    value => errinput!("invalid ROWS offset {value}"),
+                };
+                let start = match start {
+                    FrameBound::UnboundedPreceding => 0,
+                    FrameBound::Preceding(offset_value) => {
+                        position.saturating_sub(offset(offset_value)?)
+                    }
+                    FrameBound::CurrentRow => position,
+                    FrameBound::Following(offset_value) => {
+                        position.saturating_add(offset(offset_value)?)
+                    }
+                    FrameBound::UnboundedFollowing => len,
+                };
+                let end = match end {
+                    FrameBound::UnboundedPreceding => 0,
+                    FrameBound::Preceding(offset_value) => {
+                        (position + 1).saturating_sub(offset(offset_value)?)
+                    }
+                    FrameBound::CurrentRow => position + 1,
+                    FrameBound::Following(offset_value) => {
+                        (position + 1).saturating_add(offset(offset_value)?)
+                    }
+                    FrameBound::UnboundedFollowing => len,
+                };
+                start..end
+            }
+
+            // RANGE offsets are applied to the ORDER BY value in the sort
+            // direction, and the frame contains the rows whose values are
+            // within the resulting bounds. NULL values are only within
+            // bounds of NULL values.
+            FrameUnits::Range => {
+                let bound = |bound: &FrameBound| -> Result<Option<Value>> {
+                    let (offset, preceding) = match bound {
+                        FrameBound::Preceding(offset) => (offset, true),
+                        FrameBound::Following(offset) => (offset, false),
+                        _ => return Ok(None),
+                    };
+                    let (value, direction) = (&self.keys[position][0], &self.window.order_by[0].1);
+                    Ok(Some(match (preceding, direction) {
+                        (true, Direction::Ascending) | (false, Direction::Descending) => {
+                            value.checked_sub(offset)?
+                        }
+                        (true, Direction::Descending) | (false, Direction::Ascending) => {
+                            value.checked_add(offset)?
+                        }
+                    }))
+                };
+                let start = match (start, bound(start)?) {
+                    (_, Some(value)) => self.keys.partition_point(|key| {
+                        compare(self.window, &key[..1], std::slice::from_ref(&value)).is_lt()
+                    }),
+                    (FrameBound::UnboundedPreceding, _) => 0,
+                    (FrameBound::CurrentRow, _) => self.peers[position].start,
+                    (_, None) => len,
+                };
+                let end = match (end, bound(end)?) {
+                    (_, Some(value)) => self.keys.partition_point(|key| {
+                        compare(self.window, &key[..1], std::slice::from_ref(&value)).is_le()
+                    }),
+                    (FrameBound::UnboundedFollowing, _) => len,
+                    (FrameBound::CurrentRow, _) => self.peers[position].end,
+                    (_, None) => 0,
+                };
+                start..end
+            }
+        };
+        Ok(range.start.min(len)..range.end.min(len))
+    }
+}
+
+/// Compares ORDER BY values in the window's sort order. Only compares as many
+/// values as given, which allows comparing prefixes.
+fn compare(window: &Window, a: &[Value], b: &[Value]) -> Ordering {
+    let directions = window.order_by.iter().map(|(_, direction)| direction);
+    for (a, b, direction) in izip!(a, b, directions) {
+        match a.cmp(b) {
+            Ordering::Equal => {}
+            order if *direction == Direction::Descending => return order.reverse(),
+            order => return order,
+        }
+    }
+    Ordering::Equal
+}
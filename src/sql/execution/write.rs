@@ -1,21 +1,21 @@
 use crate::errinput;
 use crate::error::Result;
 use crate::sql::engine::Transaction;
-use crate::sql::types::{Expression, Rows, Table};
+use crate::sql::types::{Expression, Rows, Table, Value};
+use crate::storage::mvcc;
 
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 /// Deletes rows, taking primary keys from the source (i.e. DELETE) using the
-/// primary_key column index. Returns the number of rows deleted.
+/// primary_key column indexes. Returns the number of rows deleted.
 pub(super) fn delete(
     txn: &impl Transaction,
     table: String,
-    primary_key: usize,
+    primary_key: Vec<usize>,
     source: Rows,
 ) -> Result<u64> {
-    let ids = source
-        .map(|r| r.map(|row| row.into_iter().nth(primary_key).expect("short row")))
-        .collect::<Result<Vec<_>>>()?;
+    let ids =
+        source.map(|r| r.map(|row| project(&row, &primary_key))).collect::<Result<Vec<_>>>()?;
     let count = ids.len() as u64;
     txn.delete(&table, &ids)?;
     Ok(count)
@@ -74,12 +74,46 @@ pub(super) fn insert(
     Ok(count)
 }
 
+/// Locks rows (i.e. SELECT ... FOR UPDATE/SHARE), taking primary keys from the
+/// source using the primary_key column indexes, and emits the locked rows. If
+/// skip_locked is true, rows that can't be locked are skipped.
+///
+/// If limit is given, only that many rows are locked and emitted. Rows are
+/// locked in batches of the remaining number of rows needed, to avoid locking
+/// more rows than necessary while still amortizing the locking cost. Without a
+/// limit, all source rows are locked in a single batch.
+pub(super) fn lock(
+    txn: &impl Transaction,
+    table: String,
+    primary_key: Vec<usize>,
+    mut source: Rows,
+    mode: mvcc::LockMode,
+    skip_locked: bool,
+    limit: Option<usize>,
+) -> Result<Rows> {
+    let mut locked = Vec::new();
+    loop {
+        let remaining = limit.map(|limit| limit - locked.len()).unwrap_or(usize::MAX);
+        if remaining == 0 {
+            break;
+        }
+        let batch = source.by_ref().take(remaining).collect::<Result<Vec<_>>>()?;
+        if batch.is_empty() {
+            break;
+        }
+        let ids: Vec<_> = batch.iter().map(|row| project(row, &primary_key)).collect();
+        let ids: HashSet<_> = txn.lock(&table, &ids, mode, skip_locked)?.into_iter().collect();
+        locked.extend(batch.into_iter().filter(|row| ids.contains(&project(row, &primary_key))));
+    }
+    Ok(Box::new(locked.into_iter().map(Ok)))
+}
+
 /// Updates rows passed in from the source (i.e. UPDATE). Returns the number of
 /// rows updated.
 pub(super) fn update(
     txn: &impl Transaction,
     table: String,
-    primary_key: usize,
+    primary_key: Vec<usize>,
     mut source: Rows,
     expressions: Vec<(usize, Expression)>,
 ) -> Result<u64> {
@@ -89,10 +123,14 @@ pub(super) fn update(
         for (column, expr) in &expressions {
             new[*column] = expr.evaluate(Some(&row))?;
         }
-        let id = row.into_iter().nth(primary_key).expect("short row");
-        updates.insert(id, new);
+        updates.insert(project(&row, &primary_key), new);
     }
     let count = updates.len() as u64;
     txn.update(&table, updates)?;
     Ok(count)
 }
+
+/// Returns the values of the given columns in a row, e.g. its primary key.
+fn project(row: &[Value], columns: &[usize]) -> Vec<Value> {
+    columns.iter().map(|i| row[*i].clone()).collect()
+}
@@ -10,7 +10,7 @@ mod tests {
     use crate::sql::engine::{Engine, Local, StatementResult};
     use crate::sql::planner::{Planner, Scope};
     use crate::sql::types::Value;
-    use crate::storage::engine::test::{Emit, Mirror, Operation};
+    use crate::storage::engine::test::{decode_binary, format_range, Emit, Mirror, Operation};
     use crate::storage::{self, Engine as _};
     use crossbeam::channel::Receiver;
     use itertools::Itertools as _;
@@ -40,7 +40,7 @@ mod tests {
         let bitcask =
             storage::BitCask::new(tempdir.path().join("bitcask")).expect("bitcask failed");
         let memory = storage::Memory::new();
-        let engine = Local::new(Emit::new(Mirror::new(bitcask, memory), op_tx));
+        let engine = Local::new(Emit::new(Mirror::new(bitcask, memory), op_tx)).expect("engine failed");
         let mut runner = SQLRunner::new(&engine, op_rx);
 
         goldenscript::run(&mut runner, path).expect("goldenscript failed")
@@ -61,7 +61,11 @@ mod tests {
 
     impl<'a> SQLRunner<'a> {
         fn new(engine: &'a TestEngine, op_rx: Receiver<Operation>) -> Self {
-            let session = engine.session();
+            // Discard any operations from opening the engine.
+            while op_rx.try_recv().is_ok() {}
+            // Backfill indexes in small batches, to exercise batching.
+            let mut session = engine.session();
+            session.set_backfill_batch_size(2);
             Self { engine, session, op_rx }
         }
     }
@@ -72,6 +76,43 @@ mod tests {
 
             // Handle runner commands.
             match command.name.as_str() {
+                // check
+                // Checks the SQL data for inconsistencies.
+                "check" => {
+                    command.consume_args().reject_rest()?;
+                    let problems = self.engine.check()?;
+                    if problems.is_empty() {
+                        writeln!(output, "ok")?;
+                    }
+                    for problem in problems {
+                        writeln!(output, "{problem}")?;
+                    }
+                    return Ok(output);
+                }
+
+                // create_index_interrupted STATEMENT backfilled=N
+                // Executes a CREATE INDEX statement, but only backfills the
+                // first N rows, as if the backfill was interrupted.
+                "create_index_interrupted" => {
+                    let mut args = command.consume_args();
+                    let statement = args.next_pos().ok_or("statement not given")?.value.clone();
+                    let backfilled: usize =
+                        args.lookup_parse("backfilled")?.ok_or("backfilled not given")?;
+                    args.reject_rest()?;
+                    let ast = Parser::new(&statement).parse()?;
+                    let plan = self.session.with_txn(false, |txn| Planner::new(txn).build(ast))?;
+                    let Plan::CreateIndex { index, .. } = &plan else {
+                        return Err("expected CREATE INDEX statement".into());
+                    };
+                    let name = index.name.clone();
+                    self.session.with_txn(false, |txn| plan.execute(txn))?;
+                    if backfilled > 0 {
+                        self.session
+                            .with_txn(false, |txn| txn.backfill_index(&name, None, backfilled))?;
+                    }
+                    return Ok(output);
+                }
+
                 // dump
                 "dump" => {
                     command.consume_args().reject_rest()?;
@@ -88,6 +129,18 @@ mod tests {
                     return Ok(output);
                 }
 
+                // engine_delete KEY...
+                // Deletes raw keys directly in the storage engine.
+                "engine_delete" => {
+                    let mut args = command.consume_args();
+                    let mut engine = self.engine.mvcc.engine.lock().expect("mutex failed");
+                    for arg in args.rest_pos() {
+                        engine.delete(&decode_binary(&arg.value))?;
+                    }
+                    args.reject_rest()?;
+                    return Ok(output);
+                }
+
                 // schema [TABLE...]
                 "schema" => {
                     let mut args = command.consume_args();
@@ -173,6 +226,12 @@ mod tests {
                             format::MVCC::<format::SQL>::key(&key),
                             format::Raw::key(&key),
                         )?,
+                        Operation::DeleteRange { range } => writeln!(
+                            output,
+                            "storage delete_range {} [{}]",
+                            format_range::<format::MVCC<format::SQL>>(&range),
+                            format_range::<format::Raw>(&range),
+                        )?,
                         Operation::Flush => writeln!(output, "storage flush")?,
                         Operation::Set { key, value } => writeln!(
                             output,
@@ -209,7 +268,7 @@ mod tests {
 
     impl ExpressionRunner {
         fn new() -> Self {
-            let engine = Local::new(storage::Memory::new());
+            let engine = Local::new(storage::Memory::new()).expect("engine failed");
             Self { engine }
         }
     }
@@ -5,7 +5,8 @@ use std::collections::BTreeMap;
 /// The statement AST is the root node of the AST tree, which describes the
 /// syntactic structure of a SQL query. It is passed to the planner, which
 /// validates its contents and converts it into an execution plan.
-#[derive(Debug)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[allow(clippy::large_enum_variant)]
 pub enum Statement {
     Begin {
         read_only: bool,
@@ -17,11 +18,26 @@ pub enum Statement {
     CreateTable {
         name: String,
         columns: Vec<Column>,
+        primary_key: Vec<String>, // PRIMARY KEY table constraint, if any
     },
     DropTable {
         name: String,
         if_exists: bool,
     },
+    AlterTable {
+        name: String,
+        alteration: Alteration,
+    },
+    CreateIndex {
+        name: String,
+        table: String,
+        columns: Vec<String>,
+        unique: bool,
+    },
+    DropIndex {
+        name: String,
+        if_exists: bool,
+    },
     Delete {
         table: String,
         r#where: Option<Expression>,
@@ -37,6 +53,8 @@ pub enum Statement {
         r#where: Option<Expression>,
     },
     Select {
+        with: Vec<CommonTableExpression>,
+        distinct: Option<Vec<Expression>>, // DISTINCT [ON (expressions)], empty for plain DISTINCT
         select: Vec<(Expression, Option<String>)>,
         from: Vec<From>,
         r#where: Option<Expression>,
@@ -45,18 +63,61 @@ pub enum Statement {
         order_by: Vec<(Expression, Order)>,
         offset: Option<Expression>,
         limit: Option<Expression>,
+        lock: Option<Lock>,
+    },
+    /// Combines the rows of two queries. The operands are SELECT statements or
+    /// nested set operations, without WITH, ORDER BY, LIMIT, OFFSET, or
+    /// locking clauses -- these apply to the combined result instead.
+    SetOperation {
+        with: Vec<CommonTableExpression>,
+        operator: SetOperator,
+        all: bool,
+        left: Box<Statement>,
+        right: Box<Statement>,
+        order_by: Vec<(Expression, Order)>,
+        offset: Option<Expression>,
+        limit: Option<Expression>,
     },
 }
 
+/// A WITH common table expression (CTE): name [(columns)] AS (query). For WITH
+/// RECURSIVE, the query can be a UNION [ALL] whose right-hand side is a
+/// recursive term that references the CTE itself.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct CommonTableExpression {
+    pub name: String,
+    pub columns: Vec<String>, // empty if not given
+    pub query: Box<Statement>,
+    pub recursive: bool,
+}
+
+/// Set operators.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SetOperator {
+    Except,
+    Intersect,
+    Union,
+}
+
+impl std::fmt::Display for SetOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Except => "EXCEPT",
+            Self::Intersect => "INTERSECT",
+            Self::Union => "UNION",
+        })
+    }
+}
+
 /// A FROM item: a table or join.
-#[derive(Debug)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum From {
     Table { name: String, alias: Option<String> },
     Join { left: Box<From>, right: Box<From>, r#type: JoinType, predicate: Option<Expression> },
 }
 
 /// A CREATE TABLE column definition.
-#[derive(Debug)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Column {
     pub name: String,
     pub datatype: DataType,
@@ -68,8 +129,19 @@ pub struct Column {
     pub references: Option<String>,
 }
 
+/// An ALTER TABLE schema change.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Alteration {
+    AddColumn(Column),
+    DropColumn(String),
+    RenameColumn { column: String, new_name: String },
+    RenameTable(String),
+    SetDefault { column: String, default: Option<Expression> }, // None for DROP DEFAULT
+    SetNullable { column: String, nullable: bool },
+}
+
 /// JOIN types.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum JoinType {
     Cross,
     Inner,
@@ -88,8 +160,23 @@ impl JoinType {
     }
 }
 
+/// A SELECT row locking clause: FOR UPDATE|SHARE [NOWAIT|SKIP LOCKED].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Lock {
+    pub mode: LockMode,
+    /// SKIP LOCKED: skip rows that can't be locked, instead of erroring.
+    pub skip_locked: bool,
+}
+
+/// Row lock modes.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum LockMode {
+    Share,
+    Update,
+}
+
 /// Sort orders.
-#[derive(Debug)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Order {
     Ascending,
     Descending,
@@ -104,10 +191,54 @@ pub enum Expression {
     Column(Option<String>, String),
     /// A literal value.
     Literal(Literal),
-    /// A function call (name and parameters).
-    Function(String, Vec<Expression>),
+    /// A function call (name, parameters, and DISTINCT for aggregates).
+    Function(String, Vec<Expression>, bool),
+    /// A window function call (name, parameters, and OVER window).
+    WindowFunction(String, Vec<Expression>, Box<Window>),
     /// An operator.
     Operator(Operator),
+    /// A scalar subquery, returning a single value.
+    Subquery(Box<Statement>),
+    /// An EXISTS subquery, returning true if the subquery returns any rows.
+    Exists(Box<Statement>),
+}
+
+/// A window function OVER clause, which specifies the window of rows that the
+/// function is evaluated over for each row: the rows in the same partition,
+/// limited to the window frame around the row in the given order.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Window {
+    pub partition_by: Vec<Expression>,
+    pub order_by: Vec<(Expression, Order)>,
+    pub frame: Option<WindowFrame>, // None for the default frame
+}
+
+/// A window frame: {ROWS|RANGE} [BETWEEN] start [AND end].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct WindowFrame {
+    pub units: FrameUnits,
+    pub start: FrameBound,
+    pub end: FrameBound, // CURRENT ROW if not given
+}
+
+/// Window frame units.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum FrameUnits {
+    /// The frame bounds are offsets in the ORDER BY value, and CURRENT ROW
+    /// includes peer rows with the same ORDER BY values.
+    Range,
+    /// The frame bounds are row offsets.
+    Rows,
+}
+
+/// Window frame bounds.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum FrameBound {
+    CurrentRow,
+    Following(Expression),
+    Preceding(Expression),
+    UnboundedFollowing,
+    UnboundedPreceding,
 }
 
 /// Expression literals.
@@ -183,6 +314,8 @@ pub enum Operator {
     Subtract(Box<Expression>, Box<Expression>),
 
     Like(Box<Expression>, Box<Expression>),
+
+    In(Box<Expression>, Box<Expression>), // rhs is a subquery
 }
 
 impl Expression {
@@ -201,6 +334,7 @@ impl Expression {
             | Self::Operator(Exponentiate(lhs, rhs))
             | Self::Operator(GreaterThan(lhs, rhs))
             | Self::Operator(GreaterThanOrEqual(lhs, rhs))
+            | Self::Operator(In(lhs, rhs))
             | Self::Operator(LessThan(lhs, rhs))
             | Self::Operator(LessThanOrEqual(lhs, rhs))
             | Self::Operator(Like(lhs, rhs))
@@ -217,9 +351,19 @@ impl Expression {
             | Self::Operator(Negate(expr))
             | Self::Operator(Not(expr)) => expr.walk(visitor),
 
-            Self::Function(_, exprs) => exprs.iter().any(|expr| expr.walk(visitor)),
+            Self::Function(_, exprs, _) => exprs.iter().all(|expr| expr.walk(visitor)),
+
+            Self::WindowFunction(_, exprs, window) => {
+                exprs.iter().all(|expr| expr.walk(visitor))
+                    && window.partition_by.iter().all(|expr| expr.walk(visitor))
+                    && window.order_by.iter().all(|(expr, _)| expr.walk(visitor))
+            }
 
-            Self::All | Self::Column(_, _) | Self::Literal(_) => true,
+            Self::All
+            | Self::Column(_, _)
+            | Self::Exists(_)
+            | Self::Literal(_)
+            | Self::Subquery(_) => true,
         }
     }
 
@@ -245,6 +389,7 @@ impl Expression {
             | Self::Operator(Exponentiate(lhs, rhs))
             | Self::Operator(GreaterThan(lhs, rhs))
             | Self::Operator(GreaterThanOrEqual(lhs, rhs))
+            | Self::Operator(In(lhs, rhs))
             | Self::Operator(LessThan(lhs, rhs))
             | Self::Operator(LessThanOrEqual(lhs, rhs))
             | Self::Operator(Like(lhs, rhs))
@@ -264,9 +409,19 @@ impl Expression {
             | Self::Operator(Negate(expr))
             | Self::Operator(Not(expr)) => expr.collect(visitor, c),
 
-            Self::Function(_, exprs) => exprs.iter().for_each(|expr| expr.collect(visitor, c)),
+            Self::Function(_, exprs, _) => exprs.iter().for_each(|expr| expr.collect(visitor, c)),
+
+            Self::WindowFunction(_, exprs, window) => {
+                exprs.iter().for_each(|expr| expr.collect(visitor, c));
+                window.partition_by.iter().for_each(|expr| expr.collect(visitor, c));
+                window.order_by.iter().for_each(|(expr, _)| expr.collect(visitor, c));
+            }
 
-            Self::All | Self::Column(_, _) | Self::Literal(_) => {}
+            Self::All
+            | Self::Column(_, _)
+            | Self::Exists(_)
+            | Self::Literal(_)
+            | Self::Subquery(_) => {}
         }
     }
 }
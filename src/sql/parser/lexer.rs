@@ -5,6 +5,7 @@ use crate::error::Result;
 /// lexical tokens (e.g. keyword, number, string, etc) that are passed onto the
 /// SQL parser. In doing so, it strips away basic syntactic noise such as
 /// whitespace, case, and quotes, and performs initial validation of symbols.
+#[derive(Clone)]
 pub struct Lexer<'a> {
     chars: std::iter::Peekable<std::str::Chars<'a>>,
 }
@@ -87,35 +88,47 @@ impl From<Keyword> for Token {
 /// Reserved SQL keywords.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Keyword {
+    Add,
+    All,
+    Alter,
     And,
     As,
     Asc,
     Begin,
+    Between,
     Bool,
     Boolean,
     By,
+    Column,
     Commit,
     Create,
     Cross,
+    Current,
     Default,
     Delete,
     Desc,
+    Distinct,
     Double,
     Drop,
+    Except,
     Exists,
     Explain,
     False,
     Float,
+    Following,
+    For,
     From,
     Group,
     Having,
     If,
+    In,
     Index,
     Infinity,
     Inner,
     Insert,
     Int,
     Integer,
+    Intersect,
     Into,
     Is,
     Join,
@@ -123,8 +136,10 @@ pub enum Keyword {
     Left,
     Like,
     Limit,
+    Locked,
     NaN,
     Not,
+    Nowait,
     Null,
     Of,
     Offset,
@@ -133,25 +148,39 @@ pub enum Keyword {
     Or,
     Order,
     Outer,
+    Over,
+    Partition,
+    Preceding,
     Primary,
+    Range,
     Read,
+    Recursive,
     References,
+    Rename,
     Right,
     Rollback,
+    Row,
+    Rows,
     Select,
     Set,
+    Share,
+    Skip,
     String,
     System,
     Table,
     Text,
     Time,
+    To,
     Transaction,
     True,
+    Unbounded,
+    Union,
     Unique,
     Update,
     Values,
     Varchar,
     Where,
+    With,
     Write,
 }
 
@@ -164,35 +193,47 @@ impl TryFrom<&str> for Keyword {
         // allocating a string to change the case. Assert this.
         debug_assert!(value.chars().all(|c| !c.is_uppercase()), "keyword must be lowercase");
         Ok(match value {
+            "add" => Self::Add,
+            "all" => Self::All,
+            "alter" => Self::Alter,
             "as" => Self::As,
             "asc" => Self::Asc,
             "and" => Self::And,
             "begin" => Self::Begin,
+            "between" => Self::Between,
             "bool" => Self::Bool,
             "boolean" => Self::Boolean,
             "by" => Self::By,
+            "column" => Self::Column,
             "commit" => Self::Commit,
             "create" => Self::Create,
             "cross" => Self::Cross,
+            "current" => Self::Current,
             "default" => Self::Default,
             "delete" => Self::Delete,
             "desc" => Self::Desc,
+            "distinct" => Self::Distinct,
             "double" => Self::Double,
             "drop" => Self::Drop,
+            "except" => Self::Except,
             "exists" => Self::Exists,
             "explain" => Self::Explain,
             "false" => Self::False,
             "float" => Self::Float,
+            "following" => Self::Following,
+            "for" => Self::For,
             "from" => Self::From,
             "group" => Self::Group,
             "having" => Self::Having,
             "if" => Self::If,
+            "in" => Self::In,
             "index" => Self::Index,
             "infinity" => Self::Infinity,
             "inner" => Self::Inner,
             "insert" => Self::Insert,
             "int" => Self::Int,
             "integer" => Self::Integer,
+            "intersect" => Self::Intersect,
             "into" => Self::Into,
             "is" => Self::Is,
             "join" => Self::Join,
@@ -200,8 +241,10 @@ impl TryFrom<&str> for Keyword {
             "left" => Self::Left,
             "like" => Self::Like,
             "limit" => Self::Limit,
+            "locked" => Self::Locked,
             "nan" => Self::NaN,
             "not" => Self::Not,
+            "nowait" => Self::Nowait,
             "null" => Self::Null,
             "of" => Self::Of,
             "offset" => Self::Offset,
@@ -210,25 +253,39 @@ impl TryFrom<&str> for Keyword {
             "or" => Self::Or,
             "order" => Self::Order,
             "outer" => Self::Outer,
+            "over" => Self::Over,
+            "partition" => Self::Partition,
+            "preceding" => Self::Preceding,
             "primary" => Self::Primary,
+            "range" => Self::Range,
             "read" => Self::Read,
+            "recursive" => Self::Recursive,
             "references" => Self::References,
+            "rename" => Self::Rename,
             "right" => Self::Right,
             "rollback" => Self::Rollback,
+            "row" => Self::Row,
+            "rows" => Self::Rows,
             "select" => Self::Select,
             "set" => Self::Set,
+            "share" => Self::Share,
+            "skip" => Self::Skip,
             "string" => Self::String,
             "system" => Self::System,
             "table" => Self::Table,
             "text" => Self::Text,
             "time" => Self::Time,
+            "to" => Self::To,
             "transaction" => Self::Transaction,
             "true" => Self::True,
+            "unbounded" => Self::Unbounded,
+            "union" => Self::Union,
             "unique" => Self::Unique,
             "update" => Self::Update,
             "values" => Self::Values,
             "varchar" => Self::Varchar,
             "where" => Self::Where,
+            "with" => Self::With,
             "write" => Self::Write,
             _ => return Err("not a keyword"),
         })
@@ -239,35 +296,47 @@ impl std::fmt::Display for Keyword {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         // Display keywords as uppercase.
         f.write_str(match self {
+            Self::Add => "ADD",
+            Self::All => "ALL",
+            Self::Alter => "ALTER",
             Self::As => "AS",
             Self::Asc => "ASC",
             Self::And => "AND",
             Self::Begin => "BEGIN",
+            Self::Between => "BETWEEN",
             Self::Bool => "BOOL",
             Self::Boolean => "BOOLEAN",
             Self::By => "BY",
+            Self::Column => "COLUMN",
             Self::Commit => "COMMIT",
             Self::Create => "CREATE",
             Self::Cross => "CROSS",
+            Self::Current => "CURRENT",
             Self::Default => "DEFAULT",
             Self::Delete => "DELETE",
             Self::Desc => "DESC",
+            Self::Distinct => "DISTINCT",
             Self::Double => "DOUBLE",
             Self::Drop => "DROP",
+            Self::Except => "EXCEPT",
             Self::Exists => "EXISTS",
             Self::Explain => "EXPLAIN",
             Self::False => "FALSE",
             Self::Float => "FLOAT",
+            Self::Following => "FOLLOWING",
+            Self::For => "FOR",
             Self::From => "FROM",
             Self::Group => "GROUP",
             Self::Having => "HAVING",
             Self::If => "IF",
+            Self::In => "IN",
             Self::Index => "INDEX",
             Self::Infinity => "INFINITY",
             Self::Inner => "INNER",
             Self::Insert => "INSERT",
             Self::Int => "INT",
             Self::Integer => "INTEGER",
+            Self::Intersect => "INTERSECT",
             Self::Into => "INTO",
             Self::Is => "IS",
             Self::Join => "JOIN",
@@ -275,35 +344,51 @@ impl std::fmt::Display for Keyword {
             Self::Left => "LEFT",
             Self::Like => "LIKE",
             Self::Limit => "LIMIT",
+            Self::Locked => "LOCKED",
             Self::NaN => "NAN",
             Self::Not => "NOT",
+            Self::Nowait => "NOWAIT",
             Self::Null => "NULL",
             Self::Of => "OF",
             Self::Offset => "OFFSET",
             Self::On => "ON",
             Self::Only => "ONLY",
             Self::Outer => "OUTER",
+            Self::Over => "OVER",
+            Self::Partition => "PARTITION",
+            Self::Preceding => "PRECEDING",
             Self::Or => "OR",
             Self::Order => "ORDER",
             Self::Primary => "PRIMARY",
+            Self::Range => "RANGE",
             Self::Read => "READ",
+            Self::Recursive => "RECURSIVE",
             Self::References => "REFERENCES",
+            Self::Rename => "RENAME",
             Self::Right => "RIGHT",
             Self::Rollback => "ROLLBACK",
+            Self::Row => "ROW",
+            Self::Rows => "ROWS",
             Self::Select => "SELECT",
             Self::Set => "SET",
+            Self::Share => "SHARE",
+            Self::Skip => "SKIP",
             Self::String => "STRING",
             Self::System => "SYSTEM",
             Self::Table => "TABLE",
             Self::Text => "TEXT",
             Self::Time => "TIME",
+            Self::To => "TO",
             Self::Transaction => "TRANSACTION",
             Self::True => "TRUE",
+            Self::Unbounded => "UNBOUNDED",
+            Self::Union => "UNION",
             Self::Unique => "UNIQUE",
             Self::Update => "UPDATE",
             Self::Values => "VALUES",
             Self::Varchar => "VARCHAR",
             Self::Where => "WHERE",
+            Self::With => "WITH",
             Self::Write => "WRITE",
         })
     }
@@ -103,12 +103,13 @@ impl<'a> Parser<'a> {
             Some(Token::Keyword(Keyword::Rollback)) => self.parse_rollback(),
             Some(Token::Keyword(Keyword::Explain)) => self.parse_explain(),
 
-            Some(Token::Keyword(Keyword::Create)) => self.parse_create_table(),
-            Some(Token::Keyword(Keyword::Drop)) => self.parse_drop_table(),
+            Some(Token::Keyword(Keyword::Create)) => self.parse_create(),
+            Some(Token::Keyword(Keyword::Drop)) => self.parse_drop(),
+            Some(Token::Keyword(Keyword::Alter)) => self.parse_alter_table(),
 
             Some(Token::Keyword(Keyword::Delete)) => self.parse_delete(),
             Some(Token::Keyword(Keyword::Insert)) => self.parse_insert(),
-            Some(Token::Keyword(Keyword::Select)) => self.parse_select(),
+            Some(Token::Keyword(Keyword::Select | Keyword::With)) => self.parse_select(),
             Some(Token::Keyword(Keyword::Update)) => self.parse_update(),
 
             Some(token) => errinput!("unexpected token {token}"),
@@ -164,21 +165,43 @@ impl<'a> Parser<'a> {
         Ok(ast::Statement::Explain(Box::new(self.parse_statement()?)))
     }
 
-    /// Parses a CREATE TABLE statement.
-    fn parse_create_table(&mut self) -> Result<ast::Statement> {
+    /// Parses a CREATE TABLE or CREATE INDEX statement.
+    fn parse_create(&mut self) -> Result<ast::Statement> {
         self.expect(Keyword::Create.into())?;
-        self.expect(Keyword::Table.into())?;
+        match self.next()? {
+            Token::Keyword(Keyword::Table) => self.parse_create_table(),
+            Token::Keyword(Keyword::Index) => self.parse_create_index(false),
+            Token::Keyword(Keyword::Unique) => {
+                self.expect(Keyword::Index.into())?;
+                self.parse_create_index(true)
+            }
+            token => errinput!("unexpected token {token}"),
+        }
+    }
+
+    /// Parses a CREATE TABLE statement, after CREATE TABLE. A composite
+    /// primary key can be given as a PRIMARY KEY (columns) table constraint.
+    fn parse_create_table(&mut self) -> Result<ast::Statement> {
         let name = self.next_ident()?;
         self.expect(Token::OpenParen)?;
         let mut columns = Vec::new();
+        let mut primary_key = Vec::new();
         loop {
-            columns.push(self.parse_create_table_column()?);
+            if self.next_is(Keyword::Primary.into()) {
+                self.expect(Keyword::Key.into())?;
+                if !primary_key.is_empty() {
+                    return errinput!("multiple primary keys for table {name}");
+                }
+                primary_key = self.parse_column_list()?;
+            } else {
+                columns.push(self.parse_create_table_column()?);
+            }
             if !self.next_is(Token::Comma) {
                 break;
             }
         }
         self.expect(Token::CloseParen)?;
-        Ok(ast::Statement::CreateTable { name, columns })
+        Ok(ast::Statement::CreateTable { name, columns, primary_key })
     }
 
     /// Parses a CREATE TABLE column definition.
@@ -230,17 +253,104 @@ impl<'a> Parser<'a> {
         Ok(column)
     }
 
-    /// Parses a DROP TABLE statement.
-    fn parse_drop_table(&mut self) -> Result<ast::Statement> {
+    /// Parses a CREATE [UNIQUE] INDEX statement, after CREATE [UNIQUE] INDEX.
+    fn parse_create_index(&mut self, unique: bool) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        self.expect(Keyword::On.into())?;
+        let table = self.next_ident()?;
+        let columns = self.parse_column_list()?;
+        Ok(ast::Statement::CreateIndex { name, table, columns, unique })
+    }
+
+    /// Parses a parenthesized, comma-separated list of column names.
+    fn parse_column_list(&mut self) -> Result<Vec<String>> {
+        self.expect(Token::OpenParen)?;
+        let mut columns = Vec::new();
+        loop {
+            columns.push(self.next_ident()?);
+            if !self.next_is(Token::Comma) {
+                break;
+            }
+        }
+        self.expect(Token::CloseParen)?;
+        Ok(columns)
+    }
+
+    /// Parses a DROP TABLE or DROP INDEX statement.
+    fn parse_drop(&mut self) -> Result<ast::Statement> {
         self.expect(Token::Keyword(Keyword::Drop))?;
-        self.expect(Token::Keyword(Keyword::Table))?;
+        let index = match self.next()? {
+            Token::Keyword(Keyword::Table) => false,
+            Token::Keyword(Keyword::Index) => true,
+            token => return errinput!("unexpected token {token}"),
+        };
         let mut if_exists = false;
         if self.next_is(Keyword::If.into()) {
             self.expect(Token::Keyword(Keyword::Exists))?;
             if_exists = true;
         }
         let name = self.next_ident()?;
-        Ok(ast::Statement::DropTable { name, if_exists })
+        match index {
+            false => Ok(ast::Statement::DropTable { name, if_exists }),
+            true => Ok(ast::Statement::DropIndex { name, if_exists }),
+        }
+    }
+
+    /// Parses an ALTER TABLE statement. Only a single alteration can be given.
+    fn parse_alter_table(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Alter.into())?;
+        self.expect(Keyword::Table.into())?;
+        let name = self.next_ident()?;
+        let alteration = match self.next()? {
+            Token::Keyword(Keyword::Add) => {
+                self.skip(Keyword::Column.into());
+                ast::Alteration::AddColumn(self.parse_create_table_column()?)
+            }
+            Token::Keyword(Keyword::Drop) => {
+                self.skip(Keyword::Column.into());
+                ast::Alteration::DropColumn(self.next_ident()?)
+            }
+            Token::Keyword(Keyword::Rename) => {
+                if self.next_is(Keyword::To.into()) {
+                    ast::Alteration::RenameTable(self.next_ident()?)
+                } else {
+                    self.skip(Keyword::Column.into());
+                    let column = self.next_ident()?;
+                    self.expect(Keyword::To.into())?;
+                    ast::Alteration::RenameColumn { column, new_name: self.next_ident()? }
+                }
+            }
+            Token::Keyword(Keyword::Alter) => {
+                self.skip(Keyword::Column.into());
+                let column = self.next_ident()?;
+                match self.next()? {
+                    Token::Keyword(Keyword::Set) => match self.next()? {
+                        Token::Keyword(Keyword::Default) => {
+                            let default = Some(self.parse_expression()?);
+                            ast::Alteration::SetDefault { column, default }
+                        }
+                        Token::Keyword(Keyword::Not) => {
+                            self.expect(Keyword::Null.into())?;
+                            ast::Alteration::SetNullable { column, nullable: false }
+                        }
+                        token => return errinput!("unexpected token {token}"),
+                    },
+                    Token::Keyword(Keyword::Drop) => match self.next()? {
+                        Token::Keyword(Keyword::Default) => {
+                            ast::Alteration::SetDefault { column, default: None }
+                        }
+                        Token::Keyword(Keyword::Not) => {
+                            self.expect(Keyword::Null.into())?;
+                            ast::Alteration::SetNullable { column, nullable: true }
+                        }
+                        token => return errinput!("unexpected token {token}"),
+                    },
+                    token => return errinput!("unexpected token {token}"),
+                }
+            }
+            token => return errinput!("unexpected token {token}"),
+        };
+        Ok(ast::Statement::AlterTable { name, alteration })
     }
 
     /// Parses a DELETE statement.
@@ -319,31 +429,162 @@ impl<'a> Parser<'a> {
         Ok(ast::Statement::Update { table, set, r#where: self.parse_where_clause()? })
     }
 
-    /// Parses a SELECT statement.
+    /// Parses a SELECT statement, possibly combined with set operations.
     fn parse_select(&mut self) -> Result<ast::Statement> {
+        let with = self.parse_with_clause()?;
+        let statement = self.parse_set_operation(0)?;
+        let order_by = self.parse_order_by_clause()?;
+        // The locking clause can be given either before or after LIMIT/OFFSET.
+        let mut lock = self.parse_lock_clause()?;
+        let limit =
+            self.next_is(Keyword::Limit.into()).then(|| self.parse_expression()).transpose()?;
+        let offset =
+            self.next_is(Keyword::Offset.into()).then(|| self.parse_expression()).transpose()?;
+        if lock.is_none() {
+            lock = self.parse_lock_clause()?;
+        }
+        Ok(match statement {
+            ast::Statement::Select {
+                distinct, select, from, r#where, group_by, having, ..
+            } => ast::Statement::Select {
+                with,
+                distinct,
+                select,
+                from,
+                r#where,
+                group_by,
+                having,
+                order_by,
+                offset,
+                limit,
+                lock,
+            },
+            ast::Statement::SetOperation { operator, all, left, right, .. } => {
+                if lock.is_some() {
+                    return errinput!("FOR UPDATE/SHARE is not allowed with set operations");
+                }
+                ast::Statement::SetOperation {
+                    with,
+                    operator,
+                    all,
+                    left,
+                    right,
+                    order_by,
+                    offset,
+                    limit,
+                }
+            }
+            statement => panic!("unexpected statement {statement:?}"),
+        })
+    }
+
+    /// Parses a chain of SELECT queries combined with set operators, using
+    /// precedence climbing (see parse_expression). INTERSECT binds tighter than
+    /// UNION and EXCEPT, and all are left-associative.
+    fn parse_set_operation(&mut self, min_precedence: Precedence) -> Result<ast::Statement> {
+        let mut lhs = self.parse_select_query()?;
+        loop {
+            let (operator, precedence) = match self.peek()? {
+                Some(Token::Keyword(Keyword::Union)) => (ast::SetOperator::Union, 1),
+                Some(Token::Keyword(Keyword::Except)) => (ast::SetOperator::Except, 1),
+                Some(Token::Keyword(Keyword::Intersect)) => (ast::SetOperator::Intersect, 2),
+                _ => break,
+            };
+            if precedence < min_precedence {
+                break;
+            }
+            self.next()?;
+            let all = self.next_is(Keyword::All.into());
+            if !all {
+                self.skip(Keyword::Distinct.into());
+            }
+            let rhs = self.parse_set_operation(precedence + LEFT_ASSOCIATIVE)?;
+            lhs = ast::Statement::SetOperation {
+                with: Vec::new(),
+                operator,
+                all,
+                left: Box::new(lhs),
+                right: Box::new(rhs),
+                order_by: Vec::new(),
+                offset: None,
+                limit: None,
+            };
+        }
+        Ok(lhs)
+    }
+
+    /// Parses a single SELECT query without WITH, ORDER BY, LIMIT, OFFSET, or
+    /// locking clauses, e.g. a set operation operand.
+    fn parse_select_query(&mut self) -> Result<ast::Statement> {
+        if !self.next_is(Keyword::Select.into()) {
+            return errinput!("expected SELECT, found {}", self.next()?);
+        }
         Ok(ast::Statement::Select {
+            with: Vec::new(),
+            distinct: self.parse_distinct_clause()?,
             select: self.parse_select_clause()?,
             from: self.parse_from_clause()?,
             r#where: self.parse_where_clause()?,
             group_by: self.parse_group_by_clause()?,
             having: self.parse_having_clause()?,
-            order_by: self.parse_order_by_clause()?,
-            limit: self
-                .next_is(Keyword::Limit.into())
-                .then(|| self.parse_expression())
-                .transpose()?,
-            offset: self
-                .next_is(Keyword::Offset.into())
-                .then(|| self.parse_expression())
-                .transpose()?,
+            order_by: Vec::new(),
+            offset: None,
+            limit: None,
+            lock: None,
         })
     }
 
-    /// Parses a SELECT clause, if present.
-    fn parse_select_clause(&mut self) -> Result<Vec<(ast::Expression, Option<String>)>> {
-        if !self.next_is(Keyword::Select.into()) {
+    /// Parses a WITH clause, if present.
+    fn parse_with_clause(&mut self) -> Result<Vec<ast::CommonTableExpression>> {
+        if !self.next_is(Keyword::With.into()) {
             return Ok(Vec::new());
         }
+        let recursive = self.next_is(Keyword::Recursive.into());
+        let mut with = Vec::new();
+        loop {
+            let name = self.next_ident()?;
+            let mut columns = Vec::new();
+            if self.peek()? == Some(&Token::OpenParen) {
+                columns = self.parse_column_list()?;
+            }
+            self.expect(Keyword::As.into())?;
+            self.expect(Token::OpenParen)?;
+            let query = Box::new(self.parse_select()?);
+            self.expect(Token::CloseParen)?;
+            with.push(ast::CommonTableExpression { name, columns, query, recursive });
+            if !self.next_is(Token::Comma) {
+                break;
+            }
+        }
+        if self.peek()? != Some(&Keyword::Select.into()) {
+            return errinput!("expected SELECT after WITH");
+        }
+        Ok(with)
+    }
+
+    /// Parses a DISTINCT [ON (expressions)] or ALL clause following SELECT, if
+    /// present. Returns the DISTINCT ON expressions, if any.
+    fn parse_distinct_clause(&mut self) -> Result<Option<Vec<ast::Expression>>> {
+        if !self.next_is(Keyword::Distinct.into()) {
+            self.skip(Keyword::All.into());
+            return Ok(None);
+        }
+        let mut on = Vec::new();
+        if self.next_is(Keyword::On.into()) {
+            self.expect(Token::OpenParen)?;
+            loop {
+                on.push(self.parse_expression()?);
+                if !self.next_is(Token::Comma) {
+                    break;
+                }
+            }
+            self.expect(Token::CloseParen)?;
+        }
+        Ok(Some(on))
+    }
+
+    /// Parses the SELECT expressions, following the SELECT keyword.
+    fn parse_select_clause(&mut self) -> Result<Vec<(ast::Expression, Option<String>)>> {
         let mut select = Vec::new();
         loop {
             let expr = self.parse_expression()?;
@@ -477,6 +718,27 @@ impl<'a> Parser<'a> {
         Ok(order_by)
     }
 
+    /// Parses a FOR UPDATE|SHARE [NOWAIT|SKIP LOCKED] clause, if present.
+    /// ToyDB never waits for locks, so NOWAIT is the default behavior.
+    fn parse_lock_clause(&mut self) -> Result<Option<ast::Lock>> {
+        if !self.next_is(Keyword::For.into()) {
+            return Ok(None);
+        }
+        let mode = match self.next()? {
+            Token::Keyword(Keyword::Update) => ast::LockMode::Update,
+            Token::Keyword(Keyword::Share) => ast::LockMode::Share,
+            token => return errinput!("unexpected token {token}"),
+        };
+        let mut skip_locked = false;
+        if self.next_is(Keyword::Skip.into()) {
+            self.expect(Keyword::Locked.into())?;
+            skip_locked = true;
+        } else {
+            self.skip(Keyword::Nowait.into());
+        }
+        Ok(Some(ast::Lock { mode, skip_locked }))
+    }
+
     /// Parses an expression consisting of at least one atom operated on by any
     /// number of operators, using the precedence climbing algorithm.
     /// TODO: write a description of the algorithm.
@@ -512,6 +774,7 @@ impl<'a> Parser<'a> {
     /// * A literal value.
     /// * A column name.
     /// * A function call.
+    /// * A subquery, either scalar or EXISTS.
     /// * A parenthesized expression.
     fn parse_expression_atom(&mut self) -> Result<ast::Expression> {
         Ok(match self.next()? {
@@ -532,6 +795,10 @@ impl<'a> Parser<'a> {
 
             // Function call.
             Token::Ident(name) if self.next_is(Token::OpenParen) => {
+                let distinct = self.next_is(Keyword::Distinct.into());
+                if !distinct {
+                    self.skip(Keyword::All.into());
+                }
                 let mut args = Vec::new();
                 while !self.next_is(Token::CloseParen) {
                     if !args.is_empty() {
@@ -539,7 +806,14 @@ impl<'a> Parser<'a> {
                     }
                     args.push(self.parse_expression()?);
                 }
-                ast::Expression::Function(name, args)
+                if self.next_is(Keyword::Over.into()) {
+                    if distinct {
+                        return errinput!("DISTINCT is not supported for window functions");
+                    }
+                    let window = self.parse_window()?;
+                    return Ok(ast::Expression::WindowFunction(name, args, Box::new(window)));
+                }
+                ast::Expression::Function(name, args, distinct)
             }
 
             // Column name, either qualified as table.column or unqualified.
@@ -548,6 +822,22 @@ impl<'a> Parser<'a> {
             }
             Token::Ident(column) => ast::Expression::Column(None, column),
 
+            // Scalar subquery.
+            Token::OpenParen
+                if matches!(
+                    self.peek()?,
+                    Some(Token::Keyword(Keyword::Select | Keyword::With))
+                ) =>
+            {
+                ast::Expression::Subquery(Box::new(self.parse_subquery()?))
+            }
+
+            // EXISTS subquery.
+            Token::Keyword(Keyword::Exists) => {
+                self.expect(Token::OpenParen)?;
+                ast::Expression::Exists(Box::new(self.parse_subquery()?))
+            }
+
             // Parenthesized expression.
             Token::OpenParen => {
                 let expr = self.parse_expression()?;
@@ -559,6 +849,77 @@ impl<'a> Parser<'a> {
         })
     }
 
+    /// Parses a window function OVER clause, after the OVER keyword:
+    /// ([PARTITION BY expressions] [ORDER BY expressions] [frame]).
+    fn parse_window(&mut self) -> Result<ast::Window> {
+        self.expect(Token::OpenParen)?;
+        let mut partition_by = Vec::new();
+        if self.next_is(Keyword::Partition.into()) {
+            self.expect(Keyword::By.into())?;
+            loop {
+                partition_by.push(self.parse_expression()?);
+                if !self.next_is(Token::Comma) {
+                    break;
+                }
+            }
+        }
+        let order_by = self.parse_order_by_clause()?;
+        let frame = self.parse_window_frame()?;
+        self.expect(Token::CloseParen)?;
+        Ok(ast::Window { partition_by, order_by, frame })
+    }
+
+    /// Parses a window frame, if present: {ROWS|RANGE} start, or {ROWS|RANGE}
+    /// BETWEEN start AND end.
+    fn parse_window_frame(&mut self) -> Result<Option<ast::WindowFrame>> {
+        let units = match self.next_if_keyword() {
+            Some(Keyword::Range) => ast::FrameUnits::Range,
+            Some(Keyword::Rows) => ast::FrameUnits::Rows,
+            Some(keyword) => return errinput!("unexpected token {keyword}"),
+            None => return Ok(None),
+        };
+        let (start, end) = if self.next_is(Keyword::Between.into()) {
+            let start = self.parse_window_frame_bound()?;
+            self.expect(Keyword::And.into())?;
+            (start, self.parse_window_frame_bound()?)
+        } else {
+            (self.parse_window_frame_bound()?, ast::FrameBound::CurrentRow)
+        };
+        Ok(Some(ast::WindowFrame { units, start, end }))
+    }
+
+    /// Parses a window frame bound: UNBOUNDED {PRECEDING|FOLLOWING}, CURRENT
+    /// ROW, or offset {PRECEDING|FOLLOWING}.
+    fn parse_window_frame_bound(&mut self) -> Result<ast::FrameBound> {
+        if self.next_is(Keyword::Unbounded.into()) {
+            return match self.next()? {
+                Token::Keyword(Keyword::Preceding) => Ok(ast::FrameBound::UnboundedPreceding),
+                Token::Keyword(Keyword::Following) => Ok(ast::FrameBound::UnboundedFollowing),
+                token => errinput!("expected PRECEDING or FOLLOWING, found {token}"),
+            };
+        }
+        if self.next_is(Keyword::Current.into()) {
+            self.expect(Keyword::Row.into())?;
+            return Ok(ast::FrameBound::CurrentRow);
+        }
+        let offset = self.parse_expression()?;
+        match self.next()? {
+            Token::Keyword(Keyword::Preceding) => Ok(ast::FrameBound::Preceding(offset)),
+            Token::Keyword(Keyword::Following) => Ok(ast::FrameBound::Following(offset)),
+            token => errinput!("expected PRECEDING or FOLLOWING, found {token}"),
+        }
+    }
+
+    /// Parses a parenthesized subquery, after the opening parenthesis.
+    fn parse_subquery(&mut self) -> Result<ast::Statement> {
+        if !matches!(self.peek()?, Some(Token::Keyword(Keyword::Select | Keyword::With))) {
+            return errinput!("expected SELECT subquery");
+        }
+        let select = self.parse_select()?;
+        self.expect(Token::CloseParen)?;
+        Ok(select)
+    }
+
     /// Parses a prefix operator, if there is one and it's precedence is at
     /// least min_precedence.
     fn parse_prefix_operator(
@@ -582,6 +943,23 @@ impl<'a> Parser<'a> {
         &mut self,
         min_precedence: Precedence,
     ) -> Result<Option<InfixOperator>> {
+        // Handle NOT IN separately, since it's multiple tokens. We have to look
+        // past NOT, since it can also follow an expression as e.g. the NOT NULL
+        // constraint of a column DEFAULT expression.
+        if let Some(Token::Keyword(Keyword::Not)) = self.peek()? {
+            if InfixOperator::NotIn.precedence() < min_precedence {
+                return Ok(None);
+            }
+            let mut lookahead = self.lexer.clone();
+            lookahead.next();
+            if !matches!(lookahead.next(), Some(Ok(Token::Keyword(Keyword::In)))) {
+                return Ok(None);
+            }
+            self.expect(Keyword::Not.into())?;
+            self.expect(Keyword::In.into())?;
+            return Ok(Some(InfixOperator::NotIn));
+        }
+
         self.next_if_map(|token| {
             let operator = match token {
                 Token::Asterisk => InfixOperator::Multiply,
@@ -590,6 +968,7 @@ impl<'a> Parser<'a> {
                 Token::GreaterThan => InfixOperator::GreaterThan,
                 Token::GreaterThanOrEqual => InfixOperator::GreaterThanOrEqual,
                 Token::Keyword(Keyword::And) => InfixOperator::And,
+                Token::Keyword(Keyword::In) => InfixOperator::In,
                 Token::Keyword(Keyword::Like) => InfixOperator::Like,
                 Token::Keyword(Keyword::Or) => InfixOperator::Or,
                 Token::LessOrGreaterThan => InfixOperator::NotEqual,
@@ -685,12 +1064,14 @@ enum InfixOperator {
     Exponentiate,
     GreaterThan,
     GreaterThanOrEqual,
+    In,
     LessThan,
     LessThanOrEqual,
     Like,
     Modulo,
     Multiply,
     NotEqual,
+    NotIn,
     Or,
     Subtract,
 }
@@ -701,7 +1082,7 @@ impl InfixOperator {
         match self {
             Self::Or => 1,
             Self::And => 2,
-            Self::Equal | Self::NotEqual | Self::Like => 3,
+            Self::Equal | Self::NotEqual | Self::Like | Self::In | Self::NotIn => 3,
             Self::GreaterThan
             | Self::GreaterThanOrEqual
             | Self::LessThan
@@ -731,12 +1112,14 @@ impl InfixOperator {
             Self::Exponentiate => ast::Operator::Exponentiate(lhs, rhs).into(),
             Self::GreaterThan => ast::Operator::GreaterThan(lhs, rhs).into(),
             Self::GreaterThanOrEqual => ast::Operator::GreaterThanOrEqual(lhs, rhs).into(),
+            Self::In => ast::Operator::In(lhs, rhs).into(),
             Self::LessThan => ast::Operator::LessThan(lhs, rhs).into(),
             Self::LessThanOrEqual => ast::Operator::LessThanOrEqual(lhs, rhs).into(),
             Self::Like => ast::Operator::Like(lhs, rhs).into(),
             Self::Modulo => ast::Operator::Modulo(lhs, rhs).into(),
             Self::Multiply => ast::Operator::Multiply(lhs, rhs).into(),
             Self::NotEqual => ast::Operator::NotEqual(lhs, rhs).into(),
+            Self::NotIn => ast::Operator::Not(ast::Operator::In(lhs, rhs).into()).into(),
             Self::Or => ast::Operator::Or(lhs, rhs).into(),
             Self::Subtract => ast::Operator::Subtract(lhs, rhs).into(),
         }
@@ -2,7 +2,10 @@ mod optimizer;
 mod plan;
 mod planner;
 
-pub use plan::{Aggregate, Direction, Node, Plan};
+pub use plan::{
+    Aggregate, Alteration, Direction, FrameBound, FrameUnits, JoinType, Node, Plan, SubqueryType,
+    Window, WindowFrame, WindowFunction,
+};
 
 #[cfg(test)]
 pub(crate) use optimizer::OPTIMIZERS;
@@ -1,8 +1,11 @@
-use super::Node;
+use super::{Direction, JoinType, Node};
 use crate::error::Result;
-use crate::sql::types::{Expression, Label, Value};
+use crate::sql::types::{DataType, Expression, Label, TupleRange, Value};
 
+use itertools::Itertools as _;
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::ops::Bound;
 
 /// A plan optimizer, which takes a root node and recursively transforms it.
 pub type Optimizer = fn(Node) -> Result<Node>;
@@ -12,6 +15,8 @@ pub static OPTIMIZERS: &[(&str, Optimizer)] = &[
     ("Constant folding", fold_constants),
     ("Filter pushdown", push_filters),
     ("Index lookup", index_lookup),
+    ("Sort elimination", eliminate_sort),
+    ("Limit pushdown", push_limits),
     ("Join type", join_type),
     ("Short circuit", short_circuit),
 ];
@@ -31,7 +36,7 @@ pub(super) fn fold_constants(node: Node) -> Result<Node> {
         // expression as 1 - 2 + a to evaluate the 1 - 2 branch).
         //
         // TODO: consider doing something better.
-        if !expr.contains(&|e| matches!(e, Expression::Column(_))) {
+        if !expr.contains(&|e| matches!(e, Expression::Column(_) | Expression::Outer(_))) {
             return expr.evaluate(None).map(Expression::Constant);
         }
 
@@ -79,6 +84,9 @@ pub(super) fn push_filters(node: Node) -> Result<Node> {
                 let rhs = std::mem::replace(predicate, Expression::Constant(Value::Null));
                 *predicate = Expression::And(expr.into(), rhs.into());
             }
+            // Anti joins emit left rows that don't match the predicate, so
+            // the filter can't be pushed into it.
+            Node::NestedLoopJoin { r#type: JoinType::Anti, .. } => return Some(expr),
             Node::NestedLoopJoin { predicate, .. } => {
                 *predicate = match predicate.take() {
                     Some(predicate) => Some(Expression::And(expr.into(), predicate.into())),
@@ -118,7 +126,7 @@ pub(super) fn push_filters(node: Node) -> Result<Node> {
     // Pushes down parts of a join predicate into the left or right sources
     // where possible.
     fn push_join(node: Node) -> Node {
-        let Node::NestedLoopJoin { mut left, mut right, predicate: Some(predicate), outer } = node
+        let Node::NestedLoopJoin { mut left, mut right, predicate: Some(predicate), r#type } = node
         else {
             return node;
         };
@@ -183,6 +191,12 @@ pub(super) fn push_filters(node: Node) -> Result<Node> {
             }
         }
 
+        // Anti joins emit left rows that don't match the predicate, so left
+        // expressions must remain in the join predicate.
+        if r#type == JoinType::Anti {
+            predicate.append(&mut push_left);
+        }
+
         // Push predicates down into the sources if possible.
         if let Some(expr) = Expression::and_vec(push_left) {
             if let Some(expr) = push_into(expr, &mut left) {
@@ -203,7 +217,7 @@ pub(super) fn push_filters(node: Node) -> Result<Node> {
 
         // Leave any remaining predicates in the join node.
         let predicate = Expression::and_vec(predicate);
-        Node::NestedLoopJoin { left, right, predicate, outer }
+        Node::NestedLoopJoin { left, right, predicate, r#type }
     }
 
     /// Applies pushdown transformations to a node.
@@ -217,7 +231,7 @@ pub(super) fn push_filters(node: Node) -> Result<Node> {
     node.transform(&|n| Ok(transform(n)), &Ok)
 }
 
-/// Uses an index or primary key lookup for a filter when possible.
+/// Uses an index or primary key lookup or range scan for a filter when possible.
 pub(super) fn index_lookup(node: Node) -> Result<Node> {
     let transform = |mut node| {
         // Only handle scan filters. filter_pushdown() must have pushed filters
@@ -225,30 +239,118 @@ pub(super) fn index_lookup(node: Node) -> Result<Node> {
         let Node::Scan { table, alias, filter: Some(filter) } = node else { return node };
 
         // Convert the filter into conjunctive normal form (a list of ANDs).
-        let mut cnf = filter.clone().into_cnf_vec();
-
-        // Find the first expression that's either a primary key or secondary
-        // index lookup. We could be more clever here, but this is fine.
-        let Some(i) = cnf.iter().enumerate().find_map(|(i, e)| {
-            e.is_column_lookup()
-                .filter(|f| *f == table.primary_key || table.columns[*f].index)
-                .and(Some(i))
-        }) else {
+        let cnf = filter.clone().into_cnf_vec();
+
+        // Find the column lookups in the CNF expressions, by column index. Only
+        // the first lookup for each column is used. Also find all range
+        // comparisons by column index. These can only use range scans when
+        // comparing with a constant of the column's data type, since keys of
+        // different data types aren't ordered by value.
+        let mut lookups = HashMap::new();
+        let mut ranges: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (i, expr) in cnf.iter().enumerate() {
+            if let Some(column) = expr.is_column_lookup() {
+                lookups.entry(column).or_insert(i);
+            } else if let Some((column, value)) = expr.is_column_range() {
+                if !value.is_undefined() && value.datatype() == Some(table.columns[column].datatype)
+                {
+                    ranges.entry(column).or_default().push(i);
+                }
+            }
+        }
+
+        // Find the primary key or secondary index with the most leading columns
+        // constrained by lookups, preferably followed by a range column. On
+        // ties, prefer the earliest expression and then the primary key. We
+        // could be more clever here, but this is fine.
+        let Some((primary_key, columns, prefix, range)) =
+            std::iter::once((true, table.primary_key.clone()))
+                .chain(table.readable_indexes().into_iter().map(|columns| (false, columns)))
+                .map(|(primary_key, columns)| {
+                    let prefix = columns.iter().take_while(|c| lookups.contains_key(c)).count();
+                    let range = columns.get(prefix).is_some_and(|c| ranges.contains_key(c));
+                    (primary_key, columns, prefix, range)
+                })
+                .filter(|(_, _, prefix, range)| *prefix > 0 || *range)
+                .min_by_key(|(_, columns, prefix, range)| {
+                    let first = lookups.get(&columns[0]).unwrap_or_else(|| &ranges[&columns[0]][0]);
+                    (Reverse(*prefix), Reverse(*range), *first)
+                })
+        else {
             return Node::Scan { table, alias, filter: Some(filter) };
         };
 
-        // Extract the lookup values and expression from the cnf vector.
-        let (column, values) = cnf.remove(i).into_column_values().expect("column lookup failed");
-
-        // Build the primary key or secondary index lookup node.
-        if column == table.primary_key {
-            node = Node::KeyLookup { table, keys: values, alias };
-        } else {
-            node = Node::IndexLookup { table, column, values, alias };
+        // Extract the lookup values of the used columns from the cnf vector.
+        let mut cnf: Vec<_> = cnf.into_iter().map(Some).collect();
+        let mut column_values = HashMap::new();
+        for column in &columns[..prefix] {
+            let expr = cnf[lookups[column]].take().expect("lookup already used");
+            let (column, values) = expr.into_column_values().expect("column lookup failed");
+            column_values.insert(column, values);
         }
 
+        // Range scans can only be used when the lookups yield a single key
+        // prefix, i.e. a single value for each lookup column.
+        let range = range && column_values.values().all(|values| values.len() == 1);
+
+        node = match (primary_key, range) {
+            // Build a primary key or secondary index range scan node, using
+            // the lookup values as the range prefix.
+            (primary_key, true) => {
+                let key: Vec<_> =
+                    columns[..prefix].iter().map(|c| column_values[c][0].clone()).collect();
+                let column = &table.columns[columns[prefix]];
+                let (mut start, mut end) = (Bound::Unbounded, Bound::Unbounded);
+                for i in &ranges[&columns[prefix]] {
+                    let expr = cnf[*i].take().expect("range already used");
+                    let (_, s, e) = expr.into_column_range().expect("column range failed");
+                    start = range_start(start, s);
+                    end = range_end(end, e);
+                }
+                let with = |value| key.iter().cloned().chain(std::iter::once(value)).collect();
+                let start = match start {
+                    Bound::Included(value) => Bound::Included(with(value)),
+                    Bound::Excluded(value) => Bound::Excluded(with(value)),
+                    // NULLs sort first in keys, and never match a comparison.
+                    Bound::Unbounded if column.nullable => Bound::Excluded(with(Value::Null)),
+                    Bound::Unbounded if key.is_empty() => Bound::Unbounded,
+                    Bound::Unbounded => Bound::Included(key.clone()),
+                };
+                let end = match end {
+                    Bound::Included(value) => Bound::Included(with(value)),
+                    Bound::Excluded(value) => Bound::Excluded(with(value)),
+                    // NaNs sort last in float keys, and never match a comparison.
+                    Bound::Unbounded if column.datatype == DataType::Float => {
+                        Bound::Excluded(with(Value::Float(f64::NAN)))
+                    }
+                    Bound::Unbounded if key.is_empty() => Bound::Unbounded,
+                    Bound::Unbounded => Bound::Included(key),
+                };
+                let range = (start, end);
+                match primary_key {
+                    true => Node::KeyRangeScan { table, range, reverse: false, alias },
+                    false => Node::IndexRangeScan { table, columns, range, reverse: false, alias },
+                }
+            }
+
+            // Build a primary key or secondary index lookup node, looking up
+            // all combinations of the column values (i.e. the cartesian
+            // product). These may be a prefix of the key or index columns.
+            (primary_key, false) => {
+                let values = columns[..prefix]
+                    .iter()
+                    .map(|c| column_values.remove(c).expect("missing lookup values"))
+                    .multi_cartesian_product()
+                    .collect();
+                match primary_key {
+                    true => Node::KeyLookup { table, keys: values, alias },
+                    false => Node::IndexLookup { table, columns, values, alias },
+                }
+            }
+        };
+
         // If there's any remaining CNF expressions add a filter node for them.
-        if let Some(predicate) = Expression::and_vec(cnf) {
+        if let Some(predicate) = Expression::and_vec(cnf.into_iter().flatten().collect()) {
             node = Node::Filter { source: Box::new(node), predicate };
         }
 
@@ -257,6 +359,245 @@ pub(super) fn index_lookup(node: Node) -> Result<Node> {
     node.transform(&Ok, &|n| Ok(transform(n)))
 }
 
+/// Returns the most restrictive of two range start bounds.
+fn range_start(a: Bound<Value>, b: Bound<Value>) -> Bound<Value> {
+    use Bound::*;
+    match (a, b) {
+        (Unbounded, bound) | (bound, Unbounded) => bound,
+        (Included(a), Included(b)) => Included(a.max(b)),
+        (Excluded(a), Excluded(b)) => Excluded(a.max(b)),
+        (Included(i), Excluded(e)) | (Excluded(e), Included(i)) if i > e => Included(i),
+        (Included(_), Excluded(e)) | (Excluded(e), Included(_)) => Excluded(e),
+    }
+}
+
+/// Returns the most restrictive of two range end bounds.
+fn range_end(a: Bound<Value>, b: Bound<Value>) -> Bound<Value> {
+    use Bound::*;
+    match (a, b) {
+        (Unbounded, bound) | (bound, Unbounded) => bound,
+        (Included(a), Included(b)) => Included(a.min(b)),
+        (Excluded(a), Excluded(b)) => Excluded(a.min(b)),
+        (Included(i), Excluded(e)) | (Excluded(e), Included(i)) if i < e => Included(i),
+        (Included(_), Excluded(e)) | (Excluded(e), Included(_)) => Excluded(e),
+    }
+}
+
+/// Removes sorts when the source already emits rows in the sort order, i.e.
+/// when it scans a primary key or secondary index whose leading columns are
+/// the sort columns. Table scans are replaced by a full secondary index scan
+/// if the index matches the sort order. Descending sorts use reverse scans.
+/// This avoids buffering the entire row set, and allows a LIMIT to stop the
+/// scan early.
+pub(super) fn eliminate_sort(node: Node) -> Result<Node> {
+    /// Rewrites the source node to emit rows ordered by the given column
+    /// indexes if possible. Returns the node and whether it is ordered.
+    fn order(node: Node, columns: Vec<usize>, reverse: bool) -> (Node, bool) {
+        match node {
+            // Filters retain the source order.
+            Node::Filter { source, predicate } => {
+                let (source, ordered) = order(*source, columns, reverse);
+                (Node::Filter { source: source.into(), predicate }, ordered)
+            }
+
+            // Projections retain the order of the source columns they emit.
+            Node::Projection { source, expressions, aliases } => {
+                let source_columns = columns
+                    .iter()
+                    .map(|c| match &expressions[*c] {
+                        Expression::Column(index) => Some(*index),
+                        _ => None,
+                    })
+                    .collect();
+                let Some(source_columns) = source_columns else {
+                    return (Node::Projection { source, expressions, aliases }, false);
+                };
+                let (source, ordered) = order(*source, source_columns, reverse);
+                (Node::Projection { source: source.into(), expressions, aliases }, ordered)
+            }
+
+            // Table scans emit rows in primary key order. For descending
+            // order, use a reverse range scan across the entire table.
+            Node::Scan { table, filter, alias }
+                if is_ordered(&table.primary_key, &[], &columns) =>
+            {
+                if !reverse {
+                    return (Node::Scan { table, filter, alias }, true);
+                }
+                let range = (Bound::Unbounded, Bound::Unbounded);
+                let scan = Node::KeyRangeScan { table, range, reverse, alias };
+                match filter {
+                    Some(predicate) => (Node::Filter { source: scan.into(), predicate }, true),
+                    None => (scan, true),
+                }
+            }
+
+            // Otherwise, use a full secondary index scan if the index emits
+            // rows in the sort order, i.e. in index order and then in primary
+            // key order for each index value. This reads rows via the index
+            // rather than in bulk, but allows a LIMIT to stop the scan early.
+            Node::Scan { table, filter, alias } => {
+                let Some(index) = table.readable_indexes().into_iter().find(|index| {
+                    is_ordered(&[index.as_slice(), &table.primary_key].concat(), &[], &columns)
+                }) else {
+                    return (Node::Scan { table, filter, alias }, false);
+                };
+                let range = (Bound::Unbounded, Bound::Unbounded);
+                let scan = Node::IndexRangeScan { table, columns: index, range, reverse, alias };
+                match filter {
+                    Some(predicate) => (Node::Filter { source: scan.into(), predicate }, true),
+                    None => (scan, true),
+                }
+            }
+
+            // Range scans emit rows in key order. Key columns that are fixed
+            // by the range bounds have a single value, and don't affect it.
+            Node::KeyRangeScan { table, range, reverse: false, alias }
+                if is_ordered(
+                    &table.primary_key,
+                    &table.primary_key[..fixed_prefix(&range)],
+                    &columns,
+                ) =>
+            {
+                (Node::KeyRangeScan { table, range, reverse, alias }, true)
+            }
+
+            // Index range scans emit rows in index order, and then in primary
+            // key order for each index value.
+            Node::IndexRangeScan { table, columns: index, range, reverse: false, alias }
+                if is_ordered(
+                    &[index.as_slice(), &table.primary_key].concat(),
+                    &index[..fixed_prefix(&range)],
+                    &columns,
+                ) =>
+            {
+                (Node::IndexRangeScan { table, columns: index, range, reverse, alias }, true)
+            }
+
+            // Lookups of a single key or index value emit rows in primary key
+            // order, where the looked up columns have a single value. For
+            // descending order, use a reverse range scan of the lookup value.
+            // For index lookups of a value prefix, this emits rows in reverse
+            // order of the remaining index columns and then the primary key.
+            Node::KeyLookup { table, mut keys, alias }
+                if keys.len() == 1
+                    && is_ordered(
+                        &table.primary_key,
+                        &table.primary_key[..keys[0].len()],
+                        &columns,
+                    ) =>
+            {
+                if !reverse {
+                    return (Node::KeyLookup { table, keys, alias }, true);
+                }
+                let key = keys.remove(0);
+                let range = (Bound::Included(key.clone()), Bound::Included(key));
+                (Node::KeyRangeScan { table, range, reverse, alias }, true)
+            }
+            Node::IndexLookup { table, columns: index, mut values, alias }
+                if values.len() == 1 && {
+                    let (fixed, rest) = index.split_at(values[0].len());
+                    match reverse {
+                        false => is_ordered(&table.primary_key, fixed, &columns),
+                        true => is_ordered(&[rest, &table.primary_key].concat(), fixed, &columns),
+                    }
+                } =>
+            {
+                if !reverse {
+                    return (Node::IndexLookup { table, columns: index, values, alias }, true);
+                }
+                let value = values.remove(0);
+                let range = (Bound::Included(value.clone()), Bound::Included(value));
+                (Node::IndexRangeScan { table, columns: index, range, reverse, alias }, true)
+            }
+
+            node => (node, false),
+        }
+    }
+
+    /// Returns true if rows ordered by the given unique key columns are also
+    /// ordered by the given sort columns, ignoring columns with a fixed value.
+    fn is_ordered(key: &[usize], fixed: &[usize], columns: &[usize]) -> bool {
+        let mut key = key.iter().filter(|c| !fixed.contains(c));
+        for column in columns.iter().filter(|c| !fixed.contains(c)) {
+            match key.next() {
+                Some(c) if c == column => {}
+                Some(_) => return false,
+                // The key is unique, so further columns don't affect the order.
+                None => return true,
+            }
+        }
+        true
+    }
+
+    /// Returns the number of leading columns fixed to a single value by both
+    /// range bounds.
+    fn fixed_prefix((start, end): &TupleRange) -> usize {
+        match (start, end) {
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) => start.iter().zip(end).take_while(|(s, e)| s == e).count(),
+            _ => 0,
+        }
+    }
+
+    let transform = |node| match node {
+        // Only sorts by plain columns in a single direction can be eliminated.
+        Node::Order { source, orders } => {
+            let direction = orders.first().map(|(_, direction)| direction.clone());
+            let columns = orders
+                .iter()
+                .map(|(expr, dir)| match expr {
+                    Expression::Column(index) if Some(dir) == direction.as_ref() => Some(*index),
+                    _ => None,
+                })
+                .collect();
+            let Some(columns) = columns else { return Node::Order { source, orders } };
+            match order(*source, columns, direction == Some(Direction::Descending)) {
+                (source, true) => source,
+                (source, false) => Node::Order { source: source.into(), orders },
+            }
+        }
+        node => node,
+    };
+    node.transform(&Ok, &|n| Ok(transform(n)))
+}
+
+/// Pushes limits down into recursive unions (i.e. WITH RECURSIVE), such that
+/// they stop iterating once the limit is reached. Otherwise, an infinite
+/// recursion would never terminate, even with a LIMIT. Limits can only be
+/// pushed through nodes that emit a single row for each source row, in order.
+pub(super) fn push_limits(node: Node) -> Result<Node> {
+    fn push(node: Node, limit: usize) -> Node {
+        match node {
+            Node::CommonTable { source, name, alias, columns } => {
+                Node::CommonTable { source: push(*source, limit).into(), name, alias, columns }
+            }
+            Node::Offset { source, offset } => {
+                Node::Offset { source: push(*source, limit.saturating_add(offset)).into(), offset }
+            }
+            Node::Projection { source, expressions, aliases } => {
+                Node::Projection { source: push(*source, limit).into(), expressions, aliases }
+            }
+            Node::Remap { source, targets } => {
+                Node::Remap { source: push(*source, limit).into(), targets }
+            }
+            Node::RecursiveUnion { anchor, recursive, name, distinct, limit: existing } => {
+                let limit = Some(existing.map_or(limit, |existing| existing.min(limit)));
+                Node::RecursiveUnion { anchor, recursive, name, distinct, limit }
+            }
+            node => node,
+        }
+    }
+
+    let transform = |node| match node {
+        Node::Limit { source, limit } => Node::Limit { source: push(*source, limit).into(), limit },
+        node => node,
+    };
+    node.transform(&|n| Ok(transform(n)), &Ok)
+}
+
 /// Uses a hash join instead of a nested loop join for single-column equijoins.
 pub(super) fn join_type(node: Node) -> Result<Node> {
     let transform = |node| match node {
@@ -265,7 +606,7 @@ pub(super) fn join_type(node: Node) -> Result<Node> {
             left,
             right,
             predicate: Some(Expression::Equal(lhs, rhs)),
-            outer,
+            r#type,
         } => match (*lhs, *rhs) {
             (Expression::Column(mut left_column), Expression::Column(mut right_column)) => {
                 // The LHS column may be a column in the right table; swap them.
@@ -276,11 +617,11 @@ pub(super) fn join_type(node: Node) -> Result<Node> {
                 // joined row, while the HashJoin uses column indexes for each
                 // table individually. Adjust the RHS column reference.
                 right_column -= left.size();
-                Node::HashJoin { left, left_column, right, right_column, outer }
+                Node::HashJoin { left, left_column, right, right_column, r#type }
             }
             (lhs, rhs) => {
                 let predicate = Some(Expression::Equal(lhs.into(), rhs.into()));
-                Node::NestedLoopJoin { left, right, predicate, outer }
+                Node::NestedLoopJoin { left, right, predicate, r#type }
             }
         },
         node => node,
@@ -308,8 +649,22 @@ pub(super) fn short_circuit(node: Node) -> Result<Node> {
         Node::Scan { table, filter: Some(Constant(Boolean(true))), alias } => {
             Node::Scan { table, filter: None, alias }
         }
-        Node::NestedLoopJoin { left, right, predicate: Some(Constant(Boolean(true))), outer } => {
-            Node::NestedLoopJoin { left, right, predicate: None, outer }
+        Node::NestedLoopJoin { left, right, predicate: Some(Constant(Boolean(true))), r#type } => {
+            Node::NestedLoopJoin { left, right, predicate: None, r#type }
+        }
+
+        // Anti joins that can't match anything emit all left rows.
+        Node::NestedLoopJoin {
+            left,
+            predicate: Some(Constant(Boolean(false) | Null)),
+            r#type: JoinType::Anti,
+            ..
+        } => *left,
+        Node::HashJoin { left, right, r#type: JoinType::Anti, .. }
+        | Node::NestedLoopJoin { left, right, r#type: JoinType::Anti, .. }
+            if matches!(*right, Node::Nothing { .. }) =>
+        {
+            *left
         }
 
         // Short-circuit nodes that can't produce anything by replacing them
@@ -330,18 +685,33 @@ pub(super) fn short_circuit(node: Node) -> Result<Node> {
         //
         // NB: does not short-circuit aggregation, since an aggregation over 0
         // rows should produce a result.
-        ref node @ (Node::Filter { ref source, .. }
+        ref node @ (Node::CommonTable { ref source, .. }
+        | Node::Distinct { ref source, .. }
+        | Node::Except { left: ref source, .. }
+        | Node::Filter { ref source, .. }
         | Node::HashJoin { left: ref source, .. }
         | Node::HashJoin { right: ref source, .. }
+        | Node::Intersect { left: ref source, .. }
+        | Node::Intersect { right: ref source, .. }
+        | Node::Lock { ref source, .. }
         | Node::NestedLoopJoin { left: ref source, .. }
         | Node::NestedLoopJoin { right: ref source, .. }
         | Node::Offset { ref source, .. }
         | Node::Order { ref source, .. }
-        | Node::Projection { ref source, .. })
+        | Node::Projection { ref source, .. }
+        | Node::RecursiveUnion { anchor: ref source, .. }
+        | Node::Subquery { ref source, .. }
+        | Node::Window { ref source, .. })
             if matches!(**source, Node::Nothing { .. }) =>
         {
             nothing(node)
         }
+        ref node @ Node::Union { ref left, ref right, .. }
+            if matches!(**left, Node::Nothing { .. })
+                && matches!(**right, Node::Nothing { .. }) =>
+        {
+            nothing(node)
+        }
 
         // Remove noop projections that simply pass through the source columns.
         Node::Projection { source, expressions, aliases }
@@ -4,7 +4,11 @@ use crate::error::Result;
 use crate::sql::engine::{Catalog, Transaction};
 use crate::sql::execution::{self, ExecutionResult};
 use crate::sql::parser::ast;
-use crate::sql::types::{Expression, Label, Table, Value};
+use crate::sql::types::{
+    format_range, format_tuple, Column, DataType, Expression, Index, Label, Table, TupleRange,
+    Value,
+};
+use crate::storage::mvcc;
 
 use itertools::Itertools as _;
 use serde::{Deserialize, Serialize};
@@ -21,20 +25,36 @@ pub enum Plan {
     /// A DROP TABLE plan. Drops the given table. Errors if the table does not
     /// exist, unless if_exists is true.
     DropTable { table: String, if_exists: bool },
+    /// An ALTER TABLE plan. Applies the given schema change to the table.
+    /// Errors if the table does not exist, or the change is invalid for the
+    /// schema or the existing rows.
+    AlterTable { table: String, alteration: Alteration },
+    /// A CREATE INDEX plan. Creates the given index on the table, backfilling
+    /// it from existing rows. Errors if an index with the same name exists,
+    /// the column is already indexed, or a unique index has duplicate values.
+    CreateIndex { table: String, index: Index },
+    /// A DROP INDEX plan. Drops the given named index. Errors if the index
+    /// does not exist, unless if_exists is true.
+    DropIndex { name: String, if_exists: bool },
     /// A DELETE plan. Deletes rows in table that match the rows from source.
-    /// primary_key specifies the primary key column index in source rows.
-    Delete { table: String, primary_key: usize, source: Node },
+    /// primary_key specifies the primary key column indexes in source rows.
+    Delete { table: String, primary_key: Vec<usize>, source: Node },
     /// An INSERT plan. Inserts rows from source (typically a Values node) into
     /// table. If column_map is given, it maps table → source column indexes and
     /// must have one entry for every column in source. Columns not emitted by
     /// source will get the column's default value if any, otherwise error.
     Insert { table: Table, column_map: Option<HashMap<usize, usize>>, source: Node },
     /// An UPDATE plan. Updates rows in table that match the rows from source,
-    /// where primary_key specifies the source row column index of primary keys
+    /// where primary_key specifies the source row column indexes of primary keys
     /// to update. The given column/expression pairs specify the row updates to
     /// be made, and will be evaluated using the old row entry from source. Rows
     /// in source must be complete, existing rows from the table to update.
-    Update { table: Table, primary_key: usize, source: Node, expressions: Vec<(usize, Expression)> },
+    Update {
+        table: Table,
+        primary_key: Vec<usize>,
+        source: Node,
+        expressions: Vec<(usize, Expression)>,
+    },
     /// A SELECT plan. Recursively executes the query plan tree and returns the
     /// resulting rows.
     Select(Node),
@@ -53,14 +73,29 @@ impl Plan {
 
     /// Optimizes the plan, consuming it.
     pub fn optimize(self) -> Result<Self> {
-        let optimize = |mut node| -> Result<Node> {
+        fn optimize(mut node: Node) -> Result<Node> {
             for (_, optimizer) in OPTIMIZERS {
                 node = optimizer(node)?;
             }
-            Ok(node)
-        };
+            // Node.transform() doesn't descend into subquery plans, since they
+            // are separate queries. Optimize them separately.
+            node.transform(&Ok, &|node| match node {
+                Node::Subquery { source, subquery, r#type, correlated } => Ok(Node::Subquery {
+                    source,
+                    subquery: Box::new(optimize(*subquery)?),
+                    r#type,
+                    correlated,
+                }),
+                node => Ok(node),
+            })
+        }
         Ok(match self {
-            Self::CreateTable { .. } | Self::DropTable { .. } | Self::Insert { .. } => self,
+            Self::CreateTable { .. }
+            | Self::DropTable { .. }
+            | Self::AlterTable { .. }
+            | Self::CreateIndex { .. }
+            | Self::DropIndex { .. }
+            | Self::Insert { .. } => self,
             Self::Delete { table, primary_key, source } => {
                 Self::Delete { table, primary_key, source: optimize(source)? }
             }
@@ -72,6 +107,42 @@ impl Plan {
     }
 }
 
+/// An ALTER TABLE schema change, see the corresponding Catalog methods.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Alteration {
+    /// Adds a column. Existing rows get the column's default value.
+    AddColumn(Column),
+    /// Drops a column, along with any secondary index.
+    DropColumn(String),
+    /// Renames a column.
+    RenameColumn { column: String, new_name: String },
+    /// Renames the table.
+    RenameTable(String),
+    /// Sets or drops (if None) a column's default value.
+    SetDefault { column: String, default: Option<Value> },
+    /// Sets whether a column is nullable.
+    SetNullable { column: String, nullable: bool },
+}
+
+impl std::fmt::Display for Alteration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AddColumn(column) => write!(f, "add column {}", column.name),
+            Self::DropColumn(column) => write!(f, "drop column {column}"),
+            Self::RenameColumn { column, new_name } => {
+                write!(f, "rename column {column} to {new_name}")
+            }
+            Self::RenameTable(new_name) => write!(f, "rename to {new_name}"),
+            Self::SetDefault { column, default: Some(default) } => {
+                write!(f, "set default {default} for {column}")
+            }
+            Self::SetDefault { column, default: None } => write!(f, "drop default for {column}"),
+            Self::SetNullable { column, nullable: true } => write!(f, "drop not null for {column}"),
+            Self::SetNullable { column, nullable: false } => write!(f, "set not null for {column}"),
+        }
+    }
+}
+
 /// A query plan node. These return row iterators and can be nested.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Node {
@@ -79,33 +150,91 @@ pub enum Node {
     /// across all rows in the source node. The group_by columns are emitted first,
     /// followed by the aggregate columns, in the given order.
     Aggregate { source: Box<Node>, group_by: Vec<Expression>, aggregates: Vec<Aggregate> },
+    /// Emits the rows of a WITH common table expression (CTE), labeled with the
+    /// CTE name (or alias) and column names. The source is the CTE query, which
+    /// is executed separately for every reference.
+    CommonTable { source: Box<Node>, name: String, alias: Option<String>, columns: Vec<String> },
+    /// Discards source rows whose values for the given expressions have
+    /// already been emitted, i.e. emits the first row for each distinct set of
+    /// values (SELECT DISTINCT [ON]). Tracks emitted values in a hashset.
+    Distinct { source: Box<Node>, on: Vec<Expression> },
+    /// Emits the left source rows that aren't in the right source, by building
+    /// an in-memory hashset of the right source. If distinct is true (i.e.
+    /// EXCEPT rather than EXCEPT ALL), duplicate rows are discarded. Otherwise,
+    /// each right row cancels out one matching left row.
+    Except { left: Box<Node>, right: Box<Node>, distinct: bool },
     /// Filters source rows, by only emitting rows for which the predicate
     /// evaluates to true.
     Filter { source: Box<Node>, predicate: Expression },
     /// Joins the left and right sources on the given columns by building an
     /// in-memory hashmap of the right source and looking up matches for each
-    /// row in the left source. When outer is true (e.g. LEFT JOIN), a left row
-    /// without a right match is emitted anyway, with NULLs for the right row.
+    /// row in the left source. The join type determines which rows are
+    /// emitted, see JoinType.
     HashJoin {
         left: Box<Node>,
         left_column: usize,
         right: Box<Node>,
         right_column: usize,
-        outer: bool,
+        r#type: JoinType,
     },
     /// Looks up the given values in a secondary index and emits matching rows.
     /// NULL and NaN values are considered equal, to allow IS NULL and IS NAN
-    /// index lookups, as is -0.0 and 0.0.
-    IndexLookup { table: Table, column: usize, values: Vec<Value>, alias: Option<String> },
-    /// Looks up the given primary keys and emits their rows.
-    KeyLookup { table: Table, keys: Vec<Value>, alias: Option<String> },
-    /// Only emits the first limit rows from the source, discards the rest.
+    /// index lookups, as is -0.0 and 0.0. The values may be prefixes of the
+    /// index columns, in which case all rows matching the prefix are emitted.
+    IndexLookup {
+        table: Table,
+        columns: Vec<usize>,
+        values: Vec<Vec<Value>>,
+        alias: Option<String>,
+    },
+    /// Scans a secondary index for the given range of index values, and emits
+    /// the matching rows in index order (or reverse index order). The range
+    /// bounds may be prefixes of the index columns.
+    IndexRangeScan {
+        table: Table,
+        columns: Vec<usize>,
+        range: TupleRange,
+        reverse: bool,
+        alias: Option<String>,
+    },
+    /// Emits the left source rows that are also in the right source, by
+    /// building an in-memory hashset of the right source. If distinct is true
+    /// (i.e. INTERSECT rather than INTERSECT ALL), duplicate rows are discarded.
+    /// Otherwise, each right row matches at most one left row.
+    Intersect { left: Box<Node>, right: Box<Node>, distinct: bool },
+    /// Looks up the given primary keys and emits their rows. The keys may be
+    /// prefixes of a composite primary key, emitting all matching rows.
+    KeyLookup { table: Table, keys: Vec<Vec<Value>>, alias: Option<String> },
+    /// Scans the table rows whose primary keys are in the given range, in key
+    /// order (or reverse key order). The range bounds may be prefixes of a
+    /// composite primary key.
+    KeyRangeScan { table: Table, range: TupleRange, reverse: bool, alias: Option<String> },
+    /// Only emits the first limit rows from the source, discards the rest. If
+    /// the source is an Order node (possibly via an Offset node), only the top
+    /// rows are buffered and sorted.
     Limit { source: Box<Node>, limit: usize },
+    /// Locks the source rows of the given table in the given mode (i.e. SELECT
+    /// ... FOR UPDATE/SHARE), using the primary_key column indexes of the source
+    /// rows. If skip_locked is true, rows that can't be locked are skipped,
+    /// otherwise the lock conflict is returned as an error. If limit is given,
+    /// stops once that many rows have been locked and emitted.
+    Lock {
+        source: Box<Node>,
+        table: String,
+        primary_key: Vec<usize>,
+        mode: mvcc::LockMode,
+        skip_locked: bool,
+        limit: Option<usize>,
+    },
     /// Joins the left and right sources on the given predicate by buffering the
     /// right source and iterating over it for every row in the left source.
-    /// When outer is true (e.g. LEFT JOIN), a left row without a right match is
-    /// emitted anyway, with NULLs for the right row.
-    NestedLoopJoin { left: Box<Node>, right: Box<Node>, predicate: Option<Expression>, outer: bool },
+    /// The join type determines which rows are emitted, see JoinType.
+    NestedLoopJoin {
+        left: Box<Node>,
+        right: Box<Node>,
+        predicate: Option<Expression>,
+        r#type: JoinType,
+    },
     /// Nothing does not emit anything, but retains the column names of any
     /// replaced nodes for results and plan expression display.
     Nothing { columns: Vec<Label> },
@@ -117,14 +246,46 @@ pub enum Node {
     /// Projects the input rows by evaluating the given expressions. Aliases are
     /// only used when displaying the plan.
     Projection { source: Box<Node>, expressions: Vec<Expression>, aliases: Vec<Label> },
+    /// Evaluates a WITH RECURSIVE common table expression by iterating to a
+    /// fixpoint. Emits the anchor rows, then repeatedly executes the recursive
+    /// term with the named WorkTable bound to the rows emitted by the previous
+    /// iteration, until an iteration doesn't emit any rows. If distinct is true
+    /// (i.e. UNION rather than UNION ALL), duplicate rows are discarded. If
+    /// limit is given (pushed down from an outer LIMIT), stops iterating once
+    /// that many rows have been emitted.
+    RecursiveUnion {
+        anchor: Box<Node>,
+        recursive: Box<Node>,
+        name: String,
+        distinct: bool,
+        limit: Option<usize>,
+    },
     /// Remaps source columns to the given target column index, or None to drop
     /// the column. Unspecified target columns yield Value::Null.
     Remap { source: Box<Node>, targets: Vec<Option<usize>> },
     /// A full table scan, with an optional filter pushdown. The schema is used
     /// during plan optimization. The alias is only used for formatting.
     Scan { table: Table, filter: Option<Expression>, alias: Option<String> },
+    /// Evaluates a subquery for each source row, and emits the source row with
+    /// the subquery result appended as an additional column. Uncorrelated
+    /// subqueries are only executed once. Correlated subqueries reference
+    /// columns in the source row via Expression::Outer, and are executed for
+    /// every source row with the outer references bound to the row values.
+    Subquery { source: Box<Node>, subquery: Box<Node>, r#type: SubqueryType, correlated: bool },
+    /// Emits the left source rows followed by the right source rows. If
+    /// distinct is true (i.e. UNION rather than UNION ALL), duplicate rows are
+    /// discarded using an in-memory hashset of emitted rows.
+    Union { left: Box<Node>, right: Box<Node>, distinct: bool },
     /// A constant set of values.
     Values { rows: Vec<Vec<Expression>> },
+    /// Evaluates window functions over the source rows, and emits the source
+    /// rows with the function values appended as additional columns, in the
+    /// given order. Buffers the entire row set in memory.
+    Window { source: Box<Node>, functions: Vec<(WindowFunction, Window)> },
+    /// The work table of the recursive term of a RecursiveUnion with the given
+    /// name, i.e. the rows emitted by the previous iteration. It is replaced by
+    /// the rows during execution.
+    WorkTable { name: String, size: usize },
 }
 
 impl Node {
@@ -146,35 +307,75 @@ impl Node {
             Self::Aggregate { source, aggregates, group_by } => {
                 Self::Aggregate { source: transform(source)?, aggregates, group_by }
             }
+            Self::CommonTable { source, name, alias, columns } => {
+                Self::CommonTable { source: transform(source)?, name, alias, columns }
+            }
+            Self::Distinct { source, on } => Self::Distinct { source: transform(source)?, on },
+            Self::Except { left, right, distinct } => {
+                Self::Except { left: transform(left)?, right: transform(right)?, distinct }
+            }
             Self::Filter { source, predicate } => {
                 Self::Filter { source: transform(source)?, predicate }
             }
-            Self::HashJoin { left, left_column, right, right_column, outer } => Self::HashJoin {
+            Self::HashJoin { left, left_column, right, right_column, r#type } => Self::HashJoin {
                 left: transform(left)?,
                 left_column,
                 right: transform(right)?,
                 right_column,
-                outer,
+                r#type,
             },
+            Self::Intersect { left, right, distinct } => {
+                Self::Intersect { left: transform(left)?, right: transform(right)?, distinct }
+            }
             Self::Limit { source, limit } => Self::Limit { source: transform(source)?, limit },
-            Self::NestedLoopJoin { left, right, predicate, outer } => Self::NestedLoopJoin {
+            Self::Lock { source, table, primary_key, mode, skip_locked, limit } => Self::Lock {
+                source: transform(source)?,
+                table,
+                primary_key,
+                mode,
+                skip_locked,
+                limit,
+            },
+            Self::NestedLoopJoin { left, right, predicate, r#type } => Self::NestedLoopJoin {
                 left: transform(left)?,
                 right: transform(right)?,
                 predicate,
-                outer,
+                r#type,
             },
             Self::Offset { source, offset } => Self::Offset { source: transform(source)?, offset },
             Self::Order { source, orders } => Self::Order { source: transform(source)?, orders },
             Self::Projection { source, expressions, aliases } => {
                 Self::Projection { source: transform(source)?, expressions, aliases }
             }
+            Self::RecursiveUnion { anchor, recursive, name, distinct, limit } => {
+                Self::RecursiveUnion {
+                    anchor: transform(anchor)?,
+                    recursive: transform(recursive)?,
+                    name,
+                    distinct,
+                    limit,
+                }
+            }
             Self::Remap { source, targets } => Self::Remap { source: transform(source)?, targets },
+            // The subquery is a separate query, and is not transformed.
+            Self::Subquery { source, subquery, r#type, correlated } => {
+                Self::Subquery { source: transform(source)?, subquery, r#type, correlated }
+            }
+            Self::Union { left, right, distinct } => {
+                Self::Union { left: transform(left)?, right: transform(right)?, distinct }
+            }
+            Self::Window { source, functions } => {
+                Self::Window { source: transform(source)?, functions }
+            }
 
             node @ (Self::IndexLookup { .. }
+            | Self::IndexRangeScan { .. }
             | Self::KeyLookup { .. }
+            | Self::KeyRangeScan { .. }
             | Self::Nothing { .. }
             | Self::Scan { .. }
-            | Self::Values { .. }) => node,
+            | Self::Values { .. }
+            | Self::WorkTable { .. }) => node,
         };
         self = after(self)?;
         Ok(self)
@@ -188,6 +389,21 @@ impl Node {
         A: Fn(Expression) -> Result<Expression>,
     {
         Ok(match self {
+            Self::Aggregate { source, group_by, aggregates } => Self::Aggregate {
+                source,
+                group_by: group_by
+                    .into_iter()
+                    .map(|e| e.transform(before, after))
+                    .collect::<Result<_>>()?,
+                aggregates: aggregates
+                    .into_iter()
+                    .map(|a| a.transform(before, after))
+                    .collect::<Result<_>>()?,
+            },
+            Self::Distinct { source, on } => Self::Distinct {
+                source,
+                on: on.into_iter().map(|e| e.transform(before, after)).collect::<Result<_>>()?,
+            },
             Self::Filter { source, predicate } => {
                 Self::Filter { source, predicate: predicate.transform(before, after)? }
             }
@@ -198,12 +414,12 @@ impl Node {
                     .map(|(e, o)| e.transform(before, after).map(|e| (e, o)))
                     .collect::<Result<_>>()?,
             },
-            Self::NestedLoopJoin { left, right, predicate: Some(predicate), outer } => {
+            Self::NestedLoopJoin { left, right, predicate: Some(predicate), r#type } => {
                 Self::NestedLoopJoin {
                     left,
                     right,
                     predicate: Some(predicate.transform(before, after)?),
-                    outer,
+                    r#type,
                 }
             }
             Self::Projection { source, expressions, aliases } => Self::Projection {
@@ -217,23 +433,43 @@ impl Node {
             Self::Scan { table, alias, filter: Some(filter) } => {
                 Self::Scan { table, alias, filter: Some(filter.transform(before, after)?) }
             }
+            Self::Subquery { source, subquery, r#type: SubqueryType::In(expr), correlated } => {
+                let r#type = SubqueryType::In(expr.transform(before, after)?);
+                Self::Subquery { source, subquery, r#type, correlated }
+            }
             Self::Values { rows } => Self::Values {
                 rows: rows
                     .into_iter()
                     .map(|row| row.into_iter().map(|e| e.transform(before, after)).collect())
                     .collect::<Result<_>>()?,
             },
+            Self::Window { source, functions } => Self::Window {
+                source,
+                functions: functions
+                    .into_iter()
+                    .map(|(f, w)| Ok((f.transform(before, after)?, w.transform(before, after)?)))
+                    .collect::<Result<_>>()?,
+            },
 
-            node @ (Self::Aggregate { .. }
+            node @ (Self::CommonTable { .. }
+            | Self::Except { .. }
             | Self::HashJoin { .. }
             | Self::IndexLookup { .. }
+            | Self::IndexRangeScan { .. }
+            | Self::Intersect { .. }
             | Self::KeyLookup { .. }
+            | Self::KeyRangeScan { .. }
             | Self::Limit { .. }
+            | Self::Lock { .. }
             | Self::NestedLoopJoin { predicate: None, .. }
             | Self::Nothing { .. }
             | Self::Offset { .. }
+            | Self::RecursiveUnion { .. }
+            | Self::Remap { .. }
             | Self::Scan { filter: None, .. }
-            | Self::Remap { .. }) => node,
+            | Self::Subquery { .. }
+            | Self::Union { .. }
+            | Self::WorkTable { .. }) => node,
         })
     }
 
@@ -243,12 +479,19 @@ impl Node {
         match self {
             // Source nodes use the table/column name.
             Self::IndexLookup { table, alias, .. }
+            | Self::IndexRangeScan { table, alias, .. }
             | Self::KeyLookup { table, alias, .. }
+            | Self::KeyRangeScan { table, alias, .. }
             | Self::Scan { table, alias, .. } => Label::Qualified(
                 alias.as_ref().unwrap_or(&table.name).clone(),
                 table.columns[index].name.clone(),
             ),
 
+            // Common tables use the CTE name (or alias) and column names.
+            Self::CommonTable { name, alias, columns, .. } => {
+                Label::Qualified(alias.as_ref().unwrap_or(name).clone(), columns[index].clone())
+            }
+
             // Some nodes rearrange columns. Route them to the correct
             // upstream column where appropriate.
             Self::Aggregate { source, group_by, .. } => match group_by.get(index) {
@@ -269,8 +512,16 @@ impl Node {
                 .position(|t| t == &Some(index))
                 .map(|i| source.column_label(i))
                 .unwrap_or(Label::None),
+            Self::Subquery { source, .. } | Self::Window { source, .. }
+                if index < source.size() =>
+            {
+                source.column_label(index)
+            }
+            Self::Subquery { .. } | Self::Window { .. } => Label::None,
 
-            // Joins dispatch to the appropriate source.
+            // Joins dispatch to the appropriate source. Semi and anti joins
+            // only emit the left columns, but their predicates can reference
+            // the right columns too.
             Self::HashJoin { left, right, .. } | Self::NestedLoopJoin { left, right, .. } => {
                 if index < left.size() {
                     left.column_label(index)
@@ -280,16 +531,24 @@ impl Node {
             }
 
             // Simple nodes just dispatch to the source.
-            Self::Filter { source, .. }
+            Self::Distinct { source, .. }
+            | Self::Filter { source, .. }
             | Self::Limit { source, .. }
+            | Self::Lock { source, .. }
             | Self::Offset { source, .. }
             | Self::Order { source, .. } => source.column_label(index),
+            Self::RecursiveUnion { anchor, .. } => anchor.column_label(index),
+
+            // Set operations use the left column labels.
+            Self::Except { left, .. } | Self::Intersect { left, .. } | Self::Union { left, .. } => {
+                left.column_label(index)
+            }
 
             // Nothing nodes contain the original columns of replaced nodes.
             Self::Nothing { columns } => columns.get(index).cloned().unwrap_or(Label::None),
 
             // And some don't have any names at all.
-            Self::Values { .. } => Label::None,
+            Self::Values { .. } | Self::WorkTable { .. } => Label::None,
         }
     }
 
@@ -298,30 +557,118 @@ impl Node {
         match self {
             // Source nodes emit all table columns.
             Self::IndexLookup { table, .. }
+            | Self::IndexRangeScan { table, .. }
             | Self::KeyLookup { table, .. }
+            | Self::KeyRangeScan { table, .. }
             | Self::Scan { table, .. } => table.columns.len(),
 
             // Some nodes modify the column set.
             Self::Aggregate { aggregates, group_by, .. } => aggregates.len() + group_by.len(),
+            Self::CommonTable { columns, .. } => columns.len(),
             Self::Projection { expressions, .. } => expressions.len(),
             Self::Remap { targets, .. } => {
                 targets.iter().filter_map(|v| *v).map(|i| i + 1).max().unwrap_or(0)
             }
 
-            // Join nodes emit the combined columns.
-            Self::HashJoin { left, right, .. } | Self::NestedLoopJoin { left, right, .. } => {
-                left.size() + right.size()
-            }
+            // Join nodes emit the combined columns, except semi and anti
+            // joins which only emit the left columns.
+            Self::HashJoin { left, right, r#type, .. }
+            | Self::NestedLoopJoin { left, right, r#type, .. } => match r#type {
+                JoinType::Inner | JoinType::Outer => left.size() + right.size(),
+                JoinType::Semi | JoinType::Anti => left.size(),
+            },
+            Self::Subquery { source, .. } => source.size() + 1,
+            Self::Window { source, functions } => source.size() + functions.len(),
 
             // Simple nodes just pass through the source columns.
-            Self::Filter { source, .. }
+            Self::Distinct { source, .. }
+            | Self::Filter { source, .. }
             | Self::Limit { source, .. }
+            | Self::Lock { source, .. }
             | Self::Offset { source, .. }
             | Self::Order { source, .. } => source.size(),
+            Self::RecursiveUnion { anchor, .. } => anchor.size(),
+            Self::Except { left, .. } | Self::Intersect { left, .. } | Self::Union { left, .. } => {
+                left.size()
+            }
 
             // And some are trivial.
             Self::Nothing { columns } => columns.len(),
             Self::Values { rows } => rows.first().map(|row| row.len()).unwrap_or(0),
+            Self::WorkTable { size, .. } => *size,
+        }
+    }
+
+    /// Returns the data type of the column with the given index, if it can be
+    /// determined during planning. Returns None if unknown, e.g. for columns
+    /// that are always NULL or work table columns.
+    pub fn column_type(&self, index: usize) -> Option<DataType> {
+        match self {
+            // Source nodes use the table schema.
+            Self::IndexLookup { table, .. }
+            | Self::IndexRangeScan { table, .. }
+            | Self::KeyLookup { table, .. }
+            | Self::KeyRangeScan { table, .. }
+            | Self::Scan { table, .. } => table.columns.get(index).map(|c| c.datatype),
+
+            // Some nodes compute new columns from the source columns.
+            Self::Aggregate { source, group_by, aggregates } => match group_by.get(index) {
+                Some(expr) => expr.datatype(source),
+                None => aggregates.get(index - group_by.len())?.datatype(source),
+            },
+            Self::Projection { source, expressions, .. } => {
+                expressions.get(index)?.datatype(source)
+            }
+            Self::Remap { source, targets } => {
+                source.column_type(targets.iter().position(|t| t == &Some(index))?)
+            }
+            Self::Subquery { source, .. } | Self::Window { source, .. }
+                if index < source.size() =>
+            {
+                source.column_type(index)
+            }
+            Self::Subquery { subquery, r#type, .. } => match r#type {
+                SubqueryType::Exists | SubqueryType::In(_) => Some(DataType::Boolean),
+                SubqueryType::Scalar => subquery.column_type(0),
+            },
+            Self::Window { source, functions } => {
+                functions.get(index - source.size())?.0.datatype(source)
+            }
+
+            // Joins dispatch to the appropriate source.
+            Self::HashJoin { left, right, .. } | Self::NestedLoopJoin { left, right, .. } => {
+                if index < left.size() {
+                    left.column_type(index)
+                } else {
+                    right.column_type(index - left.size())
+                }
+            }
+
+            // Simple nodes just dispatch to the source.
+            Self::CommonTable { source, .. }
+            | Self::Distinct { source, .. }
+            | Self::Filter { source, .. }
+            | Self::Limit { source, .. }
+            | Self::Lock { source, .. }
+            | Self::Offset { source, .. }
+            | Self::Order { source, .. } => source.column_type(index),
+            Self::RecursiveUnion { anchor, .. } => anchor.column_type(index),
+
+            // Set operations have the same column types on both sides, but
+            // one side may be unknown.
+            Self::Except { left, right, .. }
+            | Self::Intersect { left, right, .. }
+            | Self::Union { left, right, .. } => {
+                left.column_type(index).or_else(|| right.column_type(index))
+            }
+
+            // Values are typically constants, and can't reference columns.
+            Self::Values { rows } => {
+                let empty = Self::Nothing { columns: Vec::new() };
+                rows.iter().find_map(|row| row.get(index)?.datatype(&empty))
+            }
+
+            Self::Nothing { .. } | Self::WorkTable { .. } => None,
         }
     }
 }
@@ -332,6 +679,13 @@ impl std::fmt::Display for Plan {
         match self {
             Self::CreateTable { schema } => write!(f, "CreateTable: {}", schema.name),
             Self::DropTable { table, .. } => write!(f, "DropTable: {table}"),
+            Self::AlterTable { table, alteration } => {
+                write!(f, "AlterTable: {table} ({alteration})")
+            }
+            Self::CreateIndex { table, index } => {
+                write!(f, "CreateIndex: {} on {table}", index.name)
+            }
+            Self::DropIndex { name, .. } => write!(f, "DropIndex: {name}"),
             Self::Delete { table, source, .. } => {
                 write!(f, "Delete: {table}")?;
                 source.format(f, String::new(), false, true)
@@ -399,12 +753,30 @@ impl Node {
                 write!(f, "Aggregate: {aggregates}")?;
                 source.format(f, prefix, false, true)?;
             }
+            Self::CommonTable { source, name, alias, .. } => {
+                write!(f, "CommonTable: {name}")?;
+                if let Some(alias) = alias {
+                    write!(f, " as {alias}")?;
+                }
+                source.format(f, prefix, false, true)?;
+            }
+            Self::Distinct { source, on } => {
+                write!(f, "Distinct: {}", on.iter().map(|e| e.format(source)).join(", "))?;
+                source.format(f, prefix, false, true)?;
+            }
+            Self::Except { left, right, distinct } => {
+                match distinct {
+                    true => write!(f, "Except: distinct")?,
+                    false => write!(f, "Except: all")?,
+                }
+                left.format(f, prefix.clone(), false, false)?;
+                right.format(f, prefix, false, true)?;
+            }
             Self::Filter { source, predicate } => {
                 write!(f, "Filter: {}", predicate.format(source))?;
                 source.format(f, prefix, false, true)?;
             }
-            Self::HashJoin { left, left_column, right, right_column, outer } => {
-                let kind = if *outer { "outer" } else { "inner" };
+            Self::HashJoin { left, left_column, right, right_column, r#type } => {
                 let left_label = match left.column_label(*left_column) {
                     Label::None => format!("left #{left_column}"),
                     label => format!("{label}"),
@@ -413,40 +785,84 @@ impl Node {
                     Label::None => format!("right #{right_column}"),
                     label => format!("{label}"),
                 };
-                write!(f, "HashJoin: {kind} on {left_label} = {right_label}")?;
+                write!(f, "HashJoin: {type} on {left_label} = {right_label}")?;
                 left.format(f, prefix.clone(), false, false)?;
                 right.format(f, prefix, false, true)?;
             }
-            Self::IndexLookup { table, column, alias, values } => {
-                let column = &table.columns[*column].name;
+            Self::IndexLookup { table, columns, alias, values } => {
+                let column = format_tuple(&table.column_names(columns));
                 let table = &table.name;
                 write!(f, "IndexLookup: {table}.{column}")?;
                 if let Some(alias) = alias {
                     write!(f, " as {alias}.{column}")?;
                 }
                 if !values.is_empty() && values.len() < 10 {
-                    write!(f, " ({})", values.iter().join(", "))?;
+                    write!(f, " ({})", values.iter().map(|v| format_tuple(v)).join(", "))?;
                 } else {
                     write!(f, " ({} values)", values.len())?;
                 }
             }
+            Self::IndexRangeScan { table, columns, range, reverse, alias } => {
+                let column = format_tuple(&table.column_names(columns));
+                let table = &table.name;
+                write!(f, "IndexRangeScan: {table}.{column}")?;
+                if let Some(alias) = alias {
+                    write!(f, " as {alias}.{column}")?;
+                }
+                write!(f, " {}", format_range(range))?;
+                if *reverse {
+                    write!(f, " reverse")?;
+                }
+            }
+            Self::Intersect { left, right, distinct } => {
+                match distinct {
+                    true => write!(f, "Intersect: distinct")?,
+                    false => write!(f, "Intersect: all")?,
+                }
+                left.format(f, prefix.clone(), false, false)?;
+                right.format(f, prefix, false, true)?;
+            }
             Self::KeyLookup { table, alias, keys } => {
                 write!(f, "KeyLookup: {}", table.name)?;
                 if let Some(alias) = alias {
                     write!(f, " as {alias}")?;
                 }
                 if !keys.is_empty() && keys.len() < 10 {
-                    write!(f, " ({})", keys.iter().join(", "))?;
+                    write!(f, " ({})", keys.iter().map(|k| format_tuple(k)).join(", "))?;
                 } else {
                     write!(f, " ({} keys)", keys.len())?;
                 }
             }
+            Self::KeyRangeScan { table, range, reverse, alias } => {
+                write!(f, "KeyRangeScan: {}", table.name)?;
+                if let Some(alias) = alias {
+                    write!(f, " as {alias}")?;
+                }
+                write!(f, " {}", format_range(range))?;
+                if *reverse {
+                    write!(f, " reverse")?;
+                }
+            }
             Self::Limit { source, limit } => {
                 write!(f, "Limit: {limit}")?;
                 source.format(f, prefix, false, true)?;
             }
-            Self::NestedLoopJoin { left, right, predicate, outer, .. } => {
-                write!(f, "NestedLoopJoin: {}", if *outer { "outer" } else { "inner" })?;
+            Self::Lock { source, table, mode, skip_locked, limit, .. } => {
+                let mode = match mode {
+                    mvcc::LockMode::Shared => "share",
+                    mvcc::LockMode::Exclusive => "update",
+                };
+                write!(f, "Lock: {table} for {mode}")?;
+                if *skip_locked {
+                    write!(f, " skip locked")?;
+                }
+                if let Some(limit) = limit {
+                    write!(f, " (limit {limit})")?;
+                }
+                source.format(f, prefix, false, true)?;
+            }
+            Self::NestedLoopJoin { left, right, predicate, r#type } => {
+                write!(f, "NestedLoopJoin: {type}")?;
                 if let Some(expr) = predicate {
                     write!(f, " on {}", expr.format(self))?;
                 }
@@ -480,6 +896,17 @@ impl Node {
                 write!(f, "Projection: {expressions}")?;
                 source.format(f, prefix, false, true)?;
             }
+            Self::RecursiveUnion { anchor, recursive, distinct, limit, .. } => {
+                match distinct {
+                    true => write!(f, "RecursiveUnion: distinct")?,
+                    false => write!(f, "RecursiveUnion: all")?,
+                }
+                if let Some(limit) = limit {
+                    write!(f, " (limit {limit})")?;
+                }
+                anchor.format(f, prefix.clone(), false, false)?;
+                recursive.format(f, prefix, false, true)?;
+            }
             Self::Remap { source, targets } => {
                 let remap = remap_sources(targets)
                     .into_iter()
@@ -516,6 +943,27 @@ impl Node {
                     write!(f, " ({})", expr.format(self))?;
                 }
             }
+            Self::Subquery { source, subquery, r#type, correlated } => {
+                write!(f, "Subquery: ")?;
+                match r#type {
+                    SubqueryType::Exists => write!(f, "exists")?,
+                    SubqueryType::In(expr) => write!(f, "in {}", expr.format(source))?,
+                    SubqueryType::Scalar => write!(f, "scalar")?,
+                }
+                if *correlated {
+                    write!(f, " (correlated)")?;
+                }
+                source.format(f, prefix.clone(), false, false)?;
+                subquery.format(f, prefix, false, true)?;
+            }
+            Self::Union { left, right, distinct } => {
+                match distinct {
+                    true => write!(f, "Union: distinct")?,
+                    false => write!(f, "Union: all")?,
+                }
+                left.format(f, prefix.clone(), false, false)?;
+                right.format(f, prefix, false, true)?;
+            }
             Self::Values { rows, .. } => {
                 write!(f, "Values: ")?;
                 match rows.len() {
@@ -524,6 +972,17 @@ impl Node {
                     n => write!(f, "{n} rows")?,
                 }
             }
+            Self::Window { source, functions } => {
+                let functions = functions
+                    .iter()
+                    .map(|(function, window)| {
+                        format!("{} over ({})", function.format(source), window.format(source))
+                    })
+                    .join(", ");
+                write!(f, "Window: {functions}")?;
+                source.format(f, prefix, false, true)?;
+            }
+            Self::WorkTable { name, .. } => write!(f, "WorkTable: {name}")?,
         };
         Ok(())
     }
@@ -534,23 +993,326 @@ impl Node {
 pub enum Aggregate {
     Average(Expression),
     Count(Expression),
+    /// Only aggregates distinct values, e.g. COUNT(DISTINCT expr).
+    Distinct(Box<Aggregate>),
     Max(Expression),
     Min(Expression),
     Sum(Expression),
 }
 
 impl Aggregate {
+    /// Transforms the aggregate expression, see Expression.transform().
+    fn transform<B, A>(self, before: &B, after: &A) -> Result<Self>
+    where
+        B: Fn(Expression) -> Result<Expression>,
+        A: Fn(Expression) -> Result<Expression>,
+    {
+        Ok(match self {
+            Self::Average(expr) => Self::Average(expr.transform(before, after)?),
+            Self::Count(expr) => Self::Count(expr.transform(before, after)?),
+            Self::Distinct(aggregate) => {
+                Self::Distinct(Box::new(aggregate.transform(before, after)?))
+            }
+            Self::Max(expr) => Self::Max(expr.transform(before, after)?),
+            Self::Min(expr) => Self::Min(expr.transform(before, after)?),
+            Self::Sum(expr) => Self::Sum(expr.transform(before, after)?),
+        })
+    }
+
+    /// Returns the aggregate's input expression.
+    pub fn expr(&self) -> &Expression {
+        match self {
+            Self::Average(expr)
+            | Self::Count(expr)
+            | Self::Max(expr)
+            | Self::Min(expr)
+            | Self::Sum(expr) => expr,
+            Self::Distinct(aggregate) => aggregate.expr(),
+        }
+    }
+
+    /// Returns the data type of the aggregate value, if known. Evaluates the
+    /// input expression against the given source node.
+    fn datatype(&self, source: &Node) -> Option<DataType> {
+        match self {
+            Self::Count(_) => Some(DataType::Integer),
+            Self::Distinct(aggregate) => aggregate.datatype(source),
+            Self::Average(expr) | Self::Max(expr) | Self::Min(expr) | Self::Sum(expr) => {
+                expr.datatype(source)
+            }
+        }
+    }
+
+    /// Returns the aggregate function name.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Average(_) => "avg",
+            Self::Count(_) => "count",
+            Self::Distinct(aggregate) => aggregate.name(),
+            Self::Max(_) => "max",
+            Self::Min(_) => "min",
+            Self::Sum(_) => "sum",
+        }
+    }
+
+    fn format(&self, node: &Node) -> String {
+        match self {
+            Self::Distinct(_) => format!("{}(distinct {})", self.name(), self.expr().format(node)),
+            _ => format!("{}({})", self.name(), self.expr().format(node)),
+        }
+    }
+}
+
+/// A window function.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WindowFunction {
+    /// An aggregate function, evaluated over the window frame.
+    Aggregate(Aggregate),
+    /// The rank of the row's peer group (rows with equal ORDER BY values),
+    /// without gaps.
+    DenseRank,
+    /// The value at the first row of the window frame.
+    FirstValue(Expression),
+    /// The value at the given offset before the row in the partition (value,
+    /// offset, default). If there is no such row, the default is used.
+    Lag(Expression, Expression, Expression),
+    /// The value at the last row of the window frame.
+    LastValue(Expression),
+    /// The value at the given offset after the row in the partition (value,
+    /// offset, default). If there is no such row, the default is used.
+    Lead(Expression, Expression, Expression),
+    /// The number of the bucket that the row falls in, when dividing the
+    /// partition into the given number of buckets of as equal size as possible.
+    Ntile(Expression),
+    /// The rank of the row's peer group, with gaps, i.e. the row number of the
+    /// first row in the peer group.
+    Rank,
+    /// The row number in the partition, starting at 1.
+    RowNumber,
+}
+
+impl WindowFunction {
+    /// Transforms the function expressions, see Expression.transform().
+    fn transform<B, A>(self, before: &B, after: &A) -> Result<Self>
+    where
+        B: Fn(Expression) -> Result<Expression>,
+        A: Fn(Expression) -> Result<Expression>,
+    {
+        let transform = |expr: Expression| expr.transform(before, after);
+        Ok(match self {
+            Self::Aggregate(aggregate) => Self::Aggregate(aggregate.transform(before, after)?),
+            Self::FirstValue(expr) => Self::FirstValue(transform(expr)?),
+            Self::Lag(expr, offset, default) => {
+                Self::Lag(transform(expr)?, transform(offset)?, transform(default)?)
+            }
+            Self::LastValue(expr) => Self::LastValue(transform(expr)?),
+            Self::Lead(expr, offset, default) => {
+                Self::Lead(transform(expr)?, transform(offset)?, transform(default)?)
+            }
+            Self::Ntile(expr) => Self::Ntile(transform(expr)?),
+            Self::DenseRank | Self::Rank | Self::RowNumber => self,
+        })
+    }
+
+    /// Returns the data type of the function value, if known. Evaluates the
+    /// function expressions against the given source node.
+    fn datatype(&self, source: &Node) -> Option<DataType> {
+        match self {
+            Self::Aggregate(aggregate) => aggregate.datatype(source),
+            Self::DenseRank | Self::Ntile(_) | Self::Rank | Self::RowNumber => {
+                Some(DataType::Integer)
+            }
+            Self::FirstValue(expr) | Self::LastValue(expr) => expr.datatype(source),
+            // The default is used when there is no such row.
+            Self::Lag(expr, _, default) | Self::Lead(expr, _, default) => match default {
+                Expression::Constant(Value::Null) => expr.datatype(source),
+                default => expr.datatype(source).filter(|t| default.datatype(source) == Some(*t)),
+            },
+        }
+    }
+
     fn format(&self, node: &Node) -> String {
         match self {
-            Self::Average(expr) => format!("avg({})", expr.format(node)),
-            Self::Count(expr) => format!("count({})", expr.format(node)),
-            Self::Max(expr) => format!("max({})", expr.format(node)),
-            Self::Min(expr) => format!("min({})", expr.format(node)),
-            Self::Sum(expr) => format!("sum({})", expr.format(node)),
+            Self::Aggregate(aggregate) => aggregate.format(node),
+            Self::DenseRank => "dense_rank()".to_string(),
+            Self::FirstValue(expr) => format!("first_value({})", expr.format(node)),
+            Self::Lag(expr, offset, default) => format!(
+                "lag({}, {}, {})",
+                expr.format(node),
+                offset.format(node),
+                default.format(node)
+            ),
+            Self::LastValue(expr) => format!("last_value({})", expr.format(node)),
+            Self::Lead(expr, offset, default) => format!(
+                "lead({}, {}, {})",
+                expr.format(node),
+                offset.format(node),
+                default.format(node)
+            ),
+            Self::Ntile(expr) => format!("ntile({})", expr.format(node)),
+            Self::Rank => "rank()".to_string(),
+            Self::RowNumber => "row_number()".to_string(),
+        }
+    }
+}
+
+/// A window that a window function is evaluated over, see ast::Window.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Window {
+    pub partition_by: Vec<Expression>,
+    pub order_by: Vec<(Expression, Direction)>,
+    pub frame: WindowFrame,
+}
+
+impl Window {
+    /// Transforms the window expressions, see Expression.transform().
+    fn transform<B, A>(self, before: &B, after: &A) -> Result<Self>
+    where
+        B: Fn(Expression) -> Result<Expression>,
+        A: Fn(Expression) -> Result<Expression>,
+    {
+        Ok(Self {
+            partition_by: self
+                .partition_by
+                .into_iter()
+                .map(|e| e.transform(before, after))
+                .collect::<Result<_>>()?,
+            order_by: self
+                .order_by
+                .into_iter()
+                .map(|(e, d)| e.transform(before, after).map(|e| (e, d)))
+                .collect::<Result<_>>()?,
+            frame: self.frame,
+        })
+    }
+
+    /// Formats the window. The default frame is omitted.
+    fn format(&self, node: &Node) -> String {
+        let mut clauses = Vec::new();
+        if !self.partition_by.is_empty() {
+            let exprs = self.partition_by.iter().map(|e| e.format(node)).join(", ");
+            clauses.push(format!("partition by {exprs}"));
+        }
+        if !self.order_by.is_empty() {
+            let orders =
+                self.order_by.iter().map(|(e, d)| format!("{} {d}", e.format(node))).join(", ");
+            clauses.push(format!("order by {orders}"));
+        }
+        if self.frame != WindowFrame::default() {
+            clauses.push(self.frame.to_string());
+        }
+        clauses.join(" ")
+    }
+}
+
+/// A window frame, i.e. the rows around the current row in the window's
+/// partition that a window function is evaluated over. Only used by aggregate,
+/// first_value, and last_value functions.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WindowFrame {
+    pub units: FrameUnits,
+    pub start: FrameBound,
+    pub end: FrameBound,
+}
+
+/// The default frame contains all rows from the start of the partition up to
+/// the current row's last peer, or the entire partition without ORDER BY.
+impl Default for WindowFrame {
+    fn default() -> Self {
+        Self {
+            units: FrameUnits::Range,
+            start: FrameBound::UnboundedPreceding,
+            end: FrameBound::CurrentRow,
+        }
+    }
+}
+
+impl std::fmt::Display for WindowFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} between {} and {}", self.units, self.start, self.end)
+    }
+}
+
+/// Window frame units, see ast::FrameUnits.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FrameUnits {
+    Range,
+    Rows,
+}
+
+impl std::fmt::Display for FrameUnits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Range => write!(f, "range"),
+            Self::Rows => write!(f, "rows"),
+        }
+    }
+}
+
+/// A window frame bound. Offsets are non-negative numbers, and row offsets are
+/// integers.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FrameBound {
+    CurrentRow,
+    Following(Value),
+    Preceding(Value),
+    UnboundedFollowing,
+    UnboundedPreceding,
+}
+
+impl std::fmt::Display for FrameBound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CurrentRow => write!(f, "current row"),
+            Self::Following(offset) => write!(f, "{offset} following"),
+            Self::Preceding(offset) => write!(f, "{offset} preceding"),
+            Self::UnboundedFollowing => write!(f, "unbounded following"),
+            Self::UnboundedPreceding => write!(f, "unbounded preceding"),
         }
     }
 }
 
+/// A join type.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum JoinType {
+    /// Emits the joined left and right rows that match the predicate.
+    Inner,
+    /// Like Inner, but a left row without a right match is emitted anyway, with
+    /// NULLs for the right row (e.g. LEFT JOIN).
+    Outer,
+    /// Emits each left row that has any right match, once. Only emits the left
+    /// columns. Used for EXISTS and IN subqueries.
+    Semi,
+    /// Emits each left row that has no right match. Only emits the left
+    /// columns. Used for NOT EXISTS subqueries.
+    Anti,
+}
+
+impl std::fmt::Display for JoinType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Inner => write!(f, "inner"),
+            Self::Outer => write!(f, "outer"),
+            Self::Semi => write!(f, "semi"),
+            Self::Anti => write!(f, "anti"),
+        }
+    }
+}
+
+/// A subquery type, determining the value of the subquery column.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SubqueryType {
+    /// EXISTS: true if the subquery returns any rows.
+    Exists,
+    /// IN: true if the expression (evaluated on the source row) equals any of
+    /// the subquery values. NULLs are handled as a chain of ORed = comparisons,
+    /// i.e. if there is no match but the values contain NULL, yields NULL.
+    In(Expression),
+    /// Scalar: the single value returned by the subquery, or NULL if it
+    /// returns no rows. Errors if the subquery returns multiple rows.
+    Scalar,
+}
+
 /// A sort order direction.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Direction {
@@ -1,39 +1,54 @@
 #![allow(clippy::module_inception)]
 
 use super::plan::remap_sources;
-use super::{Aggregate, Direction, Node, Plan};
+use super::{
+    Aggregate, Alteration, Direction, FrameBound, FrameUnits, JoinType, Node, Plan, SubqueryType,
+    Window, WindowFrame, WindowFunction,
+};
 use crate::errinput;
 use crate::error::Result;
 use crate::sql::engine::Catalog;
 use crate::sql::parser::ast;
-use crate::sql::types::{Column, Expression, Label, Table, Value};
+use crate::sql::types::{Column, ColumnId, Expression, Index, Label, Table, Value};
+use crate::storage::mvcc;
 
 use itertools::Itertools as _;
+use std::cell::{Cell, RefCell};
 use std::collections::{BTreeMap, HashMap, HashSet};
 
 /// A statement plan builder. Takes a statement AST from the parser and builds
 /// an execution plan for it, using the catalog for schema information.
 pub struct Planner<'a, C: Catalog> {
     catalog: &'a C,
+    /// WITH common table expressions that are visible to the query currently
+    /// being built, innermost last. See build_with().
+    ctes: RefCell<Vec<CommonTable>>,
 }
 
 impl<'a, C: Catalog> Planner<'a, C> {
     /// Creates a new planner.
     pub fn new(catalog: &'a C) -> Self {
-        Self { catalog }
+        Self { catalog, ctes: RefCell::new(Vec::new()) }
     }
 
     /// Builds a plan for an AST statement.
     pub fn build(&mut self, statement: ast::Statement) -> Result<Plan> {
         use ast::Statement::*;
         match statement {
-            CreateTable { name, columns } => self.build_create_table(name, columns),
+            CreateTable { name, columns, primary_key } => {
+                self.build_create_table(name, columns, primary_key)
+            }
             DropTable { name, if_exists } => Ok(Plan::DropTable { table: name, if_exists }),
+            AlterTable { name, alteration } => self.build_alter_table(name, alteration),
+            CreateIndex { name, table, columns, unique } => {
+                self.build_create_index(name, table, columns, unique)
+            }
+            DropIndex { name, if_exists } => Ok(Plan::DropIndex { name, if_exists }),
             Delete { table, r#where } => self.build_delete(table, r#where),
             Insert { table, columns, values } => self.build_insert(table, columns, values),
             Update { table, set, r#where } => self.build_update(table, set, r#where),
-            Select { select, from, r#where, group_by, having, order_by, offset, limit } => {
-                self.build_select(select, from, r#where, group_by, having, order_by, offset, limit)
+            Select { .. } | SetOperation { .. } => {
+                Ok(Plan::Select(self.build_select_statement(Scope::new(), statement)?))
             }
 
             // Transaction and explain statements are handled by Session.
@@ -43,46 +58,137 @@ impl<'a, C: Catalog> Planner<'a, C> {
         }
     }
 
-    /// Builds a CREATE TABLE plan.
-    fn build_create_table(&self, name: String, columns: Vec<ast::Column>) -> Result<Plan> {
-        let Some(primary_key) = columns.iter().position(|c| c.primary_key) else {
-            return errinput!("no primary key for table {name}");
-        };
-        if columns.iter().filter(|c| c.primary_key).count() > 1 {
+    /// Builds a CREATE TABLE plan. The primary key is either given as a column
+    /// constraint, or as a (possibly composite) PRIMARY KEY table constraint.
+    fn build_create_table(
+        &self,
+        name: String,
+        mut columns: Vec<ast::Column>,
+        primary_key: Vec<String>,
+    ) -> Result<Plan> {
+        let column_keys = columns.iter().filter(|c| c.primary_key).count();
+        if column_keys > 1 || column_keys > 0 && !primary_key.is_empty() {
             return errinput!("multiple primary keys for table {name}");
         }
+        let primary_key = match primary_key.is_empty() {
+            true => match columns.iter().position(|c| c.primary_key) {
+                Some(i) => vec![i],
+                None => return errinput!("no primary key for table {name}"),
+            },
+            false => {
+                let mut indexes = Vec::with_capacity(primary_key.len());
+                for key in primary_key {
+                    let Some(i) = columns.iter().position(|c| c.name == key) else {
+                        return errinput!("unknown primary key column {key} in table {name}");
+                    };
+                    if indexes.contains(&i) {
+                        return errinput!("duplicate primary key column {key}");
+                    }
+                    indexes.push(i);
+                }
+                indexes
+            }
+        };
+        // A single-column key behaves like a column constraint. Composite key
+        // columns aren't individually unique, but are non-nullable by default.
+        match primary_key[..] {
+            [i] => columns[i].primary_key = true,
+            _ => primary_key.iter().for_each(|i| {
+                columns[*i].nullable.get_or_insert(false);
+            }),
+        }
         let columns = columns
             .into_iter()
-            .map(|c| {
-                let nullable = c.nullable.unwrap_or(!c.primary_key);
-                Ok(Column {
-                    name: c.name,
-                    datatype: c.datatype,
-                    nullable,
-                    default: match c.default {
-                        Some(expr) => Some(Self::evaluate_constant(expr)?),
-                        None if nullable => Some(Value::Null),
-                        None => None,
-                    },
-                    unique: c.unique || c.primary_key,
-                    index: (c.index || c.unique || c.references.is_some()) && !c.primary_key,
-                    references: c.references,
-                })
+            .enumerate()
+            .map(|(i, c)| Self::build_column(i as ColumnId, c))
+            .collect::<Result<_>>()?;
+        let schema = Table {
+            id: 0, // assigned by the engine
+            name,
+            primary_key,
+            columns,
+            version: 0,
+            layouts: Vec::new(),
+            indexes: Vec::new(),
+        };
+        Ok(Plan::CreateTable { schema })
+    }
+
+    /// Builds a table column from a column definition, with the given ID.
+    fn build_column(id: ColumnId, c: ast::Column) -> Result<Column> {
+        let nullable = c.nullable.unwrap_or(!c.primary_key);
+        Ok(Column {
+            id,
+            name: c.name,
+            datatype: c.datatype,
+            nullable,
+            default: match c.default {
+                Some(expr) => Some(Self::evaluate_constant(expr)?),
+                None if nullable => Some(Value::Null),
+                None => None,
+            },
+            unique: c.unique || c.primary_key,
+            index: (c.index || c.unique || c.references.is_some()) && !c.primary_key,
+            references: c.references,
+        })
+    }
+
+    /// Builds an ALTER TABLE plan.
+    fn build_alter_table(&self, table: String, alteration: ast::Alteration) -> Result<Plan> {
+        let alteration = match alteration {
+            ast::Alteration::AddColumn(column) => {
+                if column.primary_key {
+                    return errinput!("can't add primary key column {}", column.name);
+                }
+                let id = self.catalog.must_get_table(&table)?.next_column_id();
+                Alteration::AddColumn(Self::build_column(id, column)?)
+            }
+            ast::Alteration::DropColumn(column) => Alteration::DropColumn(column),
+            ast::Alteration::RenameColumn { column, new_name } => {
+                Alteration::RenameColumn { column, new_name }
+            }
+            ast::Alteration::RenameTable(new_name) => Alteration::RenameTable(new_name),
+            ast::Alteration::SetDefault { column, default } => Alteration::SetDefault {
+                column,
+                default: default.map(Self::evaluate_constant).transpose()?,
+            },
+            ast::Alteration::SetNullable { column, nullable } => {
+                Alteration::SetNullable { column, nullable }
+            }
+        };
+        Ok(Plan::AlterTable { table, alteration })
+    }
+
+    /// Builds a CREATE INDEX plan.
+    fn build_create_index(
+        &self,
+        name: String,
+        table: String,
+        columns: Vec<String>,
+        unique: bool,
+    ) -> Result<Plan> {
+        let table = self.catalog.must_get_table(&table)?;
+        let columns = columns
+            .into_iter()
+            .map(|name| match table.columns.iter().find(|c| c.name == name) {
+                Some(column) => Ok(column.id),
+                None => errinput!("unknown column {name} in table {}", table.name),
             })
             .collect::<Result<_>>()?;
-        Ok(Plan::CreateTable { schema: Table { name, primary_key, columns } })
+        let index = Index { name, columns, unique, write_only: true }; // until backfilled
+        Ok(Plan::CreateIndex { table: table.name, index })
     }
 
     /// Builds a DELETE plan.
     fn build_delete(&self, table: String, r#where: Option<ast::Expression>) -> Result<Plan> {
         let table = self.catalog.must_get_table(&table)?;
-        let scope = Scope::from_table(&table)?;
-        let filter = r#where.map(|e| Self::build_expression(e, &scope)).transpose()?;
-        Ok(Plan::Delete {
-            table: table.name.clone(),
-            primary_key: table.primary_key,
-            source: Node::Scan { table, alias: None, filter },
-        })
+        let mut scope = Scope::from_table(&table)?;
+        let (name, primary_key) = (table.name.clone(), table.primary_key.clone());
+        let mut source = Node::Scan { table, alias: None, filter: None };
+        if let Some(expr) = r#where {
+            source = self.build_where(&mut scope, source, expr)?;
+        }
+        Ok(Plan::Delete { table: name, primary_key, source })
     }
 
     /// Builds an INSERT plan.
@@ -123,8 +229,11 @@ impl<'a, C: Catalog> Planner<'a, C> {
         r#where: Option<ast::Expression>,
     ) -> Result<Plan> {
         let table = self.catalog.must_get_table(&table)?;
-        let scope = Scope::from_table(&table)?;
-        let filter = r#where.map(|e| Self::build_expression(e, &scope)).transpose()?;
+        let mut scope = Scope::from_table(&table)?;
+        let mut source = Node::Scan { table: table.clone(), alias: None, filter: None };
+        if let Some(expr) = r#where {
+            source = self.build_where(&mut scope, source, expr)?;
+        }
         let mut expressions = Vec::with_capacity(set.len());
         for (column, expr) in set {
             let index = scope.lookup_column(None, &column)?;
@@ -137,18 +246,53 @@ impl<'a, C: Catalog> Planner<'a, C> {
             };
             expressions.push((index, expr));
         }
-        Ok(Plan::Update {
-            table: table.clone(),
-            primary_key: table.primary_key,
-            source: Node::Scan { table, alias: None, filter },
-            expressions,
-        })
+        Ok(Plan::Update { primary_key: table.primary_key.clone(), table, source, expressions })
+    }
+
+    /// Builds a query node for a SELECT statement or set operation, using the
+    /// given scope.
+    fn build_select_statement(&self, scope: Scope, statement: ast::Statement) -> Result<Node> {
+        match statement {
+            ast::Statement::Select {
+                with,
+                distinct,
+                select,
+                from,
+                r#where,
+                group_by,
+                having,
+                order_by,
+                offset,
+                limit,
+                lock,
+            } => self.build_select(
+                scope, with, distinct, select, from, r#where, group_by, having, order_by, offset,
+                limit, lock,
+            ),
+            ast::Statement::SetOperation {
+                with,
+                operator,
+                all,
+                left,
+                right,
+                order_by,
+                offset,
+                limit,
+            } => self.build_set_operation(
+                scope, with, operator, all, *left, *right, order_by, offset, limit,
+            ),
+            _ => errinput!("expected SELECT statement"),
+        }
     }
 
-    /// Builds a SELECT plan.
+    /// Builds a SELECT query node, using the given scope. The scope is empty,
+    /// except for subqueries where it contains the outer scope.
     #[allow(clippy::too_many_arguments)]
     fn build_select(
         &self,
+        mut scope: Scope,
+        with: Vec<ast::CommonTableExpression>,
+        distinct: Option<Vec<ast::Expression>>,
         mut select: Vec<(ast::Expression, Option<String>)>,
         from: Vec<ast::From>,
         r#where: Option<ast::Expression>,
@@ -157,8 +301,26 @@ impl<'a, C: Catalog> Planner<'a, C> {
         order_by: Vec<(ast::Expression, ast::Order)>,
         offset: Option<ast::Expression>,
         limit: Option<ast::Expression>,
-    ) -> Result<Plan> {
-        let mut scope = Scope::new();
+        lock: Option<ast::Lock>,
+    ) -> Result<Node> {
+        // Build WITH common table expressions. They're only visible while
+        // building this query, and are removed again below.
+        let ctes = self.ctes.borrow().len();
+        self.build_with(with)?;
+
+        // Evaluate OFFSET and LIMIT, which must be constant.
+        let offset = Self::evaluate_limit(offset, "offset")?;
+        let limit = Self::evaluate_limit(limit, "limit")?;
+
+        // FOR UPDATE/SHARE locks rows by primary key, so it requires a single
+        // table to lock.
+        let lock_table = match (&lock, from.as_slice()) {
+            (None, _) => None,
+            (Some(_), [ast::From::Table { name, .. }]) if self.lookup_cte(name).is_none() => {
+                Some(self.catalog.must_get_table(name)?)
+            }
+            (Some(_), _) => return errinput!("FOR UPDATE/SHARE requires a single table"),
+        };
 
         // Build FROM clause.
         let mut node = if !from.is_empty() {
@@ -192,18 +354,70 @@ impl<'a, C: Catalog> Planner<'a, C> {
 
         // Build WHERE clause.
         if let Some(expr) = r#where {
-            let predicate = Self::build_expression(expr, &scope)?;
-            node = Node::Filter { source: Box::new(node), predicate };
+            node = self.build_where(&mut scope, node, expr)?;
+        };
+
+        // Build FOR UPDATE/SHARE clause, locking the table rows that match
+        // the WHERE clause. Without ORDER BY, we only need to lock the rows
+        // that are emitted by LIMIT, so stop once we have enough rows.
+        let distinct_on = distinct.as_deref().unwrap_or_default();
+        let aggregates = Self::collect_aggregates(&select, &having, &order_by, distinct_on);
+        let window_exprs = || {
+            let select = select.iter().map(|(expr, _)| expr);
+            let order_by = order_by.iter().map(|(expr, _)| expr);
+            select.chain(order_by).chain(distinct_on)
         };
+        let windows = window_exprs().any(|expr| expr.contains(&Self::is_window_function));
+        if let (Some(lock), Some(table)) = (lock, lock_table) {
+            if !group_by.is_empty() || !aggregates.is_empty() {
+                return errinput!("FOR UPDATE/SHARE is not allowed with aggregates");
+            }
+            if distinct.is_some() {
+                return errinput!("FOR UPDATE/SHARE is not allowed with DISTINCT");
+            }
+            if windows {
+                return errinput!("FOR UPDATE/SHARE is not allowed with window functions");
+            }
+            let mode = match lock.mode {
+                ast::LockMode::Share => mvcc::LockMode::Shared,
+                ast::LockMode::Update => mvcc::LockMode::Exclusive,
+            };
+            let limit = match order_by.is_empty() {
+                true => limit.map(|limit| limit + offset.unwrap_or(0)),
+                false => None,
+            };
+            node = Node::Lock {
+                source: Box::new(node),
+                table: table.name,
+                primary_key: table.primary_key.clone(),
+                mode,
+                skip_locked: lock.skip_locked,
+                limit,
+            };
+        }
 
         // Build aggregate functions and GROUP BY clause.
-        let aggregates = Self::collect_aggregates(&select, &having, &order_by);
         if !group_by.is_empty() || !aggregates.is_empty() {
             node = self.build_aggregate(&mut scope, node, group_by, aggregates)?;
         }
 
+        // Build window functions in SELECT, ORDER BY, and DISTINCT ON. These are
+        // evaluated after HAVING, so it's built here rather than after the
+        // projection, and can't reference SELECT aliases.
+        let mut having = having;
+        if windows {
+            if let Some(having) = having.take() {
+                node = self.build_having(&mut scope, node, having)?;
+            }
+            node = self.build_subqueries(&mut scope, node, select.iter().map(|(expr, _)| expr))?;
+            node = Self::build_windows(&mut scope, node, window_exprs())?;
+        }
+
         // Build SELECT clause. We can omit this for a trivial SELECT *.
         if select.as_slice() != [(ast::Expression::All, None)] {
+            // Build any subqueries in the SELECT expressions.
+            node = self.build_subqueries(&mut scope, node, select.iter().map(|(expr, _)| expr))?;
+
             // Prepare the post-projection scope.
             let mut child_scope = scope.project(&select);
 
@@ -215,8 +429,13 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 aliases.push(Label::from(alias));
             }
 
-            // Add hidden columns for HAVING and ORDER BY columns not in SELECT.
-            let hidden = self.build_select_hidden(&scope, &mut child_scope, &having, &order_by);
+            // Add hidden columns for HAVING, ORDER BY, and DISTINCT ON columns
+            // not in SELECT.
+            let hidden_exprs = having
+                .iter()
+                .chain(order_by.iter().map(|(expr, _)| expr))
+                .chain(distinct_on.iter());
+            let hidden = self.build_select_hidden(&scope, &mut child_scope, hidden_exprs);
             aliases.extend(std::iter::repeat(Label::None).take(hidden.len()));
             expressions.extend(hidden);
 
@@ -226,15 +445,13 @@ impl<'a, C: Catalog> Planner<'a, C> {
 
         // Build HAVING clause.
         if let Some(having) = having {
-            if scope.aggregates.is_empty() {
-                return errinput!("HAVING requires GROUP BY or aggregate function");
-            }
-            let predicate = Self::build_expression(having, &scope)?;
-            node = Node::Filter { source: Box::new(node), predicate };
+            node = self.build_having(&mut scope, node, having)?;
         };
 
         // Build ORDER BY clause.
         if !order_by.is_empty() {
+            node =
+                self.build_subqueries(&mut scope, node, order_by.iter().map(|(expr, _)| expr))?;
             let orders = order_by
                 .into_iter()
                 .map(|(e, o)| Ok((Self::build_expression(e, &scope)?, Direction::from(o))))
@@ -242,21 +459,30 @@ impl<'a, C: Catalog> Planner<'a, C> {
             node = Node::Order { source: Box::new(node), orders };
         }
 
+        // Build DISTINCT clause, after ORDER BY such that the first row in the
+        // sort order is emitted for each distinct row. Plain DISTINCT compares
+        // all non-hidden columns.
+        if let Some(on) = distinct {
+            let on = match on.is_empty() {
+                true => (0..node.size())
+                    .filter(|i| !scope.hidden.contains(i))
+                    .map(Expression::Column)
+                    .collect(),
+                false => on
+                    .into_iter()
+                    .map(|expr| Self::build_expression(expr, &scope))
+                    .collect::<Result<_>>()?,
+            };
+            node = Node::Distinct { source: Box::new(node), on };
+        }
+
         // Build OFFSET clause.
-        if let Some(expr) = offset {
-            let offset = match Self::evaluate_constant(expr)? {
-                Value::Integer(offset) if offset >= 0 => Ok(offset as usize),
-                value => errinput!("invalid offset {value}"),
-            }?;
+        if let Some(offset) = offset {
             node = Node::Offset { source: Box::new(node), offset }
         }
 
         // Build LIMIT clause.
-        if let Some(expr) = limit {
-            let limit = match Self::evaluate_constant(expr)? {
-                Value::Integer(limit) if limit >= 0 => Ok(limit as usize),
-                value => errinput!("invalid limit {value}"),
-            }?;
+        if let Some(limit) = limit {
             node = Node::Limit { source: Box::new(node), limit }
         }
 
@@ -265,7 +491,484 @@ impl<'a, C: Catalog> Planner<'a, C> {
             node = Node::Remap { source: Box::new(node), targets }
         }
 
-        Ok(Plan::Select(node))
+        self.ctes.borrow_mut().truncate(ctes);
+        Ok(node)
+    }
+
+    /// Builds a HAVING clause filter for the given node.
+    fn build_having(
+        &self,
+        scope: &mut Scope,
+        mut node: Node,
+        having: ast::Expression,
+    ) -> Result<Node> {
+        if scope.aggregates.is_empty() {
+            return errinput!("HAVING requires GROUP BY or aggregate function");
+        }
+        node = self.build_subqueries(scope, node, [&having])?;
+        let predicate = Self::build_expression(having, scope)?;
+        Ok(Node::Filter { source: Box::new(node), predicate })
+    }
+
+    /// Builds a set operation (UNION, INTERSECT, or EXCEPT) query node. Both
+    /// operands use the given scope, e.g. for correlated subqueries. ORDER BY
+    /// applies to the combined result, and can only reference its columns by
+    /// their unqualified names, which are taken from the left operand.
+    #[allow(clippy::too_many_arguments)]
+    fn build_set_operation(
+        &self,
+        scope: Scope,
+        with: Vec<ast::CommonTableExpression>,
+        operator: ast::SetOperator,
+        all: bool,
+        left: ast::Statement,
+        right: ast::Statement,
+        order_by: Vec<(ast::Expression, ast::Order)>,
+        offset: Option<ast::Expression>,
+        limit: Option<ast::Expression>,
+    ) -> Result<Node> {
+        // Build WITH common table expressions, visible to both operands.
+        let ctes = self.ctes.borrow().len();
+        self.build_with(with)?;
+
+        let offset = Self::evaluate_limit(offset, "offset")?;
+        let limit = Self::evaluate_limit(limit, "limit")?;
+
+        let left = self.build_select_statement(scope.clone(), left)?;
+        let right = self.build_select_statement(scope, right)?;
+        let mut node = Self::build_set_node(operator, !all, left, right)?;
+
+        // Build ORDER BY clause.
+        if !order_by.is_empty() {
+            let mut scope = Scope::new();
+            for i in 0..node.size() {
+                scope.add_column(match node.column_label(i) {
+                    Label::Qualified(_, column) => Label::Unqualified(column),
+                    label => label,
+                });
+            }
+            let orders = order_by
+                .into_iter()
+                .map(|(e, o)| Ok((Self::build_expression(e, &scope)?, Direction::from(o))))
+                .collect::<Result<_>>()?;
+            node = Node::Order { source: Box::new(node), orders };
+        }
+
+        // Build OFFSET clause.
+        if let Some(offset) = offset {
+            node = Node::Offset { source: Box::new(node), offset }
+        }
+
+        // Build LIMIT clause.
+        if let Some(limit) = limit {
+            node = Node::Limit { source: Box::new(node), limit }
+        }
+
+        self.ctes.borrow_mut().truncate(ctes);
+        Ok(node)
+    }
+
+    /// Builds a set operation node for the given operands, which must have
+    /// the same number of columns and column types.
+    fn build_set_node(
+        operator: ast::SetOperator,
+        distinct: bool,
+        left: Node,
+        right: Node,
+    ) -> Result<Node> {
+        if left.size() != right.size() {
+            return errinput!("each {operator} query must have the same number of columns");
+        }
+        Self::check_set_types(&left, &right)?;
+        let (left, right) = (Box::new(left), Box::new(right));
+        Ok(match operator {
+            ast::SetOperator::Except => Node::Except { left, right, distinct },
+            ast::SetOperator::Intersect => Node::Intersect { left, right, distinct },
+            ast::SetOperator::Union => Node::Union { left, right, distinct },
+        })
+    }
+
+    /// Checks that the columns of two set operation operands have the same data
+    /// types. Columns with unknown types (e.g. NULL or work table columns) are
+    /// checked for each row during execution instead.
+    fn check_set_types(left: &Node, right: &Node) -> Result<()> {
+        for index in 0..left.size() {
+            if let (Some(ltype), Some(rtype)) = (left.column_type(index), right.column_type(index))
+            {
+                if ltype != rtype {
+                    return errinput!("mismatched types {ltype} and {rtype} in column {}", index + 1);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds WITH common table expressions (CTEs), making them visible to
+    /// later CTEs and the rest of the query (including subqueries). A CTE
+    /// shadows tables and outer CTEs with the same name. References to a CTE
+    /// build a CommonTable node with a copy of its query node.
+    ///
+    /// For WITH RECURSIVE, the right-hand side of a top-level UNION [ALL] in
+    /// the CTE query is a recursive term, which can reference the CTE itself.
+    /// This is built as a RecursiveUnion node, where the recursive term's
+    /// self-references are WorkTable nodes. If the recursive term doesn't
+    /// reference the CTE, it's built as a regular Union node.
+    fn build_with(&self, with: Vec<ast::CommonTableExpression>) -> Result<()> {
+        let mut names = HashSet::new();
+        for cte in with {
+            let ast::CommonTableExpression { name, columns, query, recursive } = cte;
+            if !names.insert(name.clone()) {
+                return errinput!("duplicate CTE name {name}");
+            }
+
+            // Split out the recursive term of a WITH RECURSIVE union, if any.
+            let (query, recursive) = match *query {
+                ast::Statement::SetOperation {
+                    with,
+                    operator: ast::SetOperator::Union,
+                    all,
+                    left,
+                    right,
+                    order_by,
+                    offset: None,
+                    limit: None,
+                } if recursive && with.is_empty() && order_by.is_empty() => {
+                    (*left, Some((*right, all)))
+                }
+                query => (query, None),
+            };
+            let anchor = self.build_cte_query(query)?;
+
+            // Name the columns, either via the column list or the query labels.
+            let columns = match columns.is_empty() {
+                true => (0..anchor.size())
+                    .map(|i| match anchor.column_label(i) {
+                        Label::Qualified(_, column) | Label::Unqualified(column) => Ok(column),
+                        Label::None => errinput!("CTE {name} column {} needs a name", i + 1),
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+                false if columns.len() != anchor.size() => {
+                    return errinput!(
+                        "CTE {name} has {} columns, but {} column names were given",
+                        anchor.size(),
+                        columns.len()
+                    );
+                }
+                false => columns,
+            };
+            if let Some(column) = columns.iter().duplicates().next() {
+                return errinput!("duplicate column {column} in CTE {name}");
+            }
+
+            // Build the recursive term, if any, with the CTE itself visible as
+            // a work table.
+            let node = match recursive {
+                None => anchor,
+                Some((recursive, all)) => {
+                    let size = anchor.size();
+                    let work_table = Node::WorkTable { name: name.clone(), size };
+                    self.ctes.borrow_mut().push(CommonTable::new(&name, &columns, work_table));
+                    let recursive = self.build_cte_query(recursive)?;
+                    let cte = self.ctes.borrow_mut().pop().expect("no work table");
+                    if !cte.referenced.get() {
+                        Self::build_set_node(ast::SetOperator::Union, !all, anchor, recursive)?
+                    } else if recursive.size() != size {
+                        return errinput!(
+                            "recursive term of CTE {name} has {} columns, expected {size}",
+                            recursive.size()
+                        );
+                    } else {
+                        Self::check_set_types(&anchor, &recursive)?;
+                        Node::RecursiveUnion {
+                            anchor: Box::new(anchor),
+                            recursive: Box::new(recursive),
+                            name: name.clone(),
+                            distinct: !all,
+                            limit: None,
+                        }
+                    }
+                }
+            };
+            self.ctes.borrow_mut().push(CommonTable::new(&name, &columns, node));
+        }
+        Ok(())
+    }
+
+    /// Builds a CTE query. It can't reference outer columns or lock rows.
+    fn build_cte_query(&self, statement: ast::Statement) -> Result<Node> {
+        if matches!(statement, ast::Statement::Select { lock: Some(_), .. }) {
+            return errinput!("FOR UPDATE/SHARE is not allowed in CTEs");
+        }
+        self.build_select_statement(Scope::new(), statement)
+    }
+
+    /// Looks up a visible CTE by name, returning its query node and column
+    /// names, and marking it as referenced.
+    fn lookup_cte(&self, name: &str) -> Option<(Node, Vec<String>)> {
+        let ctes = self.ctes.borrow();
+        let cte = ctes.iter().rev().find(|cte| cte.name == name)?;
+        cte.referenced.set(true);
+        Some((cte.node.clone(), cte.columns.clone()))
+    }
+
+    /// Builds a subquery node for a SELECT statement. The subquery can
+    /// reference columns in the outer scope, in which case it's correlated.
+    fn build_subquery(&self, statement: ast::Statement, outer: &Scope) -> Result<Node> {
+        self.build_select_statement(Scope::subquery(outer), statement)
+    }
+
+    /// Builds a WHERE clause filter for the given node.
+    ///
+    /// If the predicate contains subqueries, EXISTS, NOT EXISTS, and IN
+    /// subqueries in top-level AND clauses are decorrelated into semi and anti
+    /// joins where possible. This allows using e.g. hash joins, instead of
+    /// executing a correlated subquery for every row. Other subqueries are
+    /// built as Subquery nodes, whose hidden columns are removed again after
+    /// filtering.
+    fn build_where(
+        &self,
+        scope: &mut Scope,
+        mut node: Node,
+        expr: ast::Expression,
+    ) -> Result<Node> {
+        if !expr.contains(&Self::is_subquery) {
+            let predicate = Self::build_expression(expr, scope)?;
+            return Ok(Node::Filter { source: Box::new(node), predicate });
+        }
+
+        // Split the predicate into AND clauses, and filter on the ones without
+        // subqueries first.
+        let mut clauses = Vec::new();
+        let mut split = vec![expr];
+        while let Some(expr) = split.pop() {
+            match expr {
+                ast::Expression::Operator(ast::Operator::And(lhs, rhs)) => {
+                    split.extend([*rhs, *lhs]);
+                }
+                expr => clauses.push(expr),
+            }
+        }
+        let (subqueries, clauses): (Vec<_>, Vec<_>) =
+            clauses.into_iter().partition(|expr| expr.contains(&Self::is_subquery));
+        let predicates = clauses
+            .into_iter()
+            .map(|expr| Self::build_expression(expr, scope))
+            .collect::<Result<_>>()?;
+        if let Some(predicate) = Expression::and_vec(predicates) {
+            node = Node::Filter { source: Box::new(node), predicate };
+        }
+
+        // Decorrelate subqueries into semi and anti joins where possible.
+        let mut remaining = Vec::new();
+        for expr in subqueries {
+            match self.build_semi_join(scope, &expr)? {
+                Some((right, predicate, r#type)) => {
+                    let (left, right) = (Box::new(node), Box::new(right));
+                    node = Node::NestedLoopJoin { left, right, predicate, r#type };
+                }
+                None => remaining.push(expr),
+            }
+        }
+        if remaining.is_empty() {
+            return Ok(node);
+        }
+
+        // Build the remaining subqueries as hidden columns, and filter on them.
+        node = self.build_subqueries(scope, node, &remaining)?;
+        let predicates = remaining
+            .into_iter()
+            .map(|expr| Self::build_expression(expr, scope))
+            .collect::<Result<_>>()?;
+        if let Some(predicate) = Expression::and_vec(predicates) {
+            node = Node::Filter { source: Box::new(node), predicate };
+        }
+        if let Some(targets) = scope.remap_hidden() {
+            node = Node::Remap { source: Box::new(node), targets };
+        }
+        Ok(node)
+    }
+
+    /// Attempts to decorrelate a WHERE clause subquery into a semi or anti
+    /// join, returning the right join node, the join predicate, and the join
+    /// type. This handles EXISTS (semi), NOT EXISTS (anti), and IN (semi) for
+    /// simple SELECT subqueries of FROM and WHERE clauses, where the subquery
+    /// WHERE predicate becomes the join predicate. Outer references become
+    /// references to the left join columns. Returns None if not possible.
+    ///
+    /// NOT IN can't be built as an anti join, since it yields NULL rather than
+    /// true when the subquery returns a NULL value.
+    fn build_semi_join(
+        &self,
+        scope: &Scope,
+        expr: &ast::Expression,
+    ) -> Result<Option<(Node, Option<Expression>, JoinType)>> {
+        use ast::Expression::{Exists, Operator, Subquery};
+        let (statement, lhs, r#type) = match expr {
+            Exists(statement) => (statement, None, JoinType::Semi),
+            Operator(ast::Operator::Not(expr)) => match expr.as_ref() {
+                Exists(statement) => (statement, None, JoinType::Anti),
+                _ => return Ok(None),
+            },
+            Operator(ast::Operator::In(lhs, rhs)) => match rhs.as_ref() {
+                Subquery(statement) if !lhs.contains(&Self::is_subquery) => {
+                    (statement, Some(lhs.as_ref()), JoinType::Semi)
+                }
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+
+        // Only simple subqueries can be decorrelated.
+        // DISTINCT doesn't affect semi joins, and can be ignored.
+        let ast::Statement::Select {
+            with,
+            distinct: _,
+            select,
+            from,
+            r#where,
+            group_by,
+            having: None,
+            order_by,
+            offset: None,
+            limit: None,
+            lock: None,
+        } = statement.as_ref()
+        else {
+            return Ok(None);
+        };
+        if !with.is_empty()
+            || from.is_empty()
+            || !group_by.is_empty()
+            || !order_by.is_empty()
+            || !Self::collect_aggregates(select, &None, &[], &[]).is_empty()
+            || select.iter().any(|(expr, _)| expr.contains(&Self::is_subquery))
+            || select.iter().any(|(expr, _)| expr.contains(&Self::is_window_function))
+            || r#where.as_ref().is_some_and(|expr| expr.contains(&Self::is_subquery))
+        {
+            return Ok(None);
+        }
+
+        // Build the FROM clause as the right join node, using a subquery scope
+        // with the outer query as the left side.
+        let mut subscope = Scope::subquery(scope);
+        let right = self.build_from_clause(&mut subscope, from.clone())?;
+        if Self::is_correlated(&right) {
+            return Ok(None); // outer references in join predicates
+        }
+
+        // Build the SELECT expressions and WHERE predicate. For EXISTS, the
+        // SELECT expressions are only built to validate them.
+        let mut items = Vec::with_capacity(select.len());
+        for (expr, _) in select {
+            if expr != &ast::Expression::All {
+                items.push(Self::build_expression(expr.clone(), &subscope)?);
+            }
+        }
+        let r#where = r#where
+            .as_ref()
+            .map(|expr| Self::build_expression(expr.clone(), &subscope))
+            .transpose()?;
+
+        // Convert the subquery expressions to join predicate expressions, where
+        // the right columns follow the left columns.
+        let left_size = scope.columns.len();
+        let transform = |expr: Expression| {
+            expr.transform(
+                &|expr| match expr {
+                    Expression::Column(index) => Ok(Expression::Column(left_size + index)),
+                    Expression::Outer(index) => Ok(Expression::Column(index)),
+                    expr => Ok(expr),
+                },
+                &Ok,
+            )
+        };
+
+        // For IN, join on the lhs equaling the SELECT expression.
+        let mut predicates = Vec::new();
+        if let Some(lhs) = lhs {
+            if select.len() != 1 || items.len() != 1 {
+                return errinput!("subquery must return a single column");
+            }
+            let lhs = Self::build_expression(lhs.clone(), scope)?;
+            let rhs = transform(items.remove(0))?;
+            predicates.push(Expression::Equal(lhs.into(), rhs.into()));
+        }
+        if let Some(expr) = r#where {
+            predicates.push(transform(expr)?);
+        }
+        let predicate = Expression::and_vec(predicates);
+        Ok(Some((right, predicate, r#type)))
+    }
+
+    /// Builds Subquery nodes for any subqueries in the given expressions. Their
+    /// values are added to the scope as hidden columns, which are looked up
+    /// when building the expressions. Duplicate subqueries are only built once.
+    fn build_subqueries<'e>(
+        &self,
+        scope: &mut Scope,
+        mut node: Node,
+        exprs: impl IntoIterator<Item = &'e ast::Expression>,
+    ) -> Result<Node> {
+        let mut subqueries = Vec::new();
+        for expr in exprs {
+            expr.collect(&Self::is_subquery, &mut subqueries);
+        }
+        for expr in subqueries {
+            if scope.lookup_subquery(&expr).is_some() {
+                continue;
+            }
+            let (statement, r#type) = match expr.clone() {
+                ast::Expression::Exists(statement) => (statement, SubqueryType::Exists),
+                ast::Expression::Subquery(statement) => (statement, SubqueryType::Scalar),
+                ast::Expression::Operator(ast::Operator::In(lhs, rhs)) => {
+                    let ast::Expression::Subquery(statement) = *rhs else {
+                        panic!("IN without subquery");
+                    };
+                    // The IN operand may itself contain subqueries.
+                    node = self.build_subqueries(scope, node, [lhs.as_ref()])?;
+                    (statement, SubqueryType::In(Self::build_expression(*lhs, scope)?))
+                }
+                expr => panic!("unexpected subquery expression {expr:?}"),
+            };
+            let subquery = self.build_subquery(*statement, scope)?;
+            if r#type != SubqueryType::Exists && subquery.size() != 1 {
+                return errinput!("subquery must return a single column");
+            }
+            let correlated = Self::is_correlated(&subquery);
+            let (source, subquery) = (Box::new(node), Box::new(subquery));
+            node = Node::Subquery { source, subquery, r#type, correlated };
+            scope.add_subquery(&expr);
+        }
+        Ok(node)
+    }
+
+    /// Checks whether a given AST expression is a subquery, i.e. a scalar,
+    /// EXISTS, or IN subquery.
+    fn is_subquery(expr: &ast::Expression) -> bool {
+        match expr {
+            ast::Expression::Exists(_) | ast::Expression::Subquery(_) => true,
+            ast::Expression::Operator(ast::Operator::In(_, rhs)) => {
+                matches!(rhs.as_ref(), ast::Expression::Subquery(_))
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks whether a subquery node is correlated, i.e. whether it contains
+    /// outer column references. Nested subqueries are not considered, since
+    /// their outer references refer to this subquery.
+    fn is_correlated(node: &Node) -> bool {
+        let correlated = Cell::new(false);
+        let check = |expr: Expression| {
+            if matches!(expr, Expression::Outer(_)) {
+                correlated.set(true);
+            }
+            Ok(expr)
+        };
+        node.clone()
+            .transform(&|n| n.transform_expressions(&check, &Ok), &Ok)
+            .expect("transform failed");
+        correlated.get()
     }
 
     /// Builds a FROM clause consisting of one or more items. Each item is
@@ -286,7 +989,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 left: Box::new(node),
                 right: Box::new(right),
                 predicate: None,
-                outer: false,
+                r#type: JoinType::Inner,
             };
         }
         Ok(node)
@@ -297,15 +1000,23 @@ impl<'a, C: Catalog> Planner<'a, C> {
     fn build_from(&self, from: ast::From, parent_scope: &mut Scope) -> Result<Node> {
         // Each from item is built in its own scope, such that a join node only
         // sees the columns of its children. It's then merged into the parent.
+        // Subqueries can reference outer columns in join predicates.
         let mut scope = Scope::new();
+        scope.outer = parent_scope.outer.clone();
 
         let node = match from {
-            // A full table scan.
-            ast::From::Table { name, alias } => {
-                let table = self.catalog.must_get_table(&name)?;
-                scope.add_table(alias.as_ref().unwrap_or(&name), &table)?;
-                Node::Scan { table, alias, filter: None }
-            }
+            // A full table scan, or a CTE reference.
+            ast::From::Table { name, alias } => match self.lookup_cte(&name) {
+                Some((source, columns)) => {
+                    scope.add_cte(alias.as_ref().unwrap_or(&name), &columns)?;
+                    Node::CommonTable { source: Box::new(source), name, alias, columns }
+                }
+                None => {
+                    let table = self.catalog.must_get_table(&name)?;
+                    scope.add_table(alias.as_ref().unwrap_or(&name), &table)?;
+                    Node::Scan { table, alias, filter: None }
+                }
+            },
 
             // A two-way join. The left or right nodes may be chained joins.
             ast::From::Join { mut left, mut right, r#type, predicate } => {
@@ -321,8 +1032,11 @@ impl<'a, C: Catalog> Planner<'a, C> {
 
                 // Build the join node.
                 let predicate = predicate.map(|e| Self::build_expression(e, &scope)).transpose()?;
-                let outer = r#type.is_outer();
-                let mut node = Node::NestedLoopJoin { left, right, predicate, outer };
+                let join_type = match r#type.is_outer() {
+                    true => JoinType::Outer,
+                    false => JoinType::Inner,
+                };
+                let mut node = Node::NestedLoopJoin { left, right, predicate, r#type: join_type };
 
                 // For right joins, swap the columns.
                 if matches!(r#type, ast::JoinType::Right) {
@@ -373,64 +1087,224 @@ impl<'a, C: Catalog> Planner<'a, C> {
             .collect::<Result<_>>()?;
         let aggregates = aggregates
             .into_iter()
-            .map(|expr| Self::build_aggregate_function(scope, expr))
+            .map(|expr| {
+                let ast::Expression::Function(name, args, distinct) = expr else {
+                    panic!("aggregate expression must be function");
+                };
+                if args.iter().any(|arg| arg.contains(&Self::is_aggregate_function)) {
+                    return errinput!("aggregate functions can't be nested");
+                }
+                Self::build_aggregate_function(scope, name, args, distinct)
+            })
             .collect::<Result<_>>()?;
 
         *scope = child_scope;
         Ok(Node::Aggregate { source: Box::new(source), group_by, aggregates })
     }
 
-    /// Builds an aggregate function from an AST expression.
-    fn build_aggregate_function(scope: &Scope, expr: ast::Expression) -> Result<Aggregate> {
-        let ast::Expression::Function(name, mut args) = expr else {
-            panic!("aggregate expression must be function");
-        };
+    /// Builds an aggregate function from an AST function call.
+    fn build_aggregate_function(
+        scope: &Scope,
+        name: String,
+        mut args: Vec<ast::Expression>,
+        distinct: bool,
+    ) -> Result<Aggregate> {
         if args.len() != 1 {
             return errinput!("{name} takes 1 argument");
         }
-        if args[0].contains(&|expr| Self::is_aggregate_function(expr)) {
-            return errinput!("aggregate functions can't be nested");
-        }
         // Special-case COUNT(*) since expressions don't support tuples.
-        let expr = if name.as_str() == "count" && args[0] == ast::Expression::All {
-            Expression::Constant(Value::Boolean(true))
-        } else {
-            Self::build_expression(args.remove(0), scope)?
+        let expr = match (name.as_str(), &args[0]) {
+            ("count", ast::Expression::All) if distinct => {
+                return errinput!("DISTINCT can't be used with *");
+            }
+            ("count", ast::Expression::All) => Expression::Constant(Value::Boolean(true)),
+            _ => Self::build_expression(args.remove(0), scope)?,
         };
-        Ok(match name.as_str() {
+        let aggregate = match name.as_str() {
             "avg" => Aggregate::Average(expr),
             "count" => Aggregate::Count(expr),
             "min" => Aggregate::Min(expr),
             "max" => Aggregate::Max(expr),
             "sum" => Aggregate::Sum(expr),
             name => return errinput!("unknown aggregate function {name}"),
+        };
+        Ok(match distinct {
+            true => Aggregate::Distinct(Box::new(aggregate)),
+            false => aggregate,
         })
     }
 
     /// Checks whether a given AST expression is an aggregate function.
     fn is_aggregate_function(expr: &ast::Expression) -> bool {
-        if let ast::Expression::Function(name, _) = expr {
-            return ["avg", "count", "max", "min", "sum"].contains(&name.as_str());
+        if let ast::Expression::Function(name, _, _) = expr {
+            return Self::is_aggregate_name(name);
         }
         false
     }
 
-    /// Collects aggregate functions from SELECT, HAVING, and ORDER BY clauses.
+    /// Checks whether a given function name is an aggregate function.
+    fn is_aggregate_name(name: &str) -> bool {
+        ["avg", "count", "max", "min", "sum"].contains(&name)
+    }
+
+    /// Collects aggregate functions from SELECT, HAVING, ORDER BY, and
+    /// DISTINCT ON clauses.
     fn collect_aggregates(
         select: &[(ast::Expression, Option<String>)],
         having: &Option<ast::Expression>,
         order_by: &[(ast::Expression, ast::Order)],
+        distinct_on: &[ast::Expression],
     ) -> Vec<ast::Expression> {
         let select = select.iter().map(|(expr, _)| expr);
         let having = having.iter();
         let order_by = order_by.iter().map(|(expr, _)| expr);
         let mut aggregates = Vec::new();
-        for expr in select.chain(having).chain(order_by) {
+        for expr in select.chain(having).chain(order_by).chain(distinct_on) {
             expr.collect(&|e| Self::is_aggregate_function(e), &mut aggregates)
         }
         aggregates
     }
 
+    /// Builds a Window node for any window functions in the given expressions.
+    /// Their values are added to the scope as hidden columns, which are looked
+    /// up when building the expressions. Duplicate window functions are only
+    /// evaluated once.
+    fn build_windows<'e>(
+        scope: &mut Scope,
+        node: Node,
+        exprs: impl IntoIterator<Item = &'e ast::Expression>,
+    ) -> Result<Node> {
+        let mut windows = Vec::new();
+        for expr in exprs {
+            expr.collect(&Self::is_window_function, &mut windows);
+        }
+        let mut functions = Vec::new();
+        for expr in windows {
+            if scope.lookup_window(&expr).is_some() {
+                continue;
+            }
+            // The window function only references source columns, so it can
+            // be built before the window columns are added to the scope.
+            functions.push(Self::build_window_function(scope, expr.clone())?);
+            scope.add_window(&expr);
+        }
+        Ok(Node::Window { source: Box::new(node), functions })
+    }
+
+    /// Builds a window function and its window from an AST expression.
+    fn build_window_function(
+        scope: &Scope,
+        expr: ast::Expression,
+    ) -> Result<(WindowFunction, Window)> {
+        let ast::Expression::WindowFunction(name, args, window) = expr else {
+            panic!("expected window function");
+        };
+        if args.iter().any(|arg| arg.contains(&Self::is_window_function)) {
+            return errinput!("window functions can't be nested");
+        }
+
+        // Build the function. Aggregate function arguments may contain
+        // aggregates, which were computed by an Aggregate node.
+        let build = |expr: ast::Expression| Self::build_expression(expr, scope);
+        let mut args = args.into_iter();
+        let function = match (name.as_str(), args.len()) {
+            (name, _) if Self::is_aggregate_name(name) => WindowFunction::Aggregate(
+                Self::build_aggregate_function(scope, name.to_string(), args.collect(), false)?,
+            ),
+            ("dense_rank", 0) => WindowFunction::DenseRank,
+            ("first_value", 1) => WindowFunction::FirstValue(build(args.next().unwrap())?),
+            ("lag" | "lead", 1..=3) => {
+                let expr = build(args.next().unwrap())?;
+                let offset = args.next().map(build).transpose()?;
+                let offset = offset.unwrap_or(Expression::Constant(Value::Integer(1)));
+                let default = args.next().map(build).transpose()?;
+                let default = default.unwrap_or(Expression::Constant(Value::Null));
+                match name.as_str() {
+                    "lag" => WindowFunction::Lag(expr, offset, default),
+                    _ => WindowFunction::Lead(expr, offset, default),
+                }
+            }
+            ("last_value", 1) => WindowFunction::LastValue(build(args.next().unwrap())?),
+            ("ntile", 1) => WindowFunction::Ntile(build(args.next().unwrap())?),
+            ("rank", 0) => WindowFunction::Rank,
+            ("row_number", 0) => WindowFunction::RowNumber,
+            (name, n) => return errinput!("unknown window function {name} with {n} arguments"),
+        };
+
+        // Build the window.
+        let ast::Window { partition_by, order_by, frame } = *window;
+        let partition_by = partition_by.into_iter().map(build).collect::<Result<_>>()?;
+        let order_by: Vec<_> = order_by
+            .into_iter()
+            .map(|(expr, order)| Ok((build(expr)?, Direction::from(order))))
+            .collect::<Result<_>>()?;
+        let frame = match frame {
+            Some(frame) => Self::build_window_frame(frame, order_by.len())?,
+            None => WindowFrame::default(),
+        };
+        Ok((function, Window { partition_by, order_by, frame }))
+    }
+
+    /// Builds a window frame, given the number of window ORDER BY expressions.
+    fn build_window_frame(frame: ast::WindowFrame, order_by: usize) -> Result<WindowFrame> {
+        let units = match frame.units {
+            ast::FrameUnits::Range => FrameUnits::Range,
+            ast::FrameUnits::Rows => FrameUnits::Rows,
+        };
+        // Offsets must be constant, non-negative numbers. ROWS offsets must be
+        // integers, and RANGE offsets are added to the ORDER BY value, so
+        // there must be a single ORDER BY expression.
+        let offset = |expr: ast::Expression| -> Result<Value> {
+            let value = Self::evaluate_constant(expr)?;
+            match (&units, &value) {
+                (FrameUnits::Range, _) if order_by != 1 => {
+                    errinput!("RANGE with offset requires exactly one ORDER BY expression")
+                }
+                (_, Value::Integer(n)) if *n >= 0 => Ok(value),
+                (FrameUnits::Range, Value::Float(f)) if *f >= 0.0 => Ok(value),
+                _ => errinput!("invalid frame offset {value}"),
+            }
+        };
+        let bound = |bound: ast::FrameBound| -> Result<FrameBound> {
+            Ok(match bound {
+                ast::FrameBound::CurrentRow => FrameBound::CurrentRow,
+                ast::FrameBound::Following(expr) => FrameBound::Following(offset(expr)?),
+                ast::FrameBound::Preceding(expr) => FrameBound::Preceding(offset(expr)?),
+                ast::FrameBound::UnboundedFollowing => FrameBound::UnboundedFollowing,
+                ast::FrameBound::UnboundedPreceding => FrameBound::UnboundedPreceding,
+            })
+        };
+        let (start, end) = (bound(frame.start)?, bound(frame.end)?);
+        match (&start, &end) {
+            (FrameBound::UnboundedFollowing, _) => {
+                return errinput!("frame start can't be UNBOUNDED FOLLOWING")
+            }
+            (_, FrameBound::UnboundedPreceding) => {
+                return errinput!("frame end can't be UNBOUNDED PRECEDING")
+            }
+            (FrameBound::CurrentRow, FrameBound::Preceding(_)) => {
+                return errinput!("frame starting from current row can't have preceding rows")
+            }
+            (FrameBound::Following(_), FrameBound::Preceding(_) | FrameBound::CurrentRow) => {
+                return errinput!("frame starting from following row can't have preceding rows")
+            }
+            _ => {}
+        }
+        Ok(WindowFrame { units, start, end })
+    }
+
+    /// Checks whether a given AST expression is a window function.
+    fn is_window_function(expr: &ast::Expression) -> bool {
+        matches!(expr, ast::Expression::WindowFunction(..))
+    }
+
+    /// Checks whether a given function name is a window-only function, i.e. not
+    /// an aggregate function.
+    fn is_window_name(name: &str) -> bool {
+        ["dense_rank", "first_value", "lag", "last_value", "lead", "ntile", "rank", "row_number"]
+            .contains(&name)
+    }
+
     /// Builds hidden columns for a projection to pass through columns that are
     /// used by downstream nodes. Consider e.g.:
     ///
@@ -442,16 +1316,34 @@ impl<'a, C: Catalog> Planner<'a, C> {
     /// the projection to satisfy the ORDER BY.
     ///
     /// Hidden columns are stripped before returning the result to the client.
-    fn build_select_hidden(
+    fn build_select_hidden<'e>(
         &self,
         scope: &Scope,
         child_scope: &mut Scope,
-        having: &Option<ast::Expression>,
-        order_by: &[(ast::Expression, ast::Order)],
+        exprs: impl Iterator<Item = &'e ast::Expression>,
     ) -> Vec<Expression> {
         let mut hidden = Vec::new();
-        for expr in having.iter().chain(order_by.iter().map(|(expr, _)| expr)) {
+        for expr in exprs {
+            // Window functions are evaluated before the projection, so only
+            // their values are passed through, not their inputs. Track the
+            // input expressions to skip them.
+            let mut inputs = HashSet::new();
             expr.walk(&mut |expr| {
+                if inputs.contains(&std::ptr::from_ref(expr)) {
+                    return true;
+                }
+                if let Some(index) = scope.lookup_window(expr) {
+                    if child_scope.lookup_window(expr).is_none() {
+                        child_scope.add_passthrough(scope, index, true);
+                        hidden.push(Expression::Column(index));
+                    }
+                    expr.walk(&mut |input| {
+                        inputs.insert(std::ptr::from_ref(input));
+                        true
+                    });
+                    return true;
+                }
+
                 // If this is an aggregate or GROUP BY expression that isn't
                 // already available in the child scope, pass it through.
                 if let Some(index) = scope.lookup_aggregate(expr) {
@@ -493,6 +1385,18 @@ impl<'a, C: Catalog> Planner<'a, C> {
             return Ok(Column(index));
         }
 
+        // Look up subqueries. These were added to the scope when building the
+        // Subquery nodes, if any.
+        if let Some(index) = scope.lookup_subquery(&expr) {
+            return Ok(Column(index));
+        }
+
+        // Look up window functions. These were added to the scope when building
+        // the Window node, if any.
+        if let Some(index) = scope.lookup_window(&expr) {
+            return Ok(Column(index));
+        }
+
         // Helper for building a boxed expression.
         let build = |expr: Box<ast::Expression>| -> Result<Box<Expression>> {
             Ok(Box::new(Self::build_expression(*expr, scope)?))
@@ -510,14 +1414,26 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 ast::Literal::Float(f) => Value::Float(f),
                 ast::Literal::String(s) => Value::String(s),
             }),
+            // Columns that aren't in a subquery's scope may be outer references.
             ast::Expression::Column(table, name) => {
-                Column(scope.lookup_column(table.as_deref(), &name)?)
+                match scope.lookup_outer_column(table.as_deref(), &name) {
+                    Some(index) => Outer(index?),
+                    None => Column(scope.lookup_column(table.as_deref(), &name)?),
+                }
+            }
+            ast::Expression::Function(name, mut args, distinct) => {
+                match (name.as_str(), args.len()) {
+                    // NB: aggregate functions are processed above.
+                    (name, _) if distinct && !Self::is_aggregate_name(name) => {
+                        return errinput!("DISTINCT is only supported for aggregate functions");
+                    }
+                    (name, _) if Self::is_window_name(name) => {
+                        return errinput!("window function {name} requires an OVER clause");
+                    }
+                    ("sqrt", 1) => SquareRoot(build(Box::new(args.remove(0)))?),
+                    (name, n) => return errinput!("unknown function {name} with {n} arguments"),
+                }
             }
-            ast::Expression::Function(name, mut args) => match (name.as_str(), args.len()) {
-                // NB: aggregate functions are processed above.
-                ("sqrt", 1) => SquareRoot(build(Box::new(args.remove(0)))?),
-                (name, n) => return errinput!("unknown function {name} with {n} arguments"),
-            },
             ast::Expression::Operator(op) => match op {
                 ast::Operator::And(lhs, rhs) => And(build(lhs)?, build(rhs)?),
                 ast::Operator::Not(expr) => Not(build(expr)?),
@@ -548,16 +1464,64 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 ast::Operator::Multiply(lhs, rhs) => Multiply(build(lhs)?, build(rhs)?),
                 ast::Operator::Negate(expr) => Negate(build(expr)?),
                 ast::Operator::Subtract(lhs, rhs) => Subtract(build(lhs)?, build(rhs)?),
+
+                // NB: subqueries are processed above.
+                ast::Operator::In(_, rhs) => match *rhs {
+                    ast::Expression::Subquery(_) => {
+                        return errinput!("unsupported use of subquery")
+                    }
+                    _ => return errinput!("IN requires a subquery"),
+                },
             },
+            ast::Expression::Subquery(_) | ast::Expression::Exists(_) => {
+                return errinput!("unsupported use of subquery")
+            }
+            // NB: window functions are processed above.
+            ast::Expression::WindowFunction(..) => {
+                return errinput!("unsupported use of window function")
+            }
         })
     }
 
+    /// Evaluates a constant LIMIT or OFFSET expression, if given, which must
+    /// be a non-negative integer.
+    fn evaluate_limit(expr: Option<ast::Expression>, clause: &str) -> Result<Option<usize>> {
+        expr.map(|expr| match Self::evaluate_constant(expr)? {
+            Value::Integer(n) if n >= 0 => Ok(n as usize),
+            value => errinput!("invalid {clause} {value}"),
+        })
+        .transpose()
+    }
+
     /// Builds and evaluates a constant AST expression.
     fn evaluate_constant(expr: ast::Expression) -> Result<Value> {
         Self::build_expression(expr, &Scope::new())?.evaluate(None)
     }
 }
 
+/// A WITH common table expression, see Planner::build_with().
+struct CommonTable {
+    /// The CTE name.
+    name: String,
+    /// The CTE column names.
+    columns: Vec<String>,
+    /// The CTE query node. Copied into every reference.
+    node: Node,
+    /// Whether the CTE has been referenced. Used to detect recursive CTEs.
+    referenced: Cell<bool>,
+}
+
+impl CommonTable {
+    fn new(name: &str, columns: &[String], node: Node) -> Self {
+        Self {
+            name: name.to_string(),
+            columns: columns.to_vec(),
+            node,
+            referenced: Cell::new(false),
+        }
+    }
+}
+
 /// A scope maps column/table names to input column indexes, for lookups during
 /// expression construction. It also tracks aggregate and GROUP BY expressions,
 /// as well as hidden columns.
@@ -568,6 +1532,13 @@ impl<'a, C: Catalog> Planner<'a, C> {
 /// currently visible and what names they have. During expression planning, the
 /// scope is used to resolve column names to column indexes, which are placed in
 /// the plan and used during execution.
+///
+/// Subqueries have an outer scope, which is used to resolve outer column
+/// references that aren't visible in the subquery itself. Only the immediately
+/// enclosing query can be referenced: outer rows are passed down one level at
+/// execution time. Further enclosing scopes are retained to give a clear error
+/// for references to them.
+#[derive(Clone)]
 pub struct Scope {
     /// The currently visible columns. If empty, only constant expressions can
     /// be used (no column references).
@@ -589,6 +1560,14 @@ pub struct Scope {
     /// expressions through SELECT projection nodes if the expressions aren't
     /// already projected. They should be removed before emitting results.
     hidden: HashSet<usize>,
+    /// Index of subquery expressions to column indexes. This is used to track
+    /// output columns of Subquery nodes, and look them up from expressions.
+    subqueries: HashMap<ast::Expression, usize>,
+    /// Index of window function expressions to column indexes. This is used to
+    /// track output columns of Window nodes, and look them up from expressions.
+    windows: HashMap<ast::Expression, usize>,
+    /// The outer scope of a subquery, if any.
+    outer: Option<Box<Scope>>,
 }
 
 impl Scope {
@@ -601,6 +1580,9 @@ impl Scope {
             unqualified: HashMap::new(),
             aggregates: HashMap::new(),
             hidden: HashSet::new(),
+            subqueries: HashMap::new(),
+            windows: HashMap::new(),
+            outer: None,
         }
     }
 
@@ -611,10 +1593,18 @@ impl Scope {
         Ok(scope)
     }
 
+    /// Creates a new, empty subquery scope with the given outer scope.
+    fn subquery(outer: &Scope) -> Self {
+        let mut scope = Self::new();
+        scope.outer = Some(Box::new(outer.clone()));
+        scope
+    }
+
     /// Creates a new child scope that inherits from the parent scope.
     pub fn spawn(&self) -> Self {
         let mut child = Scope::new();
         child.tables = self.tables.clone(); // retain table names
+        child.outer = self.outer.clone(); // retain the subquery outer scope
         child
     }
 
@@ -631,6 +1621,18 @@ impl Scope {
         Ok(())
     }
 
+    /// Adds a CTE to the scope, like add_table().
+    fn add_cte(&mut self, label: &str, columns: &[String]) -> Result<()> {
+        if self.tables.contains(label) {
+            return errinput!("duplicate table name {label}");
+        }
+        for column in columns {
+            self.add_column(Label::Qualified(label.to_string(), column.clone()));
+        }
+        self.tables.insert(label.to_string());
+        Ok(())
+    }
+
     /// Adds a column and label to the scope. Returns the column index.
     fn add_column(&mut self, label: Label) -> usize {
         let index = self.columns.len();
@@ -672,6 +1674,36 @@ impl Scope {
         errinput!("unknown column {}", fmtname())
     }
 
+    /// Looks up an outer column index by name, for subqueries. Returns None if
+    /// this isn't a subquery, or if the table or column name is visible in this
+    /// scope (i.e. local names shadow outer names). Only the immediately
+    /// enclosing scope can be referenced, so references to columns further out
+    /// return an error.
+    fn lookup_outer_column(&self, table: Option<&str>, name: &str) -> Option<Result<usize>> {
+        let outer = self.outer.as_ref()?;
+        if self.is_local(table, name) {
+            return None;
+        }
+        if !outer.is_local(table, name) {
+            if let Some(Ok(_)) = outer.lookup_outer_column(table, name) {
+                let name = table.map(|t| format!("{t}.{name}")).unwrap_or(name.to_string());
+                return Some(errinput!(
+                    "column {name} can't be referenced from a nested subquery, only from the \
+                     immediately enclosing query"
+                ));
+            }
+        }
+        Some(outer.lookup_column(table, name))
+    }
+
+    /// Returns true if the table or column name is visible in this scope.
+    fn is_local(&self, table: Option<&str>, name: &str) -> bool {
+        match table {
+            Some(table) => self.tables.contains(table),
+            None => self.unqualified.contains_key(name),
+        }
+    }
+
     /// Adds an aggregate expression to the scope, returning the new column
     /// index or None if the expression already exists. This is either an
     /// aggregate function or a GROUP BY expression. It is used to look up the
@@ -702,6 +1734,33 @@ impl Scope {
         self.aggregates.get(expr).copied()
     }
 
+    /// Adds a hidden column for a subquery expression, returning its index.
+    fn add_subquery(&mut self, expr: &ast::Expression) -> usize {
+        let index = self.add_column(Label::None);
+        self.hidden.insert(index);
+        self.subqueries.insert(expr.clone(), index);
+        index
+    }
+
+    /// Looks up a subquery column index by subquery expression.
+    fn lookup_subquery(&self, expr: &ast::Expression) -> Option<usize> {
+        self.subqueries.get(expr).copied()
+    }
+
+    /// Adds a hidden column for a window function expression, returning its
+    /// index.
+    fn add_window(&mut self, expr: &ast::Expression) -> usize {
+        let index = self.add_column(Label::None);
+        self.hidden.insert(index);
+        self.windows.insert(expr.clone(), index);
+        index
+    }
+
+    /// Looks up a window function column index by window function expression.
+    fn lookup_window(&self, expr: &ast::Expression) -> Option<usize> {
+        self.windows.get(expr).copied()
+    }
+
     /// Adds a column that passes through a column from the parent scope,
     /// retaining its properties. If hide is true, the column is hidden.
     fn add_passthrough(&mut self, parent: &Scope, parent_index: usize, hide: bool) -> usize {
@@ -712,6 +1771,11 @@ impl Scope {
                 self.aggregates.entry(expr.clone()).or_insert(index);
             }
         }
+        for (expr, i) in &parent.windows {
+            if *i == parent_index {
+                self.windows.entry(expr.clone()).or_insert(index);
+            }
+        }
         if hide || parent.hidden.contains(&parent_index) {
             self.hidden.insert(index);
         }
@@ -764,6 +1828,11 @@ impl Scope {
             if !self.aggregates.is_empty() {
                 child.aggregates.entry(expr.clone()).or_insert(index);
             }
+
+            // Window functions can be looked up by downstream nodes.
+            if self.windows.contains_key(expr) {
+                child.windows.entry(expr.clone()).or_insert(index);
+            }
         }
         child
     }
@@ -794,6 +1863,8 @@ impl Scope {
         self.unqualified.iter_mut().for_each(|(_, vec)| vec.retain(|i| !hidden.contains(i)));
         self.unqualified.retain(|_, vec| !vec.is_empty());
         self.aggregates.retain(|_, index| !hidden.contains(index));
+        self.subqueries.retain(|_, index| !hidden.contains(index));
+        self.windows.retain(|_, index| !hidden.contains(index));
         Some(hidden)
     }
 
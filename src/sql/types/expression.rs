@@ -1,9 +1,10 @@
-use super::{Label, Row, Value};
+use super::{DataType, Label, Row, Value};
 use crate::errinput;
 use crate::error::Result;
 use crate::sql::planner::Node;
 
 use serde::{Deserialize, Serialize};
+use std::ops::Bound;
 
 /// An expression, made up of nested values and operators. Values can either be
 /// constants or row column references.
@@ -17,6 +18,10 @@ pub enum Expression {
     Constant(Value),
     /// A column reference. Used as row index when evaluating expressions.
     Column(usize),
+    /// An outer column reference from a correlated subquery, i.e. a column in
+    /// the row of the enclosing query. Must be replaced with a constant value
+    /// before evaluation.
+    Outer(usize),
 
     /// Logical AND of two booleans: a AND b.
     And(Box<Expression>, Box<Expression>),
@@ -71,6 +76,7 @@ impl Expression {
                 label => format!("{label}"),
             },
             Self::Constant(value) => format!("{value}"),
+            Self::Outer(index) => format!("outer #{index}"),
 
             Self::And(lhs, rhs) => format!("{} AND {}", format(lhs), format(rhs)),
             Self::Or(lhs, rhs) => format!("{} OR {}", format(lhs), format(rhs)),
@@ -97,6 +103,55 @@ impl Expression {
         }
     }
 
+    /// Returns the data type of the expression's values, if it can be
+    /// determined without evaluating it, using the given Node to look up column
+    /// types. Returns None if unknown, e.g. for NULL constants, outer column
+    /// references, or arithmetic on operands of unknown or invalid types.
+    pub fn datatype(&self, node: &Node) -> Option<DataType> {
+        use DataType::*;
+        let numeric = |lhs: &Expression, rhs: &Expression| {
+            match (lhs.datatype(node)?, rhs.datatype(node)?) {
+                (Integer, Integer) => Some(Integer),
+                (Integer | Float, Integer | Float) => Some(Float),
+                (_, _) => None,
+            }
+        };
+        match self {
+            Self::Constant(value) => value.datatype(),
+            Self::Column(index) => node.column_type(*index),
+            Self::Outer(_) => None,
+
+            Self::And(..)
+            | Self::Or(..)
+            | Self::Not(_)
+            | Self::Equal(..)
+            | Self::GreaterThan(..)
+            | Self::LessThan(..)
+            | Self::IsNull(_)
+            | Self::IsNaN(_)
+            | Self::Like(..) => Some(Boolean),
+
+            Self::Add(lhs, rhs)
+            | Self::Divide(lhs, rhs)
+            | Self::Modulo(lhs, rhs)
+            | Self::Multiply(lhs, rhs)
+            | Self::Subtract(lhs, rhs) => numeric(lhs, rhs),
+            // Integers raised to negative integer powers yield floats.
+            Self::Exponentiate(lhs, rhs) => match numeric(lhs, rhs)? {
+                Integer => None,
+                datatype => Some(datatype),
+            },
+            Self::Factorial(expr) => expr.datatype(node).filter(|t| *t == Integer),
+            Self::Identity(expr) | Self::Negate(expr) => {
+                expr.datatype(node).filter(|t| matches!(t, Integer | Float))
+            }
+            Self::SquareRoot(expr) => match expr.datatype(node)? {
+                Integer | Float => Some(Float),
+                _ => None,
+            },
+        }
+    }
+
     /// Evaluates an expression, returning a value. Column references look up
     /// values in the given row. If None, any Column references will panic.
     pub fn evaluate(&self, row: Option<&Row>) -> Result<Value> {
@@ -112,6 +167,9 @@ impl Expression {
                 None => panic!("can't reference column {index} with constant evaluation"),
             },
 
+            // Outer references are bound to constants by the subquery executor.
+            Self::Outer(index) => panic!("unbound outer column reference {index}"),
+
             // Logical AND. Inputs must be boolean or NULL. NULLs generally
             // yield NULL, except the special case NULL AND false == false.
             Self::And(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
@@ -254,7 +312,7 @@ impl Expression {
                 | Self::Not(expr)
                 | Self::SquareRoot(expr) => expr.walk(visitor),
 
-                Self::Constant(_) | Self::Column(_) => true,
+                Self::Constant(_) | Self::Column(_) | Self::Outer(_) => true,
             }
     }
 
@@ -301,7 +359,7 @@ impl Expression {
             Self::Negate(expr) => Self::Negate(transform(expr)?),
             Self::Not(expr) => Self::Not(transform(expr)?),
 
-            expr @ (Self::Constant(_) | Self::Column(_)) => expr,
+            expr @ (Self::Constant(_) | Self::Column(_) | Self::Outer(_)) => expr,
         };
         self = after(self)?;
         Ok(self)
@@ -436,6 +494,56 @@ impl Expression {
         }
     }
 
+    /// Checks if an expression is a single column range comparison against a
+    /// constant (i.e. >, >=, <, or <=), returning the column index and value.
+    pub fn is_column_range(&self) -> Option<(usize, &Value)> {
+        use Expression::*;
+        match self {
+            GreaterThan(lhs, rhs) | LessThan(lhs, rhs) => match (lhs.as_ref(), rhs.as_ref()) {
+                (Column(c), Constant(v)) | (Constant(v), Column(c)) => Some((*c, v)),
+                _ => None,
+            },
+            // The planner builds >= and <= as ORs of > or < and =.
+            Or(lhs, rhs) => match (lhs.as_ref(), rhs.as_ref()) {
+                (GreaterThan(..) | LessThan(..), Equal(l, r)) => {
+                    let (column, value) = lhs.is_column_range()?;
+                    match (l.as_ref(), r.as_ref()) {
+                        (Column(c), Constant(v)) | (Constant(v), Column(c))
+                            if *c == column && v == value =>
+                        {
+                            Some((column, value))
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Converts the expression into a single-column range if possible,
+    /// returning the column index and the range start and end bounds.
+    pub fn into_column_range(self) -> Option<(usize, Bound<Value>, Bound<Value>)> {
+        use Bound::*;
+        use Expression::*;
+        let (column, value) = self.is_column_range().map(|(c, v)| (c, v.clone()))?;
+        let range = match self {
+            GreaterThan(lhs, _) if matches!(*lhs, Column(_)) => (Excluded(value), Unbounded),
+            GreaterThan(_, _) => (Unbounded, Excluded(value)),
+            LessThan(lhs, _) if matches!(*lhs, Column(_)) => (Unbounded, Excluded(value)),
+            LessThan(_, _) => (Excluded(value), Unbounded),
+            // >= and <=: include the value in the > or < range.
+            Or(lhs, _) => match lhs.into_column_range().map(|(_, start, end)| (start, end))? {
+                (Excluded(v), Unbounded) => (Included(v), Unbounded),
+                (Unbounded, Excluded(v)) => (Unbounded, Included(v)),
+                range => panic!("unexpected range {range:?}"),
+            },
+            _ => return None,
+        };
+        Some((column, range.0, range.1))
+    }
+
     /// Replaces column references with the given column.
     pub fn replace_column(self, from: usize, to: usize) -> Self {
         let transform = |expr| match expr {
@@ -3,5 +3,5 @@ mod schema;
 mod value;
 
 pub use expression::Expression;
-pub use schema::{Column, Table};
-pub use value::{DataType, Label, Row, Rows, Value};
+pub use schema::{Column, ColumnId, Index, Table, TableId};
+pub use value::{format_range, format_tuple, DataType, Label, Row, Rows, TupleRange, Value};
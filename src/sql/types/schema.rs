@@ -1,32 +1,84 @@
-use super::{DataType, Value};
+use super::{format_tuple, DataType, Row, Value};
 use crate::encoding;
-use crate::errinput;
 use crate::error::Result;
 use crate::sql::engine::{Catalog, Transaction};
+use crate::{errdata, errinput};
 
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 
 /// A table schema, which specifies the data structure and constraints.
 ///
-/// Tables can't change after they are created. There is no ALTER TABLE nor
-/// CREATE/DROP INDEX -- only CREATE TABLE and DROP TABLE.
+/// Tables can be changed with ALTER TABLE, and secondary indexes can be added
+/// to existing columns with CREATE INDEX.
+///
+/// The schema is versioned such that rows written with an older schema version
+/// can be read with a newer one: each row is stored along with the schema
+/// version it was written with, and upgraded to the current columns when read
+/// (see upgrade_row). Adding or dropping columns thus doesn't rewrite rows.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Table {
+    /// The table ID, assigned by the engine when the table is created. Rows
+    /// and indexes are keyed by it, such that renaming a table doesn't have to
+    /// move them.
+    pub id: TableId,
     /// The table name. Can't be empty.
     pub name: String,
-    /// The primary key column index. A table must have a primary key, and it
-    /// can only be a single column.
-    pub primary_key: usize,
+    /// The primary key column indexes, in key order. A table must have a
+    /// primary key, which can span multiple columns (a composite key). Rows
+    /// are stored in primary key order.
+    pub primary_key: Vec<usize>,
     /// The table's columns. Must have at least one.
     pub columns: Vec<Column>,
+    /// The schema version, starting at 0. It must be incremented whenever the
+    /// row layout changes, i.e. when columns are added or dropped, recording
+    /// the previous layout in layouts.
+    pub version: u32,
+    /// The row layouts of previous schema versions, indexed by version, as the
+    /// column IDs of the row values in order.
+    pub layouts: Vec<Vec<ColumnId>>,
+    /// Named secondary indexes, created with CREATE INDEX. Columns declared
+    /// with INDEX or UNIQUE in CREATE TABLE have unnamed indexes.
+    pub indexes: Vec<Index>,
 }
 
+/// A table ID. These are unique across all live tables.
+pub type TableId = u32;
+
+/// A column ID. These are unique within a table, and never reused, such that
+/// rows written with an older schema version can be mapped to the current
+/// columns even if columns have been dropped or renamed.
+pub type ColumnId = u32;
+
 impl encoding::Value for Table {}
 
+/// A named secondary index, created with CREATE INDEX.
+///
+/// A single-column index uses the column's secondary index (i.e. Column::index
+/// is set), and only records its name and whether it was created as a unique
+/// index (i.e. Column::unique is set). A multi-column index is separate from
+/// the column flags, and is keyed by the tuple of column values in order, such
+/// that it can be used for lookups on a prefix of its columns.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Index {
+    /// The index name. Must be unique across all tables.
+    pub name: String,
+    /// The IDs of the indexed columns, in key order. Must have at least one.
+    pub columns: Vec<ColumnId>,
+    /// Whether the index is unique, i.e. the combined column values must be
+    /// unique (unless any of them is NULL).
+    pub unique: bool,
+    /// Whether the index is still being backfilled from existing rows. Writes
+    /// maintain a write-only index, but queries don't use it.
+    pub write_only: bool,
+}
+
 /// A table column.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Column {
+    /// The column ID, unique within the table.
+    pub id: ColumnId,
     /// Column name. Can't be empty.
     pub name: String,
     /// Column datatype.
@@ -38,13 +90,14 @@ pub struct Column {
     /// default (often Null), and Null is only a valid default when nullable.
     pub default: Option<Value>,
     /// Whether the column should only allow unique values. Must be true for a
-    /// primary key column.
+    /// single-column primary key.
     pub unique: bool,
-    /// Whether the column should have a secondary index. Never set for primary
-    /// keys, which have an implicit primary index.
+    /// Whether the column should have a secondary index. Never set for a
+    /// single-column primary key, which has an implicit primary index.
     pub index: bool,
     /// If set, this column is a foreign key reference to the given table's
-    /// primary key. Must be of the same type as the target primary key.
+    /// primary key. Must be of the same type as the target primary key, which
+    /// must be a single column.
     pub references: Option<String>,
 }
 
@@ -52,8 +105,9 @@ impl std::fmt::Display for Table {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "CREATE TABLE {} (", format_ident(&self.name))?;
         for (i, column) in self.columns.iter().enumerate() {
+            let sole_key = self.primary_key == [i];
             write!(f, "  {} {}", format_ident(&column.name), column.datatype)?;
-            if i == self.primary_key {
+            if sole_key {
                 write!(f, " PRIMARY KEY")?;
             } else if !column.nullable {
                 write!(f, " NOT NULL")?;
@@ -61,7 +115,8 @@ impl std::fmt::Display for Table {
             if let Some(default) = &column.default {
                 write!(f, " DEFAULT {default}")?;
             }
-            if i != self.primary_key {
+            // Named indexes are formatted as CREATE INDEX statements below.
+            if !sole_key && !self.indexes.iter().any(|x| x.columns == [column.id]) {
                 if column.unique {
                     write!(f, " UNIQUE")?;
                 }
@@ -72,12 +127,30 @@ impl std::fmt::Display for Table {
             if let Some(reference) = &column.references {
                 write!(f, " REFERENCES {reference}")?;
             }
-            if i < self.columns.len() - 1 {
+            if i < self.columns.len() - 1 || self.primary_key.len() > 1 {
                 write!(f, ",")?;
             }
             writeln!(f)?;
         }
-        write!(f, ")")
+        if self.primary_key.len() > 1 {
+            let columns = self.primary_key.iter().filter_map(|i| self.columns.get(*i));
+            let names = columns.map(|c| format_ident(&c.name)).collect::<Vec<_>>().join(", ");
+            writeln!(f, "  PRIMARY KEY ({names})")?;
+        }
+        write!(f, ")")?;
+        for index in &self.indexes {
+            let columns =
+                index.columns.iter().filter_map(|id| self.columns.iter().find(|c| c.id == *id));
+            let names = columns.map(|c| format_ident(&c.name)).collect::<Vec<_>>().join(", ");
+            write!(
+                f,
+                "\nCREATE {}INDEX {} ON {} ({names})",
+                if index.unique { "UNIQUE " } else { "" },
+                format_ident(&index.name),
+                format_ident(&self.name),
+            )?;
+        }
+        Ok(())
     }
 }
 
@@ -90,9 +163,62 @@ impl Table {
         if self.columns.is_empty() {
             return errinput!("table has no columns");
         }
-        if self.columns.get(self.primary_key).is_none() {
+        if self.primary_key.is_empty() {
+            return errinput!("table has no primary key");
+        }
+        if self.primary_key.iter().any(|i| self.columns.get(*i).is_none()) {
             return errinput!("invalid primary key index");
         }
+        let mut keys = HashSet::new();
+        if let Some(i) = self.primary_key.iter().find(|i| !keys.insert(**i)) {
+            return errinput!("duplicate primary key column {}", self.columns[*i].name);
+        }
+        if self.layouts.len() != self.version as usize {
+            return errinput!("missing row layouts for schema version {}", self.version);
+        }
+        let mut ids = HashSet::new();
+        if let Some(column) = self.columns.iter().find(|c| !ids.insert(c.id)) {
+            return errinput!("duplicate column ID {} for column {}", column.id, column.name);
+        }
+
+        // Validate named indexes.
+        let (mut names, mut indexed) = (HashSet::new(), HashSet::new());
+        for index in &self.indexes {
+            let iname = &index.name; // for formatting convenience
+            if iname.is_empty() {
+                return errinput!("index name can't be empty");
+            }
+            if !names.insert(iname) {
+                return errinput!("duplicate index {iname}");
+            }
+            if index.columns.is_empty() {
+                return errinput!("index {iname} has no columns");
+            }
+            if !indexed.insert(&index.columns) {
+                return errinput!("index {iname} duplicates another index");
+            }
+            let mut seen = HashSet::new();
+            for id in &index.columns {
+                if !self.columns.iter().any(|c| c.id == *id) {
+                    return errinput!("unknown column ID {id} for index {iname}");
+                }
+                if !seen.insert(id) {
+                    return errinput!("duplicate column ID {id} for index {iname}");
+                }
+            }
+            if let [id] = index.columns[..] {
+                let i = self.columns.iter().position(|c| c.id == id).expect("no column");
+                if self.primary_key == [i] {
+                    return errinput!("index {iname} can't be on primary key");
+                }
+                if !self.columns[i].index || self.columns[i].unique != index.unique {
+                    return errinput!(
+                        "index {iname} does not match column {}",
+                        self.columns[i].name
+                    );
+                }
+            }
+        }
 
         for (i, column) in self.columns.iter().enumerate() {
             if column.name.is_empty() {
@@ -101,11 +227,11 @@ impl Table {
             let cname = &column.name; // for formatting convenience
 
             // Validate primary key.
-            let is_primary_key = i == self.primary_key;
-            if is_primary_key {
-                if column.nullable {
-                    return errinput!("primary key {cname} cannot be nullable");
-                }
+            let sole_key = self.primary_key == [i];
+            if self.primary_key.contains(&i) && column.nullable {
+                return errinput!("primary key {cname} cannot be nullable");
+            }
+            if sole_key {
                 if !column.unique {
                     return errinput!("primary key {cname} must be unique");
                 }
@@ -132,22 +258,28 @@ impl Table {
             }
 
             // Validate unique index.
-            if column.unique && !column.index && !is_primary_key {
+            if column.unique && !column.index && !sole_key {
                 return errinput!("unique column {cname} must have a secondary index");
             }
 
             // Validate references.
             if let Some(reference) = &column.references {
-                if !column.index && !is_primary_key {
+                if !column.index && !sole_key {
                     return errinput!("reference column {cname} must have a secondary index");
                 }
-                let target_type = if reference == &self.name {
-                    self.columns[self.primary_key].datatype
+                let target = if reference == &self.name {
+                    Cow::Borrowed(self)
                 } else if let Some(target) = catalog.get_table(reference)? {
-                    target.columns[target.primary_key].datatype
+                    Cow::Owned(target)
                 } else {
                     return errinput!("unknown table {reference} referenced by column {cname}");
                 };
+                let [target_key] = target.primary_key[..] else {
+                    return errinput!(
+                        "can't reference composite primary key of {reference} from column {cname}"
+                    );
+                };
+                let target_type = target.columns[target_key].datatype;
                 if column.datatype != target_type {
                     return errinput!(
                         "can't reference {target_type} primary key of {reference} from {} column {cname}",
@@ -170,18 +302,16 @@ impl Table {
         }
 
         // Validate primary key.
-        let id = &row[self.primary_key];
-        let idslice = &row[self.primary_key..=self.primary_key];
-        if id.is_undefined() {
-            return errinput!("invalid primary key {id}");
+        let id = self.row_id(row);
+        if id.iter().any(|v| v.is_undefined()) {
+            return errinput!("invalid primary key {}", format_tuple(&id));
         }
-        if !update && !txn.get(&self.name, idslice)?.is_empty() {
-            return errinput!("primary key {id} already exists");
+        if !update && !txn.get(&self.name, std::slice::from_ref(&id))?.is_empty() {
+            return errinput!("primary key {} already exists", format_tuple(&id));
         }
 
         for (i, (column, value)) in self.columns.iter().zip(row).enumerate() {
             let (cname, ctype) = (&column.name, &column.datatype);
-            let valueslice = &row[i..=i];
 
             // Validate datatype.
             if let Some(ref vtype) = value.datatype() {
@@ -199,8 +329,8 @@ impl Table {
                     // NB: NaN is not a valid primary key, and not valid as a
                     // missing foreign key marker.
                     Value::Null => {}
-                    v if target == &self.name && v == id => {}
-                    v if txn.get(target, valueslice)?.is_empty() => {
+                    v if target == &self.name && id == [v.clone()] => {}
+                    v if txn.get(target, &[vec![v.clone()]])?.is_empty() => {
                         return errinput!("reference {v} not in table {target}");
                     }
                     _ => {}
@@ -208,18 +338,120 @@ impl Table {
             }
 
             // Validate uniqueness constraints. Unique columns are indexed.
-            if column.unique && i != self.primary_key && !value.is_undefined() {
-                let mut index = txn.lookup_index(&self.name, &column.name, valueslice)?;
+            if column.unique && self.primary_key != [i] && !value.is_undefined() {
+                let columns = [column.name.clone()];
+                let mut index = txn.lookup_index(&self.name, &columns, &[vec![value.clone()]])?;
                 if update {
-                    index.remove(id); // ignore existing version of this row
+                    index.remove(&id); // ignore existing version of this row
                 }
                 if !index.is_empty() {
                     return errinput!("value {value} already in unique column {cname}");
                 }
             }
         }
+
+        // Validate multi-column unique indexes.
+        for index in self.indexes.iter().filter(|x| x.unique && x.columns.len() > 1) {
+            let columns = self.index_columns(&index.columns);
+            let values: Vec<Value> = columns.iter().map(|i| row[*i].clone()).collect();
+            if values.iter().any(|v| v.is_undefined()) {
+                continue;
+            }
+            let names = self.column_names(&columns);
+            let mut ids = txn.lookup_index(&self.name, &names, std::slice::from_ref(&values))?;
+            if update {
+                ids.remove(&id); // ignore existing version of this row
+            }
+            if !ids.is_empty() {
+                let (values, names) = (format_tuple(&values), format_tuple(&names));
+                return errinput!("values {values} already in unique columns {names}");
+            }
+        }
         Ok(())
     }
+
+    /// Returns the primary key of a row, i.e. its primary key column values.
+    pub fn row_id(&self, row: &[Value]) -> Vec<Value> {
+        self.primary_key.iter().map(|i| row[*i].clone()).collect()
+    }
+
+    /// Returns the column indexes of the given column IDs. Panics if a column
+    /// does not exist, which validate() guarantees for index columns.
+    pub fn index_columns(&self, ids: &[ColumnId]) -> Vec<usize> {
+        ids.iter()
+            .map(|id| self.columns.iter().position(|c| c.id == *id).expect("unknown column ID"))
+            .collect()
+    }
+
+    /// Returns the IDs of the given column indexes.
+    pub fn column_ids(&self, columns: &[usize]) -> Vec<ColumnId> {
+        columns.iter().map(|i| self.columns[*i].id).collect()
+    }
+
+    /// Returns the names of the given column indexes.
+    pub fn column_names(&self, columns: &[usize]) -> Vec<String> {
+        columns.iter().map(|i| self.columns[*i].name.clone()).collect()
+    }
+
+    /// Returns the column indexes of every secondary index, i.e. each column
+    /// with Column::index set, and each multi-column named index.
+    pub fn secondary_indexes(&self) -> Vec<Vec<usize>> {
+        let columns =
+            self.columns.iter().enumerate().filter(|(_, c)| c.index).map(|(i, _)| vec![i]);
+        let indexes = self.indexes.iter().filter(|x| x.columns.len() > 1);
+        columns.chain(indexes.map(|x| self.index_columns(&x.columns))).collect()
+    }
+
+    /// Returns the column indexes of every secondary index that can be used by
+    /// queries, i.e. excluding write-only indexes.
+    pub fn readable_indexes(&self) -> Vec<Vec<usize>> {
+        let write_only: Vec<_> = self
+            .indexes
+            .iter()
+            .filter(|x| x.write_only)
+            .map(|x| self.index_columns(&x.columns))
+            .collect();
+        self.secondary_indexes().into_iter().filter(|c| !write_only.contains(c)).collect()
+    }
+
+    /// Returns the next unused column ID. Column IDs are never reused, so this
+    /// also considers dropped columns in previous row layouts.
+    pub fn next_column_id(&self) -> ColumnId {
+        let columns = self.columns.iter().map(|c| c.id);
+        columns.chain(self.layouts.iter().flatten().copied()).max().map_or(0, |id| id + 1)
+    }
+
+    /// Upgrades a row written with the given schema version to the current
+    /// schema version, mapping values to the current columns by column ID.
+    /// Values of dropped columns are skipped, and columns added since are
+    /// filled with their default value.
+    pub fn upgrade_row(&self, version: u32, row: Row) -> Result<Row> {
+        if version == self.version {
+            if row.len() != self.columns.len() {
+                return errdata!("invalid row size {} for table {}", row.len(), self.name);
+            }
+            return Ok(row);
+        }
+        let Some(layout) = self.layouts.get(version as usize) else {
+            return errdata!("unknown schema version {version} for table {}", self.name);
+        };
+        if row.len() != layout.len() {
+            return errdata!(
+                "invalid row size {} for table {} version {version}",
+                row.len(),
+                self.name
+            );
+        }
+        let mut values: HashMap<ColumnId, Value> = layout.iter().copied().zip(row).collect();
+        Ok(self
+            .columns
+            .iter()
+            .map(|column| match values.remove(&column.id) {
+                Some(value) => value,
+                None => column.default.clone().unwrap_or(Value::Null),
+            })
+            .collect())
+    }
 }
 
 /// Formats an identifier as valid SQL, quoting it if necessary.
@@ -229,3 +461,48 @@ fn format_ident(ident: &str) -> Cow<str> {
     }
     format!("\"{}\"", ident.replace('\"', "\"\"")).into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that rows written with older schema versions are upgraded to the
+    /// current columns, filling in defaults and skipping dropped columns.
+    #[test]
+    fn upgrade_row() -> Result<()> {
+        let column = |id, name: &str, default| Column {
+            id,
+            name: name.to_string(),
+            datatype: DataType::Integer,
+            nullable: true,
+            default,
+            unique: id == 0,
+            index: false,
+            references: None,
+        };
+        // Version 0 had columns 0,1, version 1 added 2, version 2 dropped 1
+        // and added 3 with a default.
+        let table = Table {
+            id: 1,
+            name: "test".to_string(),
+            primary_key: vec![0],
+            columns: vec![
+                column(0, "id", None),
+                column(2, "b", Some(Value::Null)),
+                column(3, "c", Some(Value::Integer(7))),
+            ],
+            version: 2,
+            layouts: vec![vec![0, 1], vec![0, 1, 2]],
+            indexes: Vec::new(),
+        };
+        use Value::Integer as I;
+
+        assert_eq!(table.upgrade_row(0, vec![I(1), I(2)])?, vec![I(1), Value::Null, I(7)]);
+        assert_eq!(table.upgrade_row(1, vec![I(1), I(2), I(3)])?, vec![I(1), I(3), I(7)]);
+        assert_eq!(table.upgrade_row(2, vec![I(1), I(3), I(4)])?, vec![I(1), I(3), I(4)]);
+        assert!(table.upgrade_row(1, vec![I(1), I(2)]).is_err());
+        assert!(table.upgrade_row(2, vec![I(1)]).is_err());
+        assert!(table.upgrade_row(3, vec![I(1), I(2), I(3)]).is_err());
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::ops::Bound;
 
 use crate::encoding;
 use crate::errdata;
@@ -7,10 +8,11 @@ use crate::error::{Error, Result};
 use crate::sql::parser::ast;
 
 use dyn_clone::DynClone;
+use itertools::Itertools as _;
 use serde::{Deserialize, Serialize};
 
 /// A primitive data type.
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub enum DataType {
     /// A boolean: true or false.
     Boolean,
@@ -285,6 +287,35 @@ impl std::fmt::Display for Value {
     }
 }
 
+/// Formats a tuple of values, e.g. a composite key, for display. A single value
+/// is formatted as is, otherwise as a parenthesized list.
+pub fn format_tuple(values: &[impl std::fmt::Display]) -> String {
+    match values {
+        [value] => value.to_string(),
+        values => format!("({})", values.iter().map(|v| v.to_string()).join(", ")),
+    }
+}
+
+/// A range of value tuples, e.g. composite primary keys or index values. The
+/// bounds may be prefixes of the tuples, in which case they cover all tuples
+/// with that prefix: an included bound includes them, an excluded one doesn't.
+pub type TupleRange = (Bound<Vec<Value>>, Bound<Vec<Value>>);
+
+/// Formats a tuple range for display, using interval notation.
+pub fn format_range((start, end): &TupleRange) -> String {
+    let start = match start {
+        Bound::Included(v) => format!("[{}", format_tuple(v)),
+        Bound::Excluded(v) => format!("({}", format_tuple(v)),
+        Bound::Unbounded => "(-∞".to_string(),
+    };
+    let end = match end {
+        Bound::Included(v) => format!("{}]", format_tuple(v)),
+        Bound::Excluded(v) => format!("{})", format_tuple(v)),
+        Bound::Unbounded => "∞)".to_string(),
+    };
+    format!("{start}, {end}")
+}
+
 impl From<bool> for Value {
     fn from(v: bool) -> Self {
         Value::Boolean(v)
@@ -1,9 +1,18 @@
-use super::{Engine, Status};
+use super::engine::{key_range, non_empty, seek_for_prev_range, seek_range, KeyRange};
+use super::{Backup, Engine, Status, WriteOp};
 use crate::error::Result;
+use crate::{errdata, errinput};
 
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
 use fs4::FileExt;
+use std::borrow::Cow;
 use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 /// A very simple variant of BitCask, itself a very simple log-structured
 /// key-value engine used e.g. by the Riak database. It is not compatible with
@@ -11,8 +20,9 @@ use std::path::PathBuf;
 /// https://riak.com/assets/bitcask-intro.pdf
 ///
 /// BitCask writes key-value pairs to an append-only log file, and keeps a
-/// mapping of keys to file positions in memory. All live keys must fit in
-/// memory. Deletes write a tombstone value to the log file. To remove old
+/// mapping of keys to file positions (the keydir) in memory. All live keys must
+/// fit in memory, unless the keydir is kept in on-disk index files instead (see
+/// KeyDirMode). Deletes write a tombstone value to the log file. To remove old
 /// garbage, logs can be compacted by writing new logs containing only live
 /// data, skipping replaced values and tombstones.
 ///
@@ -33,42 +43,304 @@ use std::path::PathBuf;
 ///   expected to be small, so the hint files would be nearly as large as
 ///   the compacted log files themselves.
 ///
-/// - Log entries don't contain timestamps or checksums.
+/// - Log entries don't contain timestamps, and only write batches contain
+///   checksums.
 ///
 /// The structure of a log entry is:
 ///
-/// - Key length as big-endian u32.
-/// - Value length as big-endian i32, or -1 for tombstones.
+/// - Key length as big-endian u32, with the high bit set if encrypted.
+/// - Value length as big-endian i32, or -1 for tombstones. Compressed values
+///   store their length L as -L-2.
 /// - Key as raw bytes (max 2 GB).
 /// - Value as raw bytes (max 2 GB).
+///
+/// Values can optionally be compressed, see set_compression(). A compressed
+/// value is prefixed by the codec ID as u8 and the uncompressed length as
+/// big-endian u32. Compression is recorded per entry, so a log can contain a
+/// mix of compressed and uncompressed values regardless of the current
+/// compression settings.
+///
+/// Databases can optionally be encrypted at rest, see new_encrypted(). Keys and
+/// values are encrypted separately with the XChaCha20-Poly1305 authenticated
+/// cipher, using a random nonce per key and value. Since the keydir holds the
+/// plaintext keys in memory, key order is preserved without an order-preserving
+/// encryption scheme, at the cost of 40 bytes overhead for every encrypted key
+/// and value. An on-disk keydir encrypts its index blocks with the same key.
+/// Encryption hides the data, but not the number and approximate size of
+/// entries, nor the order of writes. An attacker with write access can also
+/// replay or drop entries, but can't modify them or move values between keys.
+/// Compressed value headers (including the uncompressed length) are not
+/// encrypted.
+///
+/// Write batches with multiple writes are written as a single framed record
+/// containing regular entries, which is either applied entirely or not at all
+/// when the log is opened:
+///
+/// - Batch marker u32::MAX, in place of the key length.
+/// - Length of the batch's entries as big-endian u32.
+/// - Entries, as above.
+/// - CRC32 checksum of the entries as big-endian u32.
+///
+/// Range deletes are written as a single framed record, regardless of the
+/// number of keys deleted. The keys are removed from the keydir, and their
+/// space is reclaimed by the next compaction:
+///
+/// - Range delete marker u32::MAX-1, in place of the key length.
+/// - Length of the payload as big-endian u32.
+/// - Payload: an encryption flag as u8 (0 or 1), followed by the start and end
+///   bounds, each as a u8 tag (0 unbounded, 1 included, 2 excluded) and for
+///   bounded keys the key length as big-endian u32 and the key. The bounds
+///   are encrypted with an empty AAD if the flag is 1.
+/// - CRC32 checksum of the payload as big-endian u32.
+///
+/// Values are read from the log file on demand, with a read syscall per value
+/// by default. Reads can instead go through an LRU cache of log file blocks,
+/// see set_block_cache(), or a memory map of the log file, see set_mmap().
+///
+/// Writes are fsynced to disk on flush() according to the SyncPolicy, which
+/// defaults to SyncPolicy::Always. Repeated flushes without intervening writes
+/// only fsync once, so a caller can group several writes into a single fsync
+/// (i.e. group commit) by flushing after the last one.
 pub struct BitCask {
     /// The active append-only log file.
     log: Log,
     /// Maps keys to a value position and length in the log file.
     keydir: KeyDir,
+    /// The fsync policy.
+    sync: SyncPolicy,
+    /// Fsyncs the log file in the background, with SyncPolicy::Interval.
+    syncer: Option<Syncer>,
+}
+
+/// A value compression codec. See BitCask::set_compression().
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Codec {
+    /// LZ4: very fast, with a moderate compression ratio.
+    LZ4,
+    /// Deflate (as used by gzip): slower, with a better compression ratio.
+    Deflate,
+}
+
+impl Codec {
+    /// Returns the codec's ID, as stored in the log.
+    fn id(&self) -> u8 {
+        match self {
+            Self::LZ4 => 1,
+            Self::Deflate => 2,
+        }
+    }
+
+    /// Looks up a codec by ID.
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            1 => Ok(Self::LZ4),
+            2 => Ok(Self::Deflate),
+            id => errdata!("unknown compression codec {id}"),
+        }
+    }
+
+    /// Compresses a value.
+    fn compress(&self, value: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::LZ4 => Ok(lz4_flex::block::compress(value)),
+            Self::Deflate => {
+                let mut encoder = flate2::write::DeflateEncoder::new(
+                    Vec::with_capacity(value.len()),
+                    flate2::Compression::default(),
+                );
+                encoder.write_all(value)?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+
+    /// Decompresses a value with the given uncompressed length.
+    fn decompress(&self, data: &[u8], len: u32) -> Result<Vec<u8>> {
+        let value = match self {
+            Self::LZ4 => match lz4_flex::block::decompress(data, len as usize) {
+                Ok(value) => value,
+                Err(err) => return errdata!("invalid LZ4 value: {err}"),
+            },
+            Self::Deflate => {
+                let mut value = Vec::with_capacity(len as usize);
+                if let Err(err) = flate2::read::DeflateDecoder::new(data).read_to_end(&mut value) {
+                    return errdata!("invalid deflate value: {err}");
+                }
+                value
+            }
+        };
+        if value.len() != len as usize {
+            return errdata!("decompressed value has length {}, expected {len}", value.len());
+        }
+        Ok(value)
+    }
+}
+
+impl std::fmt::Display for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::LZ4 => "lz4",
+            Self::Deflate => "deflate",
+        })
+    }
+}
+
+impl std::str::FromStr for Codec {
+    type Err = crate::error::Error;
+
+    /// Parses a codec: lz4 or deflate.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lz4" => Ok(Self::LZ4),
+            "deflate" => Ok(Self::Deflate),
+            s => errinput!("invalid compression codec {s}"),
+        }
+    }
+}
+
+/// Controls when BitCask fsyncs writes to durable storage on flush().
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncPolicy {
+    /// Fsync on every flush, such that writes are durable when flush()
+    /// returns. This is the safest and slowest option.
+    Always,
+    /// Fsync flushed writes in the background, at most once per interval. A
+    /// crash may lose writes flushed during the last interval.
+    Interval(Duration),
+    /// Never fsync, leaving it to the operating system. A process crash won't
+    /// lose writes, but an operating system crash or power loss may.
+    Never,
+}
+
+impl std::str::FromStr for SyncPolicy {
+    type Err = crate::error::Error;
+
+    /// Parses a sync policy: always, never, or an interval such as 100ms or 1s.
+    fn from_str(s: &str) -> Result<Self> {
+        let parse_interval = |n: &str| n.parse::<u64>().ok().filter(|n| *n > 0);
+        match s {
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            s => {
+                let interval = if let Some(ms) = s.strip_suffix("ms") {
+                    parse_interval(ms).map(Duration::from_millis)
+                } else if let Some(secs) = s.strip_suffix('s') {
+                    parse_interval(secs).map(Duration::from_secs)
+                } else {
+                    None
+                };
+                match interval {
+                    Some(interval) => Ok(Self::Interval(interval)),
+                    None => errinput!("invalid sync policy {s}"),
+                }
+            }
+        }
+    }
+}
+
+/// A 256-bit encryption key for BitCask::new_encrypted().
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    /// Creates an encryption key from raw bytes.
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+
+    /// Reads an encryption key from a file containing it as 64 hex digits,
+    /// e.g. generated with `openssl rand -hex 32`.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let hex = std::fs::read_to_string(path)?;
+        let hex = hex.trim();
+        let mut key = [0; 32];
+        if hex.len() != 2 * key.len() || !hex.is_ascii() {
+            return errinput!("encryption key in {} must be 64 hex digits", path.display());
+        }
+        for (i, byte) in key.iter_mut().enumerate() {
+            let Ok(b) = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16) else {
+                return errinput!("encryption key in {} must be 64 hex digits", path.display());
+            };
+            *byte = b;
+        }
+        Ok(Self(key))
+    }
+
+    /// Returns a cipher using the key.
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.0.into())
+    }
+}
+
+/// Don't leak the key into logs.
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(redacted)")
+    }
+}
+
+/// A value's location in the log file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ValueLoc {
+    /// The position of the stored value.
+    pos: u64,
+    /// The length of the stored value.
+    len: u32,
+    /// The uncompressed value length, if compressed.
+    uncompressed_len: Option<u32>,
+    /// Whether the entry is encrypted.
+    encrypted: bool,
 }
 
-/// Maps keys to a value position and length in the log file.
-type KeyDir = std::collections::BTreeMap<Vec<u8>, (u64, u32)>;
+/// Where to keep the keydir, which maps every live key to its value location
+/// in the log file. See BitCask::new_compact().
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyDirMode {
+    /// Keeps the entire keydir in memory. All live keys must fit in memory.
+    Memory,
+    /// Keeps the keydir in sorted index files next to the log file, split
+    /// into blocks of block_size entries. Only the first key of each block is
+    /// kept in memory, along with a buffer of up to buffer_size recent changes
+    /// which are written out as sorted runs and periodically merged.
+    Disk { buffer_size: usize, block_size: usize },
+}
+
+/// A log record, as read when building the keydir.
+enum Record {
+    /// A single entry, or the entries of a write batch.
+    Entries(Vec<Entry>),
+    /// A range delete.
+    DeleteRange(KeyRange),
+}
+
+/// A log entry's key, end position, and value location or None for tombstones.
+type Entry = (Vec<u8>, u64, Option<ValueLoc>);
 
 impl BitCask {
     /// Opens or creates a BitCask database in the given file.
     pub fn new(path: PathBuf) -> Result<Self> {
-        log::info!("Opening database {}", path.display());
-        let mut log = Log::new(path.clone())?;
-        let keydir = log.build_keydir()?;
-        log::info!("Indexed {} live keys in {}", keydir.len(), path.display());
-        Ok(Self { log, keydir })
+        Self::open(path, None, KeyDirMode::Memory)
     }
 
-    /// Opens a BitCask database, and automatically compacts it if the amount
-    /// of garbage exceeds the given ratio and byte size when opened.
+    /// Opens or creates an encrypted BitCask database in the given file. If
+    /// the database contains unencrypted data, it is compacted to encrypt it.
+    /// Opening an encrypted database with the wrong key or without a key
+    /// errors.
+    pub fn new_encrypted(path: PathBuf, key: EncryptionKey) -> Result<Self> {
+        Self::open(path, Some(key), KeyDirMode::Memory)
+    }
+
+    /// Opens a BitCask database, encrypted with the given key if any and with
+    /// the given keydir mode, and automatically compacts it if the amount of
+    /// garbage exceeds the given ratio and byte size when opened.
     pub fn new_compact(
         path: PathBuf,
+        key: Option<EncryptionKey>,
+        keydir: KeyDirMode,
         garbage_min_fraction: f64,
         garbage_min_bytes: u64,
     ) -> Result<Self> {
-        let mut s = Self::new(path)?;
+        let mut s = Self::open(path, key, keydir)?;
 
         let status = s.status()?;
         if Self::should_compact(
@@ -95,6 +367,80 @@ impl BitCask {
         Ok(s)
     }
 
+    /// Opens or creates a BitCask database, encrypted with the given key if any
+    /// (compacting it to encrypt any unencrypted data), with the given keydir
+    /// mode.
+    fn open(path: PathBuf, key: Option<EncryptionKey>, keydir: KeyDirMode) -> Result<Self> {
+        log::info!("Opening database {}", path.display());
+        let mut log = Log::new(path.clone())?;
+        log.cipher = key.map(|key| key.cipher());
+        let mut keydir = KeyDir::new(keydir, &path, log.cipher.clone())?;
+        log.build_keydir(&mut keydir)?;
+        log::info!("Indexed {} live keys in {}", keydir.len()?, path.display());
+        let mut s = Self { log, keydir, sync: SyncPolicy::Always, syncer: None };
+
+        if s.log.cipher.is_some() {
+            let mut scan = s.keydir.scan((Bound::Unbounded, Bound::Unbounded));
+            let mut unencrypted = false;
+            while let Some((_, loc)) = scan.next().transpose()? {
+                if !loc.encrypted {
+                    unencrypted = true;
+                    break;
+                }
+            }
+            drop(scan);
+            if unencrypted {
+                log::info!("Compacting {} to encrypt it", s.log.path.display());
+                s.compact()?;
+            }
+        }
+        Ok(s)
+    }
+
+    /// Sets the fsync policy, starting or stopping the background syncer as
+    /// appropriate. Any pending writes are fsynced first.
+    pub fn set_sync(&mut self, sync: SyncPolicy) -> Result<()> {
+        self.syncer = None;
+        self.log.sync()?;
+        if let SyncPolicy::Interval(interval) = sync {
+            self.syncer = Some(Syncer::new(self.log.file.try_clone()?, interval));
+        }
+        self.sync = sync;
+        Ok(())
+    }
+
+    /// Enables value compression with the given codec, or disables it if None.
+    /// Only values of at least threshold bytes which shrink when compressed
+    /// are compressed. This only affects new writes: existing values are read
+    /// regardless, and compaction copies them as is.
+    pub fn set_compression(&mut self, codec: Option<Codec>, threshold: usize) {
+        self.log.compression = codec.map(|codec| (codec, threshold));
+    }
+
+    /// Enables an LRU cache of log file blocks for value reads, with the given
+    /// block size and total capacity in bytes, or disables it if the capacity
+    /// is 0. Point reads and scans of nearby values then only read each block
+    /// from the file once, while it remains cached. Hits and misses are
+    /// reported by status().
+    pub fn set_block_cache(&mut self, capacity: usize, block_size: usize) -> Result<()> {
+        if block_size == 0 {
+            return errinput!("block cache block size must be positive");
+        }
+        self.log.cache = match capacity {
+            0 => None,
+            capacity => Some(BlockCache::new(capacity, block_size)),
+        };
+        Ok(())
+    }
+
+    /// Enables or disables reading values via a memory map of the log file,
+    /// which avoids read syscalls and leaves caching to the operating system's
+    /// page cache. The block cache is not used with mmap reads.
+    pub fn set_mmap(&mut self, mmap: bool) {
+        self.log.mmap = mmap;
+        self.log.map = None;
+    }
+
     /// Returns true if the log file should be compacted.
     fn should_compact(
         garbage_size: u64,
@@ -110,29 +456,64 @@ impl BitCask {
 impl Engine for BitCask {
     type ScanIterator<'a> = ScanIterator<'a>;
 
+    /// Snapshots the log file as of its current length. The log is
+    /// append-only and only compacted on open, so the backup can copy this
+    /// prefix via a separate file handle while writes continue. The copy
+    /// retains entries as stored, including garbage, encryption, and
+    /// compression.
+    fn backup(&mut self) -> Result<Backup> {
+        let mut file = std::fs::File::open(&self.log.path)?;
+        let len = self.log.file.seek(SeekFrom::End(0))?;
+        Ok(Backup::new(move |path| {
+            let mut backup = std::fs::File::create_new(path)?;
+            std::io::copy(&mut (&mut file).take(len), &mut backup)?;
+            sync_file(&backup)?;
+            Ok(())
+        }))
+    }
+
     fn delete(&mut self, key: &[u8]) -> Result<()> {
         self.log.write_entry(key, None)?;
-        self.keydir.remove(key);
-        Ok(())
+        self.keydir.remove(key)
+    }
+
+    /// Writes a single range delete record, regardless of the number of keys.
+    fn delete_range(&mut self, range: (Bound<Vec<u8>>, Bound<Vec<u8>>)) -> Result<()> {
+        self.log.write_delete_range(&range)?;
+        self.keydir.remove_range(range)
     }
 
     fn flush(&mut self) -> Result<()> {
-        // Don't fsync in tests, to speed them up.
-        #[cfg(not(test))]
-        self.log.file.sync_all()?;
+        match self.sync {
+            SyncPolicy::Always => self.log.sync()?,
+            SyncPolicy::Interval(_) => {
+                // Hand the fsync off to the background syncer.
+                if std::mem::take(&mut self.log.unsynced) {
+                    self.syncer.as_ref().expect("no syncer").request();
+                }
+            }
+            SyncPolicy::Never => {}
+        }
         Ok(())
     }
 
+    /// Fsyncs the log file even if flush() already handed the pending writes
+    /// off to the background syncer, which may not have fsynced them yet.
+    fn sync(&mut self) -> Result<()> {
+        self.log.unsynced = true;
+        self.log.sync()
+    }
+
     fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        if let Some((value_pos, value_len)) = self.keydir.get(key) {
-            Ok(Some(self.log.read_value(*value_pos, *value_len)?))
+        if let Some(loc) = self.keydir.get(key)? {
+            Ok(Some(self.log.read_value(key, loc)?))
         } else {
             Ok(None)
         }
     }
 
     fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
-        ScanIterator { inner: self.keydir.range(range), log: &mut self.log }
+        ScanIterator { inner: self.keydir.scan(key_range(range)), log: &mut self.log }
     }
 
     fn scan_dyn(
@@ -143,94 +524,1099 @@ impl Engine for BitCask {
     }
 
     fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
-        let (pos, len) = self.log.write_entry(key, Some(&*value))?;
-        let value_len = value.len() as u32;
-        self.keydir.insert(key.to_vec(), (pos + len as u64 - value_len as u64, value_len));
-        Ok(())
+        let loc = self.log.write_entry(key, Some(&value))?;
+        self.keydir.insert(key.to_vec(), loc)
     }
 
     fn status(&mut self) -> Result<Status> {
-        let keys = self.keydir.len() as u64;
-        let size = self
-            .keydir
-            .iter()
-            .fold(0, |size, (key, (_, value_len))| size + key.len() as u64 + *value_len as u64);
+        let (mut keys, mut size, mut compressed_size, mut encrypted_keys) = (0, 0, 0, 0);
+        let mut scan = self.keydir.scan((Bound::Unbounded, Bound::Unbounded));
+        while let Some((key, loc)) = scan.next().transpose()? {
+            keys += 1;
+            size += key.len() as u64 + loc.uncompressed_len.unwrap_or(loc.len) as u64;
+            compressed_size += key.len() as u64 + loc.len as u64;
+            encrypted_keys += loc.encrypted as u64;
+        }
         let total_disk_size = self.log.file.metadata()?.len();
-        let live_disk_size = size + 8 * keys; // account for length prefixes
+        // Account for length prefixes and encrypted key overhead.
+        let live_disk_size =
+            compressed_size + 8 * keys + Log::ENCRYPTION_OVERHEAD as u64 * encrypted_keys;
         let garbage_disk_size = total_disk_size - live_disk_size;
+        let (cache_hits, cache_misses) =
+            self.log.cache.as_ref().map_or((0, 0), |cache| (cache.hits, cache.misses));
         Ok(Status {
             name: "bitcask".to_string(),
             keys,
             size,
+            compressed_size,
             total_disk_size,
             live_disk_size,
             garbage_disk_size,
+            cache_hits,
+            cache_misses,
         })
     }
+
+    fn write_batch(&mut self, ops: Vec<WriteOp>) -> Result<()> {
+        for (op, loc) in ops.iter().zip(self.log.write_batch(&ops)?) {
+            match op {
+                WriteOp::Delete { key } => self.keydir.remove(key)?,
+                WriteOp::Set { key, .. } => self.keydir.insert(key.clone(), loc)?,
+            };
+        }
+        Ok(())
+    }
 }
 
 pub struct ScanIterator<'a> {
-    inner: std::collections::btree_map::Range<'a, Vec<u8>, (u64, u32)>,
+    inner: KeyDirScan<'a>,
     log: &'a mut Log,
 }
 
 impl<'a> ScanIterator<'a> {
-    fn map(&mut self, item: (&Vec<u8>, &(u64, u32))) -> <Self as Iterator>::Item {
-        let (key, (value_pos, value_len)) = item;
-        Ok((key.clone(), self.log.read_value(*value_pos, *value_len)?))
+    fn map(&mut self, item: Result<(Vec<u8>, ValueLoc)>) -> <Self as Iterator>::Item {
+        let (key, loc) = item?;
+        let value = self.log.read_value(&key, loc)?;
+        Ok((key, value))
     }
 }
 
 impl<'a> Iterator for ScanIterator<'a> {
     type Item = Result<(Vec<u8>, Vec<u8>)>;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|item| self.map(item))
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|item| self.map(item))
+    }
+}
+
+impl<'a> DoubleEndedIterator for ScanIterator<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|item| self.map(item))
+    }
+}
+
+/// Seeks only look up the keydir, values are read lazily during iteration.
+impl<'a> super::ScanIterator for ScanIterator<'a> {
+    fn seek(&mut self, key: &[u8]) {
+        self.inner.seek(key)
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        self.inner.seek_for_prev(key)
+    }
+}
+
+impl BitCask {
+    /// Compacts the current log file by writing out a new log file containing
+    /// only live keys and replacing the current file with it.
+    pub fn compact(&mut self) -> Result<()> {
+        let mut tmp_path = self.log.path.clone();
+        tmp_path.set_extension("new");
+        let mut new_keydir = KeyDir::new(self.keydir.mode(), &tmp_path, self.log.cipher.clone())?;
+        let mut new_log = self.write_log(tmp_path, Some(&mut new_keydir))?;
+
+        std::fs::rename(&new_log.path, &self.log.path)?;
+        new_log.path = self.log.path.clone();
+        new_keydir.rename(&new_log.path)?;
+
+        // Carry over the read settings, dropping cached blocks of the old file.
+        new_log.mmap = self.log.mmap;
+        new_log.cache = self.log.cache.take().map(|mut cache| {
+            cache.clear();
+            cache
+        });
+
+        self.log = new_log;
+        self.keydir = new_keydir;
+
+        // Restart the background syncer for the new log file.
+        if let SyncPolicy::Interval(interval) = self.sync {
+            self.syncer = Some(Syncer::new(self.log.file.try_clone()?, interval));
+        }
+        Ok(())
+    }
+
+    /// Creates a new BitCask database in the given file containing a copy of
+    /// all live data in the given engine, e.g. for backups. The file must not
+    /// already exist.
+    pub fn create_from(path: PathBuf, source: &mut (impl Engine + ?Sized)) -> Result<Self> {
+        if path.exists() {
+            return errinput!("file {} already exists", path.display());
+        }
+        let mut s = Self::new(path)?;
+        let mut scan = source.scan_dyn((Bound::Unbounded, Bound::Unbounded));
+        while let Some((key, value)) = scan.next().transpose()? {
+            s.set(&key, value)?;
+        }
+        s.flush()?;
+        Ok(s)
+    }
+
+    /// Writes out a new log file with the live entries of the current log file
+    /// and returns it, populating the given keydir if any. Entries are written
+    /// in key order.
+    fn write_log(&mut self, path: PathBuf, mut new_keydir: Option<&mut KeyDir>) -> Result<Log> {
+        let mut new_log = Log::new(path)?;
+        new_log.file.set_len(0)?; // truncate file if it exists
+        new_log.compression = self.log.compression;
+        new_log.cipher = self.log.cipher.clone();
+        // Copy values as stored, without recompressing them. They are
+        // reencrypted, which also encrypts any unencrypted values.
+        let mut scan = self.keydir.scan((Bound::Unbounded, Bound::Unbounded));
+        while let Some((key, loc)) = scan.next().transpose()? {
+            let value = self.log.read_stored_value(&key, loc, false)?;
+            let loc = new_log.write_stored_entry(&key, Some((&value, loc.uncompressed_len)))?;
+            if let Some(new_keydir) = new_keydir.as_mut() {
+                new_keydir.insert(key, loc)?;
+            }
+        }
+        Ok(new_log)
+    }
+}
+
+/// Attempt to flush the file when the database is closed. With
+/// SyncPolicy::Interval, the background syncer is stopped and any pending
+/// writes are fsynced.
+impl Drop for BitCask {
+    fn drop(&mut self) {
+        self.syncer = None;
+        if self.sync == SyncPolicy::Never {
+            return;
+        }
+        if let Err(error) = self.log.sync() {
+            log::error!("failed to flush file: {}", error)
+        }
+    }
+}
+
+/// Fsyncs a file to durable storage.
+fn sync_file(file: &std::fs::File) -> std::io::Result<()> {
+    // Don't fsync in tests, to speed them up.
+    if cfg!(test) {
+        return Ok(());
+    }
+    file.sync_all()
+}
+
+/// Fsyncs a log file in a background thread, at most once per interval when
+/// requested. The thread fsyncs any pending request and exits when the syncer
+/// is dropped.
+struct Syncer {
+    /// Whether an fsync has been requested.
+    pending: Arc<AtomicBool>,
+    /// Signals the thread to stop when dropped.
+    stop_tx: Option<crossbeam::channel::Sender<()>>,
+    /// The syncer thread.
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Syncer {
+    /// Starts a syncer for the given file.
+    fn new(file: std::fs::File, interval: Duration) -> Self {
+        let pending = Arc::new(AtomicBool::new(false));
+        let (stop_tx, stop_rx) = crossbeam::channel::bounded::<()>(0);
+        let thread_pending = pending.clone();
+        let handle = std::thread::spawn(move || loop {
+            // Wait for the next interval, or until the syncer is dropped.
+            let stop = match stop_rx.recv_timeout(interval) {
+                Err(crossbeam::channel::RecvTimeoutError::Timeout) => false,
+                Ok(()) | Err(crossbeam::channel::RecvTimeoutError::Disconnected) => true,
+            };
+            if thread_pending.swap(false, Ordering::SeqCst) {
+                if let Err(error) = sync_file(&file) {
+                    log::error!("failed to sync file: {}", error)
+                }
+            }
+            if stop {
+                return;
+            }
+        });
+        Self { pending, stop_tx: Some(stop_tx), handle: Some(handle) }
+    }
+
+    /// Requests an fsync, which will happen within the next interval.
+    fn request(&self) {
+        self.pending.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for Syncer {
+    fn drop(&mut self) {
+        drop(self.stop_tx.take());
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                log::error!("syncer thread panicked")
+            }
+        }
+    }
+}
+
+/// Maps keys to a value location in the log file.
+enum KeyDir {
+    Memory(std::collections::BTreeMap<Vec<u8>, ValueLoc>),
+    Disk(DiskKeyDir),
+}
+
+impl KeyDir {
+    /// Creates a new, empty keydir for the given log file.
+    fn new(mode: KeyDirMode, log_path: &Path, cipher: Option<XChaCha20Poly1305>) -> Result<Self> {
+        Ok(match mode {
+            KeyDirMode::Memory => Self::Memory(std::collections::BTreeMap::new()),
+            KeyDirMode::Disk { buffer_size, block_size } => {
+                let mut path = log_path.as_os_str().to_owned();
+                path.push(".keydir");
+                Self::Disk(DiskKeyDir::new(path.into(), buffer_size, block_size, cipher)?)
+            }
+        })
+    }
+
+    /// Returns the keydir mode.
+    fn mode(&self) -> KeyDirMode {
+        match self {
+            Self::Memory(_) => KeyDirMode::Memory,
+            Self::Disk(d) => {
+                KeyDirMode::Disk { buffer_size: d.buffer_size, block_size: d.block_size }
+            }
+        }
+    }
+
+    /// Fetches a key's value location.
+    fn get(&mut self, key: &[u8]) -> Result<Option<ValueLoc>> {
+        match self {
+            Self::Memory(m) => Ok(m.get(key).copied()),
+            Self::Disk(d) => d.get(key),
+        }
+    }
+
+    /// Sets a key's value location.
+    fn insert(&mut self, key: Vec<u8>, loc: ValueLoc) -> Result<()> {
+        match self {
+            Self::Memory(m) => _ = m.insert(key, loc),
+            Self::Disk(d) => d.insert(key, Some(loc))?,
+        }
+        Ok(())
+    }
+
+    /// Removes a key.
+    fn remove(&mut self, key: &[u8]) -> Result<()> {
+        match self {
+            Self::Memory(m) => _ = m.remove(key),
+            Self::Disk(d) => d.insert(key.to_vec(), None)?,
+        }
+        Ok(())
+    }
+
+    /// Removes all keys in the given range.
+    fn remove_range(&mut self, range: KeyRange) -> Result<()> {
+        match self {
+            Self::Memory(m) => {
+                let keys: Vec<_> = m.range(range).map(|(key, _)| key.clone()).collect();
+                for key in keys {
+                    m.remove(&key);
+                }
+            }
+            Self::Disk(d) => d.remove_range(range)?,
+        }
+        Ok(())
+    }
+
+    /// Iterates over the keys in the given range.
+    fn scan(&mut self, range: KeyRange) -> KeyDirScan<'_> {
+        match self {
+            Self::Memory(m) => {
+                KeyDirScan::Memory { inner: m.range(range.clone()), keydir: m, range }
+            }
+            Self::Disk(d) => KeyDirScan::Disk { keydir: d, remaining: range.clone(), range },
+        }
+    }
+
+    /// Returns the number of live keys. For on-disk keydirs, this scans the
+    /// entire keydir.
+    fn len(&mut self) -> Result<u64> {
+        match self {
+            Self::Memory(m) => Ok(m.len() as u64),
+            Self::Disk(_) => {
+                let mut len = 0;
+                for item in self.scan((Bound::Unbounded, Bound::Unbounded)) {
+                    item?;
+                    len += 1;
+                }
+                Ok(len)
+            }
+        }
+    }
+
+    /// Renames the keydir's files (if any) after the log file was renamed,
+    /// replacing any existing keydir files at the new path.
+    fn rename(&mut self, log_path: &Path) -> Result<()> {
+        if let Self::Disk(d) = self {
+            let mut path = log_path.as_os_str().to_owned();
+            path.push(".keydir");
+            let path = PathBuf::from(path);
+            if path.is_dir() {
+                std::fs::remove_dir_all(&path)?;
+            }
+            std::fs::rename(&d.path, &path)?;
+            d.path = path;
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over keydir entries in a key range.
+enum KeyDirScan<'a> {
+    Memory {
+        inner: std::collections::btree_map::Range<'a, Vec<u8>, ValueLoc>,
+        /// The keydir and scan range, for seeks.
+        keydir: &'a std::collections::BTreeMap<Vec<u8>, ValueLoc>,
+        range: KeyRange,
+    },
+    Disk {
+        keydir: &'a mut DiskKeyDir,
+        /// The scan range, for seeks.
+        range: KeyRange,
+        /// The remaining range, narrowed as keys are emitted from each end.
+        remaining: KeyRange,
+    },
+}
+
+impl<'a> KeyDirScan<'a> {
+    /// Narrows the remaining scan range, without reading any entries.
+    fn reset(&mut self, remaining: KeyRange) {
+        match self {
+            Self::Memory { inner, keydir, .. } => *inner = keydir.range(remaining),
+            Self::Disk { remaining: r, .. } => *r = remaining,
+        }
+    }
+
+    /// Repositions the scan at the first key >= key.
+    fn seek(&mut self, key: &[u8]) {
+        let (Self::Memory { range, .. } | Self::Disk { range, .. }) = self;
+        let remaining = seek_range(range, key);
+        self.reset(remaining)
+    }
+
+    /// Repositions the scan at the last key <= key.
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        let (Self::Memory { range, .. } | Self::Disk { range, .. }) = self;
+        let remaining = seek_for_prev_range(range, key);
+        self.reset(remaining)
+    }
+}
+
+impl<'a> Iterator for KeyDirScan<'a> {
+    type Item = Result<(Vec<u8>, ValueLoc)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Memory { inner, .. } => inner.next().map(|(key, loc)| Ok((key.clone(), *loc))),
+            Self::Disk { keydir, remaining, .. } => match keydir.first(remaining) {
+                Ok(Some((key, loc))) => {
+                    remaining.0 = Bound::Excluded(key.clone());
+                    *remaining = non_empty(remaining.clone());
+                    Some(Ok((key, loc)))
+                }
+                Ok(None) => None,
+                Err(err) => Some(Err(err)),
+            },
+        }
+    }
+}
+
+impl<'a> DoubleEndedIterator for KeyDirScan<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Memory { inner, .. } => {
+                inner.next_back().map(|(key, loc)| Ok((key.clone(), *loc)))
+            }
+            Self::Disk { keydir, remaining, .. } => match keydir.last(remaining) {
+                Ok(Some((key, loc))) => {
+                    remaining.1 = Bound::Excluded(key.clone());
+                    *remaining = non_empty(remaining.clone());
+                    Some(Ok((key, loc)))
+                }
+                Ok(None) => None,
+                Err(err) => Some(Err(err)),
+            },
+        }
+    }
+}
+
+/// A keydir stored on disk, for datasets whose live keys don't fit in memory.
+///
+/// Changes are buffered in memory, and written out as a new sorted run when the
+/// buffer reaches buffer_size entries (counting removed ranges). Each run is an
+/// index file containing entries in key order, split into blocks of up to
+/// block_size entries which are read (and decrypted) as a unit. The first key
+/// of each block is kept in memory as a fence pointer, so a lookup only reads
+/// a single block per run. The most recently read block of each run is
+/// cached, which makes scans read each block once.
+///
+/// Newer runs take precedence over older runs. To shadow keys in older runs,
+/// runs contain tombstone entries for removed keys, and record the key ranges
+/// removed before their entries were written. The newest runs are merged into
+/// a single run whenever the older one is at most MERGE_RATIO times larger than
+/// the newer one, which keeps run sizes geometrically increasing: there are
+/// O(log N) runs, and each entry is rewritten O(log N) times. Merging into the
+/// oldest run drops tombstones and removed ranges, since there is nothing left
+/// for them to shadow. If the buffered keys all sort after the newest run's
+/// keys (e.g. when compacting, or when opening a compacted log), they are
+/// appended to the newest run instead, so a sorted keydir is written once.
+///
+/// The runs are stored as files named by a sequence number in an index
+/// directory. The index is only a cache: like the in-memory keydir, it is
+/// rebuilt from the log file when the database is opened.
+///
+/// The structure of an index block is:
+///
+/// - Length of the payload as big-endian u32.
+/// - Payload, encrypted with the block's file position as AAD if encryption
+///   is enabled, containing entries of:
+///   - Key length as big-endian u32.
+///   - Key as raw bytes.
+///   - Value position as big-endian u64, and length as big-endian u32 (both 0
+///     for tombstones).
+///   - Flags as u8: 0x01 if encrypted, 0x02 if compressed, 0x04 if tombstone.
+///   - Uncompressed value length as big-endian u32, if compressed.
+struct DiskKeyDir {
+    /// Path to the index directory.
+    path: PathBuf,
+    /// The sorted runs, from oldest to newest.
+    runs: Vec<SortedRun>,
+    /// The sequence number of the next run file.
+    next_run: u64,
+    /// Buffered changes not yet written to a run. None removes the key.
+    buffer: std::collections::BTreeMap<Vec<u8>, Option<ValueLoc>>,
+    /// Key ranges removed since the newest run was written. These hide run
+    /// entries, but not buffered entries written since.
+    removed: Vec<KeyRange>,
+    /// The maximum number of buffered changes before writing a run.
+    buffer_size: usize,
+    /// The maximum number of entries per index block.
+    block_size: usize,
+    /// The cipher used to encrypt index blocks, if any.
+    cipher: Option<XChaCha20Poly1305>,
+}
+
+/// A sorted run of a DiskKeyDir, stored in an index file.
+struct SortedRun {
+    /// The run's sequence number, i.e. its file name.
+    id: u64,
+    /// The index file.
+    file: std::fs::File,
+    /// The first key and file position of each block in the index file.
+    fences: Vec<(Vec<u8>, u64)>,
+    /// The number of entries in the run, including tombstones.
+    len: usize,
+    /// Key ranges removed before the run was written. These hide entries in
+    /// older runs.
+    removed: Vec<KeyRange>,
+    /// The most recently read block, by index.
+    cache: Option<(usize, Arc<Block>)>,
+}
+
+impl DiskKeyDir {
+    /// The maximum size ratio between the two newest runs before they are
+    /// merged.
+    const MERGE_RATIO: usize = 2;
+
+    /// Creates a new, empty on-disk keydir in the given index directory,
+    /// replacing the directory if it exists.
+    fn new(
+        path: PathBuf,
+        buffer_size: usize,
+        block_size: usize,
+        cipher: Option<XChaCha20Poly1305>,
+    ) -> Result<Self> {
+        if buffer_size == 0 || block_size == 0 {
+            return errinput!("keydir buffer and block sizes must be positive");
+        }
+        if path.is_dir() {
+            std::fs::remove_dir_all(&path)?;
+        } else if path.exists() {
+            std::fs::remove_file(&path)?; // written by older versions
+        }
+        std::fs::create_dir(&path)?;
+        Ok(Self {
+            path,
+            runs: Vec::new(),
+            next_run: 0,
+            buffer: std::collections::BTreeMap::new(),
+            removed: Vec::new(),
+            buffer_size,
+            block_size,
+            cipher,
+        })
+    }
+
+    /// Fetches a key's value location.
+    fn get(&mut self, key: &[u8]) -> Result<Option<ValueLoc>> {
+        if let Some(loc) = self.buffer.get(key) {
+            return Ok(*loc);
+        }
+        if self.removed.iter().any(|range| contains(range, key)) {
+            return Ok(None);
+        }
+        let range = (Bound::Included(key.to_vec()), Bound::Included(key.to_vec()));
+        for run in self.runs.iter_mut().rev() {
+            if let Some((_, loc)) = run.first(&range, self.cipher.as_ref())? {
+                return Ok(loc);
+            }
+            if run.removed.iter().any(|range| contains(range, key)) {
+                return Ok(None);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Buffers a change to a key, flushing the buffer if it's full.
+    fn insert(&mut self, key: Vec<u8>, loc: Option<ValueLoc>) -> Result<()> {
+        self.buffer.insert(key, loc);
+        self.maybe_flush()
+    }
+
+    /// Removes all keys in the given range.
+    fn remove_range(&mut self, range: KeyRange) -> Result<()> {
+        let keys: Vec<_> = self.buffer.range(range.clone()).map(|(key, _)| key.clone()).collect();
+        for key in keys {
+            self.buffer.remove(&key);
+        }
+        self.removed.push(range);
+        self.maybe_flush()
+    }
+
+    /// Returns the first live key in the given range, if any.
+    fn first(&mut self, range: &KeyRange) -> Result<Option<(Vec<u8>, ValueLoc)>> {
+        let mut range = range.clone();
+        loop {
+            // Find the first key across the buffer and runs, and the newest
+            // layer containing it. The buffer is layer runs.len().
+            let mut first = self
+                .buffer
+                .range(range.clone())
+                .next()
+                .map(|(key, loc)| (key.clone(), *loc, self.runs.len()));
+            for (layer, run) in self.runs.iter_mut().enumerate().rev() {
+                if let Some((key, loc)) = run.first(&range, self.cipher.as_ref())? {
+                    if first.as_ref().is_none_or(|(first, _, _)| &key < first) {
+                        first = Some((key, loc, layer));
+                    }
+                }
+            }
+            let Some((key, loc, layer)) = first else { return Ok(None) };
+            match loc {
+                Some(loc) if !self.is_removed(&key, layer) => return Ok(Some((key, loc))),
+                // Skip tombstones and removed keys.
+                _ => range = non_empty((Bound::Excluded(key), range.1)),
+            }
+        }
+    }
+
+    /// Returns the last live key in the given range, if any.
+    fn last(&mut self, range: &KeyRange) -> Result<Option<(Vec<u8>, ValueLoc)>> {
+        let mut range = range.clone();
+        loop {
+            let mut last = self
+                .buffer
+                .range(range.clone())
+                .next_back()
+                .map(|(key, loc)| (key.clone(), *loc, self.runs.len()));
+            for (layer, run) in self.runs.iter_mut().enumerate().rev() {
+                if let Some((key, loc)) = run.last(&range, self.cipher.as_ref())? {
+                    if last.as_ref().is_none_or(|(last, _, _)| &key > last) {
+                        last = Some((key, loc, layer));
+                    }
+                }
+            }
+            let Some((key, loc, layer)) = last else { return Ok(None) };
+            match loc {
+                Some(loc) if !self.is_removed(&key, layer) => return Ok(Some((key, loc))),
+                _ => range = non_empty((range.0, Bound::Excluded(key))),
+            }
+        }
+    }
+
+    /// Returns true if a key in the given layer (run index, or runs.len() for
+    /// the buffer) has been removed by a range removal in a newer layer.
+    fn is_removed(&self, key: &[u8], layer: usize) -> bool {
+        if layer >= self.runs.len() {
+            return false;
+        }
+        self.runs[layer + 1..]
+            .iter()
+            .flat_map(|run| &run.removed)
+            .chain(&self.removed)
+            .any(|range| contains(range, key))
+    }
+
+    /// Writes the buffer to a new run (or appends it to the newest run) if
+    /// it's full, merging runs as needed.
+    fn maybe_flush(&mut self) -> Result<()> {
+        if self.buffer.len() + self.removed.len() < self.buffer_size {
+            return Ok(());
+        }
+        let buffer = std::mem::take(&mut self.buffer);
+        let removed = std::mem::take(&mut self.removed);
+
+        // If the buffered keys all sort after the newest run's keys, append
+        // them to it. The oldest run doesn't need tombstones.
+        if let (true, Some(run)) = (removed.is_empty(), self.runs.last_mut()) {
+            if buffer.keys().next() > run.last_key(self.cipher.as_ref())?.as_ref() {
+                let base = self.runs.len() == 1;
+                let entries = buffer.into_iter().filter(|(_, loc)| !base || loc.is_some());
+                let run = self.runs.last_mut().expect("no run");
+                let pos = run.file.seek(SeekFrom::End(0))?;
+                let mut writer =
+                    IndexWriter::new(&mut run.file, pos, self.block_size, self.cipher.clone());
+                for (key, loc) in entries {
+                    writer.write(key, loc)?;
+                    run.len += 1;
+                }
+                run.fences.extend(writer.finish()?);
+                return self.maybe_merge();
+            }
+        }
+
+        // Otherwise, write a new run.
+        let base = self.runs.is_empty();
+        let (id, mut file) = self.create_run()?;
+        let mut writer = IndexWriter::new(&mut file, 0, self.block_size, self.cipher.clone());
+        let mut len = 0;
+        for (key, loc) in buffer {
+            if base && loc.is_none() {
+                continue;
+            }
+            writer.write(key, loc)?;
+            len += 1;
+        }
+        let fences = writer.finish()?;
+        let removed = if base { Vec::new() } else { removed };
+        self.runs.push(SortedRun { id, file, fences, len, removed, cache: None });
+        self.maybe_merge()
+    }
+
+    /// Merges the two newest runs while the older one is at most MERGE_RATIO
+    /// times larger than the newer one.
+    fn maybe_merge(&mut self) -> Result<()> {
+        while let [.., older, newer] = self.runs.as_slice() {
+            if older.len > Self::MERGE_RATIO * newer.len {
+                break;
+            }
+            let mut newer = self.runs.pop().expect("no run");
+            let mut older = self.runs.pop().expect("no run");
+            let base = self.runs.is_empty();
+
+            // Merge the entries, skipping older entries that are replaced or
+            // removed by the newer run. Tombstones are dropped in the oldest run.
+            let (id, mut file) = self.create_run()?;
+            let mut writer = IndexWriter::new(&mut file, 0, self.block_size, self.cipher.clone());
+            let mut len = 0;
+            let hidden = newer.removed.clone();
+            let mut newer_iter = SortedRunIter::new(&mut newer, self.cipher.as_ref());
+            let mut older_iter = SortedRunIter::new(&mut older, self.cipher.as_ref());
+            let (mut next_newer, mut next_older) =
+                (newer_iter.next().transpose()?, older_iter.next().transpose()?);
+            loop {
+                let (key, loc) = match (next_newer.take(), next_older.take()) {
+                    (None, None) => break,
+                    (newer_entry, Some(older_entry))
+                        if newer_entry.as_ref().is_none_or(|(key, _)| older_entry.0 < *key) =>
+                    {
+                        next_newer = newer_entry;
+                        next_older = older_iter.next().transpose()?;
+                        if hidden.iter().any(|range| contains(range, &older_entry.0)) {
+                            continue;
+                        }
+                        older_entry
+                    }
+                    (Some(newer_entry), older_entry) => {
+                        next_newer = newer_iter.next().transpose()?;
+                        next_older = match older_entry {
+                            Some((key, _)) if key == newer_entry.0 => {
+                                older_iter.next().transpose()?
+                            }
+                            older_entry => older_entry,
+                        };
+                        newer_entry
+                    }
+                    (None, Some(_)) => unreachable!("older entry not handled"),
+                };
+                if base && loc.is_none() {
+                    continue;
+                }
+                writer.write(key, loc)?;
+                len += 1;
+            }
+            let fences = writer.finish()?;
+            let removed = match base {
+                true => Vec::new(),
+                false => older.removed.drain(..).chain(newer.removed.drain(..)).collect(),
+            };
+            std::fs::remove_file(self.path.join(older.id.to_string()))?;
+            std::fs::remove_file(self.path.join(newer.id.to_string()))?;
+            self.runs.push(SortedRun { id, file, fences, len, removed, cache: None });
+        }
+        Ok(())
+    }
+
+    /// Creates a new run file, returning its sequence number and file.
+    fn create_run(&mut self) -> Result<(u64, std::fs::File)> {
+        let id = self.next_run;
+        self.next_run += 1;
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(self.path.join(id.to_string()))?;
+        Ok((id, file))
+    }
+}
+
+impl SortedRun {
+    /// Returns the first entry in the given range, including tombstones.
+    fn first(
+        &mut self,
+        range: &KeyRange,
+        cipher: Option<&XChaCha20Poly1305>,
+    ) -> Result<Option<(Vec<u8>, Option<ValueLoc>)>> {
+        let start = match &range.0 {
+            Bound::Included(key) | Bound::Excluded(key) => {
+                self.fences.partition_point(|(first, _)| first <= key).saturating_sub(1)
+            }
+            Bound::Unbounded => 0,
+        };
+        for block in start..self.fences.len() {
+            let entries = self.read_block(block, cipher)?;
+            let start = entries.partition_point(|(key, _)| !after_start(range, key));
+            if let Some((key, loc)) = entries.get(start) {
+                return Ok(before_end(range, key).then(|| (key.clone(), *loc)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the last entry in the given range, including tombstones.
+    fn last(
+        &mut self,
+        range: &KeyRange,
+        cipher: Option<&XChaCha20Poly1305>,
+    ) -> Result<Option<(Vec<u8>, Option<ValueLoc>)>> {
+        let end = match &range.1 {
+            Bound::Included(key) => self.fences.partition_point(|(first, _)| first <= key),
+            Bound::Excluded(key) => self.fences.partition_point(|(first, _)| first < key),
+            Bound::Unbounded => self.fences.len(),
+        };
+        for block in (0..end).rev() {
+            let entries = self.read_block(block, cipher)?;
+            let end = entries.partition_point(|(key, _)| before_end(range, key));
+            if let Some((key, loc)) = entries[..end].last() {
+                return Ok(after_start(range, key).then(|| (key.clone(), *loc)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the last key in the run, if any.
+    fn last_key(&mut self, cipher: Option<&XChaCha20Poly1305>) -> Result<Option<Vec<u8>>> {
+        Ok(self.last(&(Bound::Unbounded, Bound::Unbounded), cipher)?.map(|(key, _)| key))
+    }
+
+    /// Reads and decodes an index block, or returns it from the cache.
+    fn read_block(
+        &mut self,
+        block: usize,
+        cipher: Option<&XChaCha20Poly1305>,
+    ) -> Result<Arc<Block>> {
+        if let Some((cached, entries)) = &self.cache {
+            if *cached == block {
+                return Ok(entries.clone());
+            }
+        }
+        let pos = self.fences[block].1;
+        let mut len_buf = [0u8; 4];
+        self.file.seek(SeekFrom::Start(pos))?;
+        self.file.read_exact(&mut len_buf)?;
+        let mut payload = vec![0; u32::from_be_bytes(len_buf) as usize];
+        self.file.read_exact(&mut payload)?;
+        if let Some(cipher) = cipher {
+            let Some(plaintext) = Log::decrypt(cipher, &payload, &pos.to_be_bytes()) else {
+                return errdata!("keydir block decryption failed at offset {pos}");
+            };
+            payload = plaintext;
+        }
+
+        let mut entries = Vec::new();
+        let mut r = std::io::Cursor::new(payload);
+        let read = |r: &mut std::io::Cursor<Vec<u8>>| -> std::io::Result<BlockEntry> {
+            let mut buf = [0u8; 8];
+            r.read_exact(&mut buf[..4])?;
+            let mut key =
+                vec![0; u32::from_be_bytes(buf[..4].try_into().expect("4 bytes")) as usize];
+            r.read_exact(&mut key)?;
+            r.read_exact(&mut buf)?;
+            let pos = u64::from_be_bytes(buf);
+            r.read_exact(&mut buf[..4])?;
+            let len = u32::from_be_bytes(buf[..4].try_into().expect("4 bytes"));
+            r.read_exact(&mut buf[..1])?;
+            let flags = buf[0];
+            if flags & 0x04 != 0 {
+                return Ok((key, None));
+            }
+            let (encrypted, compressed) = (flags & 0x01 != 0, flags & 0x02 != 0);
+            let mut uncompressed_len = None;
+            if compressed {
+                r.read_exact(&mut buf[..4])?;
+                uncompressed_len = Some(u32::from_be_bytes(buf[..4].try_into().expect("4 bytes")));
+            }
+            Ok((key, Some(ValueLoc { pos, len, uncompressed_len, encrypted })))
+        };
+        while r.position() < r.get_ref().len() as u64 {
+            match read(&mut r) {
+                Ok(entry) => entries.push(entry),
+                Err(err) => return errdata!("invalid keydir block at offset {pos}: {err}"),
+            }
+        }
+        let entries = Arc::new(entries);
+        self.cache = Some((block, entries.clone()));
+        Ok(entries)
+    }
+}
+
+/// An index block entry: a key and its value location, or None for tombstones.
+type BlockEntry = (Vec<u8>, Option<ValueLoc>);
+
+/// The decoded entries of an index block, in key order.
+type Block = Vec<BlockEntry>;
+
+/// Iterates over the entries of a sorted run in key order, e.g. for merges.
+struct SortedRunIter<'a> {
+    run: &'a mut SortedRun,
+    cipher: Option<&'a XChaCha20Poly1305>,
+    /// The next block to read.
+    block: usize,
+    /// The current block, and the position of its next entry.
+    entries: Arc<Block>,
+    next: usize,
+}
+
+impl<'a> SortedRunIter<'a> {
+    fn new(run: &'a mut SortedRun, cipher: Option<&'a XChaCha20Poly1305>) -> Self {
+        Self { run, cipher, block: 0, entries: Arc::default(), next: 0 }
+    }
+}
+
+impl<'a> Iterator for SortedRunIter<'a> {
+    type Item = Result<BlockEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.get(self.next) {
+                self.next += 1;
+                return Some(Ok(entry.clone()));
+            }
+            if self.block >= self.run.fences.len() {
+                return None;
+            }
+            match self.run.read_block(self.block, self.cipher) {
+                Ok(entries) => (self.entries, self.next) = (entries, 0),
+                Err(err) => return Some(Err(err)),
+            }
+            self.block += 1;
+        }
+    }
+}
+
+/// Writes sorted entries as index blocks to a DiskKeyDir index file.
+struct IndexWriter<'a> {
+    /// The index file, buffered.
+    file: BufWriter<&'a mut std::fs::File>,
+    /// The current file position.
+    pos: u64,
+    /// The fences of the written blocks.
+    fences: Vec<(Vec<u8>, u64)>,
+    /// The pending block's first key and encoded entries.
+    block: Option<(Vec<u8>, Vec<u8>)>,
+    /// The number of entries in the pending block.
+    block_len: usize,
+    /// The maximum number of entries per block.
+    block_size: usize,
+    /// The cipher used to encrypt blocks, if any.
+    cipher: Option<XChaCha20Poly1305>,
+}
+
+impl<'a> IndexWriter<'a> {
+    /// Creates a new index writer, positioned at pos.
+    fn new(
+        file: &'a mut std::fs::File,
+        pos: u64,
+        block_size: usize,
+        cipher: Option<XChaCha20Poly1305>,
+    ) -> Self {
+        let file = BufWriter::new(file);
+        Self { file, pos, fences: Vec::new(), block: None, block_len: 0, block_size, cipher }
+    }
+
+    /// Writes an entry, or a tombstone if loc is None. Entries must be written
+    /// in key order.
+    fn write(&mut self, key: Vec<u8>, loc: Option<ValueLoc>) -> Result<()> {
+        let Ok(key_len) = u32::try_from(key.len()) else {
+            return errinput!("key too large: {} bytes", key.len());
+        };
+        let (_, buf) = self.block.get_or_insert_with(|| (key.clone(), Vec::new()));
+        buf.extend_from_slice(&key_len.to_be_bytes());
+        buf.extend_from_slice(&key);
+        let Some(loc) = loc else {
+            buf.extend_from_slice(&[0; 12]);
+            buf.push(0x04);
+            return self.finish_entry();
+        };
+        buf.extend_from_slice(&loc.pos.to_be_bytes());
+        buf.extend_from_slice(&loc.len.to_be_bytes());
+        let flags = loc.encrypted as u8 | (loc.uncompressed_len.is_some() as u8) << 1;
+        buf.push(flags);
+        if let Some(uncompressed_len) = loc.uncompressed_len {
+            buf.extend_from_slice(&uncompressed_len.to_be_bytes());
+        }
+        self.finish_entry()
+    }
+
+    /// Counts a written entry, writing out the block if it's full.
+    fn finish_entry(&mut self) -> Result<()> {
+        self.block_len += 1;
+        if self.block_len >= self.block_size {
+            self.write_block()?;
+        }
+        Ok(())
+    }
+
+    /// Writes out the pending block, if any.
+    fn write_block(&mut self) -> Result<()> {
+        let Some((first, mut payload)) = self.block.take() else { return Ok(()) };
+        if let Some(cipher) = &self.cipher {
+            payload = Log::encrypt(cipher, &payload, &self.pos.to_be_bytes())?;
+        }
+        self.file.write_all(&(payload.len() as u32).to_be_bytes())?;
+        self.file.write_all(&payload)?;
+        self.fences.push((first, self.pos));
+        self.pos += 4 + payload.len() as u64;
+        self.block_len = 0;
+        Ok(())
+    }
+
+    /// Writes out any pending block and returns the fences of the written
+    /// blocks.
+    fn finish(mut self) -> Result<Vec<(Vec<u8>, u64)>> {
+        self.write_block()?;
+        self.file.flush()?;
+        Ok(self.fences)
+    }
+}
+
+/// Returns true if the key is in the range.
+fn contains(range: &KeyRange, key: &[u8]) -> bool {
+    after_start(range, key) && before_end(range, key)
+}
+
+/// Returns true if the key is at or after the range's start bound.
+fn after_start(range: &KeyRange, key: &[u8]) -> bool {
+    match &range.0 {
+        Bound::Included(start) => key >= start.as_slice(),
+        Bound::Excluded(start) => key > start.as_slice(),
+        Bound::Unbounded => true,
     }
 }
 
-impl<'a> DoubleEndedIterator for ScanIterator<'a> {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        self.inner.next_back().map(|item| self.map(item))
+/// Returns true if the key is at or before the range's end bound.
+fn before_end(range: &KeyRange, key: &[u8]) -> bool {
+    match &range.1 {
+        Bound::Included(end) => key <= end.as_slice(),
+        Bound::Excluded(end) => key < end.as_slice(),
+        Bound::Unbounded => true,
     }
 }
 
-impl BitCask {
-    /// Compacts the current log file by writing out a new log file containing
-    /// only live keys and replacing the current file with it.
-    pub fn compact(&mut self) -> Result<()> {
-        let mut tmp_path = self.log.path.clone();
-        tmp_path.set_extension("new");
-        let (mut new_log, new_keydir) = self.write_log(tmp_path)?;
+/// An LRU cache of fixed-size, aligned log file blocks, used to serve value
+/// reads without a read syscall per value. Since the log is append-only, the
+/// contents of a cached block never change, except for the last block of the
+/// file which may be cached before it's full. Such short blocks are reread
+/// when a read extends beyond their end. Compaction writes a new log file, so
+/// the cache must be cleared then.
+struct BlockCache {
+    /// Cached blocks by block number, i.e. the file position / block_size.
+    blocks: lru::LruCache<u64, Vec<u8>>,
+    /// The block size.
+    block_size: usize,
+    /// The maximum total size of cached blocks.
+    capacity: usize,
+    /// The current total size of cached blocks.
+    size: usize,
+    /// The number of block lookups that found the block in the cache.
+    hits: u64,
+    /// The number of block lookups that read the block from the file.
+    misses: u64,
+}
 
-        std::fs::rename(&new_log.path, &self.log.path)?;
-        new_log.path = self.log.path.clone();
+impl BlockCache {
+    /// Creates a new block cache holding up to capacity bytes.
+    fn new(capacity: usize, block_size: usize) -> Self {
+        Self {
+            blocks: lru::LruCache::unbounded(),
+            block_size,
+            capacity,
+            size: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
 
-        self.log = new_log;
-        self.keydir = new_keydir;
-        Ok(())
+    /// Removes all cached blocks, retaining the hit/miss statistics.
+    fn clear(&mut self) {
+        self.blocks.clear();
+        self.size = 0;
     }
 
-    /// Writes out a new log file with the live entries of the current log file
-    /// and returns it along with its keydir. Entries are written in key order.
-    fn write_log(&mut self, path: PathBuf) -> Result<(Log, KeyDir)> {
-        let mut new_keydir = KeyDir::new();
-        let mut new_log = Log::new(path)?;
-        new_log.file.set_len(0)?; // truncate file if it exists
-        for (key, (value_pos, value_len)) in self.keydir.iter() {
-            let value = self.log.read_value(*value_pos, *value_len)?;
-            let (pos, len) = new_log.write_entry(key, Some(&value))?;
-            new_keydir.insert(key.clone(), (pos + len as u64 - *value_len as u64, *value_len));
+    /// Reads buf.len() bytes at the given file position into buf, via cached
+    /// blocks. Blocks that aren't cached are read from the file and cached.
+    fn read(&mut self, file: &mut std::fs::File, pos: u64, buf: &mut [u8]) -> Result<()> {
+        let mut read = 0;
+        while read < buf.len() {
+            let pos = pos + read as u64;
+            let block = pos / self.block_size as u64;
+            let offset = (pos % self.block_size as u64) as usize;
+            let len = std::cmp::min(self.block_size - offset, buf.len() - read);
+            let dest = &mut buf[read..read + len];
+
+            match self.blocks.get(&block) {
+                Some(data) if data.len() >= offset + len => {
+                    self.hits += 1;
+                    dest.copy_from_slice(&data[offset..offset + len]);
+                }
+                _ => {
+                    self.misses += 1;
+                    let mut data = Vec::with_capacity(self.block_size);
+                    file.seek(SeekFrom::Start(block * self.block_size as u64))?;
+                    Read::take(&*file, self.block_size as u64).read_to_end(&mut data)?;
+                    if data.len() < offset + len {
+                        return errdata!("read at offset {pos} beyond end of file");
+                    }
+                    dest.copy_from_slice(&data[offset..offset + len]);
+                    self.insert(block, data);
+                }
+            }
+            read += len;
         }
-        Ok((new_log, new_keydir))
+        Ok(())
     }
-}
 
-/// Attempt to flush the file when the database is closed.
-impl Drop for BitCask {
-    fn drop(&mut self) {
-        if let Err(error) = self.flush() {
-            log::error!("failed to flush file: {}", error)
+    /// Caches a block, evicting least recently used blocks if needed.
+    fn insert(&mut self, block: u64, data: Vec<u8>) {
+        self.size += data.len();
+        if let Some(old) = self.blocks.put(block, data) {
+            self.size -= old.len();
+        }
+        while self.size > self.capacity {
+            let Some((_, evicted)) = self.blocks.pop_lru() else { break };
+            self.size -= evicted.len();
         }
     }
 }
@@ -238,15 +1624,42 @@ impl Drop for BitCask {
 /// A BitCask append-only log file, containing a sequence of key/value
 /// entries encoded as follows;
 ///
-/// - Key length as big-endian u32.
-/// - Value length as big-endian i32, or -1 for tombstones.
+/// - Key length as big-endian u32, with the high bit set if encrypted.
+/// - Value length L as big-endian i32, -L-2 if compressed, or -1 for tombstones.
 /// - Key as raw bytes (max 2 GB).
-/// - Value as raw bytes (max 2 GB).
+/// - Value as raw bytes (max 2 GB). Compressed values are prefixed by the
+///   codec ID as u8 and the uncompressed length as big-endian u32.
+///
+/// Encrypted entries store the key as the nonce, ciphertext, and tag of the
+/// encrypted key. The value (after any compression prefix) is stored likewise,
+/// authenticated with the plaintext key. Lengths include the encryption
+/// overhead.
+///
+/// Write batches are framed as follows, containing entries as above:
+///
+/// - BATCH_MARKER (u32::MAX) in place of the key length.
+/// - Length of the entries as big-endian u32.
+/// - Entries as raw bytes.
+/// - CRC32 checksum of the entries as big-endian u32.
 struct Log {
     /// Path to the log file.
     path: PathBuf,
     /// The opened file containing the log.
     file: std::fs::File,
+    /// Whether there are writes that haven't been fsynced yet.
+    unsynced: bool,
+    /// The compression codec and minimum value size for new writes, if any.
+    compression: Option<(Codec, usize)>,
+    /// The cipher used to encrypt new writes and decrypt existing entries, if
+    /// encryption is enabled.
+    cipher: Option<XChaCha20Poly1305>,
+    /// The block cache used for value reads, if enabled.
+    cache: Option<BlockCache>,
+    /// Whether to read values via a memory map of the file instead.
+    mmap: bool,
+    /// The memory map, if mmap is enabled. It's created lazily, and recreated
+    /// when reading beyond its end since the file has grown.
+    map: Option<memmap2::Mmap>,
 }
 
 impl Log {
@@ -264,57 +1677,106 @@ impl Log {
             .truncate(false)
             .open(&path)?;
         file.try_lock_exclusive()?;
-        Ok(Self { path, file })
+        Ok(Self {
+            path,
+            file,
+            unsynced: false,
+            compression: None,
+            cipher: None,
+            cache: None,
+            mmap: false,
+            map: None,
+        })
     }
 
-    /// Builds a keydir by scanning the log file. If an incomplete entry is
-    /// encountered, it is assumed to be caused by an incomplete write operation
-    /// and the remainder of the file is truncated.
-    fn build_keydir(&mut self) -> Result<KeyDir> {
+    /// Marks a write batch record, in place of the entry key length. Keys can't
+    /// exceed 2 GB, so it can't be confused with a regular entry.
+    const BATCH_MARKER: u32 = u32::MAX;
+
+    /// Marks a range delete record, in place of the entry key length.
+    const RANGE_DELETE_MARKER: u32 = u32::MAX - 1;
+
+    /// Marks an encrypted entry, as the high bit of the key length.
+    const ENCRYPTED_FLAG: u32 = 1 << 31;
+
+    /// The length of a compressed value's header: the codec ID as u8 and the
+    /// uncompressed length as u32.
+    const COMPRESSION_HEADER_LEN: u32 = 1 + 4;
+
+    /// The length of the nonce and authentication tag added to encrypted keys
+    /// and values.
+    const ENCRYPTION_OVERHEAD: u32 = 24 + 16;
+
+    /// Builds a keydir by scanning the log file. If an incomplete entry or
+    /// write batch is encountered, it is assumed to be caused by an incomplete
+    /// write operation and the remainder of the file is truncated.
+    fn build_keydir(&mut self, keydir: &mut KeyDir) -> Result<()> {
         let mut len_buf = [0u8; 4];
-        let mut keydir = KeyDir::new();
         let file_len = self.file.metadata()?.len();
+        let cipher = self.cipher.as_ref();
         let mut r = BufReader::new(&mut self.file);
         let mut pos = r.seek(SeekFrom::Start(0))?;
 
         while pos < file_len {
-            // Read the next entry from the file, returning the key, value
-            // position, and value length or None for tombstones.
-            let result = || -> std::result::Result<(Vec<u8>, u64, Option<u32>), std::io::Error> {
+            // Read the next record from the file, i.e. a single entry or a
+            // write batch, returning its entries and end position.
+            let result = || -> std::result::Result<(Record, u64), std::io::Error> {
                 r.read_exact(&mut len_buf)?;
                 let key_len = u32::from_be_bytes(len_buf);
-                r.read_exact(&mut len_buf)?;
-                let value_len_or_tombstone = match i32::from_be_bytes(len_buf) {
-                    l if l >= 0 => Some(l as u32),
-                    _ => None, // -1 for tombstones
-                };
-                let value_pos = pos + 4 + 4 + key_len as u64;
-
-                let mut key = vec![0; key_len as usize];
-                r.read_exact(&mut key)?;
-
-                if let Some(value_len) = value_len_or_tombstone {
-                    if value_pos + value_len as u64 > file_len {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::UnexpectedEof,
-                            "value extends beyond end of file",
-                        ));
-                    }
-                    r.seek_relative(value_len as i64)?; // avoids discarding buffer
+                if key_len == Self::RANGE_DELETE_MARKER {
+                    let (payload, end) = Self::read_framed(&mut r, pos, file_len)?;
+                    let range = Self::decode_range(&payload, cipher)?;
+                    return Ok((Record::DeleteRange(range), end));
+                } else if key_len != Self::BATCH_MARKER {
+                    let entry = Self::read_entry(&mut r, key_len, pos, file_len, cipher)?;
+                    let end = entry.1;
+                    return Ok((Record::Entries(vec![entry]), end));
                 }
 
-                Ok((key, value_pos, value_len_or_tombstone))
+                // Read and verify the write batch.
+                let (batch, end) = Self::read_framed(&mut r, pos, file_len)?;
+                let batch_len = batch.len() as u32;
+                let batch_pos = pos + 4 + 4;
+
+                // Read the batch entries. The checksum matched, so any
+                // incomplete entries are corrupt rather than torn writes.
+                let mut entries = Vec::new();
+                let mut br = std::io::Cursor::new(batch);
+                let batch_end = batch_pos + batch_len as u64;
+                while br.position() < batch_len as u64 {
+                    let entry_pos = batch_pos + br.position();
+                    let entry = || -> std::result::Result<Entry, std::io::Error> {
+                        br.read_exact(&mut len_buf)?;
+                        let key_len = u32::from_be_bytes(len_buf);
+                        Self::read_entry(&mut br, key_len, entry_pos, batch_end, cipher)
+                    }()
+                    .map_err(|err| match err.kind() {
+                        std::io::ErrorKind::UnexpectedEof => {
+                            std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+                        }
+                        _ => err,
+                    })?;
+                    entries.push(entry);
+                }
+                Ok((Record::Entries(entries), end))
             }();
 
             match result {
-                // Populate the keydir with the entry, or remove it on tombstones.
-                Ok((key, value_pos, Some(value_len))) => {
-                    keydir.insert(key, (value_pos, value_len));
-                    pos = value_pos + value_len as u64;
+                // Populate the keydir with the entries, or remove them on
+                // tombstones.
+                Ok((Record::Entries(entries), end)) => {
+                    for (key, _, value) in entries {
+                        match value {
+                            Some(loc) => keydir.insert(key, loc)?,
+                            None => keydir.remove(&key)?,
+                        };
+                    }
+                    pos = end;
                 }
-                Ok((key, value_pos, None)) => {
-                    keydir.remove(&key);
-                    pos = value_pos;
+                // Remove all keys in range deletes.
+                Ok((Record::DeleteRange(range), end)) => {
+                    keydir.remove_range(range)?;
+                    pos = end;
                 }
                 // If an incomplete entry was found at the end of the file, assume an
                 // incomplete write and truncate the file.
@@ -323,46 +1785,450 @@ impl Log {
                     self.file.set_len(pos)?;
                     break;
                 }
+                Err(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+                    return errdata!("invalid log record at offset {pos}: {err}");
+                }
                 Err(err) => return Err(err.into()),
             }
         }
 
-        Ok(keydir)
+        Ok(())
+    }
+
+    /// Reads a framed record (i.e. a write batch or range delete) from the
+    /// reader, positioned after the record marker at pos, and verifies its
+    /// checksum. Returns the payload and the record's end position.
+    fn read_framed(
+        r: &mut impl Read,
+        pos: u64,
+        file_len: u64,
+    ) -> std::result::Result<(Vec<u8>, u64), std::io::Error> {
+        let mut len_buf = [0u8; 4];
+        r.read_exact(&mut len_buf)?;
+        let len = u32::from_be_bytes(len_buf);
+        let end = pos + 4 + 4 + len as u64 + 4;
+        if end > file_len {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "record extends beyond end of file",
+            ));
+        }
+        let mut payload = vec![0; len as usize];
+        r.read_exact(&mut payload)?;
+        r.read_exact(&mut len_buf)?;
+        if crc32fast::hash(&payload) != u32::from_be_bytes(len_buf) {
+            // A torn record at the end of the file is considered incomplete.
+            // Elsewhere, it's corrupt.
+            let kind = if end == file_len {
+                std::io::ErrorKind::UnexpectedEof
+            } else {
+                std::io::ErrorKind::InvalidData
+            };
+            return Err(std::io::Error::new(kind, "record checksum mismatch"));
+        }
+        Ok((payload, end))
+    }
+
+    /// Encodes a range delete payload, encrypting it if needed.
+    fn encode_range(&self, range: &KeyRange) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        for bound in [&range.0, &range.1] {
+            let (tag, key) = match bound {
+                Bound::Unbounded => (0u8, None),
+                Bound::Included(key) => (1, Some(key)),
+                Bound::Excluded(key) => (2, Some(key)),
+            };
+            buf.push(tag);
+            if let Some(key) = key {
+                let Ok(len) = u32::try_from(key.len()) else {
+                    return errinput!("key too large: {} bytes", key.len());
+                };
+                buf.extend_from_slice(&len.to_be_bytes());
+                buf.extend_from_slice(key);
+            }
+        }
+        match &self.cipher {
+            Some(cipher) => {
+                let mut payload = vec![1];
+                payload.extend(Self::encrypt(cipher, &buf, &[])?);
+                Ok(payload)
+            }
+            None => {
+                buf.insert(0, 0);
+                Ok(buf)
+            }
+        }
+    }
+
+    /// Decodes a range delete payload, decrypting it if needed.
+    fn decode_range(
+        payload: &[u8],
+        cipher: Option<&XChaCha20Poly1305>,
+    ) -> std::result::Result<KeyRange, std::io::Error> {
+        let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+        let data = match payload.split_first() {
+            Some((0, data)) => Cow::Borrowed(data),
+            Some((1, data)) => {
+                let Some(cipher) = cipher else {
+                    return Err(invalid("range is encrypted, but no encryption key was given"));
+                };
+                let data = Self::decrypt(cipher, data, &[])
+                    .ok_or_else(|| invalid("range decryption failed, wrong encryption key?"))?;
+                Cow::Owned(data)
+            }
+            _ => return Err(invalid("invalid range delete payload")),
+        };
+        let mut r = std::io::Cursor::new(data.as_ref());
+        let mut bound = || -> std::result::Result<Bound<Vec<u8>>, std::io::Error> {
+            let mut tag = [0u8; 1];
+            r.read_exact(&mut tag)?;
+            if tag[0] == 0 {
+                return Ok(Bound::Unbounded);
+            }
+            let mut len_buf = [0u8; 4];
+            r.read_exact(&mut len_buf)?;
+            let mut key = vec![0; u32::from_be_bytes(len_buf) as usize];
+            r.read_exact(&mut key)?;
+            match tag[0] {
+                1 => Ok(Bound::Included(key)),
+                2 => Ok(Bound::Excluded(key)),
+                _ => Err(invalid("invalid range bound")),
+            }
+        };
+        // The checksum matched, so a truncated payload is corrupt.
+        let range =
+            (|| Ok((bound()?, bound()?)))().map_err(|err: std::io::Error| match err.kind() {
+                std::io::ErrorKind::UnexpectedEof => invalid("truncated range delete payload"),
+                _ => err,
+            })?;
+        Ok(range)
+    }
+
+    /// Reads an entry with the given key length field from the reader,
+    /// positioned after the key length, skipping the value. pos is the entry's
+    /// position in the file, and end is the end of the region containing the
+    /// entry. Encrypted keys are decrypted with the given cipher.
+    fn read_entry(
+        r: &mut (impl Read + Seek),
+        key_len: u32,
+        pos: u64,
+        end: u64,
+        cipher: Option<&XChaCha20Poly1305>,
+    ) -> std::result::Result<Entry, std::io::Error> {
+        let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+        let encrypted = key_len & Self::ENCRYPTED_FLAG != 0;
+        let key_len = key_len & !Self::ENCRYPTED_FLAG;
+
+        let mut len_buf = [0u8; 4];
+        r.read_exact(&mut len_buf)?;
+        let (value_len_or_tombstone, compressed) = match i32::from_be_bytes(len_buf) {
+            l if l >= 0 => (Some(l as u32), false),
+            -1 => (None, false), // tombstone
+            l => (Some((-(l as i64) - 2) as u32), true),
+        };
+        let value_pos = pos + 4 + 4 + key_len as u64;
+
+        let mut key = vec![0; key_len as usize];
+        r.read_exact(&mut key)?;
+        if encrypted {
+            let Some(cipher) = cipher else {
+                return Err(invalid("entry is encrypted, but no encryption key was given"));
+            };
+            key = Self::decrypt(cipher, &key, &[])
+                .ok_or_else(|| invalid("key decryption failed, wrong encryption key?"))?;
+        }
+
+        let Some(value_len) = value_len_or_tombstone else {
+            return Ok((key, value_pos, None));
+        };
+        if value_pos + value_len as u64 > end {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "value extends beyond end of file",
+            ));
+        }
+        let mut skip = value_len;
+        let mut uncompressed_len = None;
+        if compressed {
+            if value_len < Self::COMPRESSION_HEADER_LEN {
+                return Err(invalid("compressed value is missing header"));
+            }
+            let mut header = [0u8; Self::COMPRESSION_HEADER_LEN as usize];
+            r.read_exact(&mut header)?;
+            uncompressed_len = Some(u32::from_be_bytes(header[1..].try_into().expect("4 bytes")));
+            skip -= Self::COMPRESSION_HEADER_LEN;
+        }
+        r.seek_relative(skip as i64)?; // avoids discarding buffer
+
+        let loc = ValueLoc { pos: value_pos, len: value_len, uncompressed_len, encrypted };
+        Ok((key, value_pos + value_len as u64, Some(loc)))
     }
 
-    /// Reads a value from the log file.
-    fn read_value(&mut self, value_pos: u64, value_len: u32) -> Result<Vec<u8>> {
-        let mut value = vec![0; value_len as usize];
-        self.file.seek(SeekFrom::Start(value_pos))?;
-        self.file.read_exact(&mut value)?;
+    /// Reads a value from the log file, decrypting and decompressing it as
+    /// needed.
+    fn read_value(&mut self, key: &[u8], loc: ValueLoc) -> Result<Vec<u8>> {
+        let value = self.read_stored_value(key, loc, true)?;
+        let Some(uncompressed_len) = loc.uncompressed_len else {
+            return Ok(value);
+        };
+        let header_len = Self::COMPRESSION_HEADER_LEN as usize;
+        if value.len() < header_len {
+            return errdata!("compressed value at offset {} is missing header", loc.pos);
+        }
+        Codec::from_id(value[0])?.decompress(&value[header_len..], uncompressed_len)
+    }
+
+    /// Reads a value as stored in the log file, decrypting it if needed but
+    /// leaving it compressed. If cache is false, the block cache is bypassed,
+    /// e.g. to avoid evicting hot blocks when reading all values.
+    fn read_stored_value(&mut self, key: &[u8], loc: ValueLoc, cache: bool) -> Result<Vec<u8>> {
+        let mut value = vec![0; loc.len as usize];
+        self.read_at(loc.pos, &mut value, cache)?;
+        if !loc.encrypted {
+            return Ok(value);
+        }
+        let Some(cipher) = self.cipher.as_ref() else {
+            return errdata!("value at offset {} is encrypted, but no key was given", loc.pos);
+        };
+        let header_len = match loc.uncompressed_len {
+            Some(_) => Self::COMPRESSION_HEADER_LEN as usize,
+            None => 0,
+        };
+        if value.len() < header_len {
+            return errdata!("compressed value at offset {} is missing header", loc.pos);
+        }
+        let Some(plaintext) = Self::decrypt(cipher, &value[header_len..], key) else {
+            return errdata!("value decryption failed at offset {}", loc.pos);
+        };
+        value.truncate(header_len);
+        value.extend(plaintext);
         Ok(value)
     }
 
+    /// Reads buf.len() bytes at the given position, via the memory map or the
+    /// block cache if enabled. Values larger than a cache block are read
+    /// directly from the file, since they would evict many blocks.
+    fn read_at(&mut self, pos: u64, buf: &mut [u8], cache: bool) -> Result<()> {
+        let end = pos + buf.len() as u64;
+        if self.mmap {
+            if self.map.as_ref().is_none_or(|map| (map.len() as u64) < end) {
+                // SAFETY: the mapped file must not be modified by others while
+                // mapped. We hold an exclusive lock on it, and only ever append
+                // to it after it's opened.
+                self.map = Some(unsafe { memmap2::Mmap::map(&self.file)? });
+            }
+            let map = self.map.as_ref().expect("no map");
+            if (map.len() as u64) < end {
+                return errdata!("read at offset {pos} beyond end of file");
+            }
+            buf.copy_from_slice(&map[pos as usize..end as usize]);
+            return Ok(());
+        }
+        if let Some(block_cache) =
+            self.cache.as_mut().filter(|c| cache && buf.len() <= c.block_size)
+        {
+            return block_cache.read(&mut self.file, pos, buf);
+        }
+        self.file.seek(SeekFrom::Start(pos))?;
+        self.file.read_exact(buf)?;
+        Ok(())
+    }
+
+    /// Encrypts data, authenticating it along with the associated data.
+    /// Returns the random nonce followed by the ciphertext and tag.
+    fn encrypt(cipher: &XChaCha20Poly1305, data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut rand::thread_rng());
+        let Ok(ciphertext) = cipher.encrypt(&nonce, Payload { msg: data, aad }) else {
+            return errdata!("encryption failed");
+        };
+        let mut encrypted = Vec::with_capacity(nonce.len() + ciphertext.len());
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+        Ok(encrypted)
+    }
+
+    /// Decrypts data encrypted with encrypt(), or returns None if it can't be
+    /// decrypted and authenticated with the associated data.
+    fn decrypt(cipher: &XChaCha20Poly1305, data: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        if data.len() < Self::ENCRYPTION_OVERHEAD as usize {
+            return None;
+        }
+        let (nonce, ciphertext) = data.split_at(24);
+        cipher.decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad }).ok()
+    }
+
+    /// Encodes a value for storage, compressing it if compression is enabled,
+    /// the value is at or above the size threshold, and compression shrinks
+    /// it. Returns the stored value and the uncompressed length if compressed.
+    fn encode_value<'a>(&self, value: &'a [u8]) -> Result<(Cow<'a, [u8]>, Option<u32>)> {
+        let Some((codec, threshold)) = self.compression else {
+            return Ok((Cow::Borrowed(value), None));
+        };
+        if value.len() < threshold {
+            return Ok((Cow::Borrowed(value), None));
+        }
+        let compressed = codec.compress(value)?;
+        if Self::COMPRESSION_HEADER_LEN as usize + compressed.len() >= value.len() {
+            return Ok((Cow::Borrowed(value), None));
+        }
+        let mut stored =
+            Vec::with_capacity(Self::COMPRESSION_HEADER_LEN as usize + compressed.len());
+        stored.push(codec.id());
+        stored.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        stored.extend_from_slice(&compressed);
+        Ok((Cow::Owned(stored), Some(value.len() as u32)))
+    }
+
+    /// Encodes a log entry, given a value as stored (i.e. possibly compressed)
+    /// and its uncompressed length if compressed, or None for tombstones. The
+    /// entry is encrypted if enabled. Returns the encoded entry and the value's
+    /// location, with a position relative to the start of the entry and a 0
+    /// length for tombstones.
+    fn encode_entry(
+        &self,
+        key: &[u8],
+        value: Option<(&[u8], Option<u32>)>,
+    ) -> Result<(Vec<u8>, ValueLoc)> {
+        let mut stored_key = Cow::Borrowed(key);
+        let mut stored_value = value.map(|(v, _)| Cow::Borrowed(v));
+        let uncompressed_len = value.and_then(|(_, len)| len);
+        if let Some(cipher) = &self.cipher {
+            stored_key = Cow::Owned(Self::encrypt(cipher, key, &[])?);
+            if let Some((value, _)) = value {
+                // Leave the compression header in plaintext, such that the
+                // keydir can be built without decrypting values.
+                let header_len = match uncompressed_len {
+                    Some(_) => Self::COMPRESSION_HEADER_LEN as usize,
+                    None => 0,
+                };
+                let mut encrypted = value[..header_len].to_vec();
+                encrypted.extend(Self::encrypt(cipher, &value[header_len..], key)?);
+                stored_value = Some(Cow::Owned(encrypted));
+            }
+        }
+
+        let mut key_len = stored_key.len() as u32;
+        if self.cipher.is_some() {
+            key_len |= Self::ENCRYPTED_FLAG;
+        }
+        let value_len = stored_value.as_ref().map_or(0, |v| v.len() as u32);
+        let value_len_or_tombstone = match (&stored_value, uncompressed_len) {
+            (None, _) => -1,
+            (Some(v), None) => v.len() as i32,
+            (Some(v), Some(_)) => -(v.len() as i32) - 2,
+        };
+
+        let mut entry = Vec::with_capacity(4 + 4 + stored_key.len() + value_len as usize);
+        entry.extend_from_slice(&key_len.to_be_bytes());
+        entry.extend_from_slice(&value_len_or_tombstone.to_be_bytes());
+        entry.extend_from_slice(&stored_key);
+        let loc = ValueLoc {
+            pos: entry.len() as u64,
+            len: value_len,
+            uncompressed_len,
+            encrypted: self.cipher.is_some(),
+        };
+        if let Some(value) = stored_value {
+            entry.extend_from_slice(&value);
+        }
+        Ok((entry, loc))
+    }
+
     /// Appends a key/value entry to the log file, using a None value for
-    /// tombstones. It returns the position and length of the entry.
-    fn write_entry(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<(u64, u32)> {
-        let key_len = key.len() as u32;
-        let value_len = value.map_or(0, |v| v.len() as u32);
-        let value_len_or_tombstone = value.map_or(-1, |v| v.len() as i32);
-        let len = 4 + 4 + key_len + value_len;
+    /// tombstones. The value is compressed and encrypted if enabled. It returns
+    /// the value's location, with a 0 length for tombstones.
+    fn write_entry(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<ValueLoc> {
+        let value = value.map(|v| self.encode_value(v)).transpose()?;
+        self.write_stored_entry(key, value.as_ref().map(|(v, len)| (v.as_ref(), *len)))
+    }
+
+    /// Appends a key/value entry to the log file, given a value as stored
+    /// (i.e. possibly compressed) and its uncompressed length if compressed,
+    /// or None for tombstones. The entry is encrypted if enabled. It returns
+    /// the value's location, with a 0 length for tombstones.
+    fn write_stored_entry(
+        &mut self,
+        key: &[u8],
+        value: Option<(&[u8], Option<u32>)>,
+    ) -> Result<ValueLoc> {
+        let (entry, mut loc) = self.encode_entry(key, value)?;
+        let pos = self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&entry)?;
+        self.unsynced = true;
+        loc.pos += pos;
+        Ok(loc)
+    }
+
+    /// Appends a range delete record to the log file.
+    fn write_delete_range(&mut self, range: &KeyRange) -> Result<()> {
+        let payload = self.encode_range(range)?;
+        let Ok(len) = u32::try_from(payload.len()) else {
+            return errinput!("range too large: {} bytes", payload.len());
+        };
+        self.file.seek(SeekFrom::End(0))?;
+        let mut w = BufWriter::with_capacity(4 + 4 + payload.len() + 4, &mut self.file);
+        w.write_all(&Self::RANGE_DELETE_MARKER.to_be_bytes())?;
+        w.write_all(&len.to_be_bytes())?;
+        w.write_all(&payload)?;
+        w.write_all(&crc32fast::hash(&payload).to_be_bytes())?;
+        w.flush()?;
+        self.unsynced = true;
+        Ok(())
+    }
+
+    /// Fsyncs the log file, if there are any unsynced writes.
+    fn sync(&mut self) -> Result<()> {
+        if self.unsynced {
+            sync_file(&self.file)?;
+            self.unsynced = false;
+        }
+        Ok(())
+    }
+
+    /// Appends a write batch to the log file as a single record. It returns
+    /// the value location of each operation's entry.
+    fn write_batch(&mut self, ops: &[WriteOp]) -> Result<Vec<ValueLoc>> {
+        // A single entry is atomic by itself, so write it as a regular entry.
+        match ops {
+            [] => return Ok(Vec::new()),
+            [WriteOp::Delete { key }] => return Ok(vec![self.write_entry(key, None)?]),
+            [WriteOp::Set { key, value }] => return Ok(vec![self.write_entry(key, Some(value))?]),
+            _ => {}
+        }
 
         let pos = self.file.seek(SeekFrom::End(0))?;
-        let mut w = BufWriter::with_capacity(len as usize, &mut self.file);
-        w.write_all(&key_len.to_be_bytes())?;
-        w.write_all(&value_len_or_tombstone.to_be_bytes())?;
-        w.write_all(key)?;
-        if let Some(value) = value {
-            w.write_all(value)?;
+        let mut batch = Vec::new();
+        let mut values = Vec::with_capacity(ops.len());
+        for op in ops {
+            let (key, value) = match op {
+                WriteOp::Delete { key } => (key, None),
+                WriteOp::Set { key, value } => (key, Some(self.encode_value(value)?)),
+            };
+            let value = value.as_ref().map(|(v, len)| (v.as_ref(), *len));
+            let (entry, mut loc) = self.encode_entry(key, value)?;
+            loc.pos += pos + 4 + 4 + batch.len() as u64;
+            values.push(loc);
+            batch.extend_from_slice(&entry);
         }
+        let Ok(batch_len) = u32::try_from(batch.len()) else {
+            return errinput!("write batch too large: {} bytes", batch.len());
+        };
+
+        let mut w = BufWriter::with_capacity(4 + 4 + batch.len() + 4, &mut self.file);
+        w.write_all(&Self::BATCH_MARKER.to_be_bytes())?;
+        w.write_all(&batch_len.to_be_bytes())?;
+        w.write_all(&batch)?;
+        w.write_all(&crc32fast::hash(&batch).to_be_bytes())?;
         w.flush()?;
+        self.unsynced = true;
 
-        Ok((pos, len))
+        Ok(values)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::super::engine::test::Runner;
+    use super::super::engine::test::{format_range, Runner};
     use super::*;
     use crate::encoding::format::{self, Formatter as _};
     use std::error::Error as StdError;
@@ -378,7 +2244,35 @@ mod tests {
     test_each_path! { in "src/storage/testscripts/bitcask" as scripts => test_goldenscript }
 
     fn test_goldenscript(path: &std::path::Path) {
-        goldenscript::run(&mut BitCaskRunner::new(), path).expect("goldenscript failed")
+        goldenscript::run(&mut BitCaskRunner::new(KeyDirMode::Memory), path)
+            .expect("goldenscript failed")
+    }
+
+    // Run the common and BitCask-specific tests with an on-disk keydir too,
+    // using tiny buffers and blocks to exercise merges and block boundaries.
+    test_each_path! { in "src/storage/testscripts/engine" as engine_disk_keydir => test_goldenscript_disk_keydir }
+    test_each_path! { in "src/storage/testscripts/bitcask" as scripts_disk_keydir => test_goldenscript_disk_keydir }
+
+    fn test_goldenscript_disk_keydir(path: &std::path::Path) {
+        let mode = KeyDirMode::Disk { buffer_size: 2, block_size: 2 };
+        goldenscript::run(&mut BitCaskRunner::new(mode), path).expect("goldenscript failed")
+    }
+
+    // Run the common tests with a tiny block cache, such that values span
+    // blocks and blocks are evicted, and with mmap reads.
+    test_each_path! { in "src/storage/testscripts/engine" as engine_block_cache => test_goldenscript_block_cache }
+    test_each_path! { in "src/storage/testscripts/engine" as engine_mmap => test_goldenscript_mmap }
+
+    fn test_goldenscript_block_cache(path: &std::path::Path) {
+        let mut runner = BitCaskRunner::new(KeyDirMode::Memory);
+        runner.inner.engine.set_block_cache(16, 4).expect("block cache failed");
+        goldenscript::run(&mut runner, path).expect("goldenscript failed")
+    }
+
+    fn test_goldenscript_mmap(path: &std::path::Path) {
+        let mut runner = BitCaskRunner::new(KeyDirMode::Memory);
+        runner.inner.engine.set_mmap(true);
+        goldenscript::run(&mut runner, path).expect("goldenscript failed")
     }
 
     /// Tests that exclusive locks are taken out on log files, erroring if held,
@@ -408,14 +2302,14 @@ mod tests {
         let mut log = Log::new(path.clone())?;
 
         let mut ends = vec![];
-        let (pos, len) = log.write_entry("deleted".as_bytes(), Some(&[1, 2, 3]))?;
-        ends.push(pos + len as u64);
-        let (pos, len) = log.write_entry("deleted".as_bytes(), None)?;
-        ends.push(pos + len as u64);
-        let (pos, len) = log.write_entry(&[], Some(&[]))?;
-        ends.push(pos + len as u64);
-        let (pos, len) = log.write_entry("key".as_bytes(), Some(&[1, 2, 3, 4, 5]))?;
-        ends.push(pos + len as u64);
+        let loc = log.write_entry("deleted".as_bytes(), Some(&[1, 2, 3]))?;
+        ends.push(loc.pos + loc.len as u64);
+        let loc = log.write_entry("deleted".as_bytes(), None)?;
+        ends.push(loc.pos + loc.len as u64);
+        let loc = log.write_entry(&[], Some(&[]))?;
+        ends.push(loc.pos + loc.len as u64);
+        let loc = log.write_entry("key".as_bytes(), Some(&[1, 2, 3, 4, 5]))?;
+        ends.push(loc.pos + loc.len as u64);
         drop(log);
 
         // Copy the file, and truncate it at each byte, then try to open it
@@ -448,6 +2342,70 @@ mod tests {
         Ok(())
     }
 
+    /// Tests that a log with an incomplete write batch at the end can be
+    /// recovered by discarding the entire batch.
+    #[test]
+    fn recovery_batch() -> Result<()> {
+        // Create an initial log file with an entry and a write batch.
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let path = dir.path().join("complete");
+        let mut log = Log::new(path.clone())?;
+
+        let loc = log.write_entry(b"a", Some(&[1]))?;
+        let entry_end = loc.pos + loc.len as u64;
+        log.write_batch(&[
+            WriteOp::Set { key: b"b".to_vec(), value: vec![2] },
+            WriteOp::Delete { key: b"a".to_vec() },
+            WriteOp::Set { key: b"c".to_vec(), value: vec![3] },
+        ])?;
+        drop(log);
+
+        // Truncate the file at each byte, and assert that the batch is either
+        // applied entirely or not at all.
+        let truncpath = dir.path().join("truncated");
+        let size = std::fs::metadata(&path)?.len();
+        for pos in 0..=size {
+            std::fs::copy(&path, &truncpath)?;
+            let f = std::fs::OpenOptions::new().write(true).open(&truncpath)?;
+            f.set_len(pos)?;
+            drop(f);
+
+            let expect = match pos {
+                pos if pos < entry_end => vec![],
+                pos if pos < size => vec![(b"a".to_vec(), vec![1])],
+                _ => vec![(b"b".to_vec(), vec![2]), (b"c".to_vec(), vec![3])],
+            };
+
+            let mut engine = BitCask::new(truncpath.clone())?;
+            assert_eq!(expect, engine.scan(..).collect::<Result<Vec<_>>>()?);
+        }
+
+        // A torn batch at the end of the file, where the checksum doesn't
+        // match, is also discarded.
+        std::fs::copy(&path, &truncpath)?;
+        let mut f = std::fs::OpenOptions::new().write(true).open(&truncpath)?;
+        f.seek(SeekFrom::Start(size - 5))?;
+        f.write_all(&[0xff])?;
+        drop(f);
+
+        let mut engine = BitCask::new(truncpath.clone())?;
+        assert_eq!(vec![(b"a".to_vec(), vec![1])], engine.scan(..).collect::<Result<Vec<_>>>()?);
+        drop(engine);
+        assert_eq!(std::fs::metadata(&truncpath)?.len(), entry_end);
+
+        // If the corrupt batch isn't at the end of the file, opening it errors.
+        std::fs::copy(&path, &truncpath)?;
+        let mut f = std::fs::OpenOptions::new().write(true).open(&truncpath)?;
+        f.seek(SeekFrom::Start(size - 5))?;
+        f.write_all(&[0xff])?;
+        f.seek(SeekFrom::End(0))?;
+        f.write_all(&[0, 0, 0, 1, 0, 0, 0, 1, b'd', 4])?;
+        drop(f);
+
+        assert!(BitCask::new(truncpath).is_err());
+        Ok(())
+    }
+
     /// Tests key/value sizes up to 64 MB.
     #[test]
     fn point_ops_sizes() -> Result<()> {
@@ -490,10 +2448,304 @@ mod tests {
         BitCask::should_compact(garbage_size, total_size, min_fraction, min_bytes)
     }
 
+    /// Tests that sync policies are parsed correctly.
+    #[test_case("always" => Some(SyncPolicy::Always); "always")]
+    #[test_case("never" => Some(SyncPolicy::Never); "never")]
+    #[test_case("100ms" => Some(SyncPolicy::Interval(Duration::from_millis(100))); "millis")]
+    #[test_case("2s" => Some(SyncPolicy::Interval(Duration::from_secs(2))); "secs")]
+    #[test_case("0ms" => None; "zero")]
+    #[test_case("-1s" => None; "negative")]
+    #[test_case("100" => None; "no unit")]
+    #[test_case("ms" => None; "no value")]
+    #[test_case("Always" => None; "case sensitive")]
+    #[test_case("" => None; "empty")]
+    fn sync_policy(s: &str) -> Option<SyncPolicy> {
+        s.parse().ok()
+    }
+
+    /// Tests that values of various sizes round-trip through each codec, and
+    /// that decompression verifies the uncompressed length.
+    #[test_case(Codec::LZ4; "lz4")]
+    #[test_case(Codec::Deflate; "deflate")]
+    fn codec(codec: Codec) -> Result<()> {
+        for size in [0, 1, 100, 100_000] {
+            let value: Vec<u8> = (0..size).map(|i| (i % 7) as u8).collect();
+            let compressed = codec.compress(&value)?;
+            assert_eq!(codec.decompress(&compressed, size)?, value);
+            assert!(codec.decompress(&compressed, size + 1).is_err());
+        }
+        Ok(())
+    }
+
+    /// Tests that encrypted databases can be written and read, don't contain
+    /// plaintext on disk, and can't be opened without the right key.
+    #[test]
+    fn encryption() -> Result<()> {
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let path = dir.path().join("bitcask");
+        let key = EncryptionKey::new([7; 32]);
+        let contains = |needle: &[u8]| -> Result<bool> {
+            let data = std::fs::read(&path)?;
+            Ok(data.windows(needle.len()).any(|w| w == needle))
+        };
+
+        // Write some plaintext data, including compressed values, then open it
+        // with a key. The data is encrypted by compacting it.
+        let mut engine = BitCask::new(path.clone())?;
+        engine.set_compression(Some(Codec::LZ4), 0);
+        engine.set(b"plainkey", b"plainvalue".to_vec())?;
+        engine.set(b"compressed", vec![b'x'; 100])?;
+        engine.delete(b"plainkey")?;
+        engine.set(b"plainkey", b"newvalue".to_vec())?;
+        drop(engine);
+        assert!(contains(b"plainkey")?);
+
+        let mut engine = BitCask::new_encrypted(path.clone(), key.clone())?;
+        engine.set_compression(Some(Codec::LZ4), 0);
+        assert!(!contains(b"plainkey")?);
+        assert!(!contains(b"newvalue")?);
+        assert!(!contains(b"plainvalue")?);
+
+        // Writes, batches, and compaction retain encryption.
+        engine.set(b"secretkey", b"secretvalue".to_vec())?;
+        engine.write_batch(vec![
+            WriteOp::Set { key: b"batchkey".to_vec(), value: vec![b'y'; 100] },
+            WriteOp::Delete { key: b"compressed".to_vec() },
+            WriteOp::Set { key: b"compressed".to_vec(), value: vec![b'z'; 100] },
+        ])?;
+        assert_eq!(engine.get(b"plainkey")?, Some(b"newvalue".to_vec()));
+        let status = engine.status()?;
+        assert_eq!(status.live_disk_size, status.total_disk_size - status.garbage_disk_size);
+        assert!(status.size > status.compressed_size);
+        engine.compact()?;
+        let status = engine.status()?;
+        assert_eq!((status.live_disk_size, status.garbage_disk_size), (status.total_disk_size, 0));
+        for needle in [&b"secretkey"[..], b"secretvalue", b"batchkey", b"plainkey"] {
+            assert!(!contains(needle)?);
+        }
+
+        // Backups are encrypted with the same key.
+        let backup_path = dir.path().join("backup");
+        engine.backup()?.write(&backup_path)?;
+        drop(engine);
+        let backup = std::fs::read(&backup_path)?;
+        assert!(!backup.windows(9).any(|w| w == b"secretkey"));
+
+        // Reopening with the key reads the data.
+        let expect = vec![
+            (b"batchkey".to_vec(), vec![b'y'; 100]),
+            (b"compressed".to_vec(), vec![b'z'; 100]),
+            (b"plainkey".to_vec(), b"newvalue".to_vec()),
+            (b"secretkey".to_vec(), b"secretvalue".to_vec()),
+        ];
+        for path in [&path, &backup_path] {
+            let mut engine = BitCask::new_encrypted(path.clone(), key.clone())?;
+            assert_eq!(expect, engine.scan(..).collect::<Result<Vec<_>>>()?);
+        }
+
+        // Range deletes are encrypted too, and replayed when reopened.
+        let mut engine = BitCask::new_encrypted(path.clone(), key.clone())?;
+        engine.delete_range((Bound::Included(b"secretkey".to_vec()), Bound::Unbounded))?;
+        drop(engine);
+        assert!(!contains(b"secretkey")?);
+        let mut engine = BitCask::new_encrypted(path.clone(), key.clone())?;
+        assert_eq!(expect[..3], engine.scan(..).collect::<Result<Vec<_>>>()?);
+        drop(engine);
+
+        // Opening without a key or with the wrong key errors.
+        assert!(BitCask::new(path.clone()).is_err());
+        assert!(BitCask::new_encrypted(path.clone(), EncryptionKey::new([8; 32])).is_err());
+        Ok(())
+    }
+
+    /// Tests that encryption keys are read from hex key files.
+    #[test]
+    fn encryption_key_file() -> Result<()> {
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let path = dir.path().join("key");
+
+        std::fs::write(&path, format!("{}\n", "0f".repeat(32)))?;
+        assert_eq!(EncryptionKey::from_file(&path)?.0, [0x0f; 32]);
+
+        for invalid in ["0f".repeat(31), "0f".repeat(33), "0g".repeat(32), "ø".repeat(32)] {
+            std::fs::write(&path, invalid)?;
+            assert!(EncryptionKey::from_file(&path).is_err());
+        }
+        Ok(())
+    }
+
+    /// Tests that writes are retained with all sync policies, including when
+    /// changing policies and compacting with a background syncer.
+    #[test]
+    fn sync() -> Result<()> {
+        let path = tempfile::TempDir::with_prefix("toydb")?.path().join("bitcask");
+        let mut engine = BitCask::new(path.clone())?;
+
+        let policies = [
+            SyncPolicy::Always,
+            SyncPolicy::Interval(Duration::from_millis(1)),
+            SyncPolicy::Never,
+            SyncPolicy::Interval(Duration::from_secs(60)),
+        ];
+        for (i, policy) in policies.into_iter().enumerate() {
+            engine.set_sync(policy)?;
+            engine.set(&[i as u8], vec![i as u8])?;
+            engine.flush()?;
+            engine.flush()?;
+            engine.compact()?;
+        }
+        drop(engine);
+
+        let mut engine = BitCask::new(path)?;
+        let expect: Vec<_> = (0..4).map(|i| (vec![i], vec![i])).collect();
+        assert_eq!(expect, engine.scan(..).collect::<Result<Vec<_>>>()?);
+        Ok(())
+    }
+
+    /// Tests that create_from() copies all live data from another engine, and
+    /// errors if the file already exists.
+    #[test]
+    fn create_from() -> Result<()> {
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let path = dir.path().join("bitcask");
+
+        let mut source = super::super::Memory::new();
+        source.set(b"a", vec![1])?;
+        source.set(b"b", vec![2])?;
+        source.set(b"c", vec![3])?;
+        source.delete(b"b")?;
+
+        let expect = vec![(b"a".to_vec(), vec![1]), (b"c".to_vec(), vec![3])];
+        let engine = BitCask::create_from(path.clone(), &mut source)?;
+        drop(engine);
+        let mut engine = BitCask::new(path.clone())?;
+        assert_eq!(expect, engine.scan(..).collect::<Result<Vec<_>>>()?);
+        drop(engine);
+
+        assert!(BitCask::create_from(path, &mut source).is_err());
+        Ok(())
+    }
+
+    /// Tests the on-disk keydir against an in-memory engine with random
+    /// operations, using tiny buffers and blocks to exercise merges.
+    #[test]
+    fn disk_keydir() -> Result<()> {
+        use rand::{Rng as _, SeedableRng as _};
+
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let path = dir.path().join("bitcask");
+        let mode = KeyDirMode::Disk { buffer_size: 3, block_size: 2 };
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let mut engine = BitCask::open(path.clone(), None, mode)?;
+        let mut expect = super::super::Memory::new();
+
+        let random_range = |rng: &mut rand::rngs::StdRng| {
+            let mut bound = || match rng.gen_range(0..3) {
+                0 => Bound::Unbounded,
+                1 => Bound::Included(vec![rng.gen_range(0..32)]),
+                _ => Bound::Excluded(vec![rng.gen_range(0..32)]),
+            };
+            non_empty((bound(), bound()))
+        };
+
+        for i in 0..5000 {
+            let key = vec![rng.gen_range(0..32)];
+            match rng.gen_range(0..100) {
+                0..=49 => {
+                    engine.set(&key, vec![i as u8])?;
+                    expect.set(&key, vec![i as u8])?;
+                }
+                50..=69 => {
+                    engine.delete(&key)?;
+                    expect.delete(&key)?;
+                }
+                70..=74 => {
+                    let range = random_range(&mut rng);
+                    engine.delete_range(range.clone())?;
+                    expect.delete_range(range)?;
+                }
+                75..=89 => assert_eq!(engine.get(&key)?, expect.get(&key)?),
+                90..=97 => {
+                    let range = random_range(&mut rng);
+                    let reverse = rng.gen_bool(0.5);
+                    let scan = |engine: &mut dyn Engine| -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+                        let scan = engine.scan_dyn(range.clone());
+                        match reverse {
+                            true => scan.rev().collect(),
+                            false => scan.collect(),
+                        }
+                    };
+                    assert_eq!(scan(&mut engine)?, scan(&mut expect)?);
+                }
+                98 => engine.compact()?,
+                _ => {
+                    drop(engine);
+                    engine = BitCask::open(path.clone(), None, mode)?;
+                }
+            }
+        }
+        assert_eq!(
+            engine.scan(..).collect::<Result<Vec<_>>>()?,
+            expect.scan(..).collect::<Result<Vec<_>>>()?
+        );
+        assert_eq!(engine.status()?.keys, expect.status()?.keys);
+        Ok(())
+    }
+
+    /// Tests that random writes to the on-disk keydir keep a logarithmic number
+    /// of sorted runs, and that sorted writes are appended to a single run.
+    #[test]
+    fn disk_keydir_runs() -> Result<()> {
+        use rand::{Rng as _, SeedableRng as _};
+
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let mode = KeyDirMode::Disk { buffer_size: 4, block_size: 4 };
+        let mut engine = BitCask::open(dir.path().join("bitcask"), None, mode)?;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        for i in 1..=4000u32 {
+            let key = rng.gen_range(0..u16::MAX).to_be_bytes();
+            match rng.gen_bool(0.8) {
+                true => engine.set(&key, vec![1])?,
+                false => engine.delete(&key)?,
+            }
+            let KeyDir::Disk(keydir) = &engine.keydir else { panic!("expected disk keydir") };
+            let max_runs = (i as f64 / 4.0).log2().max(0.0) as usize + 2;
+            assert!(keydir.runs.len() <= max_runs, "{} runs after {i} writes", keydir.runs.len());
+        }
+
+        engine.compact()?;
+        let KeyDir::Disk(keydir) = &engine.keydir else { panic!("expected disk keydir") };
+        assert_eq!(keydir.runs.len(), 1);
+        assert_eq!((keydir.runs[0].len + keydir.buffer.len()) as u64, engine.status()?.keys);
+        Ok(())
+    }
+
+    /// Tests that the on-disk keydir's index files are encrypted.
+    #[test]
+    fn disk_keydir_encryption() -> Result<()> {
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let path = dir.path().join("bitcask");
+        let mode = KeyDirMode::Disk { buffer_size: 2, block_size: 2 };
+        let key = EncryptionKey::new([7; 32]);
+        let mut engine = BitCask::open(path.clone(), Some(key.clone()), mode)?;
+        for secret in [&b"secret1"[..], b"secret2", b"secret3", b"secret4"] {
+            engine.set(secret, vec![1])?;
+        }
+        let mut index = Vec::new();
+        for entry in std::fs::read_dir(dir.path().join("bitcask.keydir"))? {
+            index.extend(std::fs::read(entry?.path())?);
+        }
+        assert!(!index.is_empty());
+        assert!(!index.windows(6).any(|w| w == b"secret"));
+        assert_eq!(engine.get(b"secret1")?, Some(vec![1]));
+        Ok(())
+    }
+
     /// A BitCask-specific goldenscript runner, which dispatches through to the
     /// standard Engine runner.
     struct BitCaskRunner {
         inner: Runner<BitCask>,
+        keydir: KeyDirMode,
         tempdir: tempfile::TempDir,
     }
 
@@ -501,6 +2753,17 @@ mod tests {
         fn run(&mut self, command: &goldenscript::Command) -> StdResult<String, Box<dyn StdError>> {
             let mut output = String::new();
             match command.name.as_str() {
+                // block_cache CAPACITY [block_size=BYTES]
+                // Enables the block cache with the given capacity in bytes, or
+                // disables it if 0. The block size defaults to 4.
+                "block_cache" => {
+                    let mut args = command.consume_args();
+                    let capacity = args.next_pos().ok_or("capacity not given")?.parse()?;
+                    let block_size = args.lookup_parse("block_size")?.unwrap_or(4);
+                    args.reject_rest()?;
+                    self.inner.engine.set_block_cache(capacity, block_size)?;
+                }
+
                 // compact
                 // Compacts the BitCask entry log.
                 "compact" => {
@@ -508,6 +2771,20 @@ mod tests {
                     self.inner.engine.compact()?;
                 }
 
+                // compression CODEC [threshold=BYTES]
+                // Sets the value compression codec, or none to disable it,
+                // with a minimum value size threshold (default 0).
+                "compression" => {
+                    let mut args = command.consume_args();
+                    let codec = match args.next_pos().ok_or("codec not given")?.value.as_str() {
+                        "none" => None,
+                        codec => Some(codec.parse()?),
+                    };
+                    let threshold = args.lookup_parse("threshold")?.unwrap_or(0);
+                    args.reject_rest()?;
+                    self.inner.engine.set_compression(codec, threshold);
+                }
+
                 // dump
                 // Dumps the full BitCask entry log.
                 "dump" => {
@@ -515,6 +2792,15 @@ mod tests {
                     self.dump(&mut output)?;
                 }
 
+                // mmap BOOL
+                // Enables or disables mmap reads.
+                "mmap" => {
+                    let mut args = command.consume_args();
+                    let mmap = args.next_pos().ok_or("mmap not given")?.parse()?;
+                    args.reject_rest()?;
+                    self.inner.engine.set_mmap(mmap);
+                }
+
                 // reopen [compact_fraction=FLOAT]
                 // Closes and reopens the BitCask database. If compact_ratio is
                 // given, it specifies a garbage ratio beyond which the log
@@ -526,13 +2812,18 @@ mod tests {
                     // We need to close the file before we can reopen it, which
                     // happens when the database is dropped. Replace the engine
                     // with a temporary empty engine then reopen the file.
+                    // The compression settings are retained.
                     let path = self.inner.engine.log.path.clone();
+                    let compression = self.inner.engine.log.compression;
                     self.inner.engine = BitCask::new(self.tempdir.path().join("empty"))?;
+                    let keydir = self.keydir;
                     if let Some(garbage_fraction) = compact_fraction {
-                        self.inner.engine = BitCask::new_compact(path, garbage_fraction, 0)?;
+                        self.inner.engine =
+                            BitCask::new_compact(path, None, keydir, garbage_fraction, 0)?;
                     } else {
-                        self.inner.engine = BitCask::new(path)?;
+                        self.inner.engine = BitCask::open(path, None, keydir)?;
                     }
+                    self.inner.engine.log.compression = compression;
                 }
 
                 // Pass other commands to the standard engine runner.
@@ -543,11 +2834,12 @@ mod tests {
     }
 
     impl BitCaskRunner {
-        fn new() -> Self {
+        fn new(keydir: KeyDirMode) -> Self {
             let tempdir = tempfile::TempDir::with_prefix("toydb").expect("tempdir failed");
-            let engine = BitCask::new(tempdir.path().join("bitcask")).expect("bitcask failed");
+            let engine = BitCask::open(tempdir.path().join("bitcask"), None, keydir)
+                .expect("bitcask failed");
             let inner = Runner::new(engine);
-            Self { inner, tempdir }
+            Self { inner, keydir, tempdir }
         }
 
         /// Dumps the full BitCask entry log.
@@ -563,42 +2855,119 @@ mod tests {
                 if idx > 0 {
                     writeln!(output, "--------")?;
                 }
-                write!(output, "{:<7}", format!("{idx}@{pos}"))?;
 
                 r.read_exact(&mut len_buf)?;
                 let key_len = u32::from_be_bytes(len_buf);
-                write!(output, " keylen={key_len} [{}]", hex::encode(len_buf))?;
+                if key_len == Log::RANGE_DELETE_MARKER {
+                    // Dump a range delete.
+                    let marker = hex::encode(len_buf);
+                    r.read_exact(&mut len_buf)?;
+                    let len = u32::from_be_bytes(len_buf);
+                    let mut payload = vec![0; len as usize];
+                    r.read_exact(&mut payload)?;
+                    let range = Log::decode_range(&payload, self.inner.engine.log.cipher.as_ref())?;
+                    let label = format!("{idx}@{pos}");
+                    let hexlen = hex::encode(len_buf);
+                    writeln!(output, "{label:<7} range delete [{marker}] len={len} [{hexlen}]")?;
+                    r.read_exact(&mut len_buf)?;
+                    let size = 4 + 4 + len as u64 + 4;
+                    writeln!(
+                        output,
+                        "{:<7} range={} [{}] checksum [{}]",
+                        format!("{size}b"),
+                        format_range::<format::Raw>(&range),
+                        hex::encode(&payload),
+                        hex::encode(len_buf)
+                    )?;
+                    pos += size;
+                    idx += 1;
+                    continue;
+                } else if key_len != Log::BATCH_MARKER {
+                    pos += Self::dump_entry(&mut r, output, format!("{idx}@{pos}"), key_len)?;
+                    idx += 1;
+                    continue;
+                }
 
+                // Dump a write batch, labelling its entries by index within
+                // the batch.
+                let marker = hex::encode(len_buf);
                 r.read_exact(&mut len_buf)?;
-                let value_len_or_tombstone = i32::from_be_bytes(len_buf); // NB: -1 for tombstones
-                let value_len = value_len_or_tombstone.max(0) as u32;
-                writeln!(output, " valuelen={value_len_or_tombstone} [{}]", hex::encode(len_buf))?;
-
-                let mut key = vec![0; key_len as usize];
-                r.read_exact(&mut key)?;
-                let mut value = vec![0; value_len as usize];
-                r.read_exact(&mut value)?;
-                let size = 4 + 4 + key_len as u64 + value_len as u64;
+                let batch_len = u32::from_be_bytes(len_buf);
                 writeln!(
                     output,
-                    "{:<7} key={} [{}] {}",
-                    format!("{size}b"),
-                    format::Raw::key(&key),
-                    hex::encode(key),
-                    match value_len_or_tombstone {
-                        -1 => "tombstone".to_string(),
-                        _ => format!(
-                            "value={} [{}]",
-                            format::Raw::bytes(&value),
-                            hex::encode(&value)
-                        ),
-                    },
+                    "{:<7} batch [{marker}] batchlen={batch_len} [{}]",
+                    format!("{idx}@{pos}"),
+                    hex::encode(len_buf)
                 )?;
+                let (mut entry_pos, mut entry_idx) = (pos + 4 + 4, 0);
+                while entry_pos < pos + 4 + 4 + batch_len as u64 {
+                    r.read_exact(&mut len_buf)?;
+                    let key_len = u32::from_be_bytes(len_buf);
+                    let label = format!("{idx}.{entry_idx}@{entry_pos}");
+                    entry_pos += Self::dump_entry(&mut r, output, label, key_len)?;
+                    entry_idx += 1;
+                }
+                r.read_exact(&mut len_buf)?;
+                let size = 4 + 4 + batch_len as u64 + 4;
+                writeln!(output, "{:<7} checksum [{}]", format!("{size}b"), hex::encode(len_buf))?;
 
                 pos += size;
                 idx += 1;
             }
             Ok(())
         }
+
+        /// Dumps a single log entry with the given label and key length, with
+        /// the reader positioned after the key length. Returns the entry size.
+        fn dump_entry(
+            r: &mut impl Read,
+            output: &mut String,
+            label: String,
+            key_len: u32,
+        ) -> StdResult<u64, Box<dyn StdError>> {
+            let mut len_buf = [0; 4];
+            write!(output, "{label:<7}")?;
+            write!(output, " keylen={key_len} [{}]", hex::encode(key_len.to_be_bytes()))?;
+
+            r.read_exact(&mut len_buf)?;
+            // NB: -1 for tombstones, -len-2 for compressed values.
+            let value_len_or_tombstone = i32::from_be_bytes(len_buf);
+            let value_len = match value_len_or_tombstone {
+                l if l >= 0 => l as u32,
+                -1 => 0,
+                l => (-(l as i64) - 2) as u32,
+            };
+            writeln!(output, " valuelen={value_len_or_tombstone} [{}]", hex::encode(len_buf))?;
+
+            let mut key = vec![0; key_len as usize];
+            r.read_exact(&mut key)?;
+            let mut value = vec![0; value_len as usize];
+            r.read_exact(&mut value)?;
+            let size = 4 + 4 + key_len as u64 + value_len as u64;
+            writeln!(
+                output,
+                "{:<7} key={} [{}] {}",
+                format!("{size}b"),
+                format::Raw::key(&key),
+                hex::encode(key),
+                match value_len_or_tombstone {
+                    -1 => "tombstone".to_string(),
+                    l if l >= 0 => {
+                        format!("value={} [{}]", format::Raw::bytes(&value), hex::encode(&value))
+                    }
+                    _ => {
+                        let codec = Codec::from_id(value[0])?;
+                        let len = u32::from_be_bytes(value[1..5].try_into()?);
+                        let decompressed = codec.decompress(&value[5..], len)?;
+                        format!(
+                            "{codec} len={len} value={} [{}]",
+                            format::Raw::bytes(&decompressed),
+                            hex::encode(&value)
+                        )
+                    }
+                },
+            )?;
+            Ok(size)
+        }
     }
 }
@@ -1,4 +1,5 @@
 use crate::encoding;
+use crate::errinput;
 use crate::error::Result;
 
 use serde::{Deserialize, Serialize};
@@ -16,12 +17,44 @@ pub trait Engine: Send {
     where
         Self: Sized + 'a; // omit in trait objects, for object safety
 
+    /// Takes a point-in-time snapshot of all live data, which can be written
+    /// to a new BitCask file without access to the engine, e.g. to write
+    /// backups in a background thread. By default, the data is copied into
+    /// memory.
+    fn backup(&mut self) -> Result<Backup> {
+        let mut snapshot = super::Memory::new();
+        let mut scan = self.scan_dyn((std::ops::Bound::Unbounded, std::ops::Bound::Unbounded));
+        while let Some((key, value)) = scan.next().transpose()? {
+            snapshot.set(&key, value)?;
+        }
+        drop(scan);
+        Ok(Backup::new(move |path| {
+            super::BitCask::create_from(path.to_path_buf(), &mut snapshot)?;
+            Ok(())
+        }))
+    }
+
     /// Deletes a key, or does nothing if it does not exist.
     fn delete(&mut self, key: &[u8]) -> Result<()>;
 
+    /// Deletes all keys in the given range. Engines should write a single
+    /// range tombstone rather than deleting each key, such that the write cost
+    /// doesn't depend on the number of keys.
+    fn delete_range(
+        &mut self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Result<()>;
+
     /// Flushes any buffered data to the underlying storage medium.
     fn flush(&mut self) -> Result<()>;
 
+    /// Like flush, but always makes the data durable, regardless of any
+    /// relaxed fsync policy (see BitCask's SyncPolicy). Used for writes that
+    /// must survive a crash, like Raft votes.
+    fn sync(&mut self) -> Result<()> {
+        self.flush()
+    }
+
     /// Gets a value for a key, if it exists.
     fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>>;
 
@@ -50,12 +83,120 @@ pub trait Engine: Send {
 
     /// Returns engine status.
     fn status(&mut self) -> Result<Status>;
+
+    /// Atomically applies a batch of writes in order: either all of them are
+    /// applied or none of them are, even if the process crashes midway. Like
+    /// other writes, they are only guaranteed durable after calling flush().
+    fn write_batch(&mut self, ops: Vec<WriteOp>) -> Result<()>;
+}
+
+/// A point-in-time snapshot of an engine, taken by Engine::backup(). It
+/// doesn't borrow the engine, so it can be written out while the engine
+/// continues to serve reads and writes.
+pub struct Backup(Box<BackupWriter>);
+
+/// Writes a backup to the given path.
+type BackupWriter = dyn FnOnce(&std::path::Path) -> Result<()> + Send;
+
+impl Backup {
+    /// Creates a backup from a closure that writes it to the given path.
+    pub fn new(write: impl FnOnce(&std::path::Path) -> Result<()> + Send + 'static) -> Self {
+        Self(Box::new(write))
+    }
+
+    /// Writes the backup to a new file at the given path, which must not
+    /// already exist.
+    pub fn write(self, path: &std::path::Path) -> Result<()> {
+        if path.exists() {
+            return errinput!("file {} already exists", path.display());
+        }
+        (self.0)(path)
+    }
+}
+
+/// A write operation in a write batch. See Engine::write_batch().
+#[derive(Clone, Debug, PartialEq)]
+pub enum WriteOp {
+    /// Deletes a key, or does nothing if it does not exist.
+    Delete { key: Vec<u8> },
+    /// Sets a value for a key, replacing the existing value if any.
+    Set { key: Vec<u8>, value: Vec<u8> },
+}
+
+/// A scan iterator over an ordered key range. It can also be used as a cursor,
+/// repositioning it via seek() and seek_for_prev() without creating a new scan,
+/// e.g. to skip around the keyspace during index range scans and merge joins.
+/// Seeks never go outside of the original scan range (or prefix).
+pub trait ScanIterator: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>> {
+    /// Repositions the iterator at the first key >= key, such that next()
+    /// returns it. Resets the back of the iterator to the end of the scan
+    /// range, regardless of any previous next_back() calls.
+    fn seek(&mut self, key: &[u8]);
+
+    /// Repositions the iterator at the last key <= key, such that next_back()
+    /// returns it. Resets the front of the iterator to the start of the scan
+    /// range, regardless of any previous next() calls.
+    fn seek_for_prev(&mut self, key: &[u8]);
 }
 
-/// A scan iterator, with a blanket implementation (in lieu of trait aliases).
-pub trait ScanIterator: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>> {}
+impl<I: ScanIterator + ?Sized> ScanIterator for Box<I> {
+    fn seek(&mut self, key: &[u8]) {
+        (**self).seek(key)
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        (**self).seek_for_prev(key)
+    }
+}
+
+/// An empty scan iterator, e.g. for trivially empty ranges.
+impl ScanIterator for std::iter::Empty<Result<(Vec<u8>, Vec<u8>)>> {
+    fn seek(&mut self, _: &[u8]) {}
+
+    fn seek_for_prev(&mut self, _: &[u8]) {}
+}
+
+/// An owned key range, as used by scan iterators.
+pub(super) type KeyRange = (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>);
+
+/// Converts a range to an owned key range.
+pub(super) fn key_range(range: impl std::ops::RangeBounds<Vec<u8>>) -> KeyRange {
+    (range.start_bound().cloned(), range.end_bound().cloned())
+}
+
+/// Narrows a key range to keys >= key, for ScanIterator::seek().
+pub(super) fn seek_range(range: &KeyRange, key: &[u8]) -> KeyRange {
+    use std::ops::Bound::{Excluded, Included};
+    let start = match &range.0 {
+        Included(start) | Excluded(start) if start.as_slice() >= key => range.0.clone(),
+        _ => Included(key.to_vec()),
+    };
+    non_empty((start, range.1.clone()))
+}
 
-impl<I: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>>> ScanIterator for I {}
+/// Narrows a key range to keys <= key, for ScanIterator::seek_for_prev().
+pub(super) fn seek_for_prev_range(range: &KeyRange, key: &[u8]) -> KeyRange {
+    use std::ops::Bound::{Excluded, Included};
+    let end = match &range.1 {
+        Included(end) | Excluded(end) if end.as_slice() <= key => range.1.clone(),
+        _ => Included(key.to_vec()),
+    };
+    non_empty((range.0.clone(), end))
+}
+
+/// Replaces an inverted key range with an equivalent empty range, since
+/// BTreeMap::range() panics on inverted ranges.
+pub(super) fn non_empty(range: KeyRange) -> KeyRange {
+    use std::ops::Bound::{Excluded, Included};
+    match range {
+        (Included(start), Included(end)) if start > end => (Excluded(end.clone()), Included(end)),
+        (Included(start) | Excluded(start), Excluded(end)) if start >= end => {
+            (Included(end.clone()), Excluded(end))
+        }
+        (Excluded(start), Included(end)) if start >= end => (Excluded(end.clone()), Included(end)),
+        range => range,
+    }
+}
 
 /// Engine status.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -66,12 +207,18 @@ pub struct Status {
     pub keys: u64,
     /// The logical size of live key/value pairs.
     pub size: u64,
+    /// The size of live key/value pairs as stored, after value compression.
+    pub compressed_size: u64,
     /// The on-disk size of all data, live and garbage.
     pub total_disk_size: u64,
     /// The on-disk size of live data.
     pub live_disk_size: u64,
     /// The on-disk size of garbage data.
     pub garbage_disk_size: u64,
+    /// The number of block cache lookups served from the cache.
+    pub cache_hits: u64,
+    /// The number of block cache lookups that had to read from disk.
+    pub cache_misses: u64,
 }
 
 /// Test helpers for engines.
@@ -79,11 +226,14 @@ pub struct Status {
 pub mod test {
     use super::*;
     use crate::encoding::format::{self, Formatter as _};
+    use crate::error::Error;
     use crossbeam::channel::Sender;
     use regex::Regex;
+    use std::collections::HashSet;
     use std::error::Error as StdError;
     use std::fmt::Write as _;
     use std::result::Result as StdResult;
+    use std::sync::{Arc, Mutex};
 
     /// Goldenscript runner for engines. All engines use a common set of
     /// goldenscripts in src/storage/testscripts/engine, as well as their own
@@ -102,6 +252,44 @@ pub mod test {
         fn run(&mut self, command: &goldenscript::Command) -> StdResult<String, Box<dyn StdError>> {
             let mut output = String::new();
             match command.name.as_str() {
+                // cursor [range=RANGE | prefix=PREFIX] [next | prev | seek=KEY | seek_for_prev=KEY]...
+                // Runs a sequence of cursor operations on a scan, in order.
+                "cursor" => {
+                    let mut args = command.consume_args();
+                    let range = args.lookup("range").map(|a| a.value.clone());
+                    let prefix = args.lookup("prefix").map(|a| decode_binary(&a.value));
+                    let mut scan = match (range, prefix) {
+                        (None, Some(prefix)) => self.engine.scan_prefix(&prefix),
+                        (range, None) => {
+                            self.engine.scan(parse_key_range(range.as_deref().unwrap_or(".."))?)
+                        }
+                        (Some(_), Some(_)) => return Err("can't use both range and prefix".into()),
+                    };
+                    for arg in args.rest() {
+                        let item = match (arg.key.as_deref(), arg.value.as_str()) {
+                            (None, "next") => scan.next(),
+                            (None, "prev") => scan.next_back(),
+                            (Some("seek"), key) => {
+                                scan.seek(&decode_binary(key));
+                                continue;
+                            }
+                            (Some("seek_for_prev"), key) => {
+                                scan.seek_for_prev(&decode_binary(key));
+                                continue;
+                            }
+                            _ => {
+                                return Err(format!("invalid cursor operation {}", arg.value).into())
+                            }
+                        };
+                        match item.transpose()? {
+                            Some((key, value)) => {
+                                writeln!(output, "{}", format::Raw::key_value(&key, &value))?
+                            }
+                            None => writeln!(output, "None")?,
+                        }
+                    }
+                }
+
                 // delete KEY
                 "delete" => {
                     let mut args = command.consume_args();
@@ -110,6 +298,14 @@ pub mod test {
                     self.engine.delete(&key)?;
                 }
 
+                // delete_range RANGE
+                "delete_range" => {
+                    let mut args = command.consume_args();
+                    let range = parse_key_range(&args.next_pos().ok_or("range not given")?.value)?;
+                    args.reject_rest()?;
+                    self.engine.delete_range(key_range(range))?;
+                }
+
                 // get KEY
                 "get" => {
                     let mut args = command.consume_args();
@@ -163,6 +359,24 @@ pub mod test {
                     writeln!(output, "{:#?}", self.engine.status()?)?;
                 }
 
+                // write_batch [KEY=VALUE | KEY]...
+                // Sets KEY=VALUE pairs and deletes bare KEYs in a single batch.
+                "write_batch" => {
+                    let mut args = command.consume_args();
+                    let ops = args
+                        .rest()
+                        .into_iter()
+                        .map(|arg| match &arg.key {
+                            Some(key) => WriteOp::Set {
+                                key: decode_binary(key),
+                                value: decode_binary(&arg.value),
+                            },
+                            None => WriteOp::Delete { key: decode_binary(&arg.value) },
+                        })
+                        .collect();
+                    self.engine.write_batch(ops)?;
+                }
+
                 name => return Err(format!("invalid command {name}").into()),
             }
             Ok(output)
@@ -211,6 +425,23 @@ pub mod test {
         Ok(bound)
     }
 
+    /// Formats a key range using the given key formatter, in interval
+    /// notation, e.g. [a, b) or [a, ..).
+    pub fn format_range<F: format::Formatter>(range: &KeyRange) -> String {
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+        let start = match &range.0 {
+            Included(key) => format!("[{}", F::key(key)),
+            Excluded(key) => format!("({}", F::key(key)),
+            Unbounded => "(..".to_string(),
+        };
+        let end = match &range.1 {
+            Included(key) => format!("{}]", F::key(key)),
+            Excluded(key) => format!("{})", F::key(key)),
+            Unbounded => "..)".to_string(),
+        };
+        format!("{start}, {end}")
+    }
+
     /// Wraps another engine and emits write events to the given channel.
     pub struct Emit<E: Engine> {
         /// The wrapped engine.
@@ -219,9 +450,11 @@ pub mod test {
         tx: Sender<Operation>,
     }
 
-    /// An engine operation emitted by the Emit engine.
+    /// An engine operation emitted by the Emit engine. Write batches are
+    /// emitted as their individual operations.
     pub enum Operation {
         Delete { key: Vec<u8> },
+        DeleteRange { range: KeyRange },
         Flush,
         Set { key: Vec<u8>, value: Vec<u8> },
     }
@@ -247,6 +480,15 @@ pub mod test {
             Ok(())
         }
 
+        fn delete_range(
+            &mut self,
+            range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+        ) -> Result<()> {
+            self.inner.delete_range(range.clone())?;
+            self.tx.send(Operation::DeleteRange { range })?;
+            Ok(())
+        }
+
         fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
             self.inner.get(key)
         }
@@ -271,6 +513,17 @@ pub mod test {
         fn status(&mut self) -> Result<Status> {
             self.inner.status()
         }
+
+        fn write_batch(&mut self, ops: Vec<WriteOp>) -> Result<()> {
+            self.inner.write_batch(ops.clone())?;
+            for op in ops {
+                match op {
+                    WriteOp::Delete { key } => self.tx.send(Operation::Delete { key })?,
+                    WriteOp::Set { key, value } => self.tx.send(Operation::Set { key, value })?,
+                }
+            }
+            Ok(())
+        }
     }
 
     /// An engine that wraps two others and mirrors operations across them,
@@ -299,6 +552,14 @@ pub mod test {
             self.b.delete(key)
         }
 
+        fn delete_range(
+            &mut self,
+            range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+        ) -> Result<()> {
+            self.a.delete_range(range.clone())?;
+            self.b.delete_range(range)
+        }
+
         fn flush(&mut self) -> Result<()> {
             self.a.flush()?;
             self.b.flush()
@@ -342,6 +603,11 @@ pub mod test {
             assert_eq!(a.size, b.size);
             Ok(a)
         }
+
+        fn write_batch(&mut self, ops: Vec<WriteOp>) -> Result<()> {
+            self.a.write_batch(ops.clone())?;
+            self.b.write_batch(ops)
+        }
     }
 
     pub struct MirrorIterator<'a, A: Engine + 'a, B: Engine + 'a> {
@@ -368,4 +634,298 @@ pub mod test {
             a
         }
     }
+
+    impl<'a, A: Engine, B: Engine> ScanIterator for MirrorIterator<'a, A, B> {
+        fn seek(&mut self, key: &[u8]) {
+            self.a.seek(key);
+            self.b.seek(key);
+        }
+
+        fn seek_for_prev(&mut self, key: &[u8]) {
+            self.a.seek_for_prev(key);
+            self.b.seek_for_prev(key);
+        }
+    }
+
+    /// An engine that wraps another and injects faults, for testing error
+    /// handling and crash recovery. It can fail operations with IO errors,
+    /// drop unflushed writes on a simulated crash, and corrupt stored values.
+    ///
+    /// Clones share the same underlying engine and faults, such that a test can
+    /// keep a handle to inject faults while e.g. a Raft log owns the engine,
+    /// and reopen the engine from the handle after a crash.
+    pub struct Faulty<E: Engine> {
+        shared: Arc<Mutex<FaultyShared<E>>>,
+    }
+
+    struct FaultyShared<E: Engine> {
+        /// The wrapped engine.
+        inner: E,
+        /// Operations that fail with IO errors.
+        failing: HashSet<String>,
+        /// Unflushed writes, as the key and previous value, in write order.
+        unflushed: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    }
+
+    impl<E: Engine> Clone for Faulty<E> {
+        fn clone(&self) -> Self {
+            Self { shared: self.shared.clone() }
+        }
+    }
+
+    impl<E: Engine> Faulty<E> {
+        /// Operations that can be failed via fail().
+        const OPS: [&'static str; 7] =
+            ["delete", "delete_range", "flush", "get", "scan", "set", "write_batch"];
+
+        pub fn new(inner: E) -> Self {
+            let shared = FaultyShared { inner, failing: HashSet::new(), unflushed: Vec::new() };
+            Self { shared: Arc::new(Mutex::new(shared)) }
+        }
+
+        /// Fails the given operations with IO errors until further notice,
+        /// replacing any previous faults. An empty slice clears all faults.
+        pub fn fail(&self, ops: &[&str]) -> Result<()> {
+            if let Some(op) = ops.iter().find(|op| !Self::OPS.contains(op)) {
+                return errinput!("unknown operation {op}");
+            }
+            self.lock().failing = ops.iter().map(|op| op.to_string()).collect();
+            Ok(())
+        }
+
+        /// Simulates a crash by undoing all writes since the last flush, and
+        /// clearing all faults. The engine can then be reopened from a clone.
+        pub fn crash(&self) -> Result<()> {
+            let mut shared = self.lock();
+            shared.failing.clear();
+            while let Some((key, value)) = shared.unflushed.pop() {
+                match value {
+                    Some(value) => shared.inner.set(&key, value)?,
+                    None => shared.inner.delete(&key)?,
+                }
+            }
+            shared.inner.flush()
+        }
+
+        /// Corrupts the value of the given key by flipping all bits in its
+        /// last byte, bypassing faults and crash tracking.
+        pub fn corrupt(&self, key: &[u8]) -> Result<()> {
+            let mut shared = self.lock();
+            let Some(mut value) = shared.inner.get(key)?.filter(|v| !v.is_empty()) else {
+                return errinput!("no value to corrupt");
+            };
+            *value.last_mut().expect("empty value") ^= 0xff;
+            shared.inner.set(key, value)
+        }
+
+        /// Runs a fault injection goldenscript command:
+        ///
+        /// - crash: drops unflushed writes, see crash().
+        /// - corrupt KEY: corrupts a value, see corrupt().
+        /// - fail [OP...]: fails operations with IO errors, see fail().
+        pub fn run(&self, command: &goldenscript::Command) -> StdResult<(), Box<dyn StdError>> {
+            let mut args = command.consume_args();
+            match command.name.as_str() {
+                "crash" => self.crash()?,
+                "corrupt" => {
+                    let key = decode_binary(&args.next_pos().ok_or("key not given")?.value);
+                    self.corrupt(&key)?
+                }
+                "fail" => {
+                    let ops: Vec<_> = args.rest_pos().iter().map(|a| a.value.as_str()).collect();
+                    self.fail(&ops)?
+                }
+                name => return Err(format!("invalid fault command {name}").into()),
+            }
+            args.reject_rest()?;
+            Ok(())
+        }
+
+        /// Locks the shared state. Ignores mutex poisoning, since tests may
+        /// expect panics in the inner engine (e.g. for invalid scan ranges).
+        fn lock(&self) -> std::sync::MutexGuard<'_, FaultyShared<E>> {
+            self.shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+    }
+
+    // Run Faulty goldenscript tests in src/storage/testscripts/faulty.
+    test_each_file::test_each_path! { in "src/storage/testscripts/faulty" as faulty => test_faulty }
+
+    fn test_faulty(path: &std::path::Path) {
+        let faulty = Faulty::new(super::super::Memory::new());
+        let mut runner = FaultyRunner { inner: Runner::new(faulty.clone()), faulty };
+        goldenscript::run(&mut runner, path).expect("goldenscript failed")
+    }
+
+    /// A goldenscript runner for a Faulty Memory engine, which dispatches
+    /// through to the standard Engine runner.
+    struct FaultyRunner {
+        inner: Runner<Faulty<super::super::Memory>>,
+        faulty: Faulty<super::super::Memory>,
+    }
+
+    impl goldenscript::Runner for FaultyRunner {
+        fn run(&mut self, command: &goldenscript::Command) -> StdResult<String, Box<dyn StdError>> {
+            match command.name.as_str() {
+                // crash | corrupt KEY | fail [OP...]
+                "crash" | "corrupt" | "fail" => self.faulty.run(command)?,
+
+                // flush
+                "flush" => {
+                    command.consume_args().reject_rest()?;
+                    self.inner.engine.flush()?;
+                }
+
+                // Pass other commands to the standard engine runner.
+                _ => return self.inner.run(command),
+            }
+            Ok(String::new())
+        }
+    }
+
+    impl<E: Engine> FaultyShared<E> {
+        /// Returns an IO error if the operation is failing.
+        fn check(&self, op: &str) -> Result<()> {
+            if self.failing.contains(op) {
+                return Err(Error::IO(format!("injected {op} fault")));
+            }
+            Ok(())
+        }
+
+        /// Records the current value of a key before a write, for crash().
+        fn track(&mut self, key: &[u8]) -> Result<()> {
+            let value = self.inner.get(key)?;
+            self.unflushed.push((key.to_vec(), value));
+            Ok(())
+        }
+    }
+
+    impl<E: Engine> Engine for Faulty<E> {
+        type ScanIterator<'a>
+            = FaultyIterator
+        where
+            E: 'a;
+
+        fn delete(&mut self, key: &[u8]) -> Result<()> {
+            let mut shared = self.lock();
+            shared.check("delete")?;
+            shared.track(key)?;
+            shared.inner.delete(key)
+        }
+
+        fn delete_range(
+            &mut self,
+            range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+        ) -> Result<()> {
+            let mut shared = self.lock();
+            shared.check("delete_range")?;
+            let keys: Vec<_> = shared
+                .inner
+                .scan(range.clone())
+                .map(|r| r.map(|(k, _)| k))
+                .collect::<Result<_>>()?;
+            for key in keys {
+                shared.track(&key)?;
+            }
+            shared.inner.delete_range(range)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            let mut shared = self.lock();
+            shared.check("flush")?;
+            shared.inner.flush()?;
+            shared.unflushed.clear();
+            Ok(())
+        }
+
+        fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+            let mut shared = self.lock();
+            shared.check("get")?;
+            shared.inner.get(key)
+        }
+
+        /// Buffers the entire scan, since the iterator can't borrow the engine
+        /// through the mutex.
+        fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
+            let mut shared = self.lock();
+            let items = match shared.check("scan") {
+                Ok(()) => shared.inner.scan(range).collect(),
+                Err(err) => vec![Err(err)],
+            };
+            FaultyIterator { front: 0, back: items.len(), items }
+        }
+
+        fn scan_dyn(
+            &mut self,
+            range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+        ) -> Box<dyn ScanIterator + '_> {
+            Box::new(self.scan(range))
+        }
+
+        fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+            let mut shared = self.lock();
+            shared.check("set")?;
+            shared.track(key)?;
+            shared.inner.set(key, value)
+        }
+
+        fn status(&mut self) -> Result<Status> {
+            self.lock().inner.status()
+        }
+
+        fn write_batch(&mut self, ops: Vec<WriteOp>) -> Result<()> {
+            let mut shared = self.lock();
+            shared.check("write_batch")?;
+            for op in &ops {
+                match op {
+                    WriteOp::Delete { key } | WriteOp::Set { key, .. } => shared.track(key)?,
+                }
+            }
+            shared.inner.write_batch(ops)
+        }
+    }
+
+    /// A buffered Faulty scan, see Faulty::scan().
+    pub struct FaultyIterator {
+        items: Vec<Result<(Vec<u8>, Vec<u8>)>>,
+        /// The remaining items are items[front..back].
+        front: usize,
+        back: usize,
+    }
+
+    impl Iterator for FaultyIterator {
+        type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.front >= self.back {
+                return None;
+            }
+            self.front += 1;
+            Some(self.items[self.front - 1].clone())
+        }
+    }
+
+    impl DoubleEndedIterator for FaultyIterator {
+        fn next_back(&mut self) -> Option<Self::Item> {
+            if self.front >= self.back {
+                return None;
+            }
+            self.back -= 1;
+            Some(self.items[self.back].clone())
+        }
+    }
+
+    impl ScanIterator for FaultyIterator {
+        fn seek(&mut self, key: &[u8]) {
+            self.front =
+                self.items.partition_point(|r| matches!(r, Ok((k, _)) if k.as_slice() < key));
+            self.back = self.items.len();
+        }
+
+        fn seek_for_prev(&mut self, key: &[u8]) {
+            self.front = 0;
+            self.back =
+                self.items.partition_point(|r| !matches!(r, Ok((k, _)) if k.as_slice() > key));
+        }
+    }
 }
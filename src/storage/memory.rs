@@ -1,4 +1,5 @@
-use super::{Engine, Status};
+use super::engine::{key_range, seek_for_prev_range, seek_range, KeyRange};
+use super::{Engine, Status, WriteOp};
 use crate::error::Result;
 
 /// An in-memory key/value storage engine using the Rust standard library B-tree
@@ -26,12 +27,24 @@ impl Engine for Memory {
         Ok(())
     }
 
+    fn delete_range(
+        &mut self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Result<()> {
+        let keys: Vec<_> = self.data.range(range).map(|(key, _)| key.clone()).collect();
+        for key in keys {
+            self.data.remove(&key);
+        }
+        Ok(())
+    }
+
     fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         Ok(self.data.get(key).cloned())
     }
 
     fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
-        ScanIterator { inner: self.data.range(range) }
+        let range = key_range(range);
+        ScanIterator { inner: self.data.range(range.clone()), data: &self.data, range }
     }
 
     fn scan_dyn(
@@ -47,19 +60,37 @@ impl Engine for Memory {
     }
 
     fn status(&mut self) -> Result<Status> {
+        let size = self.data.iter().fold(0, |size, (k, v)| size + k.len() as u64 + v.len() as u64);
         Ok(Status {
             name: "memory".to_string(),
             keys: self.data.len() as u64,
-            size: self.data.iter().fold(0, |size, (k, v)| size + k.len() as u64 + v.len() as u64),
+            size,
+            compressed_size: size,
             total_disk_size: 0,
             live_disk_size: 0,
             garbage_disk_size: 0,
+            cache_hits: 0,
+            cache_misses: 0,
         })
     }
+
+    fn write_batch(&mut self, ops: Vec<WriteOp>) -> Result<()> {
+        // Memory writes can't fail midway, so applying them in order is atomic.
+        for op in ops {
+            match op {
+                WriteOp::Delete { key } => self.data.remove(&key),
+                WriteOp::Set { key, value } => self.data.insert(key, value),
+            };
+        }
+        Ok(())
+    }
 }
 
 pub struct ScanIterator<'a> {
     inner: std::collections::btree_map::Range<'a, Vec<u8>, Vec<u8>>,
+    /// The data and scan range, for seeks.
+    data: &'a std::collections::BTreeMap<Vec<u8>, Vec<u8>>,
+    range: KeyRange,
 }
 
 impl<'a> ScanIterator<'a> {
@@ -83,6 +114,16 @@ impl<'a> DoubleEndedIterator for ScanIterator<'a> {
     }
 }
 
+impl<'a> super::ScanIterator for ScanIterator<'a> {
+    fn seek(&mut self, key: &[u8]) {
+        self.inner = self.data.range(seek_range(&self.range, key));
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        self.inner = self.data.range(seek_for_prev_range(&self.range, key));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::engine::test::Runner;
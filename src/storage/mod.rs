@@ -3,6 +3,6 @@ pub mod engine;
 mod memory;
 pub mod mvcc;
 
-pub use bitcask::BitCask;
-pub use engine::{Engine, ScanIterator, Status};
+pub use bitcask::{BitCask, Codec, EncryptionKey, KeyDirMode, SyncPolicy};
+pub use engine::{Backup, Engine, ScanIterator, Status, WriteOp};
 pub use memory::Memory;
@@ -130,6 +130,59 @@
 //! current active set, storing the snapshot in memory only. Read-only queries
 //! do not increment the version sequence number in Key::NextVersion.
 //!
+//! CHANGE DATA CAPTURE
+//! ===================
+//!
+//! When the change feed is enabled (see MVCC::set_change_feed), the
+//! Key::TxnWrite(version, key) records are retained when a transaction commits,
+//! which gives us an ordered log of the keys written by each committed version. MVCC::changes() uses this to emit a change feed of
+//! (version, key, old value, new value) events, where the old value is the
+//! latest version of the key below the change's version. This allows
+//! downstream consumers to follow writes without scanning the entire dataset,
+//! using a ChangeCursor (a version and the last emitted key within it) to
+//! resume the feed.
+//!
+//! A transaction with a lower version may commit after a transaction with a
+//! higher version, so the change feed only emits versions below the oldest
+//! active transaction. This guarantees that a cursor never skips past a version
+//! that later commits.
+//!
+//! ROW LOCKS
+//! =========
+//!
+//! Snapshot isolation only detects write/write conflicts. To guard against e.g.
+//! write skew, a read-write transaction can explicitly lock a key that it has
+//! only read (e.g. SQL SELECT ... FOR UPDATE). Locks are either shared or
+//! exclusive: shared locks are compatible with each other, but exclusive locks
+//! conflict with all other locks. Locks also conflict with writes by other
+//! transactions, and taking a lock conflicts with any newer or uncommitted
+//! version of the key, just like a write would.
+//!
+//! Locks are stored as Key::Lock(key, version) → LockMode, along with a
+//! Key::TxnLock(version, key) record to find and release them when the
+//! transaction commits or rolls back. ToyDB never blocks on conflicts: like
+//! write conflicts, lock conflicts immediately return a serialization error,
+//! and the caller must retry.
+//!
+//! RANGE DELETES
+//! =============
+//!
+//! Deleting a large key range (e.g. SQL DROP TABLE) would normally write a
+//! tombstone version for every key in the range. Instead, Transaction::
+//! delete_range() writes a single Key::RangeTombstone(start, version) record
+//! with the range's end bound as the value, which deletes all versions of keys
+//! in the range below its version. Reads skip versions that are covered by a
+//! visible range tombstone, and writes conflict with invisible range
+//! tombstones covering the key, just like with regular versions. Keys written
+//! after the tombstone (even by the same transaction) are not affected.
+//!
+//! Range tombstones are keyed by start key, so reads only scan tombstones that
+//! start before the end of the read range. The range delete itself checks for
+//! write conflicts in batches, releasing the engine mutex in between.
+//!
+//! Range tombstones are kept forever, like all other versions, since they are
+//! needed for time-travel queries and the change feed.
+//!
 //! GARBAGE COLLECTION
 //! ==================
 //!
@@ -137,9 +190,12 @@
 //! no longer needed by active transactions or time-travel queries. However,
 //! ToyDB does not implement garbage collection, instead keeping all history
 //! forever, both out of laziness and also because it allows unlimited time
-//! travel queries (it's a feature, not a bug!).
+//! travel queries (it's a feature, not a bug!). A garbage collector could
+//! remove range tombstones along with the keys they delete, using
+//! Engine::delete_range(), once they are visible to all transactions.
 
-use super::engine::Engine;
+use super::engine::{seek_for_prev_range, seek_range, Engine, WriteOp};
+use crate::encoding::format::{self, Formatter as _};
 use crate::encoding::{self, bincode, Key as _, Value as _};
 use crate::error::{Error, Result};
 use crate::{errdata, errinput};
@@ -168,8 +224,9 @@ pub enum Key<'a> {
     /// A snapshot of the active set at each version. Only written for
     /// versions where the active set is non-empty (excluding itself).
     TxnActiveSnapshot(Version),
-    /// Keeps track of all keys written to by an active transaction (identified
-    /// by its version), in case it needs to roll back.
+    /// Keeps track of all keys written to by a transaction (identified by its
+    /// version), in case it needs to roll back. Retained after commit if the
+    /// change feed is enabled, as a log of committed writes.
     TxnWrite(
         Version,
         #[serde(with = "serde_bytes")]
@@ -192,6 +249,31 @@ pub enum Key<'a> {
         #[serde(borrow)]
         Cow<'a, [u8]>,
     ),
+    /// Keeps track of all keys locked by an active transaction (identified by
+    /// its version), to release them when it commits or rolls back.
+    TxnLock(
+        Version,
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+    ),
+    /// A key lock held by an active transaction, with a LockMode value.
+    Lock(
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+        Version,
+    ),
+    /// A range tombstone keyed by the inclusive start key of the range and the
+    /// version of the transaction that wrote it, with the range's end bound as
+    /// the value. Deletes all versions of keys in the range that are below the
+    /// tombstone's version.
+    RangeTombstone(
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+        Version,
+    ),
 }
 
 impl<'a> encoding::Key<'a> for Key<'a> {}
@@ -210,10 +292,150 @@ enum KeyPrefix<'a> {
         Cow<'a, [u8]>,
     ),
     Unversioned,
+    TxnLock(Version),
+    Lock(
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+    ),
+    RangeTombstone,
 }
 
 impl<'a> encoding::Key<'a> for KeyPrefix<'a> {}
 
+/// A key lock mode. Shared locks are compatible with other shared locks, while
+/// exclusive locks conflict with any other lock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum LockMode {
+    /// A shared lock, e.g. SELECT ... FOR SHARE.
+    Shared,
+    /// An exclusive lock, e.g. SELECT ... FOR UPDATE.
+    Exclusive,
+}
+
+impl encoding::Value for LockMode {}
+
+impl LockMode {
+    /// Returns true if the lock modes conflict with each other.
+    fn conflicts(&self, other: &LockMode) -> bool {
+        *self == LockMode::Exclusive || *other == LockMode::Exclusive
+    }
+}
+
+/// Returns the inclusive start key of a key range's start bound.
+fn inclusive_start(bound: Bound<&Vec<u8>>) -> Vec<u8> {
+    match bound {
+        Bound::Included(k) => k.clone(),
+        Bound::Excluded(k) => [k.as_slice(), &[0]].concat(),
+        Bound::Unbounded => vec![],
+    }
+}
+
+/// A decoded Key::RangeTombstone, see Transaction::delete_range().
+#[derive(Clone, Debug, PartialEq)]
+struct RangeTombstone {
+    /// The version of the transaction that wrote the tombstone.
+    version: Version,
+    /// The inclusive start of the deleted key range.
+    start: Vec<u8>,
+    /// The end of the deleted key range.
+    end: Bound<Vec<u8>>,
+}
+
+impl RangeTombstone {
+    /// Decodes a range tombstone from a raw engine key/value pair.
+    fn decode(key: &[u8], value: &[u8]) -> Result<Self> {
+        match Key::decode(key)? {
+            Key::RangeTombstone(start, version) => {
+                Ok(Self { version, start: start.into_owned(), end: bincode::deserialize(value)? })
+            }
+            key => errdata!("expected Key::RangeTombstone got {key:?}"),
+        }
+    }
+
+    /// Returns true if the key is in the tombstone's range.
+    fn contains(&self, key: &[u8]) -> bool {
+        key >= self.start.as_slice()
+            && match &self.end {
+                Bound::Included(end) => key <= end.as_slice(),
+                Bound::Excluded(end) => key < end.as_slice(),
+                Bound::Unbounded => true,
+            }
+    }
+
+    /// Returns true if the tombstone deletes the given version of the key.
+    fn deletes(&self, key: &[u8], version: Version) -> bool {
+        version < self.version && self.contains(key)
+    }
+
+    /// Returns true if the tombstone's range overlaps the given key range,
+    /// with an inclusive start key.
+    fn overlaps(&self, start: &[u8], end: Bound<&[u8]>) -> bool {
+        self.contains(start)
+            || self.start.as_slice() >= start
+                && match end {
+                    Bound::Included(end) => self.start.as_slice() <= end,
+                    Bound::Excluded(end) => self.start.as_slice() < end,
+                    Bound::Unbounded => true,
+                }
+    }
+
+    /// Returns the user key range deleted by the tombstone.
+    fn range(&self) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+        (Bound::Included(self.start.clone()), self.end.clone())
+    }
+
+    /// Extends the tombstone's end bound to cover the other's end bound.
+    fn extend(&mut self, end: Bound<Vec<u8>>) {
+        let wider = match (&self.end, &end) {
+            (Bound::Unbounded, _) => false,
+            (_, Bound::Unbounded) => true,
+            (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b))
+                if a != b =>
+            {
+                b > a
+            }
+            (Bound::Excluded(_), Bound::Included(_)) => true,
+            _ => false,
+        };
+        if wider {
+            self.end = end;
+        }
+    }
+
+    /// Scans range tombstones (visible or not) that overlap the given key
+    /// range, with an inclusive start key. Tombstones are keyed by start key,
+    /// so this only scans tombstones that start before the range ends.
+    fn scan<E: Engine>(engine: &mut E, start: &[u8], end: Bound<&[u8]>) -> Result<Vec<Self>> {
+        let prefix = KeyPrefix::RangeTombstone.encode();
+        let scan_end = match end {
+            Bound::Included(end) | Bound::Excluded(end) => {
+                Bound::Included(Key::RangeTombstone(end.into(), Version::MAX).encode())
+            }
+            Bound::Unbounded => encoding::prefix_range(&prefix).1,
+        };
+        let mut scan = engine.scan((Bound::Included(prefix), scan_end));
+        let mut tombstones = Vec::new();
+        while let Some((key, value)) = scan.next().transpose()? {
+            let tombstone = Self::decode(&key, &value)?;
+            if tombstone.overlaps(start, end) {
+                tombstones.push(tombstone);
+            }
+        }
+        Ok(tombstones)
+    }
+
+    /// Scans all range tombstones, visible or not.
+    fn scan_all<E: Engine>(engine: &mut E) -> Result<Vec<Self>> {
+        Self::scan(engine, &[], Bound::Unbounded)
+    }
+
+    /// Returns the tombstone's key.
+    fn key(&self) -> RawKey {
+        Key::RangeTombstone((&self.start).into(), self.version).encode()
+    }
+}
+
 /// An MVCC-based transactional key-value engine. It wraps an underlying storage
 /// engine that's used for raw key/value storage.
 ///
@@ -225,17 +447,27 @@ impl<'a> encoding::Key<'a> for KeyPrefix<'a> {}
 /// serialize them anyway.
 pub struct MVCC<E: Engine> {
     pub(crate) engine: Arc<Mutex<E>>,
+    /// Whether to retain TxnWrite records for the change feed.
+    change_feed: bool,
 }
 
 impl<E: Engine> MVCC<E> {
     /// Creates a new MVCC engine with the given storage engine.
     pub fn new(engine: E) -> Self {
-        Self { engine: Arc::new(Mutex::new(engine)) }
+        Self { engine: Arc::new(Mutex::new(engine)), change_feed: false }
+    }
+
+    /// Enables or disables the change feed (see MVCC::changes). When enabled,
+    /// transactions that begin afterwards retain their TxnWrite records when
+    /// they commit, so storage grows with every committed write. Disabled by
+    /// default. Changes committed while disabled are not emitted.
+    pub fn set_change_feed(&mut self, enabled: bool) {
+        self.change_feed = enabled;
     }
 
     /// Begins a new read-write transaction.
     pub fn begin(&self) -> Result<Transaction<E>> {
-        Transaction::begin(self.engine.clone())
+        Transaction::begin(self.engine.clone(), self.change_feed)
     }
 
     /// Begins a new read-only transaction at the latest version.
@@ -250,7 +482,7 @@ impl<E: Engine> MVCC<E> {
 
     /// Resumes a transaction from the given transaction state.
     pub fn resume(&self, state: TransactionState) -> Result<Transaction<E>> {
-        Transaction::resume(self.engine.clone(), state)
+        Transaction::resume(self.engine.clone(), state, self.change_feed)
     }
 
     /// Fetches the value of an unversioned key.
@@ -263,6 +495,213 @@ impl<E: Engine> MVCC<E> {
         self.engine.lock()?.set(&Key::Unversioned(key.into()).encode(), value)
     }
 
+    /// Rewrites all versioned keys and values in place, e.g. to upgrade the
+    /// stored data format of an older database. The function is called with
+    /// each version of each key, the version, and its value (None for
+    /// deletions), and returns the new key and value. Transaction write and
+    /// lock records are rekeyed by calling it with the transaction's version
+    /// and a None value, so the new key must not depend on the value. All
+    /// changes are written in a single atomic write batch, along with the given
+    /// unversioned key/value pair (e.g. a format version), such that a crash
+    /// can't leave a partially rewritten database.
+    ///
+    /// Must not be called while transactions are running. Errors if there are
+    /// any range tombstones, since their key ranges can't be rewritten.
+    pub fn rewrite(
+        &self,
+        mut f: impl FnMut(&[u8], Version, Option<&[u8]>) -> Result<(Vec<u8>, Option<Vec<u8>>)>,
+        unversioned: (&[u8], Vec<u8>),
+    ) -> Result<()> {
+        let mut engine = self.engine.lock()?;
+        // Write all deletes before sets, in case a new key is an old key.
+        let (mut deletes, mut sets) = (Vec::new(), Vec::new());
+        let mut scan = engine.scan(..);
+        while let Some((raw, value)) = scan.next().transpose()? {
+            let (key, new_value) = match Key::decode(&raw)? {
+                Key::Version(key, version) => {
+                    let value: Option<Vec<u8>> = bincode::deserialize(&value)?;
+                    let (key, value) = f(&key, version, value.as_deref())?;
+                    (Key::Version(key.into(), version), bincode::serialize(&value))
+                }
+                // Write and lock records have the same value for the new key.
+                Key::TxnWrite(version, key) => {
+                    (Key::TxnWrite(version, f(&key, version, None)?.0.into()), value.clone())
+                }
+                Key::TxnLock(version, key) => {
+                    (Key::TxnLock(version, f(&key, version, None)?.0.into()), value.clone())
+                }
+                Key::Lock(key, version) => {
+                    (Key::Lock(f(&key, version, None)?.0.into(), version), value.clone())
+                }
+                Key::RangeTombstone(..) => return errdata!("can't rewrite range tombstones"),
+                Key::NextVersion
+                | Key::TxnActive(_)
+                | Key::TxnActiveSnapshot(_)
+                | Key::Unversioned(_) => continue,
+            };
+            let key = key.encode();
+            if key != raw {
+                deletes.push(WriteOp::Delete { key: raw });
+            } else if new_value == value {
+                continue;
+            }
+            sets.push(WriteOp::Set { key, value: new_value });
+        }
+        drop(scan);
+        let (key, value) = unversioned;
+        sets.push(WriteOp::Set { key: Key::Unversioned(key.into()).encode(), value });
+        deletes.extend(sets);
+        engine.write_batch(deletes)
+    }
+
+    /// Returns the change feed of committed writes, starting at the given
+    /// cursor, ordered by version and key. Only versions below the oldest
+    /// active transaction are emitted, since lower versions may still commit.
+    /// Range deletes emit a delete change for each live key in the range.
+    /// Returns at most limit changes, along with the cursor to resume the feed
+    /// from, which may point into the middle of a version.
+    pub fn changes(&self, from: ChangeCursor, limit: usize) -> Result<(Vec<Change>, ChangeCursor)> {
+        if !self.change_feed {
+            return errinput!("change feed is not enabled");
+        }
+        let mut engine = self.engine.lock()?;
+        let next_version = match engine.get(&Key::NextVersion.encode())? {
+            Some(ref v) => Version::decode(v)?,
+            None => 1,
+        };
+        let active = Transaction::scan_active(&mut engine)?;
+        let horizon = active.first().copied().unwrap_or(next_version);
+        if from.version >= horizon {
+            return Ok((Vec::new(), from));
+        }
+
+        // Old values must account for earlier range tombstones.
+        let mut tombstones = RangeTombstone::scan_all(&mut *engine)?;
+        tombstones.retain(|t| t.version < horizon);
+
+        let mut changes = Vec::new();
+        let mut cursor = from;
+        while changes.len() < limit {
+            // Find the next version with writes or range tombstones.
+            let range =
+                KeyPrefix::TxnWrite(cursor.version).encode()..KeyPrefix::TxnWrite(horizon).encode();
+            let next_write = match engine.scan(range).next().transpose()? {
+                Some((key, _)) => match Key::decode(&key)? {
+                    Key::TxnWrite(version, _) => Some(version),
+                    key => return errdata!("expected TxnWrite, got {key:?}"),
+                },
+                None => None,
+            };
+            let next_tombstone =
+                tombstones.iter().map(|t| t.version).filter(|v| *v >= cursor.version).min();
+            let Some(version) = next_write.into_iter().chain(next_tombstone).min() else {
+                cursor = ChangeCursor { version: horizon, after: None };
+                break;
+            };
+            if version > cursor.version {
+                cursor = ChangeCursor { version, after: None };
+            }
+
+            // Find the next keys written or range deleted at this version,
+            // after the cursor. Fetch at most the remaining number of keys
+            // from each source.
+            let remaining = limit - changes.len();
+            let after = cursor.after.clone();
+            let prefix = KeyPrefix::TxnWrite(version).encode();
+            let start = match &after {
+                Some(key) => Bound::Excluded(Key::TxnWrite(version, key.into()).encode()),
+                None => Bound::Included(prefix.clone()),
+            };
+            let mut writes = BTreeSet::new();
+            let mut scan = engine.scan((start, encoding::prefix_range(&prefix).1));
+            while let Some((key, _)) = scan.next().transpose()? {
+                if writes.len() == remaining {
+                    break;
+                }
+                match Key::decode(&key)? {
+                    Key::TxnWrite(_, key) => writes.insert(key.into_owned()),
+                    key => return errdata!("expected TxnWrite, got {key:?}"),
+                };
+            }
+            drop(scan);
+            let mut exhausted = writes.len() < remaining;
+
+            let mut keys = writes.clone();
+            for tombstone in tombstones.iter().filter(|t| t.version == version) {
+                let (mut start, end) = tombstone.range();
+                if let Some(after) = &after {
+                    if !matches!(&start, Bound::Included(s) if s > after) {
+                        start = Bound::Excluded(after.clone());
+                    }
+                }
+                let mut deleted: Vec<Vec<u8>> = Vec::new();
+                let mut scan = engine.scan(Transaction::<E>::version_range((start, end)));
+                while let Some((key, _)) = scan.next().transpose()? {
+                    match Key::decode(&key)? {
+                        Key::Version(key, v) if v < version => {
+                            if deleted.last().map(|k| k.as_slice()) == Some(&key) {
+                                continue;
+                            }
+                            if deleted.len() == remaining {
+                                break;
+                            }
+                            deleted.push(key.into_owned());
+                        }
+                        Key::Version(_, _) => {}
+                        key => return errdata!("expected Key::Version got {key:?}"),
+                    }
+                }
+                drop(scan);
+                exhausted &= deleted.len() < remaining;
+                keys.extend(deleted);
+            }
+
+            // Emit changes for the first keys. Writes take precedence over
+            // range deletes at the same version, and keys that were already
+            // deleted are skipped.
+            for key in keys.into_iter().take(remaining) {
+                let old = Self::value_before(&mut engine, &tombstones, &key, version)?;
+                if writes.contains(&key) {
+                    let new = match engine.get(&Key::Version((&key).into(), version).encode())? {
+                        Some(value) => bincode::deserialize(&value)?,
+                        None => return errdata!("missing version {version} for TxnWrite"),
+                    };
+                    changes.push(Change { version, key: key.clone(), old, new });
+                } else if old.is_some() {
+                    changes.push(Change { version, key: key.clone(), old, new: None });
+                }
+                cursor.after = Some(key);
+            }
+            if exhausted {
+                cursor = ChangeCursor { version: version + 1, after: None };
+            }
+        }
+        Ok((changes, cursor))
+    }
+
+    /// Returns the latest value of a key below the given version, or None if
+    /// it did not exist or was deleted (possibly by one of the given range
+    /// tombstones). Used for the old values in the change feed.
+    fn value_before(
+        engine: &mut MutexGuard<E>,
+        tombstones: &[RangeTombstone],
+        key: &[u8],
+        version: Version,
+    ) -> Result<Option<Vec<u8>>> {
+        let from = Key::Version(key.into(), 0).encode();
+        let to = Key::Version(key.into(), version).encode();
+        let Some((raw, value)) = engine.scan(from..to).last().transpose()? else {
+            return Ok(None);
+        };
+        let Key::Version(_, latest) = Key::decode(&raw)? else {
+            return errdata!("expected Key::Version");
+        };
+        if tombstones.iter().any(|t| t.version < version && t.deletes(key, latest)) {
+            return Ok(None);
+        }
+        bincode::deserialize(&value)
+    }
+
     /// Returns the status of the MVCC and storage engines.
     pub fn status(&self) -> Result<Status> {
         let mut engine = self.engine.lock()?;
@@ -273,6 +712,137 @@ impl<E: Engine> MVCC<E> {
         let active_txns = engine.scan_prefix(&KeyPrefix::TxnActive.encode()).count() as u64;
         Ok(Status { versions, active_txns, storage: engine.status()? })
     }
+
+    /// Checks the MVCC data for inconsistencies, returning a description of
+    /// each problem found (if any). This scans the entire engine, so it's
+    /// mostly useful for offline integrity checks, e.g. `toydump --check`.
+    pub fn check(&self) -> Result<Vec<String>> {
+        let mut engine = self.engine.lock()?;
+        let mut problems = Vec::new();
+        let next_version = match engine.get(&Key::NextVersion.encode())? {
+            Some(ref v) => Version::decode(v)?,
+            None => 1,
+        };
+        let active = Transaction::scan_active(&mut engine)?;
+
+        // Scan all keys, checking each key/value pair individually and
+        // collecting write and lock records for cross-checks below.
+        let mut writes = BTreeSet::new(); // TxnWrite(version, key)
+        let mut versions = BTreeSet::new(); // Version(key, version)
+        let mut txn_locks = BTreeSet::new(); // TxnLock(version, key)
+        let mut locks = BTreeSet::new(); // Lock(key, version)
+        let mut scan = engine.scan(..);
+        while let Some((raw, value)) = scan.next().transpose()? {
+            let fmtkey = format::MVCC::<format::Raw>::key(&raw);
+            let Ok(key) = Key::decode(&raw) else {
+                problems.push(format!("invalid key {}", format::Raw::key(&raw)));
+                continue;
+            };
+            let (version, valid) = match &key {
+                Key::NextVersion => (None, Version::decode(&value).is_ok()),
+                Key::TxnActive(v) | Key::TxnWrite(v, _) | Key::TxnLock(v, _) => {
+                    (Some(*v), value.is_empty())
+                }
+                Key::TxnActiveSnapshot(v) => (
+                    Some(*v),
+                    BTreeSet::<Version>::decode(&value).is_ok_and(|a| a.iter().all(|a| a < v)),
+                ),
+                Key::Version(_, v) => {
+                    (Some(*v), bincode::deserialize::<Option<Vec<u8>>>(&value).is_ok())
+                }
+                Key::Unversioned(_) => (None, true),
+                Key::Lock(_, v) => (Some(*v), LockMode::decode(&value).is_ok()),
+                Key::RangeTombstone(_, v) => {
+                    (Some(*v), bincode::deserialize::<Bound<Vec<u8>>>(&value).is_ok())
+                }
+            };
+            if version.is_some_and(|v| v >= next_version) {
+                problems.push(format!("{fmtkey} at or beyond next version {next_version}"));
+            }
+            if !valid {
+                problems.push(format!("{fmtkey} has invalid value {}", format::Raw::bytes(&value)));
+            }
+            match key {
+                Key::TxnWrite(version, key) => _ = writes.insert((version, key.into_owned())),
+                Key::Version(key, version) => _ = versions.insert((key.into_owned(), version)),
+                Key::TxnLock(version, key) => _ = txn_locks.insert((version, key.into_owned())),
+                Key::Lock(key, version) => _ = locks.insert((key.into_owned(), version)),
+                _ => {}
+            }
+        }
+        drop(scan);
+
+        // Every write record must have a corresponding version, and every
+        // uncommitted version must have a write record for rollbacks.
+        for (version, key) in &writes {
+            if !versions.contains(&(key.clone(), *version)) {
+                let fmtkey =
+                    format::MVCC::<format::Raw>::key(&Key::TxnWrite(*version, key.into()).encode());
+                problems.push(format!("{fmtkey} has no matching version"));
+            }
+        }
+        for (key, version) in &versions {
+            if active.contains(version) && !writes.contains(&(*version, key.clone())) {
+                let fmtkey =
+                    format::MVCC::<format::Raw>::key(&Key::Version(key.into(), *version).encode());
+                problems.push(format!("{fmtkey} is uncommitted but has no TxnWrite record"));
+            }
+        }
+
+        // Locks may only be held by active transactions, and lock records
+        // must be paired.
+        for (version, key) in &txn_locks {
+            let fmtkey =
+                format::MVCC::<format::Raw>::key(&Key::TxnLock(*version, key.into()).encode());
+            if !active.contains(version) {
+                problems.push(format!("{fmtkey} held by inactive transaction"));
+            }
+            if !locks.contains(&(key.clone(), *version)) {
+                problems.push(format!("{fmtkey} has no matching Lock record"));
+            }
+        }
+        for (key, version) in &locks {
+            let fmtkey =
+                format::MVCC::<format::Raw>::key(&Key::Lock(key.into(), *version).encode());
+            if !active.contains(version) {
+                problems.push(format!("{fmtkey} held by inactive transaction"));
+            }
+            if !txn_locks.contains(&(*version, key.clone())) {
+                problems.push(format!("{fmtkey} has no matching TxnLock record"));
+            }
+        }
+        Ok(problems)
+    }
+}
+
+/// A committed write to a key, emitted by the change feed (see MVCC::changes).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    /// The version of the transaction that wrote the key.
+    pub version: Version,
+    /// The written key.
+    pub key: Vec<u8>,
+    /// The previous value, or None if the key did not exist.
+    pub old: Option<Vec<u8>>,
+    /// The new value, or None if the key was deleted.
+    pub new: Option<Vec<u8>>,
+}
+
+/// A change feed cursor (see MVCC::changes). Points to the version to resume
+/// the feed from, and the last key within that version that was already
+/// emitted, if any.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChangeCursor {
+    /// The version to resume from.
+    pub version: Version,
+    /// The last key at the version that was already emitted, if any.
+    pub after: Option<Vec<u8>>,
+}
+
+impl From<Version> for ChangeCursor {
+    fn from(version: Version) -> Self {
+        Self { version, after: None }
+    }
 }
 
 /// MVCC engine status.
@@ -294,6 +864,8 @@ pub struct Transaction<E: Engine> {
     engine: Arc<Mutex<E>>,
     /// The transaction state.
     st: TransactionState,
+    /// Whether to retain TxnWrite records on commit, for the change feed.
+    change_feed: bool,
 }
 
 /// A Transaction's state, which determines its write version and isolation. It
@@ -347,6 +919,26 @@ impl TransactionState {
             version <= self.version
         }
     }
+
+    /// Returns the lowest version that may be invisible to the transaction,
+    /// i.e. the oldest active transaction or the next version. Lower versions
+    /// are always visible, so write conflict checks can ignore them.
+    fn min_writable_version(&self) -> Version {
+        self.active.first().copied().unwrap_or(self.version + 1)
+    }
+
+    /// Returns the range tombstones visible to the transaction that overlap
+    /// the given key range, with an inclusive start key.
+    fn visible_range_tombstones<E: Engine>(
+        &self,
+        engine: &mut E,
+        start: &[u8],
+        end: Bound<&[u8]>,
+    ) -> Result<Vec<RangeTombstone>> {
+        let mut tombstones = RangeTombstone::scan(engine, start, end)?;
+        tombstones.retain(|t| self.is_visible(t.version));
+        Ok(tombstones)
+    }
 }
 
 impl From<TransactionState> for Cow<'_, TransactionState> {
@@ -362,10 +954,14 @@ impl<'a> From<&'a TransactionState> for Cow<'a, TransactionState> {
 }
 
 impl<E: Engine> Transaction<E> {
+    /// The number of versions to scan at a time when checking a range delete
+    /// for conflicts, releasing the engine mutex in between.
+    const CONFLICT_BATCH_SIZE: usize = 1000;
+
     /// Begins a new transaction in read-write mode. This will allocate a new
     /// version that the transaction can write at, add it to the active set, and
     /// record its active snapshot for time-travel queries.
-    fn begin(engine: Arc<Mutex<E>>) -> Result<Self> {
+    fn begin(engine: Arc<Mutex<E>>, change_feed: bool) -> Result<Self> {
         let mut session = engine.lock()?;
 
         // Allocate a new version to write at.
@@ -384,7 +980,8 @@ impl<E: Engine> Transaction<E> {
         session.set(&Key::TxnActive(version).encode(), vec![])?;
         drop(session);
 
-        Ok(Self { engine, st: TransactionState { version, read_only: false, active } })
+        let st = TransactionState { version, read_only: false, active };
+        Ok(Self { engine, st, change_feed })
     }
 
     /// Begins a new read-only transaction. If version is given it will see the
@@ -418,17 +1015,18 @@ impl<E: Engine> Transaction<E> {
 
         drop(session);
 
-        Ok(Self { engine, st: TransactionState { version, read_only: true, active } })
+        let st = TransactionState { version, read_only: true, active };
+        Ok(Self { engine, st, change_feed: false })
     }
 
     /// Resumes a transaction from the given state.
-    fn resume(engine: Arc<Mutex<E>>, s: TransactionState) -> Result<Self> {
+    fn resume(engine: Arc<Mutex<E>>, s: TransactionState, change_feed: bool) -> Result<Self> {
         // For read-write transactions, verify that the transaction is still
         // active before making further writes.
         if !s.read_only && engine.lock()?.get(&Key::TxnActive(s.version).encode())?.is_none() {
             return errinput!("no active transaction at version {}", s.version);
         }
-        Ok(Self { engine, st: s })
+        Ok(Self { engine, st: s, change_feed })
     }
 
     /// Fetches the set of currently active transactions.
@@ -462,7 +1060,8 @@ impl<E: Engine> Transaction<E> {
 
     /// Commits the transaction, by removing it from the active set. This will
     /// immediately make its writes visible to subsequent transactions. Also
-    /// removes its TxnWrite records, which are no longer needed.
+    /// releases its locks, and removes its TxnWrite records unless they're
+    /// retained for the change feed (see MVCC::changes).
     ///
     /// NB: commit does not flush writes to durable storage, since we rely on
     /// the Raft log for persistence.
@@ -471,71 +1070,299 @@ impl<E: Engine> Transaction<E> {
             return Ok(());
         }
         let mut engine = self.engine.lock()?;
-        let remove = engine
-            .scan_prefix(&KeyPrefix::TxnWrite(self.st.version).encode())
-            .map(|r| r.map(|(k, _)| k))
-            .collect::<Result<Vec<_>>>()?;
-        for key in remove {
-            engine.delete(&key)?
+        let mut batch = Vec::new();
+        if !self.change_feed {
+            let mut scan = engine.scan_prefix(&KeyPrefix::TxnWrite(self.st.version).encode());
+            while let Some((key, _)) = scan.next().transpose()? {
+                batch.push(WriteOp::Delete { key });
+            }
         }
-        engine.delete(&Key::TxnActive(self.st.version).encode())
+        batch.extend(Self::release_locks(&mut engine, self.st.version)?);
+        batch.push(WriteOp::Delete { key: Key::TxnActive(self.st.version).encode() });
+        engine.write_batch(batch)
     }
 
-    /// Rolls back the transaction, by undoing all written versions and removing
-    /// it from the active set. The active set snapshot is left behind, since
-    /// this is needed for time travel queries at this version.
+    /// Rolls back the transaction, by undoing all written versions and range
+    /// tombstones and removing it from the active set. The active set snapshot
+    /// is left behind, since this is needed for time travel queries at this
+    /// version.
     pub fn rollback(self) -> Result<()> {
         if self.st.read_only {
             return Ok(());
         }
         let mut engine = self.engine.lock()?;
-        let mut rollback = Vec::new();
+        let mut batch = Vec::new();
         let mut scan = engine.scan_prefix(&KeyPrefix::TxnWrite(self.st.version).encode());
         while let Some((key, _)) = scan.next().transpose()? {
             match Key::decode(&key)? {
                 Key::TxnWrite(_, key) => {
-                    rollback.push(Key::Version(key, self.st.version).encode()) // the version
+                    let key = Key::Version(key, self.st.version).encode();
+                    batch.push(WriteOp::Delete { key }) // the version
                 }
                 key => return errdata!("expected TxnWrite, got {key:?}"),
             };
-            rollback.push(key); // the TxnWrite record
+            batch.push(WriteOp::Delete { key }); // the TxnWrite record
         }
         drop(scan);
-        for key in rollback.into_iter() {
-            engine.delete(&key)?;
+        for tombstone in RangeTombstone::scan_all(&mut *engine)? {
+            if tombstone.version == self.st.version {
+                batch.push(WriteOp::Delete { key: tombstone.key() });
+            }
         }
-        engine.delete(&Key::TxnActive(self.st.version).encode()) // remove from active set
+        batch.extend(Self::release_locks(&mut engine, self.st.version)?);
+        // Remove from active set.
+        batch.push(WriteOp::Delete { key: Key::TxnActive(self.st.version).encode() });
+        engine.write_batch(batch)
+    }
+
+    /// Returns write operations that release all locks held by the transaction
+    /// at the given version.
+    fn release_locks(engine: &mut MutexGuard<E>, version: Version) -> Result<Vec<WriteOp>> {
+        let mut release = Vec::new();
+        let mut scan = engine.scan_prefix(&KeyPrefix::TxnLock(version).encode());
+        while let Some((key, _)) = scan.next().transpose()? {
+            match Key::decode(&key)? {
+                Key::TxnLock(_, key) => {
+                    release.push(WriteOp::Delete { key: Key::Lock(key, version).encode() })
+                }
+                key => return errdata!("expected TxnLock, got {key:?}"),
+            };
+            release.push(WriteOp::Delete { key }); // the TxnLock record
+        }
+        Ok(release)
     }
 
     /// Deletes a key.
     pub fn delete(&self, key: &[u8]) -> Result<()> {
-        self.write_version(key, None)
+        self.write_batch(vec![WriteOp::Delete { key: key.to_vec() }])
+    }
+
+    /// Deletes all keys in the given range, by writing a single range
+    /// tombstone at the transaction's version. This takes a constant number of
+    /// writes regardless of the number of keys in the range, but the range is
+    /// still scanned for conflicts. The tombstone is written before the scan,
+    /// such that concurrent writes to the range conflict with it, which allows
+    /// scanning in batches without holding the engine mutex throughout. If any
+    /// key in the range has a newer or uncommitted version or is locked by
+    /// another transaction, or the range overlaps a range tombstone that's
+    /// invisible to us, the tombstone is removed again and a serialization
+    /// error is returned.
+    pub fn delete_range(&self, range: impl RangeBounds<Vec<u8>>) -> Result<()> {
+        if self.st.read_only {
+            return Err(Error::ReadOnly);
+        }
+        // Tombstones are keyed by their start key, so normalize it to an
+        // inclusive bound.
+        let start = inclusive_start(range.start_bound());
+        let mut tombstone =
+            RangeTombstone { version: self.st.version, start, end: range.end_bound().cloned() };
+
+        let mut engine = self.engine.lock()?;
+
+        // Check for overlapping range tombstones that are invisible to us, and
+        // merge with our own tombstone at the same start key (if any).
+        let mut previous = None;
+        let end = tombstone.end.as_ref().map(|k| k.as_slice());
+        for other in RangeTombstone::scan(&mut *engine, &tombstone.start, end)? {
+            if !self.st.is_visible(other.version) {
+                return Err(Error::Serialization);
+            }
+            if other.version == self.st.version && other.start == tombstone.start {
+                previous = Some(other);
+            }
+        }
+        if let Some(previous) = &previous {
+            tombstone.extend(previous.end.clone());
+        }
+
+        // Check for locks held by other transactions in the range.
+        let mut scan = engine.scan(Self::lock_range(tombstone.range()));
+        while let Some((key, _)) = scan.next().transpose()? {
+            match Key::decode(&key)? {
+                Key::Lock(_, version) if version == self.st.version => {}
+                Key::Lock(_, _) => return Err(Error::Serialization),
+                key => return errdata!("expected Key::Lock got {key:?}"),
+            }
+        }
+        drop(scan);
+
+        // Write the tombstone, and check for newer or uncommitted versions in
+        // the range. If there's a conflict, restore the previous tombstone.
+        engine.set(&tombstone.key(), bincode::serialize(&tombstone.end))?;
+        drop(engine);
+        if let Err(err) = self.check_range_conflict(&tombstone) {
+            let mut engine = self.engine.lock()?;
+            match previous {
+                Some(previous) => engine.set(&previous.key(), bincode::serialize(&previous.end))?,
+                None => engine.delete(&tombstone.key())?,
+            }
+            return Err(err);
+        }
+
+        // The tombstone only deletes versions below our own, so remove our own
+        // writes in the range, along with their write records.
+        let mut engine = self.engine.lock()?;
+        let mut batch = Vec::new();
+        let mut scan = engine.scan_prefix(&KeyPrefix::TxnWrite(self.st.version).encode());
+        while let Some((key, _)) = scan.next().transpose()? {
+            match Key::decode(&key)? {
+                Key::TxnWrite(_, k) if tombstone.contains(&k) => {
+                    batch.push(WriteOp::Delete { key: Key::Version(k, self.st.version).encode() });
+                    batch.push(WriteOp::Delete { key });
+                }
+                Key::TxnWrite(_, _) => {}
+                key => return errdata!("expected TxnWrite, got {key:?}"),
+            }
+        }
+        drop(scan);
+        engine.write_batch(batch)
+    }
+
+    /// Checks for newer or uncommitted versions of keys in a range tombstone's
+    /// range, returning a serialization error if found. Scans the range in
+    /// batches, releasing the engine mutex in between. The tombstone must
+    /// already be written, such that concurrent writes conflict with it.
+    fn check_range_conflict(&self, tombstone: &RangeTombstone) -> Result<()> {
+        let (mut start, end) = Self::version_range(tombstone.range());
+        loop {
+            let mut engine = self.engine.lock()?;
+            let mut scan = engine.scan((start, end.clone())).take(Self::CONFLICT_BATCH_SIZE);
+            let (mut count, mut last) = (0, None);
+            while let Some((key, _)) = scan.next().transpose()? {
+                match Key::decode(&key)? {
+                    Key::Version(_, version) if !self.st.is_visible(version) => {
+                        return Err(Error::Serialization)
+                    }
+                    Key::Version(_, _) => {}
+                    key => return errdata!("expected Key::Version got {key:?}"),
+                }
+                (count, last) = (count + 1, Some(key));
+            }
+            match last {
+                Some(last) if count == Self::CONFLICT_BATCH_SIZE => start = Bound::Excluded(last),
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Returns the raw engine key range containing all locks on the keys in
+    /// the given user key range.
+    fn lock_range(range: impl RangeBounds<Vec<u8>>) -> (Bound<RawKey>, Bound<RawKey>) {
+        let start = match range.start_bound() {
+            Bound::Excluded(k) => Bound::Excluded(Key::Lock(k.into(), u64::MAX).encode()),
+            Bound::Included(k) => Bound::Included(Key::Lock(k.into(), 0).encode()),
+            Bound::Unbounded => Bound::Included(Key::Lock(vec![].into(), 0).encode()),
+        };
+        let end = match range.end_bound() {
+            Bound::Excluded(k) => Bound::Excluded(Key::Lock(k.into(), 0).encode()),
+            Bound::Included(k) => Bound::Included(Key::Lock(k.into(), u64::MAX).encode()),
+            Bound::Unbounded => Bound::Excluded(KeyPrefix::RangeTombstone.encode()),
+        };
+        (start, end)
+    }
+
+    /// Deletes all keys under a given prefix. See delete_range().
+    pub fn delete_prefix(&self, prefix: &[u8]) -> Result<()> {
+        self.delete_range(encoding::prefix_range(prefix))
     }
 
     /// Sets a value for a key.
     pub fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
-        self.write_version(key, Some(value))
+        self.write_batch(vec![WriteOp::Set { key: key.to_vec(), value }])
+    }
+
+    /// Locks a key in the given mode, until the transaction commits or rolls
+    /// back. If the key has a newer or uncommitted version, or another
+    /// transaction holds a conflicting lock on it, a serialization error is
+    /// returned. Upgrading our own shared lock to an exclusive lock is fine,
+    /// as long as no other transaction holds a lock on the key.
+    pub fn lock(&self, key: &[u8], mode: LockMode) -> Result<()> {
+        if self.st.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let mut engine = self.engine.lock()?;
+        self.check_write_conflict(&mut engine, key)?;
+
+        // Check for conflicting locks, and whether we already hold the lock.
+        let mut scan = engine.scan_prefix(&KeyPrefix::Lock(key.into()).encode());
+        while let Some((k, v)) = scan.next().transpose()? {
+            match Key::decode(&k)? {
+                Key::Lock(_, version) if version == self.st.version => {
+                    if LockMode::decode(&v)? >= mode {
+                        return Ok(());
+                    }
+                }
+                Key::Lock(_, _) => {
+                    if LockMode::decode(&v)?.conflicts(&mode) {
+                        return Err(Error::Serialization);
+                    }
+                }
+                key => return errdata!("expected Key::Lock got {key:?}"),
+            }
+        }
+        drop(scan);
+
+        engine.write_batch(vec![
+            WriteOp::Set { key: Key::TxnLock(self.st.version, key.into()).encode(), value: vec![] },
+            WriteOp::Set {
+                key: Key::Lock(key.into(), self.st.version).encode(),
+                value: mode.encode(),
+            },
+        ])
     }
 
-    /// Writes a new version for a key at the transaction's version. None writes
-    /// a deletion tombstone. If a write conflict is found (either a newer or
-    /// uncommitted version), a serialization error is returned.  Replacing our
-    /// own uncommitted write is fine.
-    fn write_version(&self, key: &[u8], value: Option<Vec<u8>>) -> Result<()> {
+    /// Atomically writes a batch of new versions at the transaction's version,
+    /// where deletes write a tombstone. If a write conflict is found for any
+    /// key (either a newer or uncommitted version, or a lock held by another
+    /// transaction), a serialization error is returned and nothing is written.
+    /// Replacing our own uncommitted writes is fine.
+    pub fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
         if self.st.read_only {
             return Err(Error::ReadOnly);
         }
         let mut engine = self.engine.lock()?;
+        let mut batch = Vec::with_capacity(ops.len() * 2);
+        for op in ops {
+            let (key, value) = match op {
+                WriteOp::Delete { key } => (key, None),
+                WriteOp::Set { key, value } => (key, Some(value)),
+            };
+            self.check_write_conflict(&mut engine, &key)?;
+
+            // Check for locks held by other transactions.
+            let mut scan = engine.scan_prefix(&KeyPrefix::Lock((&key).into()).encode());
+            while let Some((k, _)) = scan.next().transpose()? {
+                match Key::decode(&k)? {
+                    Key::Lock(_, version) if version == self.st.version => {}
+                    Key::Lock(_, _) => return Err(Error::Serialization),
+                    key => return errdata!("expected Key::Lock got {key:?}"),
+                }
+            }
+            drop(scan);
+
+            // Write the new version and its write record.
+            //
+            // NB: TxnWrite contains the provided user key, not the encoded
+            // engine key, since we can construct the engine key using the
+            // version.
+            batch.push(WriteOp::Set {
+                key: Key::TxnWrite(self.st.version, (&key).into()).encode(),
+                value: vec![],
+            });
+            batch.push(WriteOp::Set {
+                key: Key::Version(key.into(), self.st.version).encode(),
+                value: bincode::serialize(&value),
+            });
+        }
+        engine.write_batch(batch)
+    }
 
-        // Check for write conflicts, i.e. if the latest key is invisible to us
-        // (either a newer version, or an uncommitted version in our past). We
-        // can only conflict with the latest key, since all transactions enforce
-        // the same invariant.
-        let from = Key::Version(
-            key.into(),
-            self.st.active.iter().min().copied().unwrap_or(self.st.version + 1),
-        )
-        .encode();
+    /// Checks for write conflicts, i.e. if the latest key is invisible to us
+    /// (either a newer version, or an uncommitted version in our past). We can
+    /// only conflict with the latest key, since all transactions enforce the
+    /// same invariant. Returns a serialization error on conflicts.
+    fn check_write_conflict(&self, engine: &mut MutexGuard<E>, key: &[u8]) -> Result<()> {
+        let from = Key::Version(key.into(), self.st.min_writable_version()).encode();
         let to = Key::Version(key.into(), u64::MAX).encode();
         if let Some((key, _)) = engine.scan(from..=to).last().transpose()? {
             match Key::decode(&key)? {
@@ -547,13 +1374,13 @@ impl<E: Engine> Transaction<E> {
                 key => return errdata!("expected Key::Version got {key:?}"),
             }
         }
-
-        // Write the new version and its write record.
-        //
-        // NB: TxnWrite contains the provided user key, not the encoded engine
-        // key, since we can construct the engine key using the version.
-        engine.set(&Key::TxnWrite(self.st.version, key.into()).encode(), vec![])?;
-        engine.set(&Key::Version(key.into(), self.st.version).encode(), bincode::serialize(&value))
+        // Range tombstones are writes to all keys in their range.
+        for tombstone in RangeTombstone::scan(&mut **engine, key, Bound::Included(key))? {
+            if !self.st.is_visible(tombstone.version) {
+                return Err(Error::Serialization);
+            }
+        }
+        Ok(())
     }
 
     /// Fetches a key's value, or None if it does not exist.
@@ -562,10 +1389,19 @@ impl<E: Engine> Transaction<E> {
         let from = Key::Version(key.into(), 0).encode();
         let to = Key::Version(key.into(), self.st.version).encode();
         let mut scan = engine.scan(from..=to).rev();
-        while let Some((key, value)) = scan.next().transpose()? {
-            match Key::decode(&key)? {
+        while let Some((k, value)) = scan.next().transpose()? {
+            match Key::decode(&k)? {
                 Key::Version(_, version) => {
                     if self.st.is_visible(version) {
+                        drop(scan);
+                        let tombstones = self.st.visible_range_tombstones(
+                            &mut *engine,
+                            key,
+                            Bound::Included(key),
+                        )?;
+                        if tombstones.iter().any(|t| t.deletes(key, version)) {
+                            return Ok(None);
+                        }
                         return bincode::deserialize(&value);
                     }
                 }
@@ -578,6 +1414,14 @@ impl<E: Engine> Transaction<E> {
     /// Returns an iterator over the latest visible key/value pairs at the
     /// transaction's version.
     pub fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> ScanIterator<E> {
+        let keys = (inclusive_start(range.start_bound()), range.end_bound().cloned());
+        let range = Self::version_range(range);
+        ScanIterator::new(self.engine.clone(), self.state().clone(), range, keys)
+    }
+
+    /// Returns the raw engine key range containing all versions of the keys in
+    /// the given user key range.
+    fn version_range(range: impl RangeBounds<Vec<u8>>) -> (Bound<RawKey>, Bound<RawKey>) {
         let start = match range.start_bound() {
             Bound::Excluded(k) => Bound::Excluded(Key::Version(k.into(), u64::MAX).encode()),
             Bound::Included(k) => Bound::Included(Key::Version(k.into(), 0).encode()),
@@ -588,7 +1432,7 @@ impl<E: Engine> Transaction<E> {
             Bound::Included(k) => Bound::Included(Key::Version(k.into(), u64::MAX).encode()),
             Bound::Unbounded => Bound::Excluded(KeyPrefix::Unversioned.encode()),
         };
-        ScanIterator::new(self.engine.clone(), self.state().clone(), (start, end))
+        (start, end)
     }
 
     /// Scans keys under a given prefix.
@@ -596,10 +1440,11 @@ impl<E: Engine> Transaction<E> {
         // Normally, KeyPrefix::Version will only match all versions of the
         // exact given key. We want all keys maching the prefix, so we chop off
         // the KeyCode byte slice terminator 0x0000 at the end.
+        let keys = (prefix.to_vec(), encoding::prefix_range(prefix).1);
         let mut prefix = KeyPrefix::Version(prefix.into()).encode();
         prefix.truncate(prefix.len() - 2);
         let range = encoding::prefix_range(&prefix);
-        ScanIterator::<E>::new(self.engine.clone(), self.state().clone(), range)
+        ScanIterator::<E>::new(self.engine.clone(), self.state().clone(), range, keys)
     }
 }
 
@@ -610,16 +1455,31 @@ impl<E: Engine> Transaction<E> {
 /// SQL engine pulls from two tables concurrently during a join). Instead, we
 /// pull and buffer 100 rows at a time, and release the mutex in between.
 ///
-/// This does not implement DoubleEndedIterator (reverse scans), since the SQL
-/// layer doesn't currently need it.
+/// The iterator can be repositioned with seek() and seek_for_prev(), which skip
+/// ahead (or back) to a key within the original scan range without creating a
+/// new scan.
+///
+/// Reverse scans (DoubleEndedIterator) pull batches from the end of the
+/// remaining range into a separate buffer, such that the front and back of the
+/// scan can be interleaved and meet in the middle.
 pub struct ScanIterator<E: Engine> {
     /// The engine.
     engine: Arc<Mutex<E>>,
     /// The transaction state.
     txn: TransactionState,
+    /// The original scan range, for seeks.
+    range: (Bound<RawKey>, Bound<RawKey>),
+    /// The user key range of the scan, with an inclusive start key, for range
+    /// tombstone lookups.
+    keys: (UserKey, Bound<UserKey>),
+    /// The number of live keys to pull from the engine at a time.
+    batch_size: usize,
     /// A buffer of live and visible key/value pairs to emit.
     buffer: VecDeque<(UserKey, UserValue)>,
-    /// The remaining range after the buffer.
+    /// A buffer of live and visible key/value pairs to emit from the back, in
+    /// reverse key order.
+    buffer_back: VecDeque<(UserKey, UserValue)>,
+    /// The remaining range between the buffers.
     remainder: Option<(Bound<RawKey>, Bound<RawKey>)>,
 }
 
@@ -629,7 +1489,11 @@ impl<E: Engine> Clone for ScanIterator<E> {
         Self {
             engine: self.engine.clone(),
             txn: self.txn.clone(),
+            range: self.range.clone(),
+            keys: self.keys.clone(),
+            batch_size: self.batch_size,
             buffer: self.buffer.clone(),
+            buffer_back: self.buffer_back.clone(),
             remainder: self.remainder.clone(),
         }
     }
@@ -643,8 +1507,7 @@ type UserKey = Vec<u8>;
 type UserValue = Vec<u8>;
 
 impl<E: Engine> ScanIterator<E> {
-    /// The number of live keys to pull from the engine at a time.
-    /// TODO: add tests that either exceed or vary this.
+    /// The default number of live keys to pull from the engine at a time.
     const BUFFER_SIZE: usize = 100;
 
     /// Creates a new scan iterator.
@@ -652,36 +1515,84 @@ impl<E: Engine> ScanIterator<E> {
         engine: Arc<Mutex<E>>,
         txn: TransactionState,
         range: (Bound<RawKey>, Bound<RawKey>),
+        keys: (UserKey, Bound<UserKey>),
     ) -> Self {
         let buffer = VecDeque::with_capacity(Self::BUFFER_SIZE);
-        Self { engine, txn, buffer, remainder: Some(range) }
+        let buffer_back = VecDeque::new();
+        let remainder = Some(range.clone());
+        let batch_size = Self::BUFFER_SIZE;
+        Self { engine, txn, range, keys, batch_size, buffer, buffer_back, remainder }
+    }
+
+    /// Returns the range tombstones visible to the transaction that overlap
+    /// the scan's key range.
+    fn visible_range_tombstones(&self, engine: &mut E) -> Result<Vec<RangeTombstone>> {
+        let (start, end) = &self.keys;
+        self.txn.visible_range_tombstones(engine, start, end.as_ref().map(|k| k.as_slice()))
+    }
+
+    /// Sets the number of live keys to pull from the engine at a time. This
+    /// is a readahead hint: scans that will only read a few keys (e.g. before
+    /// seeking elsewhere) can use a small batch, while large scans can use a
+    /// larger batch to hold the engine mutex less often.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch size must be positive");
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Repositions the iterator at the first live key >= key, clamped to the
+    /// original scan range, such that next() returns it. Resets the back of the
+    /// iterator to the end of the scan range, and discards any buffered items.
+    pub fn seek(&mut self, key: &[u8]) {
+        let key = Key::Version(key.into(), 0).encode();
+        self.buffer.clear();
+        self.buffer_back.clear();
+        self.remainder = Some(seek_range(&self.range, &key));
+    }
+
+    /// Repositions the iterator at the last live key <= key, clamped to the
+    /// original scan range, such that next_back() returns it. Resets the front
+    /// of the iterator to the start of the scan range, and discards any
+    /// buffered items.
+    pub fn seek_for_prev(&mut self, key: &[u8]) {
+        let key = Key::Version(key.into(), u64::MAX).encode();
+        self.buffer.clear();
+        self.buffer_back.clear();
+        self.remainder = Some(seek_for_prev_range(&self.range, &key));
     }
 
     /// Fills the buffer, if there's any pending items.
     fn fill_buffer(&mut self) -> Result<()> {
         // Check if there's anything to buffer.
-        if self.buffer.len() >= Self::BUFFER_SIZE {
+        if self.buffer.len() >= self.batch_size {
             return Ok(());
         }
         let Some(range) = self.remainder.take() else { return Ok(()) };
         let range_end = range.1.clone();
 
         let mut engine = self.engine.lock()?;
-        let mut iter = VersionIterator::<E>::new(&self.txn, engine.scan(range)).peekable();
-        while let Some((key, _, value)) = iter.next().transpose()? {
+        let tombstones = self.visible_range_tombstones(&mut engine)?;
+        let mut iter = VersionIterator::new(&self.txn, engine.scan(range)).peekable();
+        while let Some((key, version, value)) = iter.next().transpose()? {
             // If the next key equals this one, we're not at the latest version.
             match iter.peek() {
                 Some(Ok((next, _, _))) if next == &key => continue,
                 Some(Err(err)) => return Err(err.clone()),
                 Some(Ok(_)) | None => {}
             }
+            // If the latest version is deleted by a range tombstone, skip it.
+            if tombstones.iter().any(|t| t.deletes(&key, version)) {
+                continue;
+            }
             // If the key is live (not a tombstone), buffer it.
             let Some(value) = bincode::deserialize(&value)? else { continue };
             self.buffer.push_back((key, value));
             // If we filled the buffer, save the remaining range (if any) and
             // return. peek() has already buffered next(), so pull it.
-            if self.buffer.len() == Self::BUFFER_SIZE {
-                if let Some((next, _, _)) = iter.next().transpose()? {
+            if self.buffer.len() == self.batch_size {
+                if let Some((next, version, _)) = iter.next().transpose()? {
+                    let next = Key::Version(next.into(), version).encode();
                     self.remainder = Some((Bound::Included(next), range_end));
                 }
                 return Ok(());
@@ -689,6 +1600,45 @@ impl<E: Engine> ScanIterator<E> {
         }
         Ok(())
     }
+
+    /// Fills the back buffer from the end of the remaining range, if there's
+    /// any pending items.
+    fn fill_buffer_back(&mut self) -> Result<()> {
+        // Check if there's anything to buffer.
+        if self.buffer_back.len() >= self.batch_size {
+            return Ok(());
+        }
+        let Some(range) = self.remainder.take() else { return Ok(()) };
+        let range_start = range.0.clone();
+
+        let mut engine = self.engine.lock()?;
+        let tombstones = self.visible_range_tombstones(&mut engine)?;
+        let mut iter = VersionIterator::new(&self.txn, engine.scan(range).rev());
+        let mut last: Option<UserKey> = None;
+        while let Some((key, version, value)) = iter.next().transpose()? {
+            // Versions are visited newest first, so only the first visible
+            // version of each key is the latest one.
+            if last.as_ref() == Some(&key) {
+                continue;
+            }
+            last = Some(key.clone());
+            // If the latest version is deleted by a range tombstone, skip it.
+            if tombstones.iter().any(|t| t.deletes(&key, version)) {
+                continue;
+            }
+            // If the key is live (not a tombstone), buffer it.
+            let Some(value) = bincode::deserialize(&value)? else { continue };
+            self.buffer_back.push_back((key, value));
+            // If we filled the buffer, save the remaining range before all
+            // versions of the last key and return.
+            if self.buffer_back.len() == self.batch_size {
+                let end = Key::Version(last.unwrap_or_default().into(), 0).encode();
+                self.remainder = Some((range_start, Bound::Excluded(end)));
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
 }
 
 impl<E: Engine> Iterator for ScanIterator<E> {
@@ -699,22 +1649,35 @@ impl<E: Engine> Iterator for ScanIterator<E> {
                 return Some(Err(error));
             }
         }
-        self.buffer.pop_front().map(Ok)
+        // Once the remaining range is exhausted, drain the back buffer.
+        self.buffer.pop_front().or_else(|| self.buffer_back.pop_back()).map(Ok)
+    }
+}
+
+impl<E: Engine> DoubleEndedIterator for ScanIterator<E> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.buffer_back.is_empty() {
+            if let Err(error) = self.fill_buffer_back() {
+                return Some(Err(error));
+            }
+        }
+        // Once the remaining range is exhausted, drain the front buffer.
+        self.buffer_back.pop_front().or_else(|| self.buffer.pop_back()).map(Ok)
     }
 }
 
 /// An iterator that decodes raw engine key/value pairs into MVCC key/value
 /// versions, and skips invisible versions. Helper for ScanIterator.
-struct VersionIterator<'a, E: Engine + 'a> {
+struct VersionIterator<'a, I: Iterator<Item = Result<(RawKey, Vec<u8>)>>> {
     /// The transaction the scan is running in.
     txn: &'a TransactionState,
-    /// The inner engine scan iterator.
-    inner: E::ScanIterator<'a>,
+    /// The inner engine scan iterator, possibly reversed.
+    inner: I,
 }
 
-impl<'a, E: Engine + 'a> VersionIterator<'a, E> {
+impl<'a, I: Iterator<Item = Result<(RawKey, Vec<u8>)>>> VersionIterator<'a, I> {
     /// Creates a new MVCC version iterator for the given engine iterator.
-    fn new(txn: &'a TransactionState, inner: E::ScanIterator<'a>) -> Self {
+    fn new(txn: &'a TransactionState, inner: I) -> Self {
         Self { txn, inner }
     }
 
@@ -743,7 +1706,7 @@ impl<'a, E: Engine + 'a> VersionIterator<'a, E> {
     }
 }
 
-impl<'a, E: Engine> Iterator for VersionIterator<'a, E> {
+impl<I: Iterator<Item = Result<(RawKey, Vec<u8>)>>> Iterator for VersionIterator<'_, I> {
     type Item = Result<(UserKey, Version, UserValue)>;
     fn next(&mut self) -> Option<Self::Item> {
         self.try_next().transpose()
@@ -753,8 +1716,9 @@ impl<'a, E: Engine> Iterator for VersionIterator<'a, E> {
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::encoding::format::{self, Formatter as _};
-    use crate::storage::engine::test::{decode_binary, parse_key_range, Emit, Mirror, Operation};
+    use crate::storage::engine::test::{
+        decode_binary, format_range, parse_key_range, Emit, Mirror, Operation,
+    };
     use crate::storage::{BitCask, Memory};
 
     use crossbeam::channel::Receiver;
@@ -779,6 +1743,9 @@ pub mod tests {
     #[test_case(KeyPrefix::TxnWrite(1), Key::TxnWrite(1, b"foo".as_slice().into()); "TxnWrite")]
     #[test_case(KeyPrefix::Version(b"foo".as_slice().into()), Key::Version(b"foo".as_slice().into(), 1); "Version")]
     #[test_case(KeyPrefix::Unversioned, Key::Unversioned(b"foo".as_slice().into()); "Unversioned")]
+    #[test_case(KeyPrefix::TxnLock(1), Key::TxnLock(1, b"foo".as_slice().into()); "TxnLock")]
+    #[test_case(KeyPrefix::Lock(b"foo".as_slice().into()), Key::Lock(b"foo".as_slice().into(), 1); "Lock")]
+    #[test_case(KeyPrefix::RangeTombstone, Key::RangeTombstone(b"foo".as_slice().into(), 1); "RangeTombstone")]
     fn key_prefix(prefix: KeyPrefix, key: Key) {
         let prefix = prefix.encode();
         let key = key.encode();
@@ -823,6 +1790,47 @@ pub mod tests {
                     self.txns.insert(name.to_string(), txn);
                 }
 
+                // changes [from=VERSION] [after=KEY] [limit=N]
+                "changes" => {
+                    Self::no_txn(command)?;
+                    let mut args = command.consume_args();
+                    let version = args.lookup_parse("from")?.unwrap_or(0);
+                    let after = args.lookup("after").map(|a| decode_binary(&a.value));
+                    let limit = args.lookup_parse("limit")?.unwrap_or(usize::MAX);
+                    args.reject_rest()?;
+                    let from = ChangeCursor { version, after };
+                    let (changes, next) = self.mvcc.changes(from, limit)?;
+                    for change in changes {
+                        let key = format::Raw::key(&change.key);
+                        let old = change.old.as_deref().map(format::Raw::bytes);
+                        let new = change.new.as_deref().map(format::Raw::bytes);
+                        let fmtold = old.unwrap_or("None".to_string());
+                        let fmtnew = new.unwrap_or("None".to_string());
+                        writeln!(output, "v{} {key}: {fmtold} → {fmtnew}", change.version)?;
+                    }
+                    match next.after {
+                        Some(after) => {
+                            let after = format::Raw::key(&after);
+                            writeln!(output, "next={} after={after}", next.version)?
+                        }
+                        None => writeln!(output, "next={}", next.version)?,
+                    }
+                }
+
+                // check
+                // Checks the MVCC data for inconsistencies.
+                "check" => {
+                    Self::no_txn(command)?;
+                    command.consume_args().reject_rest()?;
+                    let problems = self.mvcc.check()?;
+                    if problems.is_empty() {
+                        writeln!(output, "ok")?;
+                    }
+                    for problem in problems {
+                        writeln!(output, "{problem}")?;
+                    }
+                }
+
                 // txn: commit
                 "commit" => {
                     let name = Self::txn_name(&command.prefix)?;
@@ -842,6 +1850,24 @@ pub mod tests {
                     args.reject_rest()?;
                 }
 
+                // txn: delete_range RANGE
+                "delete_range" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let range = parse_key_range(&args.next_pos().ok_or("range not given")?.value)?;
+                    args.reject_rest()?;
+                    txn.delete_range(range)?;
+                }
+
+                // txn: delete_prefix PREFIX
+                "delete_prefix" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let prefix = decode_binary(&args.next_pos().ok_or("prefix not given")?.value);
+                    args.reject_rest()?;
+                    txn.delete_prefix(&prefix)?;
+                }
+
                 // dump
                 "dump" => {
                     command.consume_args().reject_rest()?;
@@ -854,6 +1880,39 @@ pub mod tests {
                     }
                 }
 
+                // enable_change_feed
+                // Enables the change feed for transactions that begin afterwards.
+                "enable_change_feed" => {
+                    Self::no_txn(command)?;
+                    command.consume_args().reject_rest()?;
+                    self.mvcc.set_change_feed(true);
+                }
+
+                // engine_delete KEY...
+                // Deletes raw keys directly in the storage engine.
+                "engine_delete" => {
+                    Self::no_txn(command)?;
+                    let mut args = command.consume_args();
+                    let mut engine = self.mvcc.engine.lock().unwrap();
+                    for arg in args.rest_pos() {
+                        engine.delete(&decode_binary(&arg.value))?;
+                    }
+                    args.reject_rest()?;
+                }
+
+                // engine_set KEY=VALUE...
+                // Sets raw key/value pairs directly in the storage engine.
+                "engine_set" => {
+                    Self::no_txn(command)?;
+                    let mut args = command.consume_args();
+                    let mut engine = self.mvcc.engine.lock().unwrap();
+                    for kv in args.rest_key() {
+                        let key = decode_binary(kv.key.as_ref().unwrap());
+                        engine.set(&key, decode_binary(&kv.value))?;
+                    }
+                    args.reject_rest()?;
+                }
+
                 // txn: get KEY...
                 "get" => {
                     let txn = self.get_txn(&command.prefix)?;
@@ -907,6 +1966,22 @@ pub mod tests {
                     txn.commit()?;
                 }
 
+                // txn: lock [mode=shared|exclusive] KEY...
+                "lock" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let mode = match args.lookup("mode").map(|a| a.value.as_str()) {
+                        Some("shared") => LockMode::Shared,
+                        Some("exclusive") | None => LockMode::Exclusive,
+                        Some(v) => return Err(format!("invalid lock mode {v}").into()),
+                    };
+                    for arg in args.rest_pos() {
+                        let key = decode_binary(&arg.value);
+                        txn.lock(&key, mode)?;
+                    }
+                    args.reject_rest()?;
+                }
+
                 // txn: resume JSON
                 "resume" => {
                     let name = Self::txn_name(&command.prefix)?;
@@ -926,20 +2001,66 @@ pub mod tests {
                     txn.rollback()?;
                 }
 
-                // txn: scan [RANGE]
+                // txn: scan [reverse=BOOL] [RANGE]
                 "scan" => {
                     let txn = self.get_txn(&command.prefix)?;
                     let mut args = command.consume_args();
+                    let reverse = args.lookup_parse("reverse")?.unwrap_or(false);
                     let range =
                         parse_key_range(args.next_pos().map(|a| a.value.as_str()).unwrap_or(".."))?;
                     args.reject_rest()?;
 
-                    let kvs: Vec<_> = txn.scan(range).collect::<crate::error::Result<_>>()?;
+                    let kvs: Vec<_> = if reverse {
+                        txn.scan(range).rev().collect::<crate::error::Result<_>>()?
+                    } else {
+                        txn.scan(range).collect::<crate::error::Result<_>>()?
+                    };
                     for (key, value) in kvs {
                         writeln!(output, "{}", format::Raw::key_value(&key, &value))?;
                     }
                 }
 
+                // txn: cursor [range=RANGE | prefix=PREFIX] [batch=N] [next | prev | seek=KEY | seek_for_prev=KEY]...
+                // Runs a sequence of cursor operations on a scan, in order.
+                "cursor" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let range = args.lookup("range").map(|a| a.value.clone());
+                    let prefix = args.lookup("prefix").map(|a| decode_binary(&a.value));
+                    let batch = args.lookup_parse("batch")?;
+                    let mut scan = match (range, prefix) {
+                        (None, Some(prefix)) => txn.scan_prefix(&prefix),
+                        (range, None) => {
+                            txn.scan(parse_key_range(range.as_deref().unwrap_or(".."))?)
+                        }
+                        (Some(_), Some(_)) => return Err("can't use both range and prefix".into()),
+                    };
+                    if let Some(batch) = batch {
+                        scan = scan.with_batch_size(batch);
+                    }
+                    for arg in args.rest() {
+                        match (arg.key.as_deref(), arg.value.as_str()) {
+                            (None, "next") => match scan.next().transpose()? {
+                                Some((key, value)) => {
+                                    writeln!(output, "{}", format::Raw::key_value(&key, &value))?
+                                }
+                                None => writeln!(output, "None")?,
+                            },
+                            (None, "prev") => match scan.next_back().transpose()? {
+                                Some((key, value)) => {
+                                    writeln!(output, "{}", format::Raw::key_value(&key, &value))?
+                                }
+                                None => writeln!(output, "None")?,
+                            },
+                            (Some("seek"), key) => scan.seek(&decode_binary(key)),
+                            (Some("seek_for_prev"), key) => scan.seek_for_prev(&decode_binary(key)),
+                            _ => {
+                                return Err(format!("invalid cursor operation {}", arg.value).into())
+                            }
+                        }
+                    }
+                }
+
                 // txn: scan_prefix PREFIX
                 "scan_prefix" => {
                     let txn = self.get_txn(&command.prefix)?;
@@ -998,6 +2119,25 @@ pub mod tests {
                     writeln!(output, "{status:#?}")?;
                 }
 
+                // txn: write_batch [KEY=VALUE | KEY]...
+                // Sets KEY=VALUE pairs and deletes bare KEYs in a single batch.
+                "write_batch" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let ops = args
+                        .rest()
+                        .into_iter()
+                        .map(|arg| match &arg.key {
+                            Some(key) => WriteOp::Set {
+                                key: decode_binary(key),
+                                value: decode_binary(&arg.value),
+                            },
+                            None => WriteOp::Delete { key: decode_binary(&arg.value) },
+                        })
+                        .collect();
+                    txn.write_batch(ops)?;
+                }
+
                 name => return Err(format!("invalid command {name}").into()),
             }
             Ok(output)
@@ -1027,6 +2167,11 @@ pub mod tests {
                         let rawkey = format::Raw::key(&key);
                         writeln!(output, "engine delete {fmtkey} [{rawkey}]")?
                     }
+                    Operation::DeleteRange { range } => {
+                        let fmtrange = format_range::<format::MVCC<format::Raw>>(&range);
+                        let rawrange = format_range::<format::Raw>(&range);
+                        writeln!(output, "engine delete_range {fmtrange} [{rawrange}]")?
+                    }
                     Operation::Flush => writeln!(output, "engine flush")?,
                     Operation::Set { key, value } => {
                         let fmtkv = format::MVCC::<format::Raw>::key_value(&key, &value);
@@ -3,7 +3,7 @@ use super::{assert_row, assert_rows, dataset, TestCluster};
 use toydb::error::{Error, Result};
 use toydb::raft;
 use toydb::server::Status;
-use toydb::sql::engine::StatementResult;
+use toydb::sql::engine::{Change, Changes, StatementResult};
 use toydb::sql::types::{Column, DataType, Label, Table, Value};
 use toydb::storage::{engine, mvcc};
 
@@ -22,10 +22,12 @@ fn get_table() -> Result<()> {
     assert_eq!(
         c.get_table("movies")?,
         Table {
+            id: 4,
             name: "movies".into(),
-            primary_key: 0,
+            primary_key: vec![0],
             columns: vec![
                 Column {
+                    id: 0,
                     name: "id".into(),
                     datatype: DataType::Integer,
                     nullable: false,
@@ -35,6 +37,7 @@ fn get_table() -> Result<()> {
                     references: None,
                 },
                 Column {
+                    id: 1,
                     name: "title".into(),
                     datatype: DataType::String,
                     nullable: false,
@@ -44,6 +47,7 @@ fn get_table() -> Result<()> {
                     references: None,
                 },
                 Column {
+                    id: 2,
                     name: "studio_id".into(),
                     datatype: DataType::Integer,
                     nullable: false,
@@ -53,6 +57,7 @@ fn get_table() -> Result<()> {
                     references: Some("studios".into()),
                 },
                 Column {
+                    id: 3,
                     name: "genre_id".into(),
                     datatype: DataType::Integer,
                     nullable: false,
@@ -62,6 +67,7 @@ fn get_table() -> Result<()> {
                     references: Some("genres".into()),
                 },
                 Column {
+                    id: 4,
                     name: "released".into(),
                     datatype: DataType::Integer,
                     nullable: false,
@@ -71,6 +77,7 @@ fn get_table() -> Result<()> {
                     references: None,
                 },
                 Column {
+                    id: 5,
                     name: "rating".into(),
                     datatype: DataType::Float,
                     nullable: true,
@@ -80,6 +87,7 @@ fn get_table() -> Result<()> {
                     references: None,
                 },
                 Column {
+                    id: 6,
                     name: "ultrahd".into(),
                     datatype: DataType::Boolean,
                     nullable: true,
@@ -88,7 +96,10 @@ fn get_table() -> Result<()> {
                     index: false,
                     references: None,
                 },
-            ]
+            ],
+            version: 0,
+            layouts: Vec::new(),
+            indexes: Vec::new(),
         }
     );
     Ok(())
@@ -123,10 +134,13 @@ fn status() -> Result<()> {
                 storage: engine::Status {
                     name: "bitcask".to_string(),
                     keys: 13,
-                    size: 952,
-                    total_disk_size: 1166,
-                    live_disk_size: 1056,
+                    size: 986,
+                    compressed_size: 986,
+                    total_disk_size: 1200,
+                    live_disk_size: 1090,
                     garbage_disk_size: 110,
+                    cache_hits: 0,
+                    cache_misses: 0,
                 },
             },
             mvcc: mvcc::Status {
@@ -134,11 +148,14 @@ fn status() -> Result<()> {
                 active_txns: 0,
                 storage: engine::Status {
                     name: "bitcask".to_string(),
-                    keys: 36,
-                    size: 2177,
-                    total_disk_size: 7601,
-                    live_disk_size: 2465,
-                    garbage_disk_size: 5136,
+                    keys: 38,
+                    size: 2148,
+                    compressed_size: 2148,
+                    total_disk_size: 7705,
+                    live_disk_size: 2452,
+                    garbage_disk_size: 5253,
+                    cache_hits: 0,
+                    cache_misses: 0,
                 },
             }
         },
@@ -146,6 +163,62 @@ fn status() -> Result<()> {
     Ok(())
 }
 
+#[test]
+#[serial]
+fn changes() -> Result<()> {
+    let mut tc = TestCluster::new(3)?;
+    tc.add_config("change_feed: true\n");
+    tc.start()?;
+    let mut c = tc.connect_any()?;
+
+    c.execute("CREATE TABLE test (id INTEGER PRIMARY KEY, value STRING)")?;
+    c.execute("INSERT INTO test VALUES (1, 'a'), (2, 'b')")?;
+    c.execute("UPDATE test SET value = 'c' WHERE id = 1")?;
+    c.execute("DELETE FROM test WHERE id = 2")?;
+
+    let change = |version, id: i64, old: Option<&str>, new: Option<&str>| Change {
+        version,
+        table: "test".into(),
+        id: vec![Value::Integer(id)],
+        old: old.map(|v| vec![Value::Integer(id), Value::String(v.into())]),
+        new: new.map(|v| vec![Value::Integer(id), Value::String(v.into())]),
+    };
+
+    // The CREATE TABLE at version 1 doesn't emit any row changes.
+    assert_eq!(
+        c.changes(0)?,
+        Changes {
+            changes: vec![
+                change(2, 1, None, Some("a")),
+                change(2, 2, None, Some("b")),
+                change(3, 1, Some("a"), Some("c")),
+                change(4, 2, Some("b"), None),
+            ],
+            next: 5.into(),
+        }
+    );
+
+    // Resuming from a version only returns later changes.
+    assert_eq!(
+        c.changes(4)?,
+        Changes { changes: vec![change(4, 2, Some("b"), None)], next: 5.into() }
+    );
+    assert_eq!(c.changes(5)?, Changes { changes: vec![], next: 5.into() });
+
+    // A subscription emits new changes as they are committed, from a
+    // separate client.
+    let mut other = tc.connect_any()?;
+    other.execute("INSERT INTO test VALUES (3, 'd')")?;
+
+    let mut subscription = c.subscribe(3);
+    assert_eq!(subscription.next().transpose()?, Some(change(3, 1, Some("a"), Some("c"))));
+    assert_eq!(subscription.next().transpose()?, Some(change(4, 2, Some("b"), None)));
+    assert_eq!(subscription.next().transpose()?, Some(change(5, 3, None, Some("d"))));
+    assert_eq!(subscription.cursor(), 6);
+
+    Ok(())
+}
+
 #[test]
 #[serial]
 fn execute() -> Result<()> {
@@ -154,3 +154,133 @@ fn anomaly_phantom_read() -> Result<()> {
 }
 
 // FIXME We should test write skew, but we need to implement serializable snapshot isolation first.
+
+#[test]
+#[serial]
+// Write skew can be prevented by explicitly locking the rows that are read, using
+// SELECT ... FOR UPDATE. Here, a reads 1 and writes 2 while b reads 2 and writes 1.
+fn select_for_update_write_skew() -> Result<()> {
+    let tc = TestCluster::run_with(5, dataset::TEST_TABLE)?;
+    let mut a = tc.connect_any()?;
+    let mut b = tc.connect_any()?;
+    let mut c = tc.connect_any()?;
+
+    c.execute("INSERT INTO test VALUES (1, 'c'), (2, 'c')")?;
+
+    a.execute("BEGIN")?;
+    b.execute("BEGIN")?;
+
+    assert_row(
+        a.execute("SELECT * FROM test WHERE id = 1 FOR UPDATE")?,
+        vec![Value::Integer(1), Value::String("c".into())],
+    );
+    assert_row(
+        b.execute("SELECT * FROM test WHERE id = 2 FOR UPDATE")?,
+        vec![Value::Integer(2), Value::String("c".into())],
+    );
+    assert_eq!(a.execute("UPDATE test SET value = 'a' WHERE id = 2"), Err(Error::Serialization));
+    assert_eq!(b.execute("UPDATE test SET value = 'b' WHERE id = 1"), Err(Error::Serialization));
+
+    // NOWAIT is the default, since toyDB never waits for locks.
+    assert_eq!(
+        b.execute("SELECT * FROM test WHERE id = 1 FOR UPDATE NOWAIT"),
+        Err(Error::Serialization)
+    );
+    assert_eq!(b.execute("SELECT * FROM test WHERE id = 1 FOR SHARE"), Err(Error::Serialization));
+
+    // Once a writes row 1 and commits, b still can't lock it, since there is
+    // a newer version.
+    a.execute("UPDATE test SET value = 'a' WHERE id = 1")?;
+    a.execute("COMMIT")?;
+    assert_eq!(b.execute("SELECT * FROM test WHERE id = 1 FOR UPDATE"), Err(Error::Serialization));
+    b.execute("UPDATE test SET value = 'b' WHERE id = 2")?;
+    b.execute("COMMIT")?;
+
+    assert_rows(
+        c.execute("SELECT * FROM test")?,
+        vec![
+            vec![Value::Integer(1), Value::String("a".into())],
+            vec![Value::Integer(2), Value::String("b".into())],
+        ],
+    );
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+// SELECT ... FOR SHARE locks are compatible with each other, but not with writes
+// or FOR UPDATE locks by other transactions.
+fn select_for_share() -> Result<()> {
+    let tc = TestCluster::run_with(5, dataset::TEST_TABLE)?;
+    let mut a = tc.connect_any()?;
+    let mut b = tc.connect_any()?;
+    let mut c = tc.connect_any()?;
+
+    c.execute("INSERT INTO test VALUES (1, 'c')")?;
+
+    a.execute("BEGIN")?;
+    b.execute("BEGIN")?;
+
+    let row = vec![Value::Integer(1), Value::String("c".into())];
+    assert_row(a.execute("SELECT * FROM test FOR SHARE")?, row.clone());
+    assert_row(b.execute("SELECT * FROM test FOR SHARE")?, row.clone());
+
+    assert_eq!(a.execute("SELECT * FROM test FOR UPDATE"), Err(Error::Serialization));
+    assert_eq!(c.execute("UPDATE test SET value = 'c' WHERE id = 1"), Err(Error::Serialization));
+    assert_eq!(c.execute("DELETE FROM test WHERE id = 1"), Err(Error::Serialization));
+
+    // Once b rolls back, a can upgrade its lock and update the row.
+    b.execute("ROLLBACK")?;
+    assert_row(a.execute("SELECT * FROM test FOR UPDATE")?, row);
+    a.execute("UPDATE test SET value = 'a' WHERE id = 1")?;
+    a.execute("COMMIT")?;
+
+    assert_row(
+        c.execute("SELECT * FROM test")?,
+        vec![Value::Integer(1), Value::String("a".into())],
+    );
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+// SELECT ... FOR UPDATE SKIP LOCKED can be used to implement a work queue, where
+// concurrent workers each pick a different row.
+fn select_for_update_skip_locked() -> Result<()> {
+    let tc = TestCluster::run_with(5, dataset::TEST_TABLE)?;
+    let mut a = tc.connect_any()?;
+    let mut b = tc.connect_any()?;
+    let mut c = tc.connect_any()?;
+
+    c.execute("INSERT INTO test VALUES (1, 'c'), (2, 'c'), (3, 'c')")?;
+
+    a.execute("BEGIN")?;
+    b.execute("BEGIN")?;
+
+    assert_row(
+        a.execute("SELECT * FROM test FOR UPDATE SKIP LOCKED LIMIT 1")?,
+        vec![Value::Integer(1), Value::String("c".into())],
+    );
+    assert_row(
+        b.execute("SELECT * FROM test FOR UPDATE SKIP LOCKED LIMIT 1")?,
+        vec![Value::Integer(2), Value::String("c".into())],
+    );
+    assert_rows(
+        c.execute("SELECT * FROM test FOR SHARE SKIP LOCKED")?,
+        vec![vec![Value::Integer(3), Value::String("c".into())]],
+    );
+
+    a.execute("DELETE FROM test WHERE id = 1")?;
+    a.execute("COMMIT")?;
+    b.execute("DELETE FROM test WHERE id = 2")?;
+    b.execute("COMMIT")?;
+
+    assert_rows(
+        c.execute("SELECT * FROM test")?,
+        vec![vec![Value::Integer(3), Value::String("c".into())]],
+    );
+
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use super::{assert_row, dataset, TestCluster};
+use super::{assert_row, assert_rows, dataset, TestCluster};
 
 use serial_test::serial;
 use toydb::error::{Error, Result};
@@ -48,3 +48,101 @@ fn client_commit_error() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+#[serial]
+// A cluster can be restored from a backup of a single node, and the restored
+// cluster contains the data as of the backup.
+fn backup_restore() -> Result<()> {
+    let dir = tempfile::TempDir::with_prefix("toydb")?;
+    let backup = dir.path().join("backup");
+
+    let tc = TestCluster::run_with(3, dataset::TEST_TABLE)?;
+    let mut c = tc.connect(2)?;
+    c.execute("INSERT INTO test VALUES (1, 'a'), (2, 'b')")?;
+    c.backup("backup")?;
+
+    // Writes after the backup are not included, and the backup name can't be
+    // reused.
+    c.execute("INSERT INTO test VALUES (3, 'c')")?;
+    assert!(matches!(c.backup("backup"), Err(Error::InvalidInput(_))));
+    drop(c);
+
+    // Move the backup out of the cluster's directory before it's removed.
+    std::fs::rename(tc.backup_dir(2).join("backup"), &backup)?;
+    drop(tc);
+
+    // Restore all nodes of a new cluster from the backup.
+    let mut tc = TestCluster::new(3)?;
+    tc.restore(&backup)?;
+    tc.start()?;
+
+    let mut c = tc.connect_any()?;
+    assert_rows(
+        c.execute("SELECT * FROM test")?,
+        vec![
+            vec![Value::Integer(1), Value::String("a".into())],
+            vec![Value::Integer(2), Value::String("b".into())],
+        ],
+    );
+
+    // The restored cluster accepts writes.
+    c.execute("INSERT INTO test VALUES (3, 'c')")?;
+    assert_row(
+        c.execute("SELECT * FROM test WHERE id = 3")?,
+        vec![Value::Integer(3), Value::String("c".into())],
+    );
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+// With an encryption key configured, the Raft log and SQL data are not stored
+// as plaintext, and can be read back after a restart.
+fn encryption() -> Result<()> {
+    let dir = tempfile::TempDir::with_prefix("toydb")?;
+    let key_file = dir.path().join("key");
+    std::fs::write(&key_file, "0f".repeat(32))?;
+
+    let mut tc = TestCluster::new(3)?;
+    tc.add_config(&format!("encryption_key_file: {}\n", key_file.display()));
+    tc.start()?;
+    let mut c = tc.connect_any()?;
+    c.execute(dataset::TEST_TABLE)?;
+    c.execute("INSERT INTO test VALUES (1, 'supersecret')")?;
+    drop(c);
+    tc.stop();
+
+    for id in 1..=3 {
+        for file in ["log", "state"] {
+            let data = std::fs::read(tc.node_path(id).join(file))?;
+            assert!(!data.windows(11).any(|w| w == b"supersecret"), "plaintext in n{id} {file}");
+        }
+    }
+
+    tc.start()?;
+    let mut c = tc.connect_any()?;
+    assert_row(
+        c.execute("SELECT * FROM test")?,
+        vec![Value::Integer(1), Value::String("supersecret".into())],
+    );
+    Ok(())
+}
+
+#[test]
+#[serial]
+// Backup names must be plain file names within the server's backup directory.
+fn backup_invalid_name() -> Result<()> {
+    let tc = TestCluster::run(1)?;
+    let mut c = tc.connect(1)?;
+    for name in ["", ".", "..", "../backup", "a/b", "/tmp/backup", "a\\b", "a..b"] {
+        assert!(matches!(c.backup(name), Err(Error::InvalidInput(_))), "{name}");
+    }
+    assert!(!tc.backup_dir(1).exists());
+
+    c.backup("backup")?;
+    assert!(tc.backup_dir(1).join("backup").join("log").exists());
+    assert!(tc.backup_dir(1).join("backup").join("state").exists());
+    Ok(())
+}
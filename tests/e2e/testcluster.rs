@@ -14,6 +14,7 @@ use toydb::Client;
 pub struct TestCluster {
     nodes: u8,
     dir: tempfile::TempDir,
+    config: String,
     children: std::collections::HashMap<NodeID, std::process::Child>,
 }
 
@@ -26,6 +27,7 @@ impl TestCluster {
         Ok(Self {
             nodes,
             dir: tempfile::TempDir::with_prefix("toydb")?,
+            config: String::new(),
             children: std::collections::HashMap::new(),
         })
     }
@@ -51,6 +53,12 @@ impl TestCluster {
         Ok(tc)
     }
 
+    /// Adds YAML configuration for all nodes, e.g. "compression: lz4\n". Must
+    /// be called before starting the cluster.
+    pub fn add_config(&mut self, config: &str) {
+        self.config.push_str(config);
+    }
+
     /// Returns an iterator over the cluster node IDs.
     fn ids(&self) -> impl Iterator<Item = NodeID> {
         1..=self.nodes
@@ -71,24 +79,32 @@ impl TestCluster {
     }
 
     /// Returns the path to the given node's directory.
-    fn node_path(&self, id: NodeID) -> std::path::PathBuf {
+    pub fn node_path(&self, id: NodeID) -> std::path::PathBuf {
         self.assert_id(id);
         self.dir.path().join(format!("toydb{}", id))
     }
 
+    /// Returns the given node's backup directory.
+    pub fn backup_dir(&self, id: NodeID) -> std::path::PathBuf {
+        self.node_path(id).join("backups")
+    }
+
     /// Generates a config file for the given node.
     fn node_config(&self, id: NodeID) -> String {
         self.assert_id(id);
         let mut cfg = String::new();
         cfg.push_str(&format!("id: {}\n", id));
         cfg.push_str(&format!("data_dir: {}\n", self.node_path(id).to_string_lossy()));
+        cfg.push_str(&format!("backup_dir: {}\n", self.backup_dir(id).to_string_lossy()));
         cfg.push_str(&format!("listen_sql: {}\n", self.node_address_sql(id)));
         cfg.push_str(&format!("listen_raft: {}\n", self.node_address_raft(id)));
+        cfg.push_str("fsync: never\n"); // don't fsync in tests, to speed them up
         cfg.push_str("peers: {\n");
         for peer in self.ids().filter(|p| p != &id) {
             cfg.push_str(&format!("  '{}': {},\n", peer, self.node_address_raft(peer)))
         }
         cfg.push_str("}\n");
+        cfg.push_str(&self.config);
         cfg
     }
 
@@ -150,6 +166,34 @@ impl TestCluster {
         Ok(())
     }
 
+    /// Stops the test cluster by killing the nodes. It can be started again.
+    pub fn stop(&mut self) {
+        for (_, mut child) in self.children.drain() {
+            child.kill().expect("Failed to kill node");
+            child.wait().expect("Failed to wait for node to terminate");
+        }
+    }
+
+    /// Seeds all node data directories from the given backup directory, using
+    /// the toydb restore subcommand. Must be called before starting the cluster.
+    pub fn restore(&self, backup: &std::path::Path) -> Result<()> {
+        let build = escargot::CargoBuild::new().bin("toydb").run().expect("Failed to build binary");
+        for id in self.ids() {
+            std::fs::create_dir_all(self.node_path(id))?;
+            std::fs::write(self.node_path(id).join("toydb.yaml"), self.node_config(id))?;
+            let status = build
+                .command()
+                .args(vec!["-c", &self.node_path(id).join("toydb.yaml").to_string_lossy()])
+                .arg("restore")
+                .arg(backup)
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .status()?;
+            assert!(status.success(), "Node {id} restore failed with status {status}");
+        }
+        Ok(())
+    }
+
     /// Connects to the given cluster node.
     pub fn connect(&self, id: NodeID) -> Result<Client> {
         self.assert_id(id);
@@ -169,9 +213,6 @@ impl Drop for TestCluster {
     /// Note that cargo will itself kill all child processes if the tests are
     /// aborted via e.g. Ctrl-C: https://github.com/rust-lang/cargo/issues/5598
     fn drop(&mut self) {
-        for (_, mut child) in self.children.drain() {
-            child.kill().expect("Failed to kill node");
-            child.wait().expect("Failed to wait for node to terminate");
-        }
+        self.stop()
     }
 }